@@ -8,6 +8,10 @@ pub fn compile_attr(table: &Table, name: &str, value: &str, strings: &Strings) -
     let entry = table.entry_by_ref(Ref::attr(name))?;
     let attr_type = entry.attribute_type().unwrap();
     let (data, data_type) = match attr_type {
+        _ if value.starts_with('@') && entry.accepts_reference() => {
+            let id = table.entry_by_ref(Ref::parse(value)?)?.id();
+            (u32::from(id), ResValueType::Reference)
+        }
         ResAttributeType::Reference => {
             let id = table.entry_by_ref(Ref::parse(value)?)?.id();
             (u32::from(id), ResValueType::Reference)
@@ -1,140 +1,669 @@
 use crate::manifest::AndroidManifest;
 use crate::res::{
-    Chunk, ResTableConfig, ResTableEntry, ResTableHeader, ResTablePackageHeader,
-    ResTableTypeHeader, ResTableTypeSpecHeader, ResTableValue, ResValue, ScreenType,
+    Chunk, ResTableConfig, ResTableEntry, ResTableHeader, ResTableMap, ResTableMapEntry,
+    ResTablePackageHeader, ResTableTypeHeader, ResTableTypeSpecHeader, ResTableValue, ResValue,
+    ScreenType,
 };
 use anyhow::Result;
+use std::io::Cursor;
 
 mod attributes;
 mod table;
 mod xml;
 
-pub use table::Table;
+pub use table::{Ref, Table};
 
 pub fn compile_manifest(manifest: &AndroidManifest, table: &Table) -> Result<Chunk> {
     let xml = quick_xml::se::to_string(manifest)?;
     xml::compile_xml(&xml, table)
 }
 
-const DPI_SIZE: [u32; 5] = [48, 72, 96, 144, 192];
+/// Scale factors of the mdpi, hdpi, xhdpi, xxhdpi and xxxhdpi densities.
+const DPI_SCALE: [f32; 5] = [1.0, 1.5, 2.0, 3.0, 4.0];
+const DENSITIES: [u16; 5] = [160, 240, 320, 480, 640];
+/// Size of launcher icons in dp.
+const ICON_DP: f32 = 48.0;
+/// Size of the adaptive icon layers in dp.
+const LAYER_DP: f32 = 108.0;
 
-fn variants(name: &str) -> impl Iterator<Item = (String, u32)> + '_ {
-    DPI_SIZE
+/// Png files of the mipmap `name` by density, with their size in px.
+pub(crate) fn variants(name: &str, dp: f32) -> impl Iterator<Item = (String, u32)> + '_ {
+    DPI_SCALE.into_iter().map(move |scale| {
+        let size = (dp * scale) as u32;
+        (format!("res/{0}/{0}{1}.png", name, size), size)
+    })
+}
+
+/// Launcher icon `name` and, if `adaptive`, its `<name>_foreground` and
+/// `<name>_background` layers, and the `<name>_monochrome` layer if
+/// `monochrome`, with their sizes in px.
+pub(crate) fn icon_variants(
+    name: &str,
+    adaptive: bool,
+    monochrome: bool,
+) -> Vec<(String, String, u32)> {
+    let mut mipmaps = vec![(name.to_string(), ICON_DP)];
+    if adaptive {
+        mipmaps.push((format!("{}_foreground", name), LAYER_DP));
+        mipmaps.push((format!("{}_background", name), LAYER_DP));
+        if monochrome {
+            mipmaps.push((format!("{}_monochrome", name), LAYER_DP));
+        }
+    }
+    mipmaps
         .into_iter()
-        .map(move |size| (format!("res/{0}/{0}{1}.png", name, size), size))
+        .flat_map(|(mipmap, dp)| {
+            variants(&mipmap, dp)
+                .map(|(path, size)| (mipmap.clone(), path, size))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Size of the android 12 splash screen icon in dp.
+const SPLASH_DP: f32 = 288.0;
+
+/// Png files of the splash icon by density with their size in px, and the
+/// night variants if `dark`.
+pub(crate) fn splash_variants(dark: bool) -> Vec<(bool, Vec<(String, u32)>)> {
+    let mut variants = vec![(false, self::variants("splash_icon", SPLASH_DP).collect())];
+    if dark {
+        variants.push((
+            true,
+            self::variants("splash_icon_night", SPLASH_DP).collect(),
+        ));
+    }
+    variants
+}
+
+/// Path of the adaptive icon drawable of the mipmap `name`.
+pub fn adaptive_icon_path(name: &str) -> String {
+    format!("res/mipmap-anydpi-v26/{}.xml", name)
 }
 
-pub fn compile_mipmap<'a>(package_name: &str, name: &'a str) -> Result<Mipmap<'a>> {
-    let chunk = Chunk::Table(
+/// Compiles the `<adaptive-icon>` drawable of the mipmap `name`, which
+/// needs to be imported into `table` with its layers. Themed icons need a
+/// dedicated `monochrome` layer, tinting the foreground fills it.
+pub fn compile_adaptive_icon(name: &str, monochrome: bool, table: &Table) -> Result<Chunk> {
+    let monochrome = if monochrome {
+        format!(
+            r#"<monochrome android:drawable="@mipmap/{}_monochrome"/>"#,
+            name
+        )
+    } else {
+        String::new()
+    };
+    let xml = format!(
+        r#"<adaptive-icon xmlns:android="http://schemas.android.com/apk/res/android"><background android:drawable="@mipmap/{0}_background"/><foreground android:drawable="@mipmap/{0}_foreground"/>{1}</adaptive-icon>"#,
+        name, monochrome
+    );
+    xml::compile_xml(&xml, table)
+}
+
+/// String resource referenced as `@string/<name>`. Values are keyed by
+/// locale like `de` or `pt-BR`, the `None` value is the default.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StringResource {
+    pub name: String,
+    pub values: Vec<(Option<String>, String)>,
+}
+
+/// Splash screen theme referenced as `@style/splash`, showing
+/// `@color/splash_background` and on api 31 the `@drawable/splash_icon`.
+/// Attributes are the resolved ids of the framework attributes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SplashStyle {
+    /// Theme the style inherits from, set as `postSplashScreenTheme` to
+    /// switch to after the splash screen.
+    pub parent: u32,
+    pub window_background: u32,
+    /// `windowSplashScreenBackground` and `windowSplashScreenAnimatedIcon`,
+    /// if the platform has them.
+    pub splash_screen: Option<(u32, u32)>,
+    /// Background color as rgba
+    pub background: [u8; 4],
+    pub dark_background: Option<[u8; 4]>,
+    pub image: bool,
+    pub dark_image: bool,
+}
+
+/// Resources of the app package.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Resources<'a> {
+    /// Launcher icon, adaptive if `adaptive` is set.
+    pub mipmap: Option<&'a str>,
+    pub adaptive: bool,
+    /// Adaptive icon with a monochrome layer.
+    pub monochrome: bool,
+    pub strings: &'a [StringResource],
+    pub splash: Option<&'a SplashStyle>,
+}
+
+/// Compiles the resource table of the app package.
+pub fn compile_resources(package_name: &str, res: Resources) -> Result<Chunk> {
+    let mut global_strings = vec![];
+    let mut type_strings = vec![];
+    let mut key_strings = vec![];
+    let mut chunks = vec![];
+    if let Some(name) = res.mipmap {
+        let type_id = type_strings.len() as u8 + 1;
+        type_strings.push("mipmap".to_string());
+        let variants = icon_variants(name, res.adaptive, res.monochrome);
+        let mut names = variants
+            .iter()
+            .map(|(mipmap, _, _)| mipmap.clone())
+            .collect::<Vec<_>>();
+        names.dedup();
+        let first_key = key_strings.len() as u32;
+        key_strings.extend(names.iter().cloned());
+        // CONFIG_DENSITY, and CONFIG_VERSION for the adaptive icon
+        let mut flags = vec![0x100; names.len()];
+        if res.adaptive {
+            flags[0] |= 0x400;
+        }
+        chunks.push(type_spec(type_id, flags));
+        for (i, density) in DENSITIES.into_iter().enumerate() {
+            let entries = (0..names.len())
+                .map(|key| {
+                    let string_id = (global_strings.len() + key * DENSITIES.len() + i) as u32;
+                    Some(string_entry(first_key + key as u32, string_id))
+                })
+                .collect();
+            chunks.push(table_type(type_id, config(0, density), entries));
+        }
+        global_strings.extend(variants.into_iter().map(|(_, path, _)| path));
+        if res.adaptive {
+            // anydpi-v26
+            let mut config = config(0, 0xfffe);
+            config.version = 26;
+            let mut entries = vec![None; names.len()];
+            entries[0] = Some(string_entry(first_key, global_strings.len() as u32));
+            global_strings.push(adaptive_icon_path(name));
+            chunks.push(table_type(type_id, config, entries));
+        }
+    }
+    let strings = res.strings;
+    if !strings.is_empty() {
+        let type_id = type_strings.len() as u8 + 1;
+        type_strings.push("string".to_string());
+        let first_key = key_strings.len() as u32;
+        let mut locales = vec![];
+        for string in strings {
+            anyhow::ensure!(
+                string.values.iter().any(|(locale, _)| locale.is_none()),
+                "string resource {} has no default value",
+                string.name
+            );
+            key_strings.push(string.name.clone());
+            for (locale, _) in &string.values {
+                if !locales.contains(locale) {
+                    locales.push(locale.clone());
+                }
+            }
+        }
+        // The default config comes first, the manifest compiler resolves
+        // references with it
+        locales.sort();
+        chunks.push(type_spec(
+            type_id,
+            strings
+                .iter()
+                .map(|string| {
+                    // CONFIG_LOCALE
+                    if string.values.len() > 1 {
+                        4
+                    } else {
+                        0
+                    }
+                })
+                .collect(),
+        ));
+        for locale in &locales {
+            let packed = locale.as_deref().map(pack_locale).transpose()?.unwrap_or(0);
+            let entries = strings
+                .iter()
+                .enumerate()
+                .map(|(i, string)| {
+                    string
+                        .values
+                        .iter()
+                        .find(|(l, _)| l == locale)
+                        .map(|(_, value)| {
+                            let string_id = global_strings.len() as u32;
+                            global_strings.push(value.clone());
+                            string_entry(first_key + i as u32, string_id)
+                        })
+                })
+                .collect();
+            chunks.push(table_type(type_id, config(packed, 0), entries));
+        }
+    }
+    if let Some(splash) = res.splash {
+        let color_id = type_strings.len() as u8 + 1;
+        type_strings.push("color".to_string());
+        let key = key_strings.len() as u32;
+        key_strings.push("splash_background".to_string());
+        // CONFIG_UI_MODE
+        let flags = if splash.dark_background.is_some() {
+            0x1000
+        } else {
+            0
+        };
+        chunks.push(type_spec(color_id, vec![flags]));
+        let entry = color_entry(key, splash.background);
+        chunks.push(table_type(color_id, config(0, 0), vec![Some(entry)]));
+        if let Some(dark) = splash.dark_background {
+            let entry = color_entry(key, dark);
+            chunks.push(table_type(color_id, night(config(0, 0)), vec![Some(entry)]));
+        }
+
+        let drawable_id = type_strings.len() as u8 + 1;
+        if splash.image {
+            type_strings.push("drawable".to_string());
+            let key = key_strings.len() as u32;
+            key_strings.push("splash_icon".to_string());
+            let flags = if splash.dark_image { 0x1100 } else { 0x100 };
+            chunks.push(type_spec(drawable_id, vec![flags]));
+            for (dark, variants) in splash_variants(splash.dark_image) {
+                for (density, (path, _)) in DENSITIES.into_iter().zip(variants) {
+                    let mut config = config(0, density);
+                    if dark {
+                        config = night(config);
+                    }
+                    let entry = string_entry(key, global_strings.len() as u32);
+                    global_strings.push(path);
+                    chunks.push(table_type(drawable_id, config, vec![Some(entry)]));
+                }
+            }
+        }
+
+        // `postSplashScreenTheme` like declared by androidx.core:core-splashscreen
+        let attr_id = type_strings.len() as u8 + 1;
+        type_strings.push("attr".to_string());
+        let key = key_strings.len() as u32;
+        key_strings.push("postSplashScreenTheme".to_string());
+        chunks.push(type_spec(attr_id, vec![0]));
+        let entry = attr_entry(key, ATTR_FORMAT_REFERENCE);
+        chunks.push(table_type(attr_id, config(0, 0), vec![Some(entry)]));
+
+        let style_id = type_strings.len() as u8 + 1;
+        type_strings.push("style".to_string());
+        let key = key_strings.len() as u32;
+        key_strings.push("splash".to_string());
+        let color = reference(color_id, 0);
+        let mut attrs = vec![
+            (splash.window_background, color),
+            (reference(attr_id, 0), splash.parent),
+        ];
+        // CONFIG_VERSION
+        let flags = if splash.splash_screen.is_some() {
+            0x400
+        } else {
+            0
+        };
+        chunks.push(type_spec(style_id, vec![flags]));
+        chunks.push(table_type(
+            style_id,
+            config(0, 0),
+            vec![Some(style_entry(key, splash.parent, attrs.clone()))],
+        ));
+        if let Some((background, icon)) = splash.splash_screen {
+            attrs.push((background, color));
+            if splash.image {
+                attrs.push((icon, reference(drawable_id, 0)));
+            }
+            let mut config = config(0, 0);
+            config.version = 31;
+            let entry = style_entry(key, splash.parent, attrs);
+            chunks.push(table_type(style_id, config, vec![Some(entry)]));
+        }
+    }
+
+    let type_strings = Chunk::StringPool(type_strings, vec![]);
+    let key_strings = Chunk::StringPool(key_strings, vec![]);
+    let mut buf = vec![];
+    type_strings.write(&mut Cursor::new(&mut buf))?;
+    // Chunk header, id, 256 byte utf16 name and the five offsets
+    let header_size = 8 + 4 + 256 + 5 * 4;
+    let (num_types, num_keys) = match (&type_strings, &key_strings) {
+        (Chunk::StringPool(types, _), Chunk::StringPool(keys, _)) => (types.len(), keys.len()),
+        _ => unreachable!(),
+    };
+    let mut package_chunks = vec![type_strings, key_strings];
+    package_chunks.extend(chunks);
+    Ok(Chunk::Table(
         ResTableHeader { package_count: 1 },
         vec![
-            Chunk::StringPool(variants(name).map(|(res, _)| res).collect(), vec![]),
+            Chunk::StringPool(global_strings, vec![]),
             Chunk::TablePackage(
                 ResTablePackageHeader {
                     id: 127,
                     name: package_name.to_string(),
-                    type_strings: 288,
-                    last_public_type: 1,
-                    key_strings: 332,
-                    last_public_key: 1,
+                    type_strings: header_size,
+                    last_public_type: num_types as u32,
+                    key_strings: header_size + buf.len() as u32,
+                    last_public_key: num_keys as u32,
                     type_id_offset: 0,
                 },
-                vec![
-                    Chunk::StringPool(vec!["mipmap".to_string()], vec![]),
-                    Chunk::StringPool(vec!["icon".to_string()], vec![]),
-                    Chunk::TableTypeSpec(
-                        ResTableTypeSpecHeader {
-                            id: 1,
-                            res0: 0,
-                            res1: 0,
-                            entry_count: 1,
-                        },
-                        vec![256],
-                    ),
-                    mipmap_table_type(1, 160, 0),
-                    mipmap_table_type(1, 240, 1),
-                    mipmap_table_type(1, 320, 2),
-                    mipmap_table_type(1, 480, 3),
-                    mipmap_table_type(1, 640, 4),
-                ],
+                package_chunks,
             ),
         ],
+    ))
+}
+
+/// Packs `de` or `pt-BR` into the language and country bytes of a
+/// `ResTable_config`.
+fn pack_locale(locale: &str) -> Result<u32> {
+    let (language, region) = match locale.split_once(['-', '_']) {
+        Some((language, region)) => (language, region.trim_start_matches('r')),
+        None => (locale, ""),
+    };
+    anyhow::ensure!(
+        language.len() == 2 && (region.is_empty() || region.len() == 2) && locale.is_ascii(),
+        "unsupported android locale {}, expected a two letter language and region",
+        locale
     );
-    Ok(Mipmap { name, chunk })
+    let language = language.to_ascii_lowercase().into_bytes();
+    let region = region.to_ascii_uppercase().into_bytes();
+    let region = if region.is_empty() {
+        vec![0, 0]
+    } else {
+        region
+    };
+    Ok(u32::from_le_bytes([
+        language[0],
+        language[1],
+        region[0],
+        region[1],
+    ]))
 }
 
-fn mipmap_table_type(type_id: u8, density: u16, string_id: u32) -> Chunk {
-    Chunk::TableType(
-        ResTableTypeHeader {
+fn config(locale: u32, density: u16) -> ResTableConfig {
+    ResTableConfig {
+        size: 28 + 36,
+        imsi: 0,
+        locale,
+        screen_type: ScreenType {
+            orientation: 0,
+            touchscreen: 0,
+            density,
+        },
+        input: 0,
+        screen_size: 0,
+        version: if density == 0 { 0 } else { 4 },
+        unknown: vec![0; 36],
+    }
+}
+
+/// `config` restricted to night mode, which needs api 8.
+fn night(mut config: ResTableConfig) -> ResTableConfig {
+    config.unknown[1] = 0x20;
+    config.version = config.version.max(8);
+    config
+}
+
+fn type_spec(type_id: u8, flags: Vec<u32>) -> Chunk {
+    Chunk::TableTypeSpec(
+        ResTableTypeSpecHeader {
             id: type_id,
             res0: 0,
             res1: 0,
-            entry_count: 1,
-            entries_start: 88,
-            config: ResTableConfig {
-                size: 28 + 36,
-                imsi: 0,
-                locale: 0,
-                screen_type: ScreenType {
-                    orientation: 0,
-                    touchscreen: 0,
-                    density,
-                },
-                input: 0,
-                screen_size: 0,
-                version: 4,
-                unknown: vec![0; 36],
-            },
+            entry_count: flags.len() as u32,
         },
-        vec![0],
-        vec![Some(ResTableEntry {
-            size: 8,
-            flags: 0,
-            key: 0,
-            value: ResTableValue::Simple(ResValue {
-                size: 8,
-                res0: 0,
-                data_type: 3,
-                data: string_id,
-            }),
-        })],
+        flags,
     )
 }
 
-pub struct Mipmap<'a> {
-    name: &'a str,
-    chunk: Chunk,
+/// Id of the entry `index` of the app package type `type_id`.
+fn reference(type_id: u8, index: u32) -> u32 {
+    0x7f00_0000 | (type_id as u32) << 16 | index
 }
 
-impl<'a> Mipmap<'a> {
-    pub fn chunk(&self) -> &Chunk {
-        &self.chunk
+fn simple_entry(key: u32, data_type: u8, data: u32) -> ResTableEntry {
+    ResTableEntry {
+        size: 8,
+        flags: 0,
+        key,
+        value: ResTableValue::Simple(ResValue {
+            size: 8,
+            res0: 0,
+            data_type,
+            data,
+        }),
     }
+}
+
+/// Entry with the global string `string_id` as value.
+fn string_entry(key: u32, string_id: u32) -> ResTableEntry {
+    simple_entry(key, 3, string_id)
+}
 
-    pub fn variants(&self) -> impl Iterator<Item = (String, u32)> + 'a {
-        variants(self.name)
+/// Entry with an argb8 color value.
+fn color_entry(key: u32, [r, g, b, a]: [u8; 4]) -> ResTableEntry {
+    simple_entry(key, 0x1c, u32::from_be_bytes([a, r, g, b]))
+}
+
+/// `format="reference"` of an attribute.
+const ATTR_FORMAT_REFERENCE: u32 = 1;
+
+/// Attribute entry accepting values of `format`.
+fn attr_entry(key: u32, format: u32) -> ResTableEntry {
+    ResTableEntry {
+        size: 16,
+        flags: 1,
+        key,
+        value: ResTableValue::Complex(
+            ResTableMapEntry {
+                parent: 0,
+                count: 1,
+            },
+            vec![ResTableMap {
+                // ATTR_TYPE
+                name: 0x0100_0000,
+                value: ResValue {
+                    size: 8,
+                    res0: 0,
+                    // TYPE_INT_DEC
+                    data_type: 0x10,
+                    data: format,
+                },
+            }],
+        ),
+    }
+}
+
+/// Style entry setting the attributes in `attrs` to references.
+fn style_entry(key: u32, parent: u32, mut attrs: Vec<(u32, u32)>) -> ResTableEntry {
+    // Bags are looked up by binary search
+    attrs.sort();
+    ResTableEntry {
+        size: 16,
+        flags: 1,
+        key,
+        value: ResTableValue::Complex(
+            ResTableMapEntry {
+                parent,
+                count: attrs.len() as u32,
+            },
+            attrs
+                .into_iter()
+                .map(|(name, data)| ResTableMap {
+                    name,
+                    value: ResValue {
+                        size: 8,
+                        res0: 0,
+                        data_type: 1,
+                        data,
+                    },
+                })
+                .collect(),
+        ),
     }
 }
 
+fn table_type(type_id: u8, config: ResTableConfig, entries: Vec<Option<ResTableEntry>>) -> Chunk {
+    let mut index = vec![];
+    let mut offset = 0;
+    for entry in &entries {
+        if let Some(entry) = entry {
+            index.push(offset);
+            offset += match &entry.value {
+                ResTableValue::Simple(_) => 16,
+                ResTableValue::Complex(_, map) => 16 + 12 * map.len() as u32,
+            };
+        } else {
+            index.push(u32::MAX);
+        }
+    }
+    Chunk::TableType(
+        ResTableTypeHeader {
+            id: type_id,
+            res0: 0,
+            res1: 0,
+            entry_count: entries.len() as u32,
+            entries_start: 20 + config.size + 4 * entries.len() as u32,
+            config,
+        },
+        index,
+        entries,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::compiler::table::Ref;
-    use crate::manifest::Activity;
+    use crate::manifest::{Activity, Profileable};
     use std::io::Cursor;
 
     #[test]
     fn test_compile_mipmap() -> Result<()> {
         crate::tests::init_logger();
-        let mipmap = compile_mipmap("com.example.helloworld", "icon")?;
+        let res = Resources {
+            mipmap: Some("icon"),
+            ..Default::default()
+        };
+        let mipmap = compile_resources("com.example.helloworld", res)?;
         let mut buf = vec![];
         let mut cursor = Cursor::new(&mut buf);
-        mipmap.chunk().write(&mut cursor)?;
+        mipmap.write(&mut cursor)?;
         let mut cursor = Cursor::new(&buf);
         let chunk = Chunk::parse(&mut cursor)?;
-        println!("{:#?}", mipmap.chunk());
+        println!("{:#?}", mipmap);
         println!("{:#?}", chunk);
-        assert_eq!(*mipmap.chunk(), chunk);
+        assert_eq!(mipmap, chunk);
+        Ok(())
+    }
+
+    #[test]
+    fn test_icon_variants() {
+        let layers = |monochrome| {
+            let mut names = icon_variants("icon", true, monochrome)
+                .into_iter()
+                .map(|(mipmap, _, _)| mipmap)
+                .collect::<Vec<_>>();
+            names.dedup();
+            names
+        };
+        assert_eq!(
+            layers(false),
+            ["icon", "icon_foreground", "icon_background"]
+        );
+        assert_eq!(
+            layers(true),
+            [
+                "icon",
+                "icon_foreground",
+                "icon_background",
+                "icon_monochrome"
+            ]
+        );
+        assert_eq!(icon_variants("icon", false, true).len(), DENSITIES.len());
+    }
+
+    #[test]
+    fn test_compile_strings() -> Result<()> {
+        let res = Resources {
+            mipmap: Some("icon"),
+            ..Default::default()
+        };
+        let mipmap = compile_resources("com.example.helloworld", res)?;
+        if let Chunk::Table(_, chunks) = &mipmap {
+            if let Chunk::TablePackage(header, _) = &chunks[1] {
+                assert_eq!(header.type_strings, 288);
+                assert_eq!(header.key_strings, 332);
+            }
+        }
+        let strings = [StringResource {
+            name: "app_name".into(),
+            values: vec![
+                (None, "Hello".into()),
+                (Some("de".into()), "Hallo".into()),
+                (Some("pt-BR".into()), "Olá".repeat(50)),
+            ],
+        }];
+        let res = Resources {
+            mipmap: Some("icon"),
+            adaptive: true,
+            monochrome: false,
+            strings: &strings,
+            splash: None,
+        };
+        let chunk = compile_resources("com.example.helloworld", res)?;
+        let mut buf = vec![];
+        chunk.write(&mut Cursor::new(&mut buf))?;
+        assert_eq!(Chunk::parse(&mut Cursor::new(&buf))?, chunk);
+        assert_eq!(pack_locale("pt-BR")?, u32::from_le_bytes(*b"ptBR"));
+        assert!(pack_locale("fil").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_splash() -> Result<()> {
+        let splash = SplashStyle {
+            parent: 0x0103_0128,
+            window_background: 0x0101_0054,
+            splash_screen: Some((0x0101_066a, 0x0101_066b)),
+            background: [255, 128, 0, 255],
+            dark_background: Some([0, 0, 0, 255]),
+            image: true,
+            dark_image: false,
+        };
+        let res = Resources {
+            splash: Some(&splash),
+            ..Default::default()
+        };
+        let chunk = compile_resources("com.example.helloworld", res)?;
+        let mut buf = vec![];
+        chunk.write(&mut Cursor::new(&mut buf))?;
+        assert_eq!(Chunk::parse(&mut Cursor::new(&buf))?, chunk);
+        assert_eq!(reference(3, 0), 0x7f03_0000);
+
+        let chunks = match &chunk {
+            Chunk::Table(_, chunks) => match &chunks[1] {
+                Chunk::TablePackage(_, chunks) => chunks,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let styles = chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                Chunk::TableType(header, _, entries) if header.id == 4 => entries[0].as_ref(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(styles.len(), 2);
+        for style in styles {
+            match &style.value {
+                ResTableValue::Complex(entry, attrs) => {
+                    assert_eq!(entry.parent, splash.parent);
+                    let post = attrs.iter().find(|attr| attr.name == reference(3, 0));
+                    assert_eq!(post.unwrap().value.data, splash.parent);
+                }
+                _ => unreachable!(),
+            }
+        }
         Ok(())
     }
 
@@ -157,6 +686,10 @@ mod tests {
         manifest.application.label = Some("helloworld".into());
         manifest.application.theme = Some("@android:style/Theme.Light.NoTitleBar".into());
         manifest.application.debuggable = Some(true);
+        manifest.application.profileable = Some(Profileable {
+            shell: Some(true),
+            ..Default::default()
+        });
         let activity = Activity {
             config_changes: Some("orientation|keyboardHidden".into()),
             launch_mode: Some("singleTop".into()),
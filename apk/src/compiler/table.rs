@@ -149,6 +149,16 @@ impl<'a> Entry<'a> {
         self.id
     }
 
+    /// Whether the attribute takes references in addition to its type, like
+    /// the `reference|string` of `android:label`.
+    pub fn accepts_reference(self) -> bool {
+        if let ResTableValue::Complex(_, entries) = &self.entry.value {
+            entries[0].value.data & 0b1 != 0
+        } else {
+            false
+        }
+    }
+
     pub fn attribute_type(self) -> Option<ResAttributeType> {
         if let ResTableValue::Complex(_, entries) = &self.entry.value {
             let data = entries[0].value.data;
@@ -218,7 +228,7 @@ impl Table {
         }
     }
 
-    fn lookup_package(&self, id: u8) -> Result<Package> {
+    fn lookup_package(&self, id: u8) -> Result<Package<'_>> {
         for package in &self.packages {
             if let Chunk::TablePackage(header, chunks) = package {
                 if header.id == id as u32 {
@@ -229,7 +239,7 @@ impl Table {
         anyhow::bail!("failed to locate package {}", id);
     }
 
-    pub fn entry_by_ref(&self, r: Ref) -> Result<Entry> {
+    pub fn entry_by_ref(&self, r: Ref) -> Result<Entry<'_>> {
         let id = self.lookup_package_id(r.package)?;
         let package = self.lookup_package(id)?;
         let id = package.lookup_type_id(r.ty)?;
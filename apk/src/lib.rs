@@ -1,9 +1,9 @@
-use crate::compiler::Table;
+use crate::compiler::{Resources, SplashStyle, Table};
 use crate::res::Chunk;
 use anyhow::{Context, Result};
 use std::io::Cursor;
 use std::path::{Path, PathBuf};
-use xcommon::{Scaler, ScalerOpts, Zip, ZipFileOptions};
+use xcommon::{Icon, Scaler, ScalerOpts, ScalerOptsBuilder, Splash, Zip, ZipFileOptions};
 
 mod compiler;
 pub mod manifest;
@@ -11,60 +11,168 @@ pub mod res;
 mod sign;
 mod utils;
 
+pub use crate::compiler::StringResource;
 pub use crate::manifest::AndroidManifest;
 pub use crate::utils::{Target, VersionCode};
-pub use xcommon::{Certificate, Signer};
+pub use xcommon::{Certificate, Compression, Signer};
 pub use zip;
 
 pub struct Apk {
     manifest: AndroidManifest,
     path: PathBuf,
     zip: Zip,
+    icon_cache: Option<PathBuf>,
+    icons_cached: Option<bool>,
+    splash: Option<Splash>,
 }
 
 impl Apk {
-    pub fn new(path: PathBuf, manifest: AndroidManifest, compress: bool) -> Result<Self> {
-        let zip = Zip::new(&path, compress)?;
+    pub fn new(path: PathBuf, manifest: AndroidManifest, compression: Compression) -> Result<Self> {
+        ensure_supported(compression)?;
+        let zip = Zip::new(&path, compression)?;
         Ok(Self {
             manifest,
             path,
             zip,
+            icon_cache: None,
+            icons_cached: None,
+            splash: None,
         })
     }
 
-    pub fn add_res(&mut self, icon: Option<&Path>, android: &Path) -> Result<()> {
+    /// See [`xcommon::ZipBuilder::set_deterministic`].
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.zip.set_deterministic(deterministic);
+    }
+
+    /// Caches scaled icons in `dir`, see [`xcommon::Scaler::with_cache`].
+    pub fn set_icon_cache(&mut self, dir: &Path) {
+        self.icon_cache = Some(dir.to_path_buf());
+    }
+
+    /// Whether [`Self::add_res`] read every scaled icon from the icon cache,
+    /// `None` if it didn't scale any.
+    pub fn icons_cached(&self) -> Option<bool> {
+        self.icons_cached
+    }
+
+    /// Shows `splash` until the first frame is drawn, using the android 12
+    /// splash screen api if the platform supports it.
+    pub fn set_splash(&mut self, splash: Splash) {
+        self.splash = Some(splash);
+    }
+
+    /// Compiles the manifest and the resource table with the adaptive
+    /// launcher icon, `strings` and the splash screen.
+    pub fn add_res(
+        &mut self,
+        icon: Option<&Icon>,
+        strings: &[StringResource],
+        android: &Path,
+    ) -> Result<()> {
         let mut buf = vec![];
         let mut table = Table::default();
         table.import_apk(android)?;
-        if let Some(path) = icon {
-            let mut scaler = Scaler::open(path)?;
-            scaler.optimize();
+        let splash = self
+            .splash
+            .as_ref()
+            .map(|splash| self.splash_style(splash, &table))
+            .transpose()?;
+        if icon.is_some() || !strings.is_empty() || splash.is_some() {
             let package = if let Some(package) = self.manifest.package.as_ref() {
                 package
             } else {
                 anyhow::bail!("missing manifest.package");
             };
-            let mipmap = crate::compiler::compile_mipmap(package, "icon")?;
+            let res = Resources {
+                mipmap: icon.map(|_| "icon"),
+                adaptive: true,
+                monochrome: icon.and_then(Icon::monochrome).is_some(),
+                strings,
+                splash: splash.as_ref(),
+            };
+            let chunk = crate::compiler::compile_resources(package, res)?;
 
             let mut cursor = Cursor::new(&mut buf);
-            mipmap.chunk().write(&mut cursor)?;
+            chunk.write(&mut cursor)?;
             self.zip.create_file(
                 Path::new("resources.arsc"),
                 ZipFileOptions::Aligned(4),
                 &buf,
             )?;
-
-            for (name, size) in mipmap.variants() {
+            table.import_chunk(&chunk);
+        }
+        if let Some(icon) = icon {
+            let monochrome = icon.monochrome();
+            for (mipmap, name, size) in
+                crate::compiler::icon_variants("icon", true, monochrome.is_some())
+            {
+                let path = if mipmap.ends_with("_foreground") {
+                    icon.foreground()
+                } else if let Some(path) = monochrome.filter(|_| mipmap.ends_with("_monochrome")) {
+                    path
+                } else if mipmap.ends_with("_background") {
+                    icon.background()
+                } else {
+                    icon.path()
+                };
+                let mut scaler = Scaler::open(path)?;
+                if let Some(cache) = self.icon_cache.as_ref() {
+                    scaler = scaler.with_cache(cache);
+                }
+                scaler.optimize();
+                let cached = scaler.is_cached(ScalerOpts::new(size));
+                self.icons_cached = Some(self.icons_cached.unwrap_or(true) && cached);
                 buf.clear();
                 let mut cursor = Cursor::new(&mut buf);
                 scaler.write(&mut cursor, ScalerOpts::new(size))?;
                 self.zip
                     .create_file(name.as_ref(), ZipFileOptions::Aligned(4), &buf)?;
             }
-
-            table.import_chunk(mipmap.chunk());
+            let adaptive =
+                crate::compiler::compile_adaptive_icon("icon", monochrome.is_some(), &table)?;
+            buf.clear();
+            adaptive.write(&mut Cursor::new(&mut buf))?;
+            self.zip.create_file(
+                crate::compiler::adaptive_icon_path("icon").as_ref(),
+                ZipFileOptions::Compressed,
+                &buf,
+            )?;
             self.manifest.application.icon = Some("@mipmap/icon".into());
         }
+        if let Some(splash) = self.splash.as_ref() {
+            let images = [
+                splash.image.as_ref(),
+                splash.image.as_ref().and(splash.dark_image.as_ref()),
+            ];
+            for ((_, variants), image) in crate::compiler::splash_variants(true)
+                .into_iter()
+                .zip(images)
+            {
+                let image = if let Some(image) = image {
+                    image
+                } else {
+                    continue;
+                };
+                let mut scaler = Scaler::open(image)?;
+                if let Some(cache) = self.icon_cache.as_ref() {
+                    scaler = scaler.with_cache(cache);
+                }
+                scaler.optimize();
+                for (name, size) in variants {
+                    // Icons without a background are masked by a circle of
+                    // two thirds of the size
+                    let opts = ScalerOptsBuilder::new(size, size).padding(0.53).build();
+                    let cached = scaler.is_cached(opts);
+                    self.icons_cached = Some(self.icons_cached.unwrap_or(true) && cached);
+                    buf.clear();
+                    scaler.write(&mut Cursor::new(&mut buf), opts)?;
+                    self.zip
+                        .create_file(name.as_ref(), ZipFileOptions::Aligned(4), &buf)?;
+                }
+            }
+            self.manifest.application.theme = Some("@style/splash".into());
+        }
         let manifest = crate::compiler::compile_manifest(&self.manifest, &table)?;
         buf.clear();
         let mut cursor = Cursor::new(&mut buf);
@@ -77,7 +185,47 @@ impl Apk {
         Ok(())
     }
 
+    /// Resolves the framework attributes of the splash style, which inherits
+    /// from the theme of the manifest and switches back to it after the
+    /// splash screen.
+    fn splash_style(&self, splash: &Splash, table: &Table) -> Result<SplashStyle> {
+        let theme = self
+            .manifest
+            .application
+            .theme
+            .as_deref()
+            .unwrap_or("@android:style/Theme.DeviceDefault.NoActionBar");
+        let parent = table
+            .entry_by_ref(crate::compiler::Ref::parse(theme)?)
+            .with_context(|| format!("splash screens need a framework theme, found {}", theme))?;
+        let attr = |name| {
+            table
+                .entry_by_ref(crate::compiler::Ref::attr(name))
+                .map(|entry| u32::from(entry.id()))
+        };
+        let splash_screen = match (
+            attr("windowSplashScreenBackground"),
+            attr("windowSplashScreenAnimatedIcon"),
+        ) {
+            (Ok(background), Ok(icon)) => Some((background, icon)),
+            _ => {
+                tracing::warn!("android.jar is older than api 31, using a plain splash screen");
+                None
+            }
+        };
+        Ok(SplashStyle {
+            parent: u32::from(parent.id()),
+            window_background: attr("windowBackground")?,
+            splash_screen,
+            background: splash.background,
+            dark_background: splash.dark_background,
+            image: splash.image.is_some(),
+            dark_image: splash.image.is_some() && splash.dark_image.is_some(),
+        })
+    }
+
     pub fn add_asset(&mut self, asset: &Path, opts: ZipFileOptions) -> Result<()> {
+        ensure_supported(opts.compression(self.zip.compression()))?;
         let file_name = asset
             .file_name()
             .context("Asset must have file_name component")?;
@@ -205,6 +353,16 @@ impl Apk {
     }
 }
 
+/// Android only supports stored and deflated zip entries.
+fn ensure_supported(compression: Compression) -> Result<()> {
+    anyhow::ensure!(
+        compression.is_portable(),
+        "apk entries must be stored or deflated, {:?} is not supported",
+        compression
+    );
+    Ok(())
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct EntryPoint {
     pub package: String,
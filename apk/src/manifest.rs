@@ -82,6 +82,7 @@ pub struct Application {
     #[serde(rename(serialize = "meta-data"))]
     #[serde(default)]
     pub meta_data: Vec<MetaData>,
+    pub profileable: Option<Profileable>,
     #[serde(rename(serialize = "activity"))]
     #[serde(default)]
     pub activities: Vec<Activity>,
@@ -197,6 +198,16 @@ pub struct IntentFilterData {
     pub mime_type: Option<String>,
 }
 
+/// Android [profileable element](https://developer.android.com/guide/topics/manifest/profileable-element).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profileable {
+    #[serde(rename(serialize = "android:shell"))]
+    pub shell: Option<bool>,
+    #[serde(rename(serialize = "android:enabled"))]
+    pub enabled: Option<bool>,
+}
+
 /// Android [meta-data element](https://developer.android.com/guide/topics/manifest/meta-data-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -224,7 +235,7 @@ pub struct Feature {
     ///   for available levels and the respective Vulkan features required/provided.
     ///
     /// - `name="android.hardware.vulkan.version"`: Represents the value of Vulkan's `VkPhysicalDeviceProperties::apiVersion`. See the [Android documentation](https://developer.android.com/reference/android/content/pm/PackageManager#FEATURE_VULKAN_HARDWARE_VERSION)
-    ///    for available levels and the respective Vulkan features required/provided.
+    ///   for available levels and the respective Vulkan features required/provided.
     #[serde(rename(serialize = "android:version"))]
     pub version: Option<u32>,
     #[serde(rename(serialize = "android:glEsVersion"))]
@@ -897,13 +897,13 @@ impl Chunk {
                     if string_pool_header.is_utf8() {
                         let charsh = r.read_u8()? as u16;
                         let _chars = if charsh > 0x7f {
-                            charsh & 0x7f | r.read_u8()? as u16
+                            (charsh & 0x7f) << 8 | r.read_u8()? as u16
                         } else {
                             charsh
                         };
                         let bytesh = r.read_u8()? as u16;
                         let bytes = if bytesh > 0x7f {
-                            bytesh & 0x7f | r.read_u8()? as u16
+                            (bytesh & 0x7f) << 8 | r.read_u8()? as u16
                         } else {
                             bytesh
                         };
@@ -919,7 +919,7 @@ impl Chunk {
                     } else {
                         let charsh = r.read_u16::<LittleEndian>()? as u32;
                         let chars = if charsh > 0x7fff {
-                            charsh & 0x7fff | r.read_u16::<LittleEndian>()? as u32
+                            (charsh & 0x7fff) << 16 | r.read_u16::<LittleEndian>()? as u32
                         } else {
                             charsh
                         };
@@ -932,7 +932,7 @@ impl Chunk {
                                 break;
                             }
                         }
-                        let s = String::from_utf16(unsafe { std::mem::transmute(buf.as_slice()) })?;
+                        let s = String::from_utf16(&buf)?;
                         strings.push(s);
                     }
                 }
@@ -1109,10 +1109,14 @@ impl Chunk {
                 let strings_start = w.stream_position()?;
                 for string in strings {
                     indices.push(w.stream_position()? - strings_start);
-                    assert!(string.len() < 0x7f);
-                    let chars = string.chars().count();
-                    w.write_u8(chars as u8)?;
-                    w.write_u8(string.len() as u8)?;
+                    anyhow::ensure!(string.len() <= 0x7fff, "string too long: {}", string);
+                    // Lengths above 0x7f take two bytes with the high bit set
+                    for len in [string.chars().count(), string.len()] {
+                        if len > 0x7f {
+                            w.write_u8((len >> 8) as u8 | 0x80)?;
+                        }
+                        w.write_u8(len as u8)?;
+                    }
                     w.write_all(string.as_bytes())?;
                     w.write_u8(0)?;
                 }
@@ -1,13 +1,13 @@
 use anyhow::Result;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use rasn_pkix::Certificate;
-use rsa::pkcs8::{DecodePublicKey, EncodePublicKey};
+use rsa::pkcs8::DecodePublicKey;
 use rsa::{PaddingScheme, PublicKey, RsaPublicKey};
 use sha2::{Digest as _, Sha256};
 use std::fs::File;
 use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use xcommon::{Signer, ZipInfo};
+use xcommon::{SignatureAlgorithm, Signer, ZipInfo};
 
 const DEBUG_PEM: &str = include_str!("../assets/debug.pem");
 
@@ -16,6 +16,7 @@ const APK_SIGNING_BLOCK_V2_ID: u32 = 0x7109871a;
 const APK_SIGNING_BLOCK_V3_ID: u32 = 0xf05368c0;
 const APK_SIGNING_BLOCK_V4_ID: u32 = 0x42726577;
 const RSA_PKCS1V15_SHA2_256: u32 = 0x0103;
+const ECDSA_SHA2_256: u32 = 0x0201;
 const MAX_CHUNK_SIZE: usize = 1024 * 1024;
 
 pub fn verify(path: &Path) -> Result<Vec<Certificate>> {
@@ -46,7 +47,7 @@ pub fn verify(path: &Path) -> Result<Vec<Certificate>> {
     } else {
         anyhow::bail!("no signing block v2 found");
     };
-    let zip_hash = compute_digest(&mut r, sblock.sb_start, sblock.cd_start, sblock.cde_start)?;
+    let zip_hash = compute_digest(&mut r, &sblock)?;
     let mut certificates = vec![];
     for signer in &block.signers {
         anyhow::ensure!(
@@ -54,15 +55,24 @@ pub fn verify(path: &Path) -> Result<Vec<Certificate>> {
             "found no signatures in v2 block"
         );
         for sig in &signer.signatures {
-            anyhow::ensure!(
-                sig.algorithm == RSA_PKCS1V15_SHA2_256,
-                "found unsupported signature algorithm 0x{:x}",
-                sig.algorithm
-            );
-            let pubkey = RsaPublicKey::from_public_key_der(&signer.public_key)?;
-            let digest = Sha256::digest(&signer.signed_data);
-            let padding = PaddingScheme::new_pkcs1v15_sign::<sha2::Sha256>();
-            pubkey.verify(padding, &digest, &sig.signature)?;
+            match sig.algorithm {
+                RSA_PKCS1V15_SHA2_256 => {
+                    let pubkey = RsaPublicKey::from_public_key_der(&signer.public_key)?;
+                    let digest = Sha256::digest(&signer.signed_data);
+                    let padding = PaddingScheme::new_pkcs1v15_sign::<sha2::Sha256>();
+                    pubkey.verify(padding, &digest, &sig.signature)?;
+                }
+                ECDSA_SHA2_256 => {
+                    use p256::ecdsa::signature::Verifier;
+                    let pubkey =
+                        p256::ecdsa::VerifyingKey::from_public_key_der(&signer.public_key)?;
+                    let signature = p256::ecdsa::Signature::from_der(&sig.signature)?;
+                    pubkey.verify(&signer.signed_data, &signature)?;
+                }
+                algorithm => {
+                    anyhow::bail!("found unsupported signature algorithm 0x{:x}", algorithm);
+                }
+            }
         }
         let mut r = Cursor::new(&signer.signed_data[..]);
         let signed_data = SignedData::read(&mut r)?;
@@ -72,7 +82,7 @@ pub fn verify(path: &Path) -> Result<Vec<Certificate>> {
         );
         for digest in &signed_data.digests {
             anyhow::ensure!(
-                digest.algorithm == RSA_PKCS1V15_SHA2_256,
+                [RSA_PKCS1V15_SHA2_256, ECDSA_SHA2_256].contains(&digest.algorithm),
                 "found unsupported digest algorithm 0x{:x}",
                 digest.algorithm
             );
@@ -98,7 +108,7 @@ pub fn sign(path: &Path, signer: Option<Signer>) -> Result<()> {
     let apk = std::fs::read(path)?;
     let mut r = Cursor::new(&apk);
     let block = parse_apk_signing_block(&mut r)?;
-    let zip_hash = compute_digest(&mut r, block.sb_start, block.cd_start, block.cde_start)?;
+    let zip_hash = compute_digest(&mut r, &block)?;
     let mut nblock = vec![];
     let mut w = Cursor::new(&mut nblock);
     write_apk_signing_block(&mut w, zip_hash, &signer)?;
@@ -106,20 +116,14 @@ pub fn sign(path: &Path, signer: Option<Signer>) -> Result<()> {
     f.write_all(&apk[..(block.sb_start as usize)])?;
     f.write_all(&nblock)?;
     let cd_start = f.stream_position()?;
-    f.write_all(&apk[(block.cd_start as usize)..(block.cde_start as usize)])?;
-    let cde_start = f.stream_position()?;
-    f.write_all(&apk[(block.cde_start as usize)..])?;
-    f.seek(SeekFrom::Start(cde_start + 16))?;
-    f.write_u32::<LittleEndian>(cd_start as u32)?;
+    f.write_all(&apk[(block.cd_start as usize)..(block.cd_end() as usize)])?;
+    let mut cde = block.cde(&apk);
+    block.relocate_cd(&mut cde, cd_start as i64 - block.cd_start as i64)?;
+    f.write_all(&cde)?;
     Ok(())
 }
 
-fn compute_digest<R: Read + Seek>(
-    r: &mut R,
-    sb_start: u64,
-    cd_start: u64,
-    cde_start: u64,
-) -> Result<[u8; 32]> {
+fn compute_digest<R: Read + Seek>(r: &mut R, block: &ApkSignatureBlock) -> Result<[u8; 32]> {
     let mut chunks = vec![];
     let mut hasher = Sha256::new();
     let mut chunk = vec![0u8; MAX_CHUNK_SIZE];
@@ -127,22 +131,34 @@ fn compute_digest<R: Read + Seek>(
     // chunk contents
     r.rewind()?;
     let mut pos = 0;
-    while pos < sb_start {
-        hash_chunk(&mut chunks, r, sb_start, &mut hasher, &mut chunk, &mut pos)?;
+    while pos < block.sb_start {
+        hash_chunk(
+            &mut chunks,
+            r,
+            block.sb_start,
+            &mut hasher,
+            &mut chunk,
+            &mut pos,
+        )?;
     }
 
     // chunk cd
-    let mut pos = r.seek(SeekFrom::Start(cd_start))?;
-    while pos < cde_start {
-        hash_chunk(&mut chunks, r, cde_start, &mut hasher, &mut chunk, &mut pos)?;
+    let mut pos = r.seek(SeekFrom::Start(block.cd_start))?;
+    while pos < block.cd_end() {
+        hash_chunk(
+            &mut chunks,
+            r,
+            block.cd_end(),
+            &mut hasher,
+            &mut chunk,
+            &mut pos,
+        )?;
     }
 
-    // chunk cde
+    // chunk cde, hashed as if the cd started at the signing block
     chunk.clear();
     r.read_to_end(&mut chunk)?;
-    let mut cursor = Cursor::new(&mut chunk);
-    cursor.seek(SeekFrom::Start(16))?;
-    cursor.write_u32::<LittleEndian>(sb_start as u32)?;
+    block.relocate_cd(&mut chunk, block.sb_start as i64 - block.cd_start as i64)?;
     hasher.update([0xa5]);
     assert!(chunk.len() <= MAX_CHUNK_SIZE);
     hasher.update((chunk.len() as u32).to_le_bytes());
@@ -178,6 +194,17 @@ fn hash_chunk<R: Read + Seek>(
     Ok(())
 }
 
+/// Signature algorithm id of `signer` in apk signing blocks.
+fn signature_algorithm_id(signer: &Signer) -> Result<u32> {
+    Ok(match signer.algorithm() {
+        SignatureAlgorithm::RsaPkcs1Sha256 => RSA_PKCS1V15_SHA2_256,
+        SignatureAlgorithm::EcdsaP256Sha256 => ECDSA_SHA2_256,
+        SignatureAlgorithm::Ed25519 => {
+            anyhow::bail!("ed25519 keys are not supported by the apk signature scheme")
+        }
+    })
+}
+
 #[derive(Debug, Default)]
 struct Digest {
     pub algorithm: u32,
@@ -185,9 +212,9 @@ struct Digest {
 }
 
 impl Digest {
-    fn new(hash: [u8; 32]) -> Self {
+    fn new(algorithm: u32, hash: [u8; 32]) -> Self {
         Self {
-            algorithm: RSA_PKCS1V15_SHA2_256,
+            algorithm,
             digest: hash.to_vec(),
         }
     }
@@ -224,7 +251,7 @@ struct SignedData {
 impl SignedData {
     fn new(hash: [u8; 32], signer: &Signer) -> Result<Self> {
         Ok(Self {
-            digests: vec![Digest::new(hash)],
+            digests: vec![Digest::new(signature_algorithm_id(signer)?, hash)],
             certificates: vec![
                 rasn::der::encode(signer.cert()).map_err(|err| anyhow::anyhow!("{}", err))?
             ],
@@ -312,10 +339,10 @@ impl ApkSignatureBlockV2 {
             signers: vec![ApkSigner {
                 signed_data,
                 signatures: vec![ApkSignature {
-                    algorithm: RSA_PKCS1V15_SHA2_256,
+                    algorithm: signature_algorithm_id(signer)?,
                     signature,
                 }],
-                public_key: signer.pubkey().to_public_key_der()?.as_ref().to_vec(),
+                public_key: signer.public_key_der()?,
             }],
         })
     }
@@ -391,6 +418,44 @@ struct ApkSignatureBlock {
     pub sb_start: u64,
     pub cd_start: u64,
     pub cde_start: u64,
+    pub zip64_cde_start: Option<u64>,
+}
+
+impl ApkSignatureBlock {
+    fn cd_end(&self) -> u64 {
+        self.zip64_cde_start.unwrap_or(self.cde_start)
+    }
+
+    /// The (zip64) end of central directory records of `apk`.
+    fn cde(&self, apk: &[u8]) -> Vec<u8> {
+        apk[(self.cd_end() as usize)..].to_vec()
+    }
+
+    /// Moves the offsets stored in the end of central directory records `cde`
+    /// by `delta`.
+    fn relocate_cd(&self, cde: &mut Vec<u8>, delta: i64) -> Result<()> {
+        let mut cde = Cursor::new(cde);
+        let eocd = self.cde_start - self.cd_end();
+        cde.seek(SeekFrom::Start(eocd + 16))?;
+        let cd_start = cde.read_u32::<LittleEndian>()?;
+        // saturated offsets are stored in the zip64 record instead
+        if cd_start != u32::MAX {
+            let cd_start = u32::try_from(cd_start as i64 + delta)
+                .map_err(|_| anyhow::anyhow!("central directory offset requires zip64"))?;
+            cde.seek(SeekFrom::Start(eocd + 16))?;
+            cde.write_u32::<LittleEndian>(cd_start)?;
+        }
+        if self.zip64_cde_start.is_some() {
+            // cd offset in the zip64 record and zip64 record offset in the locator
+            for pos in [48, eocd - 12] {
+                cde.seek(SeekFrom::Start(pos))?;
+                let offset = cde.read_u64::<LittleEndian>()?;
+                cde.seek(SeekFrom::Start(pos))?;
+                cde.write_u64::<LittleEndian>((offset as i64 + delta) as u64)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -421,6 +486,7 @@ fn parse_apk_signing_block<R: Read + Seek>(r: &mut R) -> Result<ApkSignatureBloc
     let mut block = ApkSignatureBlock {
         cde_start: info.cde_start,
         cd_start: info.cd_start,
+        zip64_cde_start: info.zip64_cde_start,
         ..Default::default()
     };
     r.seek(SeekFrom::Start(block.cd_start - 16 - 8))?;
@@ -445,3 +511,40 @@ fn parse_apk_signing_block<R: Read + Seek>(r: &mut R) -> Result<ApkSignatureBloc
     }
     Ok(block)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xcommon::{Compression, Zip, ZipFileOptions};
+
+    #[test]
+    fn sign_ecdsa() -> Result<()> {
+        let path = std::env::temp_dir().join("apk-sign-ecdsa.apk");
+        let mut zip = Zip::new(&path, Compression::Stored)?;
+        zip.create_file("classes.dex".as_ref(), ZipFileOptions::Unaligned, &[])?;
+        zip.finish()?;
+        let signer = Signer::new(include_str!("../../xcommon/assets/test-ec.pem"))?;
+        sign(&path, Some(signer))?;
+        verify(&path)?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sign_zip64() -> Result<()> {
+        let path = std::env::temp_dir().join("apk-sign-zip64.apk");
+        let mut zip = Zip::new(&path, Compression::Stored)?;
+        // more than 65535 entries forces zip64 end of central directory records
+        for i in 0..=u16::MAX as u32 {
+            zip.create_file(format!("{i}").as_ref(), ZipFileOptions::Unaligned, &[])?;
+        }
+        zip.finish()?;
+        sign(&path, None)?;
+        verify(&path)?;
+        let mut archive = zip::ZipArchive::new(File::open(&path)?)?;
+        assert_eq!(archive.len(), u16::MAX as usize + 1);
+        archive.by_name("65535")?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -6,28 +6,35 @@ use apple_codesign::notarization::{
 use apple_codesign::stapling::Stapler;
 use apple_codesign::{BundleSigner, CodeSignatureFlags, SettingsScope, SigningSettings};
 use icns::{IconFamily, Image};
-use pkcs8::EncodePrivateKey;
-use plist::Value;
+use plist::{Dictionary, Value};
 use rasn_cms::{ContentInfo, SignedData};
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufWriter, Cursor};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use x509_certificate::{CapturedX509Certificate, InMemorySigningKeyPair};
-use xcommon::{Scaler, ScalerOpts, Signer};
+use xcommon::{Compression, Scaler, ScalerOpts, Signer, Splash, Zip, ZipFileOptions};
 
 mod info;
 
+use info::UiLaunchScreen;
+
 pub use info::InfoPlist;
 
 const MACOS_ICON_SIZES: [u32; 6] = [16, 32, 64, 128, 256, 512];
 const IOS_ICON_SIZES: [u32; 7] = [58, 76, 80, 120, 152, 167, 1024];
+/// Size of the splash screen image in points.
+const IOS_SPLASH_SIZE: u32 = 200;
 
 pub struct AppBundle {
     appdir: PathBuf,
     info: InfoPlist,
     entitlements: Option<Value>,
     development: bool,
+    deterministic: bool,
+    icon_cache: Option<PathBuf>,
+    info_plist_keys: Dictionary,
 }
 
 impl AppBundle {
@@ -41,9 +48,22 @@ impl AppBundle {
             info,
             entitlements: None,
             development: false,
+            deterministic: false,
+            icon_cache: None,
+            info_plist_keys: Dictionary::new(),
         })
     }
 
+    /// See [`xcommon::ZipBuilder::set_deterministic`], applies to [`Self::ipa`].
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Caches scaled icons in `dir`, see [`xcommon::Scaler::with_cache`].
+    pub fn set_icon_cache(&mut self, dir: &Path) {
+        self.icon_cache = Some(dir.to_path_buf());
+    }
+
     pub fn appdir(&self) -> &Path {
         &self.appdir
     }
@@ -82,7 +102,10 @@ impl AppBundle {
     }
 
     pub fn add_icon(&mut self, path: &Path) -> Result<()> {
-        let scaler = Scaler::open(path)?;
+        let mut scaler = Scaler::open(path)?;
+        if let Some(cache) = self.icon_cache.as_ref() {
+            scaler = scaler.with_cache(cache);
+        }
         let sizes = if self.ios() {
             &IOS_ICON_SIZES[..]
         } else {
@@ -118,6 +141,153 @@ impl AppBundle {
         Ok(())
     }
 
+    /// Compiles the icon into the `AppIcon` of an `Assets.car` with
+    /// `actool`, which the app store expects on ios, and the splash screen
+    /// into the `SplashBackground` color and `SplashImage` shown by the
+    /// `UILaunchScreen`. Returns `false` without xcode,
+    /// [`Self::add_icon`] adds the icon files in that case.
+    pub fn add_asset_catalog(
+        &mut self,
+        icon: Option<&Path>,
+        splash: Option<&Splash>,
+        build_dir: &Path,
+    ) -> Result<bool> {
+        if !cfg!(target_os = "macos") || !has_actool() {
+            if splash.is_some() {
+                log::warn!("splash screens need xcode, using a blank launch screen");
+                self.info.ui_launch_screen = Some(Default::default());
+            }
+            return Ok(false);
+        }
+        let catalog = build_dir.join("Assets.xcassets");
+        std::fs::remove_dir_all(&catalog).ok();
+        std::fs::create_dir_all(&catalog)?;
+        let info = r#"{"info":{"author":"xcode","version":1}}"#;
+        std::fs::write(catalog.join("Contents.json"), info)?;
+        if let Some(icon) = icon {
+            let iconset = catalog.join("AppIcon.appiconset");
+            std::fs::create_dir_all(&iconset)?;
+            let mut scaler = Scaler::open(icon)?;
+            if let Some(cache) = self.icon_cache.as_ref() {
+                scaler = scaler.with_cache(cache);
+            }
+            scaler.write(
+                &mut BufWriter::new(File::create(iconset.join("icon.png"))?),
+                ScalerOpts::new(1024),
+            )?;
+            std::fs::write(
+                iconset.join("Contents.json"),
+                r#"{"images":[{"filename":"icon.png","idiom":"universal","platform":"ios","size":"1024x1024"}],"info":{"author":"xcode","version":1}}"#,
+            )?;
+        }
+        if let Some(splash) = splash {
+            self.add_splash_assets(splash, &catalog)?;
+        }
+        let partial = build_dir.join("assets-info.plist");
+        let mut cmd = std::process::Command::new("xcrun");
+        cmd.arg("actool")
+            .arg(&catalog)
+            .arg("--compile")
+            .arg(self.resource_dir())
+            .arg("--platform")
+            .arg("iphoneos")
+            .arg("--minimum-deployment-target")
+            .arg(self.info.minimum_os_version.as_deref().unwrap_or("12.0"));
+        if icon.is_some() {
+            cmd.arg("--app-icon").arg("AppIcon");
+        }
+        let status = cmd
+            .arg("--target-device")
+            .arg("iphone")
+            .arg("--target-device")
+            .arg("ipad")
+            .arg("--output-partial-info-plist")
+            .arg(&partial)
+            .status()?;
+        anyhow::ensure!(status.success(), "actool failed");
+        let keys = Value::from_file(&partial)?
+            .into_dictionary()
+            .context("invalid actool info plist")?;
+        self.add_info_plist_keys(keys);
+        Ok(true)
+    }
+
+    /// Writes the colorset and imageset of the splash screen with their
+    /// dark appearances and sets the `UILaunchScreen`.
+    fn add_splash_assets(&mut self, splash: &Splash, catalog: &Path) -> Result<()> {
+        let dark = r#""appearances":[{"appearance":"luminosity","value":"dark"}],"#;
+        let color = |[r, g, b, a]: [u8; 4], appearance: &str| {
+            let component = |c: u8| format!("{:.3}", c as f32 / 255.0);
+            format!(
+                r#"{{{}"color":{{"color-space":"srgb","components":{{"red":"{}","green":"{}","blue":"{}","alpha":"{}"}}}},"idiom":"universal"}}"#,
+                appearance,
+                component(r),
+                component(g),
+                component(b),
+                component(a)
+            )
+        };
+        let mut colors = vec![color(splash.background, "")];
+        if let Some(background) = splash.dark_background {
+            colors.push(color(background, dark));
+        }
+        let colorset = catalog.join("SplashBackground.colorset");
+        std::fs::create_dir_all(&colorset)?;
+        std::fs::write(
+            colorset.join("Contents.json"),
+            format!(
+                r#"{{"colors":[{}],"info":{{"author":"xcode","version":1}}}}"#,
+                colors.join(",")
+            ),
+        )?;
+        let mut launch_screen = UiLaunchScreen {
+            ui_color_name: Some("SplashBackground".into()),
+            ..Default::default()
+        };
+        if let Some(image) = splash.image.as_ref() {
+            let imageset = catalog.join("SplashImage.imageset");
+            std::fs::create_dir_all(&imageset)?;
+            let mut images = vec![];
+            let variants = [
+                ("splash", "", Some(image)),
+                ("splash-dark", dark, splash.dark_image.as_ref()),
+            ];
+            for (name, appearance, image) in variants {
+                let image = if let Some(image) = image {
+                    image
+                } else {
+                    continue;
+                };
+                let mut scaler = Scaler::open(image)?;
+                if let Some(cache) = self.icon_cache.as_ref() {
+                    scaler = scaler.with_cache(cache);
+                }
+                for scale in 1..=3 {
+                    let file_name = format!("{}@{}x.png", name, scale);
+                    scaler.write(
+                        &mut BufWriter::new(File::create(imageset.join(&file_name))?),
+                        ScalerOpts::new(IOS_SPLASH_SIZE * scale),
+                    )?;
+                    images.push(format!(
+                        r#"{{{}"filename":"{}","idiom":"universal","scale":"{}x"}}"#,
+                        appearance, file_name, scale
+                    ));
+                }
+            }
+            std::fs::write(
+                imageset.join("Contents.json"),
+                format!(
+                    r#"{{"images":[{}],"info":{{"author":"xcode","version":1}}}}"#,
+                    images.join(",")
+                ),
+            )?;
+            launch_screen.ui_image_name = Some("SplashImage".into());
+            launch_screen.ui_image_respects_safe_area_insets = Some(true);
+        }
+        self.info.ui_launch_screen = Some(launch_screen);
+        Ok(())
+    }
+
     pub fn add_file(&self, path: &Path, dest: &Path) -> Result<()> {
         let dest = self.resource_dir().join(dest);
         if let Some(parent) = dest.parent() {
@@ -127,6 +297,29 @@ impl AppBundle {
         Ok(())
     }
 
+    /// Writes the strings table `<locale>.lproj/<table>.strings`, like
+    /// `InfoPlist` which localizes the keys of the `Info.plist`.
+    pub fn add_strings(
+        &self,
+        locale: &str,
+        table: &str,
+        strings: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let dir = self.resource_dir().join(format!("{}.lproj", locale));
+        std::fs::create_dir_all(&dir)?;
+        let escape = |s: &str| {
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        };
+        let mut contents = String::new();
+        for (key, value) in strings {
+            contents.push_str(&format!("\"{}\" = \"{}\";\n", escape(key), escape(value)));
+        }
+        std::fs::write(dir.join(format!("{}.strings", table)), contents)?;
+        Ok(())
+    }
+
     pub fn add_directory(&self, source: &Path, dest: &Path) -> Result<()> {
         let resource_dir = self.resource_dir().join(dest);
         std::fs::create_dir_all(&resource_dir)?;
@@ -160,6 +353,30 @@ impl AppBundle {
         Ok(())
     }
 
+    /// Adds keys to `Info.plist` that aren't covered by [`InfoPlist`],
+    /// overriding existing keys.
+    pub fn add_info_plist_keys(&mut self, keys: Dictionary) {
+        self.info_plist_keys.extend(keys);
+    }
+
+    /// Merges the entitlements plist at `path` into the entitlements the app
+    /// is signed with. Needs to be called after
+    /// [`Self::add_provisioning_profile`].
+    pub fn add_entitlements(&mut self, path: &Path) -> Result<()> {
+        let value = Value::from_file(path)
+            .with_context(|| format!("While reading entitlements `{}`", path.display()))?;
+        let entitlements = value
+            .into_dictionary()
+            .context("entitlements must be a dictionary")?;
+        let current = self
+            .entitlements
+            .get_or_insert_with(|| Value::Dictionary(Dictionary::new()))
+            .as_dictionary_mut()
+            .context("invalid entitlements")?;
+        current.extend(entitlements);
+        Ok(())
+    }
+
     pub fn add_provisioning_profile(&mut self, raw_profile: &[u8]) -> Result<()> {
         let info = rasn::der::decode::<ContentInfo>(raw_profile)
             .map_err(|err| anyhow::anyhow!("{}", err))?;
@@ -206,10 +423,14 @@ impl AppBundle {
 
     pub fn finish(&self, signer: Option<Signer>) -> Result<()> {
         let path = self.content_dir().join("Info.plist");
-        plist::to_file_xml(path, &self.info)?;
+        let mut info = plist::to_value(&self.info)?;
+        info.as_dictionary_mut()
+            .context("invalid Info.plist")?
+            .extend(self.info_plist_keys.clone());
+        info.to_file_xml(path)?;
 
         if let Some(signer) = signer {
-            println!("signing {}", self.appdir().display());
+            eprintln!("signing {}", self.appdir().display());
             anyhow::ensure!(
                 self.info.cf_bundle_identifier.is_some(),
                 "missing bundle identifier"
@@ -217,8 +438,8 @@ impl AppBundle {
             let mut signing_settings = SigningSettings::default();
             let cert =
                 CapturedX509Certificate::from_der(rasn::der::encode(signer.cert()).unwrap())?;
-            let secret = signer.key().to_pkcs8_der().unwrap();
-            let key = InMemorySigningKeyPair::from_pkcs8_der(secret.as_bytes())?;
+            let secret = signer.pkcs8_der()?;
+            let key = InMemorySigningKeyPair::from_pkcs8_der(&secret)?;
             signing_settings.set_signing_key(&key, cert);
             signing_settings.chain_apple_certificates();
             signing_settings
@@ -243,13 +464,31 @@ impl AppBundle {
         Ok(())
     }
 
+    /// Packages the (signed) app bundle into an ipa at `out`.
+    pub fn ipa(&self, out: &Path, compression: Compression) -> Result<()> {
+        anyhow::ensure!(
+            compression.is_portable(),
+            "ipa entries must be stored or deflated, {:?} is not supported",
+            compression
+        );
+        let mut ipa = Zip::new(out, compression)?;
+        ipa.set_deterministic(self.deterministic);
+        ipa.add_directory(
+            self.appdir(),
+            &Path::new("Payload").join(self.appdir().file_name().unwrap()),
+            ZipFileOptions::Compressed,
+        )?;
+        ipa.finish()?;
+        Ok(())
+    }
+
     pub fn sign_dmg(&self, path: &Path, signer: &Signer) -> Result<()> {
-        println!("signing {}", path.display());
+        eprintln!("signing {}", path.display());
         let mut f = OpenOptions::new().read(true).write(true).open(path)?;
         let mut signing_settings = SigningSettings::default();
         let cert = CapturedX509Certificate::from_der(rasn::der::encode(signer.cert()).unwrap())?;
-        let secret = signer.key().to_pkcs8_der().unwrap();
-        let key = InMemorySigningKeyPair::from_pkcs8_der(secret.as_bytes())?;
+        let secret = signer.pkcs8_der()?;
+        let key = InMemorySigningKeyPair::from_pkcs8_der(&secret)?;
         signing_settings.set_signing_key(&key, cert);
         signing_settings.chain_apple_certificates();
         signing_settings
@@ -284,7 +523,7 @@ pub fn app_bundle_identifier(bundle: &Path) -> Result<String> {
 }
 
 pub fn notarize(path: &Path, api_key: &Path) -> Result<()> {
-    println!("notarizing {}", path.display());
+    eprintln!("notarizing {}", path.display());
     let notarizer = Notarizer::from_api_key(api_key)?;
     let submission_id =
         if let NotarizationUpload::UploadId(submission_id) = notarizer.notarize_path(path, None)? {
@@ -292,16 +531,16 @@ pub fn notarize(path: &Path, api_key: &Path) -> Result<()> {
         } else {
             anyhow::bail!("impossible");
         };
-    println!("submission id: {}", submission_id);
+    eprintln!("submission id: {}", submission_id);
     let start_time = Instant::now();
     loop {
         let resp = notarizer.get_submission(&submission_id)?;
         let status = resp.data.attributes.status;
         let elapsed = start_time.elapsed();
-        println!("poll state after {}s: {:?}", elapsed.as_secs(), status,);
+        eprintln!("poll state after {}s: {:?}", elapsed.as_secs(), status,);
         if status != SubmissionResponseStatus::InProgress {
             let log = notarizer.fetch_notarization_log(&submission_id)?;
-            println!("{}", log);
+            eprintln!("{}", log);
             resp.into_result()?;
             break;
         }
@@ -311,3 +550,13 @@ pub fn notarize(path: &Path, api_key: &Path) -> Result<()> {
     stapler.staple_path(path)?;
     Ok(())
 }
+
+/// Whether xcode's `actool` is installed.
+fn has_actool() -> bool {
+    std::process::Command::new("xcrun")
+        .arg("--find")
+        .arg("actool")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
@@ -1,17 +1,31 @@
 use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use xcommon::Signer;
+use xcommon::{Scaler, ScalerOpts, Signer};
+
+mod squashfs;
+
+pub use squashfs::Squashfs;
 
 static RUNTIME: &[u8] = include_bytes!("../assets/runtime-x86_64");
 
+/// Icon sizes of the `hicolor` theme desktop environments look up.
+const HICOLOR_SIZES: [u32; 8] = [16, 24, 32, 48, 64, 128, 256, 512];
+
 pub struct AppImage {
     appdir: PathBuf,
     name: String,
+    icon_cache: Option<PathBuf>,
+    deterministic: bool,
+    runtime: Cow<'static, [u8]>,
+    /// Symlinks of the appdir, also added to the image on hosts that can't
+    /// create them.
+    symlinks: Vec<(PathBuf, String)>,
 }
 
 impl AppImage {
@@ -19,43 +33,103 @@ impl AppImage {
         let appdir = build_dir.join(format!("{}.AppDir", name));
         std::fs::remove_dir_all(&appdir).ok();
         std::fs::create_dir_all(&appdir)?;
-        Ok(Self { appdir, name })
+        Ok(Self {
+            appdir,
+            name,
+            icon_cache: None,
+            deterministic: false,
+            runtime: Cow::Borrowed(RUNTIME),
+            symlinks: vec![],
+        })
+    }
+
+    /// Sets the timestamps of the squashfs image and its entries to zero.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Caches scaled icons in `dir`, see [`xcommon::Scaler::with_cache`].
+    pub fn set_icon_cache(&mut self, dir: &Path) {
+        self.icon_cache = Some(dir.to_path_buf());
+    }
+
+    /// Prepends the type 2 runtime at `path` instead of the bundled x86_64
+    /// one, for other architectures.
+    pub fn set_runtime(&mut self, path: &Path) -> Result<()> {
+        let runtime =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        self.runtime = Cow::Owned(runtime);
+        Ok(())
+    }
+
+    /// The AppImage type 2 runtime prepended to the squashfs image.
+    pub fn runtime(&self) -> &[u8] {
+        &self.runtime
     }
 
     pub fn appdir(&self) -> &Path {
         &self.appdir
     }
 
-    pub fn add_apprun(&self) -> Result<()> {
+    pub fn add_apprun(&mut self) -> Result<()> {
+        self.add_symlink(Path::new("AppRun"), &self.name.clone())
+    }
+
+    fn add_symlink(&mut self, path: &Path, target: &str) -> Result<()> {
         #[cfg(unix)]
-        std::os::unix::fs::symlink(&self.name, self.appdir.join("AppRun"))?;
+        std::os::unix::fs::symlink(target, self.appdir.join(path))?;
+        self.symlinks.push((path.to_path_buf(), target.to_string()));
         Ok(())
     }
 
     pub fn add_desktop(&self) -> Result<()> {
+        self.add_desktop_entries(&Default::default())
+    }
+
+    /// Writes the desktop file, `entries` override the defaults or add new keys.
+    pub fn add_desktop_entries(&self, entries: &BTreeMap<String, String>) -> Result<()> {
         let mut f = File::create(self.appdir.join(format!("{}.desktop", &self.name)))?;
         writeln!(f, "[Desktop Entry]")?;
-        writeln!(f, "Version=1.0")?;
-        writeln!(f, "Type=Application")?;
-        writeln!(f, "Terminal=false")?;
-        writeln!(f, "Name={}", self.name)?;
-        writeln!(f, "Exec={} %u", self.name)?;
-        writeln!(f, "Icon={}", self.name)?;
-        writeln!(f, "Categories=Utility;")?;
+        let defaults = [
+            ("Version", "1.0".to_string()),
+            ("Type", "Application".to_string()),
+            ("Terminal", "false".to_string()),
+            ("Name", self.name.clone()),
+            ("Exec", format!("{} %u", self.name)),
+            ("Icon", self.name.clone()),
+            ("Categories", "Utility;".to_string()),
+        ];
+        for (key, value) in &defaults {
+            let value = entries.get(*key).unwrap_or(value);
+            writeln!(f, "{}={}", key, value)?;
+        }
+        for (key, value) in entries {
+            if !defaults.iter().any(|(default, _)| default == key) {
+                writeln!(f, "{}={}", key, value)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn add_icon(&self, path: &Path) -> Result<()> {
-        let ext = path
-            .extension()
-            .map(|ext| ext.to_str())
-            .unwrap_or_default()
-            .context("unsupported extension")?;
-        let name = format!("{}.{}", self.name, ext);
-        self.add_file(path, Path::new(&name))?;
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(name, self.appdir.join(".DirIcon"))?;
-        Ok(())
+    /// Adds the icon in the `hicolor` theme sizes, and at the root as the
+    /// `.DirIcon` of the appimage.
+    pub fn add_icon(&mut self, path: &Path) -> Result<()> {
+        let mut scaler = Scaler::open(path)?;
+        if let Some(cache) = self.icon_cache.as_ref() {
+            scaler = scaler.with_cache(cache);
+        }
+        let name = format!("{}.png", self.name);
+        for size in HICOLOR_SIZES {
+            let dir = self
+                .appdir
+                .join(format!("usr/share/icons/hicolor/{0}x{0}/apps", size));
+            std::fs::create_dir_all(&dir)?;
+            let mut icon = BufWriter::new(File::create(dir.join(&name))?);
+            scaler.write(&mut icon, ScalerOpts::new(size))?;
+        }
+        let mut icon = BufWriter::new(File::create(self.appdir.join(&name))?);
+        scaler.write(&mut icon, ScalerOpts::new(256))?;
+        self.add_symlink(Path::new(".DirIcon"), &name)
     }
 
     pub fn add_file(&self, path: &Path, name: &Path) -> Result<()> {
@@ -80,24 +154,17 @@ impl AppImage {
             .parent()
             .unwrap()
             .join(format!("{}.squashfs", self.name));
-        let status = Command::new("mksquashfs")
-            .arg(&self.appdir)
-            .arg(&squashfs)
-            .arg("-root-owned")
-            .arg("-noappend")
-            .arg("-quiet")
-            .status()?;
-        anyhow::ensure!(
-            status.success(),
-            "mksquashfs failed with exit code {:?}",
-            status
-        );
+        let mut image = Squashfs::from_dir(&self.appdir, self.deterministic)?;
+        for (path, target) in &self.symlinks {
+            image.add_symlink(path, target)?;
+        }
+        image.write(&squashfs)?;
         let mut squashfs = BufReader::new(File::open(squashfs)?);
         let mut f = File::create(out)?;
         #[cfg(unix)]
         f.set_permissions(std::fs::Permissions::from_mode(0o755))?;
         let mut out = BufWriter::new(&mut f);
-        out.write_all(RUNTIME)?;
+        out.write_all(&self.runtime)?;
         std::io::copy(&mut squashfs, &mut out)?;
         // TODO: sign
         Ok(())
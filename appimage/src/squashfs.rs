@@ -0,0 +1,547 @@
+//! Squashfs 4.0 writer, so appimages can be created on hosts without
+//! `mksquashfs`. Entries are owned by root, data and metadata are zlib
+//! compressed and small files are stored in their own blocks instead of
+//! fragments.
+
+use anyhow::{Context, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+const MAGIC: u32 = 0x7371_7368;
+const BLOCK_LOG: u16 = 17;
+const BLOCK_SIZE: usize = 1 << BLOCK_LOG;
+const METADATA_SIZE: usize = 8192;
+const SUPERBLOCK_SIZE: u64 = 96;
+const GZIP: u16 = 1;
+const NO_FRAGMENTS: u16 = 0x10;
+const NO_XATTRS: u16 = 0x200;
+const NONE: u64 = u64::MAX;
+/// Set in the size of blocks stored without compression.
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+
+const BASIC_DIR: u16 = 1;
+const BASIC_FILE: u16 = 2;
+const BASIC_SYMLINK: u16 = 3;
+const EXTENDED_DIR: u16 = 8;
+
+enum Node {
+    Dir(BTreeMap<String, Entry>),
+    File(PathBuf),
+    Symlink(String),
+}
+
+struct Entry {
+    node: Node,
+    mode: u16,
+    mtime: u32,
+}
+
+impl Entry {
+    fn dir(mtime: u32) -> Self {
+        Self {
+            node: Node::Dir(Default::default()),
+            mode: 0o755,
+            mtime,
+        }
+    }
+
+    /// Number of inodes below this entry.
+    fn descendants(&self) -> u32 {
+        match &self.node {
+            Node::Dir(entries) => entries.values().map(|entry| 1 + entry.descendants()).sum(),
+            _ => 0,
+        }
+    }
+}
+
+/// Squashfs image of a directory, written with [`Squashfs::write`].
+pub struct Squashfs {
+    root: Entry,
+    mtime: u32,
+}
+
+impl Squashfs {
+    /// Reads the tree of `dir`. If `deterministic` all timestamps are zero,
+    /// otherwise the ones of the files are kept.
+    pub fn from_dir(dir: &Path, deterministic: bool) -> Result<Self> {
+        let mtime = if deterministic {
+            0
+        } else {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs() as u32
+        };
+        let mut root = read_entry(dir, deterministic)?;
+        root.mode = 0o755;
+        Ok(Self { root, mtime })
+    }
+
+    /// Adds a symlink at `path` relative to the root, unless the entry
+    /// exists. Used on hosts that can't create symlinks in the appdir.
+    pub fn add_symlink(&mut self, path: &Path, target: &str) -> Result<()> {
+        let mut components = path
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => name
+                    .to_str()
+                    .map(str::to_string)
+                    .context("non utf8 file name"),
+                _ => anyhow::bail!("expected a relative path, found {}", path.display()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let name = components.pop().context("empty symlink path")?;
+        let mtime = self.root.mtime;
+        let mut dir = &mut self.root;
+        for component in components {
+            dir = match &mut dir.node {
+                Node::Dir(entries) => entries
+                    .entry(component)
+                    .or_insert_with(|| Entry::dir(mtime)),
+                _ => anyhow::bail!("{} is not a directory", path.display()),
+            };
+        }
+        match &mut dir.node {
+            Node::Dir(entries) => {
+                entries.entry(name).or_insert_with(|| Entry {
+                    node: Node::Symlink(target.to_string()),
+                    mode: 0o777,
+                    mtime,
+                });
+            }
+            _ => anyhow::bail!("{} is not a directory", path.display()),
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.seek(SeekFrom::Start(SUPERBLOCK_SIZE))?;
+        let mut writer = Writer {
+            out,
+            pos: SUPERBLOCK_SIZE,
+            inodes: Metadata::default(),
+            dirs: Metadata::default(),
+        };
+        let inode_count = 1 + self.root.descendants();
+        // Inodes are numbered in the order they're written, children before
+        // their parent, the parent of the root is one past the last inode
+        let root = writer.write_entry(&self.root, 1, inode_count + 1)?;
+        let Writer {
+            mut out,
+            mut pos,
+            inodes,
+            dirs,
+        } = writer;
+
+        let inode_table_start = pos;
+        let inodes = inodes.finish()?;
+        out.write_all(&inodes)?;
+        pos += inodes.len() as u64;
+        let directory_table_start = pos;
+        let dirs = dirs.finish()?;
+        out.write_all(&dirs)?;
+        pos += dirs.len() as u64;
+        // Empty, there are no fragments
+        let fragment_table_start = pos;
+        // A single id, root
+        let mut ids = Metadata::default();
+        ids.write(&0u32.to_le_bytes())?;
+        let ids = ids.finish()?;
+        out.write_all(&ids)?;
+        let id_table_start = pos + ids.len() as u64;
+        out.write_all(&pos.to_le_bytes())?;
+        let bytes_used = id_table_start + 8;
+        // Images are padded to 4K, the size of loop devices
+        let padding = (4096 - bytes_used % 4096) % 4096;
+        out.write_all(&vec![0; padding as usize])?;
+
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&MAGIC.to_le_bytes())?;
+        out.write_all(&inode_count.to_le_bytes())?;
+        out.write_all(&self.mtime.to_le_bytes())?;
+        out.write_all(&(BLOCK_SIZE as u32).to_le_bytes())?;
+        // Fragments
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&GZIP.to_le_bytes())?;
+        out.write_all(&BLOCK_LOG.to_le_bytes())?;
+        out.write_all(&(NO_FRAGMENTS | NO_XATTRS).to_le_bytes())?;
+        // Ids
+        out.write_all(&1u16.to_le_bytes())?;
+        // Version 4.0
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0u16.to_le_bytes())?;
+        for value in [
+            root.reference,
+            bytes_used,
+            id_table_start,
+            // Xattrs
+            NONE,
+            inode_table_start,
+            directory_table_start,
+            fragment_table_start,
+            // Export table
+            NONE,
+        ] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+fn read_entry(path: &Path, deterministic: bool) -> Result<Entry> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mtime = if deterministic {
+        0
+    } else {
+        metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_secs() as u32)
+            .unwrap_or_default()
+    };
+    let node = if metadata.is_dir() {
+        let mut entries = BTreeMap::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|name| anyhow::anyhow!("non utf8 file name {:?}", name))?;
+            anyhow::ensure!(name.len() <= 256, "file name {} is too long", name);
+            entries.insert(name, read_entry(&entry.path(), deterministic)?);
+        }
+        Node::Dir(entries)
+    } else if metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(path)?;
+        Node::Symlink(
+            target
+                .to_str()
+                .context("non utf8 symlink target")?
+                .to_string(),
+        )
+    } else {
+        Node::File(path.to_path_buf())
+    };
+    Ok(Entry {
+        mode: mode(path, &metadata, &node)?,
+        node,
+        mtime,
+    })
+}
+
+#[cfg(unix)]
+fn mode(_path: &Path, metadata: &std::fs::Metadata, _node: &Node) -> Result<u16> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok((metadata.permissions().mode() & 0o7777) as u16)
+}
+
+/// Hosts without unix permissions mark executables and scripts as
+/// executable.
+#[cfg(not(unix))]
+fn mode(path: &Path, _metadata: &std::fs::Metadata, node: &Node) -> Result<u16> {
+    Ok(match node {
+        Node::Dir(_) => 0o755,
+        Node::Symlink(_) => 0o777,
+        Node::File(_) => {
+            let mut magic = [0; 4];
+            let len = File::open(path)?.read(&mut magic)?;
+            if magic[..len].starts_with(b"\x7fELF") || magic[..len].starts_with(b"#!") {
+                0o755
+            } else {
+                0o644
+            }
+        }
+    })
+}
+
+/// Reference to a written inode.
+struct Inode {
+    /// Start of the metadata block relative to the inode table in the upper
+    /// bits, offset in the uncompressed block in the lower 16.
+    reference: u64,
+    number: u32,
+    ty: u16,
+}
+
+struct Writer {
+    out: BufWriter<File>,
+    pos: u64,
+    inodes: Metadata,
+    dirs: Metadata,
+}
+
+impl Writer {
+    /// Writes `entry` numbered after its descendants, which are numbered
+    /// from `first`.
+    fn write_entry(&mut self, entry: &Entry, first: u32, parent: u32) -> Result<Inode> {
+        let number = first + entry.descendants();
+        let header = |ty: u16| {
+            let mut header = Vec::with_capacity(16);
+            header.extend(ty.to_le_bytes());
+            header.extend(entry.mode.to_le_bytes());
+            // Uid and gid index of root
+            header.extend(0u16.to_le_bytes());
+            header.extend(0u16.to_le_bytes());
+            header.extend(entry.mtime.to_le_bytes());
+            header.extend(number.to_le_bytes());
+            header
+        };
+        let (ty, inode) = match &entry.node {
+            Node::Dir(entries) => {
+                let mut children = vec![];
+                let mut next = first;
+                for (name, child) in entries {
+                    let inode = self.write_entry(child, next, number)?;
+                    next = inode.number + 1;
+                    children.push((name, inode));
+                }
+                let (dir_block, dir_offset) = self.dirs.position();
+                let size = self.write_listing(&children)?;
+                let links = 2 + children.iter().filter(|(_, i)| i.ty == BASIC_DIR).count() as u32;
+                let mut inode;
+                // Listings are three bytes larger than their size
+                let file_size = size + 3;
+                if file_size <= u16::MAX as u32 {
+                    inode = header(BASIC_DIR);
+                    inode.extend(dir_block.to_le_bytes());
+                    inode.extend(links.to_le_bytes());
+                    inode.extend((file_size as u16).to_le_bytes());
+                    inode.extend(dir_offset.to_le_bytes());
+                    inode.extend(parent.to_le_bytes());
+                } else {
+                    inode = header(EXTENDED_DIR);
+                    inode.extend(links.to_le_bytes());
+                    inode.extend(file_size.to_le_bytes());
+                    inode.extend(dir_block.to_le_bytes());
+                    inode.extend(parent.to_le_bytes());
+                    // No directory index
+                    inode.extend(0u16.to_le_bytes());
+                    inode.extend(dir_offset.to_le_bytes());
+                    inode.extend(u32::MAX.to_le_bytes());
+                }
+                (BASIC_DIR, inode)
+            }
+            Node::File(path) => {
+                let start = self.pos;
+                let (size, blocks) = self.write_data(path)?;
+                anyhow::ensure!(
+                    self.pos <= u32::MAX as u64,
+                    "appimages larger than 4GiB are not supported"
+                );
+                let mut inode = header(BASIC_FILE);
+                inode.extend((start as u32).to_le_bytes());
+                // No fragment
+                inode.extend(u32::MAX.to_le_bytes());
+                inode.extend(0u32.to_le_bytes());
+                inode.extend(size.to_le_bytes());
+                for block in blocks {
+                    inode.extend(block.to_le_bytes());
+                }
+                (BASIC_FILE, inode)
+            }
+            Node::Symlink(target) => {
+                let mut inode = header(BASIC_SYMLINK);
+                inode.extend(1u32.to_le_bytes());
+                inode.extend((target.len() as u32).to_le_bytes());
+                inode.extend(target.as_bytes());
+                (BASIC_SYMLINK, inode)
+            }
+        };
+        // Directories are written after their children
+        let (block, offset) = self.inodes.position();
+        self.inodes.write(&inode)?;
+        Ok(Inode {
+            reference: (block as u64) << 16 | offset as u64,
+            number,
+            ty,
+        })
+    }
+
+    /// Writes the blocks of a file, returning its size and the sizes of the
+    /// blocks.
+    fn write_data(&mut self, path: &Path) -> Result<(u32, Vec<u32>)> {
+        let mut file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let mut size = 0u64;
+        let mut blocks = vec![];
+        let mut buf = vec![0; BLOCK_SIZE];
+        loop {
+            let mut len = 0;
+            while len < BLOCK_SIZE {
+                let n = file.read(&mut buf[len..])?;
+                if n == 0 {
+                    break;
+                }
+                len += n;
+            }
+            if len == 0 {
+                break;
+            }
+            size += len as u64;
+            let block = &buf[..len];
+            let compressed = compress(block)?;
+            if compressed.len() < block.len() {
+                self.out.write_all(&compressed)?;
+                self.pos += compressed.len() as u64;
+                blocks.push(compressed.len() as u32);
+            } else {
+                self.out.write_all(block)?;
+                self.pos += block.len() as u64;
+                blocks.push(block.len() as u32 | DATA_UNCOMPRESSED);
+            }
+        }
+        anyhow::ensure!(
+            size <= u32::MAX as u64,
+            "{} is larger than 4GiB",
+            path.display()
+        );
+        Ok((size as u32, blocks))
+    }
+
+    /// Writes the sorted entries of a directory, returning the size of the
+    /// listing.
+    fn write_listing(&mut self, children: &[(&String, Inode)]) -> Result<u32> {
+        let mut size = 0;
+        let mut i = 0;
+        while i < children.len() {
+            // Entries of a header share the inode block and are numbered
+            // relative to the first one
+            let block = children[i].1.reference >> 16;
+            let base = children[i].1.number;
+            let count = children[i..]
+                .iter()
+                .take(256)
+                .take_while(|(_, inode)| {
+                    inode.reference >> 16 == block
+                        && (inode.number as i64 - base as i64) <= i16::MAX as i64
+                })
+                .count();
+            let mut listing = vec![];
+            listing.extend((count as u32 - 1).to_le_bytes());
+            listing.extend((block as u32).to_le_bytes());
+            listing.extend(base.to_le_bytes());
+            for (name, inode) in &children[i..i + count] {
+                listing.extend((inode.reference as u16).to_le_bytes());
+                listing.extend(((inode.number - base) as i16).to_le_bytes());
+                listing.extend(inode.ty.to_le_bytes());
+                listing.extend((name.len() as u16 - 1).to_le_bytes());
+                listing.extend(name.as_bytes());
+            }
+            self.dirs.write(&listing)?;
+            size += listing.len() as u32;
+            i += count;
+        }
+        Ok(size)
+    }
+}
+
+/// Table of metadata blocks, 8K of data compressed with a 16 bit header.
+#[derive(Default)]
+struct Metadata {
+    blocks: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl Metadata {
+    /// Start of the current block relative to the table and the offset in
+    /// the uncompressed block.
+    fn position(&self) -> (u32, u16) {
+        (self.blocks.len() as u32, self.buf.len() as u16)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.buf.extend_from_slice(bytes);
+        while self.buf.len() >= METADATA_SIZE {
+            let rest = self.buf.split_off(METADATA_SIZE);
+            let block = std::mem::replace(&mut self.buf, rest);
+            self.write_block(&block)?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> Result<()> {
+        let compressed = compress(block)?;
+        if compressed.len() < block.len() {
+            self.blocks.extend((compressed.len() as u16).to_le_bytes());
+            self.blocks.extend(compressed);
+        } else {
+            self.blocks
+                .extend((block.len() as u16 | METADATA_UNCOMPRESSED).to_le_bytes());
+            self.blocks.extend_from_slice(block);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>> {
+        if !self.buf.is_empty() {
+            let block = std::mem::take(&mut self.buf);
+            self.write_block(&block)?;
+        }
+        Ok(self.blocks)
+    }
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Extracts the image with the squashfs reader of the bundled runtime.
+    #[test]
+    fn test_extract() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("squashfs-{}", std::process::id()));
+        let src = dir.join("src");
+        // Spans several data blocks, metadata blocks and directory headers
+        let big = (0..3 * BLOCK_SIZE as u32 + 7)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        std::fs::create_dir_all(src.join("a/b/empty"))?;
+        std::fs::write(src.join("a/b/big"), &big)?;
+        std::fs::write(src.join("zeros"), vec![0; BLOCK_SIZE + 1])?;
+        for i in 0..600 {
+            std::fs::write(src.join("a").join(format!("file-{:03}", i)), i.to_string())?;
+        }
+        std::os::unix::fs::symlink("a/b/big", src.join("link"))?;
+
+        let image = dir.join("image");
+        let mut squashfs = Squashfs::from_dir(&src, true)?;
+        squashfs.add_symlink(Path::new("a/b/added"), "../file-000")?;
+        squashfs.write(&image)?;
+        let appimage = dir.join("test.AppImage");
+        let mut bytes = crate::RUNTIME.to_vec();
+        bytes.extend(std::fs::read(&image)?);
+        std::fs::write(&appimage, bytes)?;
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("chmod +x test.AppImage && ./test.AppImage --appimage-extract > /dev/null")
+            .current_dir(&dir)
+            .status()?;
+        assert!(status.success());
+
+        let root = dir.join("squashfs-root");
+        assert_eq!(std::fs::read(root.join("a/b/big"))?, big);
+        assert_eq!(std::fs::read(root.join("zeros"))?, vec![0; BLOCK_SIZE + 1]);
+        for i in 0..600 {
+            let file = root.join("a").join(format!("file-{:03}", i));
+            assert_eq!(std::fs::read_to_string(file)?, i.to_string());
+        }
+        assert!(root.join("a/b/empty").is_dir());
+        assert_eq!(std::fs::read_link(root.join("link"))?, Path::new("a/b/big"));
+        assert_eq!(std::fs::read_to_string(root.join("a/b/added"))?, "0");
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -28,7 +28,8 @@ impl BlockMapBuilder {
             .join("\\");
         let size = f.size();
         let mut file = File {
-            lfh_size: 30 + name.len() as u16,
+            // Includes the extra fields, like the zip64 one of large entries
+            lfh_size: (f.data_start() - f.header_start()) as u16,
             name,
             size,
             ..Default::default()
@@ -0,0 +1,220 @@
+use crate::manifest::{AppxManifest, Identity, Resource};
+use crate::{to_xml, Msix};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use xcommon::{Compression, Signer, Zip, ZipFileOptions};
+use zip::ZipArchive;
+
+/// Bundle of the msix packages of every architecture, installed as a single
+/// package that picks the one matching the device.
+pub struct MsixBundle {
+    path: PathBuf,
+    identity: Option<Identity>,
+    packages: Vec<(PathBuf, BundlePackage)>,
+    deterministic: bool,
+}
+
+impl MsixBundle {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            identity: None,
+            packages: vec![],
+            deterministic: false,
+        }
+    }
+
+    /// See [`xcommon::ZipBuilder::set_deterministic`].
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Adds the msix at `path` built from `manifest`. Every package needs the
+    /// same identity and a different processor architecture.
+    pub fn add_package(&mut self, path: &Path, manifest: &AppxManifest) -> Result<()> {
+        let identity = &manifest.identity;
+        let architecture = identity
+            .processor_architecture
+            .clone()
+            .context("bundled packages need a processor architecture")?;
+        anyhow::ensure!(
+            self.packages
+                .iter()
+                .all(|(_, package)| package.architecture != architecture),
+            "bundle already contains a {} package",
+            architecture
+        );
+        let bundle_identity = self.identity.get_or_insert_with(|| Identity {
+            processor_architecture: None,
+            ..identity.clone()
+        });
+        anyhow::ensure!(
+            bundle_identity.name == identity.name
+                && bundle_identity.publisher == identity.publisher
+                && bundle_identity.version == identity.version,
+            "bundled packages need the same name, publisher and version"
+        );
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("invalid package path")?;
+        let package = BundlePackage {
+            ty: "application".into(),
+            version: identity.version.clone().unwrap_or_default(),
+            architecture: architecture.clone(),
+            file_name: format!(
+                "{}_{}.msix",
+                file_name.trim_end_matches(".msix"),
+                architecture
+            ),
+            offset: 0,
+            size: 0,
+            resources: BundleResources {
+                resource: manifest.resources.resource.clone(),
+            },
+        };
+        self.packages.push((path.to_path_buf(), package));
+        Ok(())
+    }
+
+    pub fn finish(mut self, signer: Option<Signer>) -> Result<()> {
+        let identity = self
+            .identity
+            .take()
+            .context("bundle contains no packages")?;
+        // Packages are stored so the installer can read them in place
+        let compression = Compression::Stored;
+        let mut zip = Zip::new(&self.path, compression)?;
+        zip.set_deterministic(self.deterministic);
+        for (path, package) in &self.packages {
+            zip.add_file(path, package.file_name.as_ref(), ZipFileOptions::Unaligned)?;
+        }
+        zip.finish()?;
+
+        let mut archive = ZipArchive::new(BufReader::new(File::open(&self.path)?))?;
+        for (_, package) in &mut self.packages {
+            let file = archive.by_name(&package.file_name)?;
+            package.offset = file.data_start();
+            package.size = file.compressed_size();
+        }
+        let manifest = AppxBundleManifest {
+            ns: default_namespace(),
+            schema_version: "3.0".into(),
+            identity,
+            packages: BundlePackages {
+                package: self
+                    .packages
+                    .into_iter()
+                    .map(|(_, package)| package)
+                    .collect(),
+            },
+        };
+        let mut zip = Zip::append(&self.path, compression)?;
+        zip.set_deterministic(self.deterministic);
+        zip.create_file(
+            "AppxMetadata/AppxBundleManifest.xml".as_ref(),
+            ZipFileOptions::Compressed,
+            &to_xml(&manifest, true),
+        )?;
+        zip.finish()?;
+        Msix::sign(&self.path, signer, compression, self.deterministic)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename = "Bundle")]
+struct AppxBundleManifest {
+    #[serde(rename(serialize = "xmlns"))]
+    ns: String,
+    #[serde(rename(serialize = "SchemaVersion"))]
+    schema_version: String,
+    #[serde(rename(serialize = "Identity"))]
+    identity: Identity,
+    #[serde(rename(serialize = "Packages"))]
+    packages: BundlePackages,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BundlePackages {
+    #[serde(rename(serialize = "Package"))]
+    package: Vec<BundlePackage>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BundlePackage {
+    #[serde(rename(serialize = "Type"))]
+    ty: String,
+    #[serde(rename(serialize = "Version"))]
+    version: String,
+    #[serde(rename(serialize = "Architecture"))]
+    architecture: String,
+    #[serde(rename(serialize = "FileName"))]
+    file_name: String,
+    /// Offset of the package data in the bundle.
+    #[serde(rename(serialize = "Offset"))]
+    offset: u64,
+    #[serde(rename(serialize = "Size"))]
+    size: u64,
+    #[serde(rename(serialize = "Resources"))]
+    resources: BundleResources,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BundleResources {
+    #[serde(rename(serialize = "Resource"))]
+    resource: Vec<Resource>,
+}
+
+fn default_namespace() -> String {
+    "http://schemas.microsoft.com/appx/2013/bundle".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_package_offsets() -> Result<()> {
+        let dir = std::env::temp_dir().join("msix-bundle-test");
+        std::fs::create_dir_all(&dir)?;
+        let exe = dir.join("app.exe");
+        std::fs::write(&exe, b"MZ")?;
+        let mut bundle = MsixBundle::new(dir.join("app.msixbundle"));
+        for arch in ["x64", "arm64"] {
+            let mut manifest = AppxManifest::default();
+            manifest.identity.name = Some("com.example.app".into());
+            manifest.identity.version = Some("1.0.0.0".into());
+            manifest.identity.processor_architecture = Some(arch.into());
+            let path = dir.join(arch).join("app.msix");
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            let mut msix = Msix::new(path.clone(), manifest.clone(), Compression::default())?;
+            msix.add_file(&exe, "app.exe".as_ref(), ZipFileOptions::Compressed)?;
+            msix.finish(None)?;
+            bundle.add_package(&path, &manifest)?;
+        }
+        bundle.finish(None)?;
+
+        let bytes = std::fs::read(dir.join("app.msixbundle"))?;
+        let mut archive = ZipArchive::new(std::io::Cursor::new(&bytes))?;
+        let mut xml = String::new();
+        archive
+            .by_name("AppxMetadata/AppxBundleManifest.xml")?
+            .read_to_string(&mut xml)?;
+        for arch in ["x64", "arm64"] {
+            let package = &xml[xml.find(&format!("app_{}.msix", arch)).unwrap()..];
+            let attr = |name: &str| -> usize {
+                let value =
+                    &package[package.find(&format!("{}=\"", name)).unwrap() + name.len() + 2..];
+                value[..value.find('"').unwrap()].parse().unwrap()
+            };
+            let (offset, size) = (attr("Offset"), attr("Size"));
+            let msix = std::fs::read(dir.join(arch).join("app.msix"))?;
+            assert_eq!(&bytes[offset..offset + size], &msix[..]);
+        }
+        Ok(())
+    }
+}
@@ -10,13 +10,26 @@ pub struct ContentTypesBuilder {
 
 impl ContentTypesBuilder {
     pub fn add(&mut self, path: &Path) {
+        if path == Path::new("AppxMetadata/AppxBundleManifest.xml") {
+            self.inner.as_mut().unwrap().rules.push(Rule::Override {
+                part_name: "/AppxMetadata/AppxBundleManifest.xml".into(),
+                mime: "application/vnd.ms-appx.bundlemanifest+xml".into(),
+            });
+            return;
+        }
         if let Some(ext) = path.extension() {
             if let Some(ext) = ext.to_str() {
                 if !self.ext.contains(ext) {
-                    let mime = mime_guess::from_ext(ext).first_or_octet_stream();
+                    let mime = match ext {
+                        // Packages in a bundle
+                        "msix" | "appx" => "application/vnd.ms-appx".into(),
+                        _ => mime_guess::from_ext(ext)
+                            .first_or_octet_stream()
+                            .to_string(),
+                    };
                     self.inner.as_mut().unwrap().rules.push(Rule::Default {
                         ext: ext.into(),
-                        mime: mime.to_string(),
+                        mime,
                     });
                     self.ext.insert(ext.to_string());
                 }
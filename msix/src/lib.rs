@@ -1,5 +1,6 @@
 use crate::block_map::BlockMapBuilder;
 use crate::content_types::ContentTypesBuilder;
+use crate::manifest::SplashScreen;
 use crate::p7x::Digests;
 use anyhow::Result;
 use serde::Serialize;
@@ -7,15 +8,20 @@ use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::{BufReader, Cursor, Read, Seek};
 use std::path::{Path, PathBuf};
-use xcommon::{Scaler, ScalerOptsBuilder, Signer, Zip, ZipFileOptions, ZipInfo};
+use xcommon::{
+    Compression, Scaler, ScalerOptsBuilder, SignatureAlgorithm, Signer, Splash, Zip,
+    ZipFileOptions, ZipInfo,
+};
 use zip::ZipArchive;
 
 mod block_map;
+mod bundle;
 mod content_types;
 pub mod manifest;
 pub mod p7x;
 mod pkcs7;
 
+pub use crate::bundle::MsixBundle;
 pub use crate::manifest::AppxManifest;
 
 const DEBUG_PEM: &str = include_str!("../assets/debug.pem");
@@ -35,25 +41,50 @@ pub struct Msix {
     manifest: AppxManifest,
     path: PathBuf,
     zip: Zip,
-    compress: bool,
+    compression: Compression,
+    icon_cache: Option<PathBuf>,
+    splash_image: bool,
 }
 
 impl Msix {
-    pub fn new(path: PathBuf, manifest: AppxManifest, compress: bool) -> Result<Self> {
+    pub fn new(path: PathBuf, manifest: AppxManifest, compression: Compression) -> Result<Self> {
+        anyhow::ensure!(
+            compression.is_portable(),
+            "msix entries must be stored or deflated, {:?} is not supported",
+            compression
+        );
         Ok(Self {
             manifest,
-            zip: Zip::new(&path, compress)?,
+            zip: Zip::new(&path, compression)?,
             path,
-            compress,
+            compression,
+            icon_cache: None,
+            splash_image: false,
         })
     }
 
+    /// See [`xcommon::ZipBuilder::set_deterministic`].
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.zip.set_deterministic(deterministic);
+    }
+
+    /// Caches scaled icons in `dir`, see [`xcommon::Scaler::with_cache`].
+    pub fn set_icon_cache(&mut self, dir: &Path) {
+        self.icon_cache = Some(dir.to_path_buf());
+    }
+
     pub fn add_icon(&mut self, path: &Path) -> Result<()> {
         let mut scaler = Scaler::open(path)?;
+        if let Some(cache) = self.icon_cache.as_ref() {
+            scaler = scaler.with_cache(cache);
+        }
         scaler.optimize();
         let images = Path::new("Images");
         let mut buf = vec![];
         for (base_name, (width, height), padding) in IMAGES {
+            if base_name == "SplashScreen" && self.splash_image {
+                continue;
+            }
             for scale in [1.0, 1.25, 1.5, 2.0, 4.0] {
                 buf.clear();
                 let opts = ScalerOptsBuilder::new(width, height)
@@ -69,6 +100,48 @@ impl Msix {
         Ok(())
     }
 
+    /// Sets the splash screen background of every application and, if the
+    /// splash has an image, writes it instead of the icon. Windows has no
+    /// dark variant. Needs to be called before [`Self::add_icon`].
+    pub fn add_splash_screen(&mut self, splash: &Splash) -> Result<()> {
+        let [r, g, b, _] = splash.background;
+        let background = format!("#{:02x}{:02x}{:02x}", r, g, b);
+        for application in &mut self.manifest.applications.application {
+            let splash_screen = application
+                .visual_elements
+                .splash_screen
+                .get_or_insert_with(|| SplashScreen {
+                    image: "Images\\SplashScreen.png".into(),
+                    background_color: None,
+                });
+            splash_screen.background_color = Some(background.clone());
+        }
+        let image = if let Some(image) = splash.image.as_ref() {
+            image
+        } else {
+            return Ok(());
+        };
+        let mut scaler = Scaler::open(image)?;
+        if let Some(cache) = self.icon_cache.as_ref() {
+            scaler = scaler.with_cache(cache);
+        }
+        scaler.optimize();
+        let mut buf = vec![];
+        for scale in [1.0, 1.25, 1.5, 2.0, 4.0] {
+            buf.clear();
+            let opts = ScalerOptsBuilder::new(620, 300).scale(scale).build();
+            scaler.write(&mut Cursor::new(&mut buf), opts)?;
+            let name = format!("SplashScreen.scale-{}.png", (scale * 100.0) as u32);
+            self.zip.create_file(
+                &Path::new("Images").join(name),
+                ZipFileOptions::Unaligned,
+                &buf,
+            )?;
+        }
+        self.splash_image = true;
+        Ok(())
+    }
+
     pub fn add_file(&mut self, source: &Path, dest: &Path, opts: ZipFileOptions) -> Result<()> {
         self.zip.add_file(source, dest, opts)
     }
@@ -88,11 +161,17 @@ impl Msix {
             ZipFileOptions::Compressed,
             &to_xml(&self.manifest, true),
         )?;
+        let deterministic = self.zip.deterministic();
         self.zip.finish()?;
-        Self::sign(&self.path, signer, self.compress)
+        Self::sign(&self.path, signer, self.compression, deterministic)
     }
 
-    pub fn sign(path: &Path, signer: Option<Signer>, compress: bool) -> Result<()> {
+    pub fn sign(
+        path: &Path,
+        signer: Option<Signer>,
+        compression: Compression,
+        deterministic: bool,
+    ) -> Result<()> {
         let signer = signer
             .map(Ok)
             .unwrap_or_else(|| Signer::new(DEBUG_PEM))
@@ -111,7 +190,8 @@ impl Msix {
         let axct = Sha256::digest(&content_types);
         let block_map = to_xml(&block_map.finish(), false);
         let axbm = Sha256::digest(&block_map);
-        let mut zip = Zip::append(path, compress)?;
+        let mut zip = Zip::append(path, compression)?;
+        zip.set_deterministic(deterministic);
         zip.create_file(
             "[Content_Types].xml".as_ref(),
             ZipFileOptions::Compressed,
@@ -145,7 +225,8 @@ impl Msix {
 
         // sign zip
         let sig = p7x::p7x(&signer, &digests);
-        let mut zip = Zip::append(path, compress)?;
+        let mut zip = Zip::append(path, compression)?;
+        zip.set_deterministic(deterministic);
         zip.create_file(
             "AppxSignature.p7x".as_ref(),
             ZipFileOptions::Compressed,
@@ -169,3 +250,36 @@ fn to_xml<T: Serialize>(xml: &T, standalone: bool) -> Vec<u8> {
     quick_xml::se::to_writer(&mut buf, xml).unwrap();
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn sign_zip64() -> Result<()> {
+        let path = std::env::temp_dir().join("msix-sign-zip64.msix");
+        let mut msix = Msix::new(path.clone(), AppxManifest::default(), Compression::Stored)?;
+        // large files get a zip64 extra field in their local header
+        msix.zip
+            .start_large_file("large.bin".as_ref(), ZipFileOptions::Unaligned)?;
+        msix.zip.write_all(&[0; 16])?;
+        msix.finish(None)?;
+        let mut archive = ZipArchive::new(File::open(&path)?)?;
+        let lfh_size = {
+            let file = archive.by_name("large.bin")?;
+            file.data_start() - file.header_start()
+        };
+        assert_eq!(lfh_size, 30 + "large.bin".len() as u64 + 20);
+        let mut block_map = String::new();
+        archive
+            .by_name("AppxBlockMap.xml")?
+            .read_to_string(&mut block_map)?;
+        assert!(block_map.contains(&format!(
+            r#"<File Name="large.bin" Size="16" LfhSize="{}">"#,
+            lfh_size
+        )));
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
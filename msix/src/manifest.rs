@@ -210,6 +210,8 @@ pub struct ShowOn {
 pub struct SplashScreen {
     #[serde(rename(serialize = "Image"))]
     pub image: String,
+    #[serde(rename(serialize = "BackgroundColor"))]
+    pub background_color: Option<String>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -317,6 +319,7 @@ mod tests {
                         }),
                         splash_screen: Some(SplashScreen {
                             image: "Images\\SplashScreen.png".into(),
+                            background_color: None,
                         }),
                         lock_screen: Some(LockScreen {
                             badge_logo: "Images\\BadgeLogo.png".into(),
@@ -1,26 +1,55 @@
-use crate::Signer;
+use crate::{SignatureAlgorithm, Signer};
 use rasn::prelude::*;
 use rasn_cms::pkcs7_compat::{EncapsulatedContentInfo, SignedData};
 use rasn_cms::{AlgorithmIdentifier, IssuerAndSerialNumber, SignerIdentifier, SignerInfo};
 use rasn_pkix::Attribute;
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512};
 use std::collections::BTreeSet;
 
 pub const SPC_INDIRECT_DATA_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 4]);
 pub const SPC_SP_OPUS_INFO_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 12]);
 pub const SPC_SIPINFO_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 30]);
+pub const ECDSA_WITH_SHA256_OBJID: ConstOid = ConstOid(&[1, 2, 840, 10045, 4, 3, 2]);
+pub const ED25519_OBJID: ConstOid = ConstOid(&[1, 3, 101, 112]);
+pub const SHA512_OBJID: ConstOid = ConstOid(&[2, 16, 840, 1, 101, 3, 4, 2, 3]);
 
 #[allow(clippy::mutable_key_type)]
 pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo) -> SignedData {
-    let digest = Sha256::digest(&encap_content_info.content.as_bytes()[8..]);
-    let signature = signer.sign(&encap_content_info.content.as_bytes()[8..]);
+    let content = &encap_content_info.content.as_bytes()[8..];
+    let signature = signer.sign(content);
     let cert = signer.cert();
 
+    // ed25519 requires sha512 message digests (RFC 8419)
+    let (digest, digest_algorithm) = if signer.algorithm() == SignatureAlgorithm::Ed25519 {
+        (
+            Sha512::digest(content).to_vec(),
+            ObjectIdentifier::from(SHA512_OBJID),
+        )
+    } else {
+        (
+            Sha256::digest(content).to_vec(),
+            Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256
+                .into(),
+        )
+    };
     let digest_algorithm = AlgorithmIdentifier {
-        algorithm:
-            Oid::JOINT_ISO_ITU_T_COUNTRY_US_ORGANIZATION_GOV_CSOR_NIST_ALGORITHMS_HASH_SHA256.into(),
+        algorithm: digest_algorithm,
         parameters: Some(Any::new(vec![5, 0])),
     };
+    let signature_algorithm = match signer.algorithm() {
+        SignatureAlgorithm::RsaPkcs1Sha256 => AlgorithmIdentifier {
+            algorithm: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS1.into(),
+            parameters: Some(Any::new(vec![5, 0])),
+        },
+        SignatureAlgorithm::EcdsaP256Sha256 => AlgorithmIdentifier {
+            algorithm: ECDSA_WITH_SHA256_OBJID.into(),
+            parameters: None,
+        },
+        SignatureAlgorithm::Ed25519 => AlgorithmIdentifier {
+            algorithm: ED25519_OBJID.into(),
+            parameters: None,
+        },
+    };
     let signer_info = SignerInfo {
         version: 1.into(),
         sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
@@ -42,7 +71,7 @@ pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo)
             signed_attrs.insert(Attribute {
                 r#type: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS9_MESSAGE_DIGEST.into(),
                 values: {
-                    let digest = OctetString::from(digest.to_vec());
+                    let digest = OctetString::from(digest);
                     let mut digests = BTreeSet::default();
                     digests.insert(Any::new(rasn::der::encode(&digest).unwrap()));
                     digests
@@ -54,10 +83,7 @@ pub fn build_pkcs7(signer: &Signer, encap_content_info: EncapsulatedContentInfo)
             });
             signed_attrs
         }),
-        signature_algorithm: AlgorithmIdentifier {
-            algorithm: Oid::ISO_MEMBER_BODY_US_RSADSI_PKCS1.into(),
-            parameters: Some(Any::new(vec![5, 0])),
-        },
+        signature_algorithm,
         signature: OctetString::from(signature.to_vec()),
         unsigned_attrs: Some({
             // TODO: 1.3.6.1.4.1.311.3.3.1 timestamp? optional?
@@ -14,7 +14,7 @@ pub use data_item::DataItem;
 pub use decision_info::{Decision, DecisionInfo, Qualifier, QualifierSet, QualifierType};
 pub use hierarchical_schema::{HierarchicalSchema, ResourceMapEntry};
 pub use pri_descriptor::{PriDescriptor, PriDescriptorFlags};
-pub use resource_map::{ResourceMap, ResourceValueType};
+pub use resource_map::{Candidate, CandidateSet, ResourceMap, ResourceValueType};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct PriFile {
@@ -18,6 +18,11 @@ pub enum Inheritable<T> {
 pub struct Manifest {
     pub workspace: Option<Workspace>,
     pub package: Option<Package>,
+    pub lib: Option<Target>,
+    #[serde(default)]
+    pub bin: Vec<Target>,
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
 }
 
 impl Manifest {
@@ -126,8 +131,22 @@ pub struct WorkspacePackage {
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Package {
     pub name: String,
     pub version: Inheritable<String>,
     pub description: Option<Inheritable<String>>,
+    pub default_run: Option<String>,
+}
+
+/// A custom `[profile.<name>]`
+#[derive(Clone, Debug, Deserialize)]
+pub struct Profile {
+    pub inherits: Option<String>,
+}
+
+/// A `[lib]` or `[[bin]]` target
+#[derive(Clone, Debug, Deserialize)]
+pub struct Target {
+    pub name: Option<String>,
 }
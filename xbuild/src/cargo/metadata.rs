@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Subset of the output of `cargo metadata --format-version 1`.
+#[derive(Debug, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub resolve: Option<Resolve>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+    pub source: Option<String>,
+    pub manifest_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub deps: Vec<NodeDep>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeDep {
+    pub pkg: String,
+    pub dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepKind {
+    /// `None` for normal dependencies, otherwise `dev` or `build`.
+    pub kind: Option<String>,
+}
+
+impl Metadata {
+    /// Packages linked into the artifacts of `root`, excluding `root` itself.
+    /// Build and dev dependencies are skipped since nothing of them ends up
+    /// in the artifact.
+    pub fn dependencies(&self, root: &str) -> Vec<&Package> {
+        let nodes = self
+            .resolve
+            .iter()
+            .flat_map(|resolve| &resolve.nodes)
+            .map(|node| (node.id.as_str(), node))
+            .collect::<HashMap<_, _>>();
+        let mut seen = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let node = if let Some(node) = nodes.get(id) {
+                node
+            } else {
+                continue;
+            };
+            for dep in &node.deps {
+                let normal = dep.dep_kinds.iter().any(|kind| kind.kind.is_none());
+                if normal && seen.insert(dep.pkg.as_str()) {
+                    stack.push(&dep.pkg);
+                }
+            }
+        }
+        let mut packages = self
+            .packages
+            .iter()
+            .filter(|package| package.id != root && seen.contains(package.id.as_str()))
+            .collect::<Vec<_>>();
+        packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        packages
+    }
+}
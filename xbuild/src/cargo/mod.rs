@@ -1,33 +1,39 @@
 use anyhow::{Context, Result};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, Stdio};
 
 mod artifact;
 pub mod config;
 pub mod manifest;
+pub mod metadata;
 mod utils;
 
 pub use artifact::{Artifact, CrateType};
 
 use self::config::LocalizedConfig;
 use self::manifest::Manifest;
-use crate::{CompileTarget, Opt};
+use self::metadata::Metadata;
+use crate::{Arch, CompileTarget, Container, MessageFormat, Opt, Platform};
 
 pub struct Cargo {
     package: String,
     features: Vec<String>,
-    workspace_manifest: Option<Manifest>,
+    profile: Option<String>,
+    workspace_manifest: Option<(PathBuf, Manifest)>,
     manifest: Manifest,
     package_root: PathBuf,
     target_dir: PathBuf,
     offline: bool,
+    /// Target selected with `--bin` or `--example`.
+    artifact: Option<Artifact>,
 }
 
 impl Cargo {
     pub fn new(
         package: Option<&str>,
         features: Vec<String>,
+        profile: Option<String>,
         manifest_path: Option<PathBuf>,
         target_dir: Option<PathBuf>,
         offline: bool,
@@ -104,11 +110,13 @@ impl Cargo {
         Ok(Self {
             package: package.clone(),
             features,
-            workspace_manifest: workspace_manifest.map(|(_path, manifest)| manifest),
+            profile,
+            workspace_manifest,
             manifest,
             package_root: package_root.to_owned(),
             target_dir,
             offline,
+            artifact: None,
         })
     }
 
@@ -121,7 +129,16 @@ impl Cargo {
     }
 
     pub fn workspace_manifest(&self) -> Option<&Manifest> {
-        self.workspace_manifest.as_ref()
+        self.workspace_manifest
+            .as_ref()
+            .map(|(_path, manifest)| manifest)
+    }
+
+    /// Path to the Cargo.toml of the workspace root, if in a workspace.
+    pub fn workspace_manifest_path(&self) -> Option<&Path> {
+        self.workspace_manifest
+            .as_ref()
+            .map(|(path, _)| path.as_path())
     }
 
     pub fn manifest(&self) -> &Manifest {
@@ -148,14 +165,69 @@ impl Cargo {
         Ok(artifacts)
     }
 
-    pub fn build(&self, target: CompileTarget, target_dir: &Path) -> Result<CargoBuild> {
-        CargoBuild::new(
-            target,
-            &self.features,
-            self.package_root(),
-            target_dir,
-            self.offline,
-        )
+    /// Builds and packages only `artifact`, a `[[bin]]` or `[[example]]`
+    /// target.
+    pub fn set_artifact(&mut self, artifact: Artifact) {
+        self.artifact = Some(artifact);
+    }
+
+    pub fn selected_artifact(&self) -> Option<&Artifact> {
+        self.artifact.as_ref()
+    }
+
+    /// Runs `cargo build` with the rustup `toolchain` if set, otherwise the
+    /// default one.
+    pub fn build(
+        &self,
+        target: CompileTarget,
+        toolchain: Option<&str>,
+        target_dir: &Path,
+    ) -> Result<CargoBuild> {
+        let mut build = CargoBuild::new("build", self, target, toolchain, target_dir)?;
+        match &self.artifact {
+            Some(Artifact::Root(name)) => {
+                build.arg("--bin");
+                build.arg(name);
+            }
+            Some(Artifact::Example(name)) => {
+                build.arg("--example");
+                build.arg(name);
+            }
+            None => {}
+        }
+        Ok(build)
+    }
+
+    /// Resolves the dependency graph for `triple` with `features` enabled in
+    /// addition to the ones passed on the command line.
+    pub fn metadata(&self, triple: &str, features: &[String]) -> Result<Metadata> {
+        let mut cmd = Command::new("cargo");
+        cmd.current_dir(self.package_root());
+        cmd.arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .arg("--filter-platform")
+            .arg(triple);
+        if self.offline {
+            cmd.arg("--offline");
+        }
+        for features in self.features.iter().chain(features) {
+            cmd.arg("--features").arg(features);
+        }
+        let output = cmd.stderr(Stdio::inherit()).output()?;
+        anyhow::ensure!(output.status.success(), "cargo metadata failed");
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// Runs `cargo test` with the rustup `toolchain` if set, otherwise the
+    /// default one.
+    pub fn test(
+        &self,
+        target: CompileTarget,
+        toolchain: Option<&str>,
+        target_dir: &Path,
+    ) -> Result<CargoBuild> {
+        CargoBuild::new("test", self, target, toolchain, target_dir)
     }
 
     pub fn artifact(
@@ -170,8 +242,14 @@ impl Cargo {
         } else {
             target_dir.join(target.rust_triple()?)
         };
-        let opt_dir = arch_dir.join(target.opt().to_string());
-        let artifact = artifact.unwrap_or_else(|| Artifact::Root(self.package.clone()));
+        let opt_dir = arch_dir.join(self.profile_dir(target.opt()));
+        let artifact = artifact.unwrap_or_else(|| match ty {
+            CrateType::Bin => self
+                .artifact
+                .clone()
+                .unwrap_or_else(|| Artifact::Root(self.bin_name())),
+            _ => Artifact::Root(self.lib_name()),
+        });
         let triple = target.rust_triple()?;
         let bin_path = opt_dir
             .join(artifact.as_ref())
@@ -184,6 +262,80 @@ impl Cargo {
         Ok(bin_path)
     }
 
+    /// Builds with the cargo profile `profile` instead of `--release`.
+    pub fn set_profile(&mut self, profile: String) {
+        self.profile = Some(profile);
+    }
+
+    /// Directory name of the cargo output for `opt`, or of the custom profile
+    /// passed with `--profile`.
+    pub fn profile_dir(&self, opt: Opt) -> String {
+        match self.profile.as_deref() {
+            Some("dev") | Some("test") => "debug".into(),
+            Some("bench") => "release".into(),
+            Some(profile) => profile.into(),
+            None => opt.to_string(),
+        }
+    }
+
+    /// Resolves the built-in profile a custom profile inherits from, following
+    /// the `inherits` keys of the `[profile]` tables in the workspace root.
+    pub fn profile_opt(&self) -> Result<Option<Opt>> {
+        let mut profile = if let Some(profile) = &self.profile {
+            profile.as_str()
+        } else {
+            return Ok(None);
+        };
+        let profiles = &self.workspace_manifest().unwrap_or(&self.manifest).profile;
+        for _ in 0..=profiles.len() {
+            match profile {
+                "dev" | "test" => return Ok(Some(Opt::Debug)),
+                "release" | "bench" => return Ok(Some(Opt::Release)),
+                _ => {}
+            }
+            profile = profiles
+                .get(profile)
+                .and_then(|p| p.inherits.as_deref())
+                .with_context(|| format!("profile `{}` must inherit from a profile", profile))?;
+        }
+        anyhow::bail!("profile inheritance cycle")
+    }
+
+    /// Name of the binary to package: the selected target, `default-run`, a
+    /// single `[[bin]]` or `src/bin/*.rs` target, or the package name
+    /// otherwise.
+    pub fn bin_name(&self) -> String {
+        if let Some(artifact) = &self.artifact {
+            return artifact.name().to_string();
+        }
+        let package = self.manifest.package.as_ref().unwrap();
+        if let Some(default_run) = &package.default_run {
+            return default_run.clone();
+        }
+        if let [bin] = &self.manifest.bin[..] {
+            if let Some(name) = &bin.name {
+                return name.clone();
+            }
+        }
+        if !self.package_root.join("src").join("main.rs").exists() {
+            if let Ok(bins) = self.bins() {
+                if let [bin] = &bins[..] {
+                    return bin.name().to_string();
+                }
+            }
+        }
+        self.package.clone()
+    }
+
+    /// Name of the `[lib]` target, defaults to the package name.
+    pub fn lib_name(&self) -> String {
+        self.manifest
+            .lib
+            .as_ref()
+            .and_then(|lib| lib.name.clone())
+            .unwrap_or_else(|| self.package.clone())
+    }
+
     pub fn lib_search_paths(
         &self,
         target_dir: &Path,
@@ -194,7 +346,7 @@ impl Cargo {
         } else {
             target_dir.join(target.rust_triple()?)
         };
-        let opt_dir = arch_dir.join(target.opt().to_string());
+        let opt_dir = arch_dir.join(self.profile_dir(target.opt()));
         let build_deps_dir = opt_dir.join("build");
 
         let mut paths = vec![];
@@ -234,18 +386,26 @@ pub struct CargoBuild {
     cmd: Command,
     target: CompileTarget,
     triple: Option<&'static str>,
+    /// Target spec json passed to `--target` instead of the triple.
+    target_spec: Option<PathBuf>,
+    target_dir: PathBuf,
     c_flags: String,
     cxx_flags: String,
     rust_flags: String,
+    /// Variables of the generated cmake toolchain file, empty unless an sdk
+    /// is used.
+    cmake_vars: Vec<(&'static str, String)>,
+    /// Runs cargo in a container instead of on the host.
+    container: Option<Container>,
 }
 
 impl CargoBuild {
     fn new(
+        subcommand: &str,
+        cargo: &Cargo,
         target: CompileTarget,
-        features: &[String],
-        root_dir: &Path,
+        toolchain: Option<&str>,
         target_dir: &Path,
-        offline: bool,
     ) -> Result<Self> {
         let triple = if target.is_host()? {
             None
@@ -253,54 +413,73 @@ impl CargoBuild {
             Some(target.rust_triple()?)
         };
         let mut cmd = Command::new("cargo");
-        cmd.current_dir(root_dir);
-        cmd.arg("build");
+        cmd.current_dir(cargo.package_root());
+        if let Some(toolchain) = toolchain {
+            cmd.arg(format!("+{}", toolchain));
+        }
+        cmd.arg(subcommand);
         cmd.arg("--target-dir").arg(target_dir);
-        if target.opt() == Opt::Release {
+        if let Some(profile) = &cargo.profile {
+            cmd.arg("--profile").arg(profile);
+        } else if target.opt() == Opt::Release {
             cmd.arg("--release");
         }
-        if let Some(triple) = triple.as_ref() {
-            cmd.arg("--target").arg(triple);
-        }
-        if offline {
+        if cargo.offline {
             cmd.arg("--offline");
         }
-        for features in features {
+        for features in &cargo.features {
             cmd.arg("--features").arg(features);
         }
         Ok(Self {
             cmd,
             target,
             triple,
+            target_spec: None,
+            target_dir: target_dir.to_path_buf(),
             c_flags: Default::default(),
             cxx_flags: Default::default(),
             rust_flags: Default::default(),
+            cmake_vars: Default::default(),
+            container: None,
         })
     }
 
-    pub fn use_android_ndk(
-        &mut self,
-        path: &Path,
-        target_sdk_version: u32,
-        target: &CompileTarget,
-    ) -> Result<()> {
+    /// Compiles and links native code against the ndk for `api_level`, the
+    /// min sdk version of the app.
+    pub fn use_android_ndk(&mut self, path: &Path, api_level: u32) -> Result<()> {
         let path = dunce::canonicalize(path)?;
         let ndk_triple = self.target.ndk_triple();
         self.cfg_tool(Tool::Cc, "clang");
         self.cfg_tool(Tool::Cxx, "clang++");
         self.cfg_tool(Tool::Ar, "llvm-ar");
+        self.cfg_tool(Tool::Ranlib, "llvm-ranlib");
         self.cfg_tool(Tool::Linker, "clang");
         self.set_sysroot(&path);
         self.add_cxxflag("-stdlib=libc++");
+        // The api level in the clang target selects the headers and defines
+        // `__ANDROID_API__`, the target cc passes has none
+        let clang_target = match self.target.arch() {
+            Arch::Arm => "armv7a-linux-androideabi",
+            _ => ndk_triple,
+        };
+        let clang_target = format!("{}{}", clang_target, api_level);
+        self.add_cflag(&format!("--target={}", clang_target));
+        self.cmake_system("Linux", &clang_target);
+        // The ndk sysroot doesn't include the cmake support of a full ndk, so
+        // the variables android projects check are set manually
+        self.cmake_var("ANDROID", "TRUE");
+        self.cmake_var("ANDROID_ABI", self.target.android_abi().android_abi());
+        self.cmake_var("ANDROID_PLATFORM", format!("android-{}", api_level));
+        self.cmake_var("ANDROID_NATIVE_API_LEVEL", api_level.to_string());
         let lib_dir = path.join("usr").join("lib").join(ndk_triple);
-        let sdk_lib_dir = lib_dir.join(target_sdk_version.to_string());
+        let sdk_lib_dir = lib_dir.join(api_level.to_string());
         anyhow::ensure!(
             sdk_lib_dir.exists(),
-            "ndk doesn't support sdk version {}",
-            target_sdk_version
+            "ndk doesn't support api level {}",
+            api_level
         );
         self.use_ld("lld");
-        self.add_link_arg(&format!("--target={}", target.rust_triple()?));
+        self.add_link_arg(&format!("--target={}", clang_target));
         self.add_link_arg(&format!("-B{}", sdk_lib_dir.display()));
         self.add_link_arg(&format!("-L{}", sdk_lib_dir.display()));
         self.add_link_arg(&format!("-L{}", lib_dir.display()));
@@ -309,6 +488,18 @@ impl CargoBuild {
 
     pub fn use_windows_sdk(&mut self, path: &Path) -> Result<()> {
         let path = dunce::canonicalize(path)?;
+        let (triple, arch) = match self.target.arch() {
+            Arch::Arm64 => ("aarch64-pc-windows-msvc", "aarch64"),
+            Arch::X64 => ("x86_64-pc-windows-msvc", "x86_64"),
+            arch => anyhow::bail!("the windows sdk doesn't support {}", arch),
+        };
+        let crt = path.join("crt").join("lib").join(arch);
+        anyhow::ensure!(
+            crt.exists(),
+            "the windows sdk in {} has no {} libraries, build on a windows host",
+            path.display(),
+            arch
+        );
         self.cfg_tool(Tool::Cc, "clang");
         self.cfg_tool(Tool::Cxx, "clang++");
         self.cfg_tool(Tool::Ar, "llvm-lib");
@@ -316,13 +507,14 @@ impl CargoBuild {
         self.use_ld("lld-link");
         self.add_target_feature("+crt-static");
         self.add_cxxflag("-stdlib=libc++");
+        self.cmake_system("Windows", triple);
         self.add_include_dir(&path.join("crt").join("include"));
         self.add_include_dir(&path.join("sdk").join("include").join("um"));
         self.add_include_dir(&path.join("sdk").join("include").join("ucrt"));
         self.add_include_dir(&path.join("sdk").join("include").join("shared"));
-        self.add_lib_dir(&path.join("crt").join("lib").join("x86_64"));
-        self.add_lib_dir(&path.join("sdk").join("lib").join("um").join("x86_64"));
-        self.add_lib_dir(&path.join("sdk").join("lib").join("ucrt").join("x86_64"));
+        self.add_lib_dir(&crt);
+        self.add_lib_dir(&path.join("sdk").join("lib").join("um").join(arch));
+        self.add_lib_dir(&path.join("sdk").join("lib").join("ucrt").join(arch));
         Ok(())
     }
 
@@ -331,11 +523,21 @@ impl CargoBuild {
         self.cfg_tool(Tool::Cc, "clang");
         self.cfg_tool(Tool::Cxx, "clang++");
         self.cfg_tool(Tool::Ar, "llvm-ar");
+        self.cfg_tool(Tool::Ranlib, "llvm-ranlib");
         self.cfg_tool(Tool::Linker, "clang");
         self.use_ld("lld");
         self.set_sysroot(&path);
         self.add_cxxflag("-stdlib=libc++");
         self.add_cflag(&format!("-mmacosx-version-min={}", minimum_version));
+        // Read by cc and rustc
+        self.cmd.env("MACOSX_DEPLOYMENT_TARGET", minimum_version);
+        let clang_target = match self.target.arch() {
+            Arch::Arm64 => "arm64-apple-macosx",
+            _ => "x86_64-apple-macosx",
+        };
+        self.cmake_system("Darwin", clang_target);
+        self.cmake_var("CMAKE_OSX_SYSROOT", path.display().to_string());
+        self.cmake_var("CMAKE_OSX_DEPLOYMENT_TARGET", minimum_version);
         self.add_link_arg("--target=x86_64-apple-darwin");
         self.add_link_arg(&format!("-mmacosx-version-min={}", minimum_version));
         self.add_link_arg("-rpath");
@@ -352,6 +554,36 @@ impl CargoBuild {
         Ok(())
     }
 
+    /// Compiles and links against the glibc sysroot at `path` with clang
+    /// and lld, for building linux apps on other hosts.
+    pub fn use_linux_sysroot(&mut self, path: &Path) -> Result<()> {
+        let path = dunce::canonicalize(path)?;
+        let clang_target = match self.target.arch() {
+            Arch::Arm64 => "aarch64-linux-gnu",
+            Arch::X64 => "x86_64-linux-gnu",
+            arch => anyhow::bail!("no linux sysroot for {}", arch),
+        };
+        self.cfg_tool(Tool::Cc, "clang");
+        self.cfg_tool(Tool::Cxx, "clang++");
+        self.cfg_tool(Tool::Ar, "llvm-ar");
+        self.cfg_tool(Tool::Ranlib, "llvm-ranlib");
+        self.cfg_tool(Tool::Linker, "clang");
+        self.use_ld("lld");
+        self.set_sysroot(&path);
+        self.add_cflag(&format!("--target={}", clang_target));
+        self.cmake_system("Linux", clang_target);
+        self.add_link_arg(&format!("--target={}", clang_target));
+        Ok(())
+    }
+
+    /// Builds for the ios simulator with the compilers and sdk of xcode.
+    pub fn use_ios_simulator(&mut self, minimum_version: &str) -> Result<()> {
+        self.triple = Some(self.target.ios_simulator_triple()?);
+        // Read by cc and rustc
+        self.cmd.env("IPHONEOS_DEPLOYMENT_TARGET", minimum_version);
+        Ok(())
+    }
+
     pub fn use_ios_sdk(&mut self, path: &Path, minimum_version: &str) -> Result<()> {
         let path = dunce::canonicalize(path)?;
         // on macos it is picked up via xcrun. on other platforms setting SDKROOT prevents
@@ -361,11 +593,17 @@ impl CargoBuild {
         self.cfg_tool(Tool::Cc, "clang");
         self.cfg_tool(Tool::Cxx, "clang++");
         self.cfg_tool(Tool::Ar, "llvm-ar");
+        self.cfg_tool(Tool::Ranlib, "llvm-ranlib");
         self.cfg_tool(Tool::Linker, "clang");
         self.use_ld("lld");
         self.set_sysroot(&path);
         self.add_cxxflag("-stdlib=libc++");
         self.add_cflag(&format!("-miphoneos-version-min={}", minimum_version));
+        // Read by cc and rustc
+        self.cmd.env("IPHONEOS_DEPLOYMENT_TARGET", minimum_version);
+        self.cmake_system("iOS", "arm64-apple-ios");
+        self.cmake_var("CMAKE_OSX_SYSROOT", path.display().to_string());
+        self.cmake_var("CMAKE_OSX_DEPLOYMENT_TARGET", minimum_version);
         self.add_link_arg("--target=arm64-apple-ios");
         self.add_link_arg(&format!("-miphoneos-version-min={}", minimum_version));
         self.add_link_arg("-rpath");
@@ -376,16 +614,37 @@ impl CargoBuild {
     }
 
     pub fn cfg_tool<P: AsRef<Path>>(&mut self, tool: Tool, path: P) {
+        let path = path.as_ref().to_str().unwrap();
+        let cmake_var = match tool {
+            Tool::Cc => Some("CMAKE_C_COMPILER"),
+            Tool::Cxx => Some("CMAKE_CXX_COMPILER"),
+            Tool::Ar => Some("CMAKE_AR"),
+            Tool::Ranlib => Some("CMAKE_RANLIB"),
+            Tool::Linker => None,
+        };
+        if let Some(var) = cmake_var {
+            self.cmake_var(var, path);
+        }
         match tool {
-            Tool::Cc | Tool::Cxx | Tool::Ar => {
-                self.cc_triple_env(&tool.to_string(), path.as_ref().to_str().unwrap());
+            Tool::Cc | Tool::Cxx | Tool::Ar | Tool::Ranlib => {
+                self.cc_triple_env(&tool.to_string(), path);
             }
             Tool::Linker => {
-                self.cargo_target_env("LINKER", path.as_ref().to_str().unwrap());
+                self.cargo_target_env("LINKER", path);
             }
         }
     }
 
+    /// Overrides the linker of the target.
+    pub fn set_linker(&mut self, linker: &str) {
+        if self.triple.is_some() {
+            self.cfg_tool(Tool::Linker, linker);
+        } else {
+            // Host builds have no `CARGO_TARGET_<triple>_LINKER` to set
+            self.add_rustflag(&format!("-Clinker={}", linker));
+        }
+    }
+
     /// Configures a cargo target specific environment variable.
     fn cargo_target_env(&mut self, name: &str, value: &str) {
         if let Some(triple) = self.triple {
@@ -406,6 +665,100 @@ impl CargoBuild {
         }
     }
 
+    /// Routes the `cc` crate compilers through `x cc-cache`, `exe` being the
+    /// path of the xbuild executable.
+    pub fn use_compiler_cache(&mut self, exe: &Path) {
+        for (tool, default) in [(Tool::Cc, "cc"), (Tool::Cxx, "c++")] {
+            let name = if let Some(triple) = self.triple {
+                format!("{}_{}", tool, triple)
+            } else {
+                tool.to_string()
+            };
+            let compiler = self
+                .cmd
+                .get_envs()
+                .find(|(key, _)| *key == OsStr::new(&name))
+                .and_then(|(_, value)| value)
+                .map(|value| value.to_str().unwrap().to_string())
+                .or_else(|| std::env::var(&name).ok())
+                .unwrap_or_else(|| default.to_string());
+            let wrapper = format!("{} cc-cache {}", exe.display(), compiler);
+            self.cc_triple_env(&tool.to_string(), &wrapper);
+        }
+        let launcher = format!("{};cc-cache", exe.display());
+        self.cmake_var("CMAKE_C_COMPILER_LAUNCHER", launcher.clone());
+        self.cmake_var("CMAKE_CXX_COMPILER_LAUNCHER", launcher);
+    }
+
+    /// Sets the system name and compiler target of the cmake toolchain.
+    fn cmake_system(&mut self, name: &str, clang_target: &str) {
+        let processor = match self.target.arch() {
+            Arch::Arm => "armv7-a",
+            Arch::Arm64 if self.target.platform() == Platform::Android => "aarch64",
+            Arch::Arm64 => "arm64",
+            Arch::X64 => "x86_64",
+            Arch::Wasm32 => "wasm32",
+        };
+        self.cmake_var("CMAKE_SYSTEM_NAME", name);
+        self.cmake_var("CMAKE_SYSTEM_PROCESSOR", processor);
+        self.cmake_var("CMAKE_C_COMPILER_TARGET", clang_target);
+        self.cmake_var("CMAKE_CXX_COMPILER_TARGET", clang_target);
+    }
+
+    fn cmake_var(&mut self, name: &'static str, value: impl Into<String>) {
+        let value = value.into();
+        if let Some(var) = self.cmake_vars.iter_mut().find(|(var, _)| *var == name) {
+            var.1 = value;
+        } else {
+            self.cmake_vars.push((name, value));
+        }
+    }
+
+    /// Writes a cmake toolchain file for the sdk in use, which the `cmake`
+    /// crate picks up through `CMAKE_TOOLCHAIN_FILE_<target>`.
+    fn write_cmake_toolchain(&mut self) -> Result<()> {
+        let triple = if let Some(triple) = self.triple {
+            triple
+        } else {
+            return Ok(());
+        };
+        let env = format!("CMAKE_TOOLCHAIN_FILE_{}", triple);
+        if self.cmake_vars.is_empty() || std::env::var_os(env).is_some() {
+            return Ok(());
+        }
+        let mut toolchain = String::new();
+        for (name, value) in &self.cmake_vars {
+            toolchain.push_str(&format!("set({} \"{}\")\n", name, cmake_escape(value)));
+        }
+        let c_flags = self.c_flags.trim().to_string();
+        let cxx_flags = format!("{}{}", self.c_flags, self.cxx_flags);
+        toolchain.push_str(&format!(
+            "set(CMAKE_C_FLAGS_INIT \"{}\")\n",
+            cmake_escape(&c_flags)
+        ));
+        toolchain.push_str(&format!(
+            "set(CMAKE_CXX_FLAGS_INIT \"{}\")\n",
+            cmake_escape(cxx_flags.trim())
+        ));
+        // Linking is done by rustc, so the compiler checks don't link
+        toolchain.push_str("set(CMAKE_TRY_COMPILE_TARGET_TYPE STATIC_LIBRARY)\n");
+        if self
+            .cmake_vars
+            .iter()
+            .any(|(name, _)| *name == "CMAKE_SYSROOT")
+        {
+            toolchain.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n");
+            toolchain.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)\n");
+            toolchain.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)\n");
+            toolchain.push_str("set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)\n");
+        }
+        std::fs::create_dir_all(&self.target_dir)?;
+        let path = self.target_dir.join("toolchain.cmake");
+        std::fs::write(&path, toolchain)?;
+        self.cc_triple_env("CMAKE_TOOLCHAIN_FILE", path.to_str().unwrap());
+        Ok(())
+    }
+
     pub fn add_lib_dir(&mut self, path: &Path) {
         self.rust_flags
             .push_str(&format!("-Lnative={} ", path.display()));
@@ -446,6 +799,7 @@ impl CargoBuild {
         let arg = format!("--sysroot={}", path.display());
         self.add_cflag(&arg);
         self.add_link_arg(&arg);
+        self.cmake_var("CMAKE_SYSROOT", path.display().to_string());
     }
 
     pub fn add_cflag(&mut self, flag: &str) {
@@ -462,20 +816,144 @@ impl CargoBuild {
         self.add_link_arg(&format!("-fuse-ld={}", name));
     }
 
+    pub fn add_rustflag(&mut self, flag: &str) {
+        self.rust_flags.push_str(flag);
+        self.rust_flags.push(' ');
+    }
+
     pub fn arg(&mut self, arg: &str) {
         self.cmd.arg(arg);
     }
 
-    pub fn exec(mut self) -> Result<()> {
+    pub fn env(&mut self, name: &str, value: &str) {
+        self.cmd.env(name, value);
+    }
+
+    /// Runs cargo in `container`, see [`Container::wrap`].
+    pub fn use_container(&mut self, container: Container) {
+        self.container = Some(container);
+    }
+
+    /// Builds the standard library `crates` from source with `features`,
+    /// needs a nightly toolchain.
+    pub fn use_build_std(&mut self, crates: &[String], features: &[String]) {
+        self.cmd.arg(format!("-Zbuild-std={}", crates.join(",")));
+        if !features.is_empty() {
+            self.cmd
+                .arg(format!("-Zbuild-std-features={}", features.join(",")));
+        }
+    }
+
+    /// Passes the target spec json at `path` to `--target` instead of the
+    /// triple. Cargo names the output dir after the file, so it must be
+    /// named after the triple.
+    pub fn set_target_spec(&mut self, path: &Path) -> Result<()> {
+        let triple = self
+            .triple
+            .context("target specs are only supported when cross compiling")?;
+        anyhow::ensure!(
+            path.file_name() == Some(OsStr::new(&format!("{}.json", triple))),
+            "the target spec of {} must be named {}.json, found {}",
+            triple,
+            triple,
+            path.display()
+        );
+        self.target_spec = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn set_flags_env(&mut self) -> Result<()> {
+        if let Some(spec) = &self.target_spec {
+            self.cmd.arg("--target").arg(spec);
+        } else if let Some(triple) = self.triple {
+            self.cmd.arg("--target").arg(triple);
+        }
         self.cargo_target_env("RUSTFLAGS", &self.rust_flags.clone());
         self.cc_triple_env("CFLAGS", &self.c_flags.clone());
         // These strings already end with a space if they're non-empty:
         self.cc_triple_env("CXXFLAGS", &format!("{}{}", self.c_flags, self.cxx_flags));
-        if !self.cmd.status()?.success() {
-            std::process::exit(1);
+        if !self.c_flags.is_empty() {
+            // Target, sysroot and includes for the clang of bindgen
+            self.cc_triple_env("BINDGEN_EXTRA_CLANG_ARGS", &self.c_flags.clone());
+        }
+        self.write_cmake_toolchain()?;
+        if let Some(container) = &self.container {
+            self.cmd = container.wrap(&self.cmd)?;
         }
         Ok(())
     }
+
+    pub fn exec(mut self) -> Result<()> {
+        self.set_flags_env()?;
+        anyhow::ensure!(self.cmd.status()?.success(), "cargo build failed");
+        Ok(())
+    }
+
+    /// Compiles the test harnesses without running them and returns the paths
+    /// of the test executables. With `--message-format json` the messages of
+    /// cargo are passed through on stdout like the ones of a build, otherwise
+    /// the rendered diagnostics are printed.
+    pub fn test_executables(mut self, message_format: MessageFormat) -> Result<Vec<PathBuf>> {
+        self.set_flags_env()?;
+        self.cmd
+            .arg("--no-run")
+            .arg("--message-format")
+            .arg("json-diagnostic-rendered-ansi")
+            .stdout(Stdio::piped());
+        let output = self.cmd.spawn()?.wait_with_output()?;
+        let mut executables = vec![];
+        for line in std::str::from_utf8(&output.stdout)?.lines() {
+            let message: serde_json::Value = serde_json::from_str(line)?;
+            if message_format == MessageFormat::Json {
+                println!("{}", line);
+            }
+            match message["reason"].as_str() {
+                Some("compiler-artifact") if message["profile"]["test"] == true => {
+                    if let Some(executable) = message["executable"].as_str() {
+                        executables.push(PathBuf::from(executable));
+                    }
+                }
+                Some("compiler-message") if message_format == MessageFormat::Human => {
+                    if let Some(rendered) = message["message"]["rendered"].as_str() {
+                        eprint!("{}", rendered);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !output.status.success() {
+            std::process::exit(1);
+        }
+        Ok(executables)
+    }
+
+    /// Environment cargo would be run with, for build scripts and tools
+    /// invoked outside of xbuild. `CARGO_BUILD_TARGET` is set when cross
+    /// compiling.
+    pub fn into_env(mut self) -> Result<Vec<(String, String)>> {
+        self.container = None;
+        self.set_flags_env()?;
+        let mut env = self
+            .cmd
+            .get_envs()
+            .filter_map(|(name, value)| {
+                Some((name.to_str()?.to_string(), value?.to_str()?.to_string()))
+            })
+            .collect::<Vec<_>>();
+        if let Some(spec) = &self.target_spec {
+            env.push(("CARGO_BUILD_TARGET".into(), spec.display().to_string()));
+        } else if let Some(triple) = self.triple {
+            env.push(("CARGO_BUILD_TARGET".into(), triple.to_string()));
+        }
+        env.sort();
+        Ok(env)
+    }
+
+    /// Starts the build without waiting for it to finish.
+    pub fn spawn(mut self) -> Result<Child> {
+        self.set_flags_env()?;
+        Ok(self.cmd.spawn()?)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -484,6 +962,7 @@ pub enum Tool {
     Cxx,
     Linker,
     Ar,
+    Ranlib,
 }
 
 impl std::fmt::Display for Tool {
@@ -493,6 +972,66 @@ impl std::fmt::Display for Tool {
             Self::Cxx => write!(f, "CXX"),
             Self::Linker => write!(f, "LINKER"),
             Self::Ar => write!(f, "AR"),
+            Self::Ranlib => write!(f, "RANLIB"),
         }
     }
 }
+
+fn cmake_escape(value: &str) -> String {
+    value.replace('\\', "/").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cargo(name: &str) -> Cargo {
+        let dir = std::env::temp_dir().join(format!("xbuild-cargo-{}", name));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"example\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("src").join("lib.rs"), "").unwrap();
+        Cargo::new(
+            None,
+            vec![],
+            None,
+            Some(dir.join("Cargo.toml")),
+            None,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_into_env() {
+        let cargo = cargo("env");
+        let target_dir = cargo.target_dir().join("x");
+        let target = CompileTarget::new(Platform::Android, Arch::Arm64, Opt::Debug);
+        let mut build = cargo.build(target, None, &target_dir).unwrap();
+        build.add_cflag("--target=aarch64-linux-android21");
+        build.set_linker("clang");
+        let env = build.into_env().unwrap();
+        let var = |name: &str| {
+            env.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(var("CARGO_BUILD_TARGET"), Some("aarch64-linux-android"));
+        assert_eq!(
+            var("CFLAGS_aarch64-linux-android").map(str::trim),
+            Some("--target=aarch64-linux-android21")
+        );
+        assert_eq!(
+            var("BINDGEN_EXTRA_CLANG_ARGS_aarch64-linux-android"),
+            var("CFLAGS_aarch64-linux-android")
+        );
+        assert_eq!(
+            var("CARGO_TARGET_AARCH64_LINUX_ANDROID_LINKER"),
+            Some("clang")
+        );
+        assert!(env.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -93,14 +93,56 @@ Alternatively, to keep it out of the workspace, add an empty `[workspace]` table
             )
         }
         // Otherwise use the manifest we just found, as long as it contains `[package]`
-        None => {
-            anyhow::ensure!(
-                potential_manifest.package.is_some(),
-                "Failed to parse manifest at `{}`: virtual manifests must be configured with `[workspace]`",
-                potential_manifest_path.display(),
-            );
+        None if potential_manifest.package.is_some() => {
             Ok((potential_manifest_path, potential_manifest))
         }
+        // In the root of a virtual workspace pick the only default member, or the only member
+        // with an xbuild `manifest.yaml`
+        None => {
+            let default_members = &workspace_manifest
+                .workspace
+                .as_ref()
+                .unwrap()
+                .default_members;
+            let mut candidates = workspace_members
+                .into_iter()
+                .filter(|(manifest_dir, _)| {
+                    default_members.is_empty()
+                        || default_members
+                            .iter()
+                            .any(|member| workspace_manifest_dir.join(member) == *manifest_dir)
+                })
+                .collect::<Vec<_>>();
+            if candidates.len() > 1 {
+                let configured = candidates
+                    .iter()
+                    .filter(|(manifest_dir, _)| manifest_dir.join("manifest.yaml").exists())
+                    .count();
+                if configured > 0 {
+                    candidates
+                        .retain(|(manifest_dir, _)| manifest_dir.join("manifest.yaml").exists());
+                }
+            }
+            match candidates.len() {
+                1 => Ok(candidates.pop().unwrap().1),
+                0 => anyhow::bail!(
+                    "workspace `{}` has no members",
+                    workspace_manifest_path.display(),
+                ),
+                _ => {
+                    let mut names = candidates
+                        .iter()
+                        .map(|(_, (_, manifest))| manifest.package.as_ref().unwrap().name.as_str())
+                        .collect::<Vec<_>>();
+                    names.sort_unstable();
+                    anyhow::bail!(
+                        "workspace `{}` has multiple packages, use `-p` to select one of: {}",
+                        workspace_manifest_path.display(),
+                        names.join(", "),
+                    )
+                }
+            }
+        }
     }
 }
 
@@ -1,59 +1,217 @@
 use crate::cargo::CrateType;
 use crate::download::DownloadManager;
-use crate::task::TaskRunner;
-use crate::{BuildEnv, Format, Opt, Platform};
+use crate::l10n::Translations;
+use crate::message::Message;
+use crate::packager::{CompiledArtifact, PackageInput, Packager};
+use crate::sbom::Sbom;
+use crate::task::{Cache, TaskRunner};
+use crate::uniffi::Language;
+use crate::web::WebSite;
+use crate::{BuildEnv, CompileTarget, Format, MessageFormat, Opt, Platform};
 use anyhow::{Context, Result};
 use apk::Apk;
 use appbundle::AppBundle;
 use appimage::AppImage;
-use msix::Msix;
+use msix::{Msix, MsixBundle};
+use path_slash::PathExt;
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::path::Path;
-use xcommon::{Zip, ZipFileOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use xcommon::{CertificateInfo, Compression, Icon, ZipFileOptions};
 
 pub fn build(env: &BuildEnv) -> Result<()> {
+    build_phases(env, true, true)
+}
+
+/// Only compiles the rust artifacts, without packaging them.
+pub fn compile(env: &BuildEnv) -> Result<()> {
+    build_phases(env, true, false)
+}
+
+/// Packages and signs previously compiled rust artifacts found in the build
+/// dir, which may have been copied from another machine.
+pub fn bundle(env: &BuildEnv) -> Result<()> {
+    build_phases(env, false, true)
+}
+
+fn build_phases(env: &BuildEnv, compile: bool, bundle: bool) -> Result<()> {
     let platform_dir = env.platform_dir();
     std::fs::create_dir_all(&platform_dir)?;
 
-    let mut runner = TaskRunner::new(3, env.verbose());
+    let platform = env.target().platform();
+    let pre_build = if compile {
+        env.config().pre_build(platform)
+    } else {
+        vec![]
+    };
+    let post_build = if bundle {
+        env.config().post_build(platform)
+    } else {
+        vec![]
+    };
+    // Also validates the name template before building
+    let artifact_path = env.artifact_path()?;
+    let num_tasks = 1
+        + compile as u32
+        + bundle as u32
+        + (bundle && env.config().sbom(platform)) as u32
+        + (bundle && artifact_path.is_some()) as u32
+        + !pre_build.is_empty() as u32
+        + !post_build.is_empty() as u32;
+    let mut runner = TaskRunner::new(num_tasks, env.verbose(), env.message_format());
+    if env.reproducible()
+        && bundle
+        && matches!(platform, Platform::Macos | Platform::Ios)
+        && env.target().signer().is_some()
+    {
+        log::warn!(
+            "apple code signatures contain the signing time, only unsigned builds are reproducible"
+        );
+    }
 
     runner.start_task("Fetch precompiled artifacts");
-    let manager = DownloadManager::new(env)?;
-    if !env.offline() {
-        manager.prefetch()?;
-        runner.end_verbose_task();
+    // In offline mode this only checks that everything was vendored
+    let downloads = DownloadManager::new(env)?;
+    downloads.prefetch()?;
+    for (component, time, cache) in downloads.take_fetches() {
+        runner.record_stage(format!("Fetch {}", component), time, cache);
+    }
+    runner.end_task();
+
+    if !pre_build.is_empty() {
+        runner.start_verbose_task("Run pre-build hooks");
+        run_hooks(env, &pre_build)?;
+        runner.end_task();
     }
 
-    runner.start_task(format!("Build rust `{}`", env.name));
     let bin_target = env.target().platform() != Platform::Android;
-    let has_lib = env.root_dir().join("src").join("lib.rs").exists();
-    if bin_target || has_lib {
-        if env.target().platform() == Platform::Android && env.config().android().gradle {
-            crate::gradle::prepare(env)?;
-        }
-        for target in env.target().compile_targets() {
-            let arch_dir = platform_dir.join(target.arch().to_string());
-            let mut cargo = env.cargo_build(target, &arch_dir.join("cargo"))?;
-            if !bin_target {
-                cargo.arg("--lib");
+    // Android examples are built as a `cdylib` and packaged like the lib
+    let has_lib = env.root_dir().join("src").join("lib.rs").exists()
+        || (!bin_target && env.cargo().selected_artifact().is_some());
+    if compile {
+        runner.start_verbose_task(format!("Build rust `{}`", env.name));
+        if bin_target || has_lib {
+            if env.target().platform() == Platform::Android && env.target().gradle() {
+                crate::gradle::prepare(env)?;
+            }
+            let mut targets = vec![];
+            let mut builds = vec![];
+            for target in env.target().compile_targets() {
+                let arch_dir = platform_dir.join(target.arch().to_string());
+                let mut cargo = env.cargo_build(target, &arch_dir.join("cargo"))?;
+                if !bin_target && env.cargo().selected_artifact().is_none() {
+                    cargo.arg("--lib");
+                }
+                targets.push(target);
+                builds.push(cargo);
             }
-            cargo.exec()?;
+            let crate_type = if bin_target {
+                CrateType::Bin
+            } else {
+                CrateType::Cdylib
+            };
+            // The artifact is left untouched if cargo had nothing to rebuild
+            let modified = |target: CompileTarget| {
+                let target_dir = platform_dir.join(target.arch().to_string()).join("cargo");
+                let artefact = env.cargo_artefact(&target_dir, target, crate_type).ok()?;
+                std::fs::metadata(artefact).ok()?.modified().ok()
+            };
+            let before = targets
+                .iter()
+                .map(|target| modified(*target))
+                .collect::<Vec<_>>();
+            let start = Instant::now();
+            let mut times = vec![None; builds.len()];
+            if builds.len() > 1 {
+                // Each architecture has its own target dir, so they can be built concurrently
+                let mut children = vec![];
+                for cargo in builds {
+                    match cargo.spawn() {
+                        Ok(child) => children.push(child),
+                        Err(err) => {
+                            // Keeps the started builds from writing to the
+                            // target dirs after returning
+                            for mut child in children {
+                                child.kill().ok();
+                                child.wait().ok();
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                let mut success = true;
+                while times.iter().any(Option::is_none) {
+                    for (child, time) in children.iter_mut().zip(&mut times) {
+                        if time.is_none() {
+                            if let Some(status) = child.try_wait()? {
+                                success &= status.success();
+                                *time = Some(start.elapsed());
+                            }
+                        }
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                anyhow::ensure!(success, "cargo build failed");
+            } else {
+                for (cargo, time) in builds.into_iter().zip(&mut times) {
+                    cargo.exec()?;
+                    *time = Some(start.elapsed());
+                }
+            }
+            for ((target, time), before) in targets.into_iter().zip(times).zip(before) {
+                let hit = before.is_some() && before == modified(target);
+                let stage = format!("Build {}", target.rust_triple()?);
+                runner.record_stage(stage, time.unwrap_or_default(), Cache::hit(hit));
+            }
+            runner.end_task();
         }
-        runner.end_verbose_task();
     }
 
-    runner.start_task(format!("Create {}", env.target().format()));
+    if !bundle {
+        return runner.write_timings(env);
+    }
+
+    let package = env
+        .target()
+        .packager()
+        .map(str::to_string)
+        .unwrap_or_else(|| env.target().format().to_string());
+    runner.start_task(format!("Create {}", package));
+    // Native libraries bundled into the artifact
+    let mut sbom = Sbom::default();
+    let translations = env.translations()?;
+    let icon = env.render_icon()?;
+    let splash = env.splash()?;
+    if let Some(packager) = crate::packager::find(env)? {
+        run_packager(
+            env,
+            &mut runner,
+            packager.as_ref(),
+            icon.as_ref(),
+            bin_target,
+            has_lib,
+        )?;
+        runner.end_task();
+        return finish_build(env, runner, sbom, artifact_path, &post_build);
+    }
     match env.target().platform() {
         Platform::Linux => {
             let target = env.target().compile_targets().next().unwrap();
             let arch_dir = platform_dir.join(target.arch().to_string());
 
-            let appimage = AppImage::new(&arch_dir, env.name().to_string())?;
+            let mut appimage = AppImage::new(&arch_dir, env.name().to_string())?;
+            appimage.set_deterministic(env.deterministic());
+            appimage.set_icon_cache(&env.icon_cache_dir());
             appimage.add_apprun()?;
-            appimage.add_desktop()?;
-            if let Some(icon) = env.icon() {
-                appimage.add_icon(icon)?;
+            let mut desktop = env.config().linux().desktop.clone();
+            if let Some(translations) = &translations {
+                translations.desktop_entries(&mut desktop);
+            }
+            appimage.add_desktop_entries(&desktop)?;
+            if let Some(icon) = &icon {
+                appimage.add_icon(icon.path())?;
             }
 
             let main = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Bin)?;
@@ -65,23 +223,62 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             }
 
             if env.target().format() == Format::Appimage {
-                let out = arch_dir.join(format!("{}.AppImage", env.name()));
-                appimage.build(&out, env.target().signer().cloned())?;
+                runner.stage("Create appimage");
+                if let Some(runtime) = env.appimage_runtime(target) {
+                    appimage.set_runtime(&runtime)?;
+                }
+                sbom.add_native("appimage-runtime", None, appimage.runtime());
+                appimage.build(&env.output(), env.target().signer().cloned())?;
             }
         }
         Platform::Android => {
             let out = platform_dir.join(format!("{}.{}", env.name(), env.target().format()));
-            if env.config().android().gradle {
-                crate::gradle::build(env, &out)?;
-                runner.end_verbose_task();
-                return Ok(());
+            if env.target().gradle() {
+                runner.stage("Run gradle");
+                runner.suspend(|| crate::gradle::build(env, icon.as_ref(), &out))?;
+                if has_lib {
+                    // Always included by the gradle project
+                    let ndk = env.android_ndk();
+                    let ndk_version = crate::sbom::ndk_version(&ndk);
+                    for target in env.target().compile_targets() {
+                        let cpp_shared = ndk
+                            .join("usr/lib")
+                            .join(target.ndk_triple())
+                            .join("libc++_shared.so");
+                        sbom.add_native_file(&cpp_shared, ndk_version.as_deref())?;
+                    }
+                }
             } else {
-                let mut apk = Apk::new(
-                    out,
-                    env.config().android().manifest.clone(),
-                    env.target().opt() != Opt::Debug,
-                )?;
-                apk.add_res(env.icon(), &env.android_jar())?;
+                let mut manifest = env.config().android().manifest.clone();
+                let strings = match &translations {
+                    Some(translations) => translations.android_strings(&mut manifest)?,
+                    None => vec![],
+                };
+                let mut apk = Apk::new(out, manifest, env.compression())?;
+                apk.set_deterministic(env.deterministic());
+                apk.set_icon_cache(&env.icon_cache_dir());
+                if let Some(splash) = splash {
+                    apk.set_splash(splash);
+                }
+                runner.stage("Compile resources");
+                apk.add_res(icon.as_ref(), &strings, &env.android_jar())?;
+                if let Some(cached) = apk.icons_cached() {
+                    runner.cache(Cache::hit(cached));
+                }
+                runner.stage("Generate bindings");
+                let kotlin = if has_lib {
+                    let target = env.target().compile_targets().next().unwrap();
+                    let cargo_dir = platform_dir.join(target.arch().to_string()).join("cargo");
+                    let lib = env.cargo_artefact(&cargo_dir, target, CrateType::Cdylib)?;
+                    crate::uniffi::generate(env, Language::Kotlin, &lib)?
+                } else {
+                    None
+                };
+                runner.stage("Compile java and dex");
+                if let Some((dex, cache)) = crate::jni::build(env, kotlin.as_deref())? {
+                    runner.cache(cache);
+                    apk.add_dex(&dex)?;
+                }
 
                 for asset in &env.config().android().assets {
                     let path = env.cargo().package_root().join(asset.path());
@@ -91,6 +288,7 @@ pub fn build(env: &BuildEnv) -> Result<()> {
                     }
                 }
 
+                runner.stage("Add native libraries");
                 if has_lib {
                     for target in env.target().compile_targets() {
                         let arch_dir = platform_dir.join(target.arch().to_string());
@@ -105,7 +303,7 @@ pub fn build(env: &BuildEnv) -> Result<()> {
                             } else {
                                 cargo_dir.join(target.rust_triple()?)
                             };
-                            let opt_dir = arch_dir.join(target.opt().to_string());
+                            let opt_dir = arch_dir.join(env.cargo().profile_dir(target.opt()));
                             opt_dir.join("deps")
                         };
 
@@ -130,13 +328,7 @@ pub fn build(env: &BuildEnv) -> Result<()> {
                                 // Use libraries (symbols) from the lowest NDK that is supported by the application,
                                 // to prevent inadvertently making newer APIs available:
                                 // https://developer.android.com/ndk/guides/sdk-versions
-                                env.config()
-                                    .android()
-                                    .manifest
-                                    .sdk
-                                    .min_sdk_version
-                                    .unwrap()
-                                    .to_string(),
+                                env.min_sdk_version().to_string(),
                             ),
                         ];
 
@@ -177,6 +369,9 @@ pub fn build(env: &BuildEnv) -> Result<()> {
 
                         let mut needs_cpp_shared = false;
 
+                        for lib in explicit_libs.iter().skip(1) {
+                            sbom.add_native_file(lib, None)?;
+                        }
                         for lib in explicit_libs {
                             apk.add_lib(target.android_abi(), &lib)?;
 
@@ -196,15 +391,24 @@ pub fn build(env: &BuildEnv) -> Result<()> {
                             needs_cpp_shared |= cpp_shared;
                             for lib in &extra_libs {
                                 apk.add_lib(target.android_abi(), lib)?;
+                                sbom.add_native_file(lib, None)?;
                             }
                         }
+                        if kotlin.is_some() {
+                            let jnidispatch = crate::uniffi::jnidispatch(env, target.arch())?;
+                            apk.add_lib(target.android_abi(), &jnidispatch)?;
+                            sbom.add_native_file(&jnidispatch, Some(crate::uniffi::JNA_VERSION))?;
+                        }
                         if needs_cpp_shared {
                             let cpp_shared = ndk_sysroot_libs.join("libc++_shared.so");
                             apk.add_lib(target.android_abi(), &cpp_shared)?;
+                            let ndk_version = crate::sbom::ndk_version(&ndk);
+                            sbom.add_native_file(&cpp_shared, ndk_version.as_deref())?;
                         }
                     }
                 }
 
+                finish_stage(env, &mut runner);
                 apk.finish(env.target().signer().cloned())?;
             }
         }
@@ -213,8 +417,14 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             let arch_dir = platform_dir.join(target.arch().to_string());
 
             let mut app = AppBundle::new(&arch_dir, env.config().macos().info.clone())?;
-            if let Some(icon) = env.icon() {
-                app.add_icon(icon)?;
+            app.set_icon_cache(&env.icon_cache_dir());
+            app.add_info_plist_keys(env.config().macos().info_plist.clone());
+            add_info_plist_strings(&mut app, translations.as_ref())?;
+            if let Some(entitlements) = env.config().macos().entitlements.as_ref() {
+                app.add_entitlements(&env.root_dir().join(entitlements))?;
+            }
+            if let Some(icon) = &icon {
+                app.add_icon(icon.path())?;
             }
 
             let main = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Bin)?;
@@ -223,19 +433,27 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             if has_lib {
                 let lib = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Cdylib)?;
                 app.add_lib(&lib)?;
+                crate::uniffi::generate(env, Language::Swift, &lib)?;
+            } else {
+                crate::uniffi::generate(env, Language::Swift, &main)?;
             }
 
+            finish_stage(env, &mut runner);
             app.finish(env.target().signer().cloned())?;
-            if let Some(api_key) = env.target().api_key() {
-                appbundle::notarize(app.appdir(), api_key)?;
+            if let Some(api_key) = env.target().api_key()? {
+                runner.stage("Notarize");
+                appbundle::notarize(app.appdir(), api_key.path())?;
             }
             if env.target().format() == Format::Dmg {
+                runner.stage("Create dmg");
                 let out = arch_dir.join(format!("{}.dmg", env.name()));
                 apple_dmg::create_dmg(app.appdir(), &out, env.name(), 0x40000)?;
                 if let Some(signer) = env.target().signer() {
+                    runner.stage("Sign dmg");
                     app.sign_dmg(&out, signer)?;
-                    if let Some(api_key) = env.target().api_key() {
-                        appbundle::notarize(&out, api_key)?;
+                    if let Some(api_key) = env.target().api_key()? {
+                        runner.stage("Notarize dmg");
+                        appbundle::notarize(&out, api_key.path())?;
                     }
                 }
             }
@@ -245,76 +463,414 @@ pub fn build(env: &BuildEnv) -> Result<()> {
             let arch_dir = platform_dir.join(target.arch().to_string());
             std::fs::create_dir_all(&arch_dir)?;
             let mut app = AppBundle::new(&arch_dir, env.config().ios().info.clone())?;
-            if let Some(icon) = env.icon() {
-                app.add_icon(icon)?;
+            app.set_deterministic(env.deterministic());
+            app.set_icon_cache(&env.icon_cache_dir());
+            app.add_info_plist_keys(env.config().ios().info_plist.clone());
+            add_info_plist_strings(&mut app, translations.as_ref())?;
+            let icon = icon.as_ref().map(|icon| icon.path());
+            // A prebuilt `Assets.car` replaces the compiled one
+            let assets_car = env.config().ios().assets_car.is_some();
+            let catalog = !assets_car
+                && (icon.is_some() || splash.is_some())
+                && app.add_asset_catalog(icon, splash.as_ref(), &arch_dir)?;
+            if let Some(icon) = icon {
+                if !catalog {
+                    app.add_icon(icon)?;
+                }
             }
             let main = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Bin)?;
             app.add_executable(&main)?;
+            crate::uniffi::generate(env, Language::Swift, &main)?;
             if let Some(provisioning_profile) = env.target().provisioning_profile() {
                 app.add_provisioning_profile(provisioning_profile)?;
             }
             if let Some(assets_car) = env.config().ios().assets_car.as_ref() {
                 app.add_file(assets_car, "Assets.car".as_ref())?;
             }
+            finish_stage(env, &mut runner);
             app.finish(env.target().signer().cloned())?;
             if env.target().format() == Format::Ipa {
-                let app = arch_dir.join(format!("{}.app", env.name()));
+                runner.stage("Create ipa");
                 let out = arch_dir.join(format!("{}.ipa", env.name()));
-                let mut ipa = Zip::new(&out, false)?;
-                ipa.add_directory(
-                    &app,
-                    &Path::new("Payload").join(format!("{}.app", env.name())),
-                    ZipFileOptions::Compressed,
-                )?;
-                ipa.finish()?;
+                let compression = env
+                    .config()
+                    .compression(Platform::Ios)
+                    .unwrap_or(Compression::Stored);
+                app.ipa(&out, compression)?;
             }
         }
-        Platform::Windows => {
+        Platform::Web => {
             let target = env.target().compile_targets().next().unwrap();
             let arch_dir = platform_dir.join(target.arch().to_string());
-            std::fs::create_dir_all(&arch_dir)?;
-            let out = arch_dir.join(format!("{}.{}", env.name(), env.target().format()));
+            let site = WebSite::new(env.output(), env.name().to_string())?;
             let main = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Bin)?;
-            match env.target().format() {
-                Format::Exe => {
-                    std::fs::copy(&main, &out)?;
-                }
-                Format::Msix => {
-                    let mut msix = Msix::new(
-                        out,
-                        env.config().windows().manifest.clone(),
-                        target.opt() != Opt::Debug,
-                    )?;
-                    if let Some(icon) = env.icon() {
-                        msix.add_icon(icon)?;
+            site.add_wasm(&main)?;
+            if let Some(icon) = &icon {
+                site.add_icon(icon.path(), &env.icon_cache_dir())?;
+            }
+            let web = env.config().web();
+            let template = web
+                .index_html
+                .as_ref()
+                .map(|path| env.root_dir().join(path));
+            site.add_index_html(template.as_deref(), web.title.as_deref().unwrap())?;
+        }
+        Platform::Windows => {
+            let mut bundle = MsixBundle::new(env.output());
+            bundle.set_deterministic(env.deterministic());
+            for target in env.target().compile_targets() {
+                let arch_dir = platform_dir.join(target.arch().to_string());
+                std::fs::create_dir_all(&arch_dir)?;
+                let out = arch_dir.join(format!("{}.{}", env.name(), env.target().format()));
+                let main = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Bin)?;
+                match env.target().format() {
+                    Format::Exe => {
+                        std::fs::copy(&main, &out)?;
                     }
-                    // TODO: *.pri
-
-                    msix.add_file(
-                        &main,
-                        format!("{}.exe", env.name()).as_ref(),
-                        ZipFileOptions::Compressed,
-                    )?;
+                    Format::Msix => {
+                        let mut manifest = env.config().windows().manifest.clone();
+                        manifest
+                            .identity
+                            .processor_architecture
+                            .get_or_insert_with(|| target.msix_architecture().into());
+                        let pri = match &translations {
+                            Some(translations) => translations
+                                .msix_resources(&arch_dir.join("resources"), &mut manifest)?,
+                            None => None,
+                        };
+                        let mut msix = Msix::new(out.clone(), manifest.clone(), env.compression())?;
+                        msix.set_deterministic(env.deterministic());
+                        msix.set_icon_cache(&env.icon_cache_dir());
+                        if let Some(splash) = &splash {
+                            msix.add_splash_screen(splash)?;
+                        }
+                        if let Some(icon) = &icon {
+                            msix.add_icon(icon.path())?;
+                        }
+                        if let Some(pri) = pri {
+                            msix.add_file(
+                                &pri,
+                                "resources.pri".as_ref(),
+                                ZipFileOptions::Compressed,
+                            )?;
+                        }
 
-                    if has_lib {
-                        let lib =
-                            env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Cdylib)?;
                         msix.add_file(
-                            &lib,
-                            Path::new(lib.file_name().unwrap()),
+                            &main,
+                            format!("{}.exe", env.name()).as_ref(),
                             ZipFileOptions::Compressed,
                         )?;
-                    }
 
-                    msix.finish(env.target().signer().cloned())?;
-                }
-                _ => {
-                    anyhow::bail!("unsupported windows format");
+                        if has_lib {
+                            let lib = env.cargo_artefact(
+                                &arch_dir.join("cargo"),
+                                target,
+                                CrateType::Cdylib,
+                            )?;
+                            msix.add_file(
+                                &lib,
+                                Path::new(lib.file_name().unwrap()),
+                                ZipFileOptions::Compressed,
+                            )?;
+                        }
+
+                        finish_stage(env, &mut runner);
+                        msix.finish(env.target().signer().cloned())?;
+                        if env.is_msix_bundle() {
+                            bundle.add_package(&out, &manifest)?;
+                        }
+                    }
+                    _ => {
+                        anyhow::bail!("unsupported windows format");
+                    }
                 }
             }
+            if env.is_msix_bundle() {
+                runner.stage("Bundle");
+                bundle.finish(env.target().signer().cloned())?;
+            }
         }
     }
     runner.end_task();
 
+    finish_build(env, runner, sbom, artifact_path, &post_build)
+}
+
+/// Packages the compiled artifacts with the backend selected with
+/// `--packager` instead of the format of the platform.
+fn run_packager(
+    env: &BuildEnv,
+    runner: &mut TaskRunner,
+    packager: &dyn Packager,
+    icon: Option<&Icon>,
+    bin_target: bool,
+    has_lib: bool,
+) -> Result<()> {
+    runner.stage(format!("Run {} packager", packager.name()));
+    let mut artifacts = vec![];
+    for target in env.target().compile_targets() {
+        let cargo_dir = env.arch_dir(target.arch()).join("cargo");
+        let mut crate_types = vec![];
+        if bin_target {
+            crate_types.push(CrateType::Bin);
+        }
+        if has_lib {
+            crate_types.push(CrateType::Cdylib);
+        }
+        for crate_type in crate_types {
+            artifacts.push(CompiledArtifact {
+                target,
+                crate_type,
+                path: env.cargo_artefact(&cargo_dir, target, crate_type)?,
+            });
+        }
+    }
+    let options = &env
+        .config()
+        .packager(packager.name())
+        .map(|config| config.options.clone())
+        .unwrap_or_default();
+    let output = env.output();
+    if let Some(dir) = output.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    packager.package(&PackageInput {
+        env,
+        artifacts: &artifacts,
+        icon: icon.map(|icon| icon.path()),
+        options,
+        signer: env.target().signer(),
+        output: &output,
+    })?;
+    Ok(())
+}
+
+/// Writes the sbom, copies the artifact and reports it, then runs the
+/// post-build hooks.
+fn finish_build(
+    env: &BuildEnv,
+    mut runner: TaskRunner,
+    mut sbom: Sbom,
+    artifact_path: Option<PathBuf>,
+    post_build: &[String],
+) -> Result<()> {
+    let platform = env.target().platform();
+
+    let sbom_path = if env.config().sbom(platform) {
+        runner.start_task("Write sbom");
+        // Every architecture may pull in different crates
+        for target in env.target().compile_targets() {
+            let metadata = env
+                .cargo()
+                .metadata(target.rust_triple()?, &env.config().features(platform))?;
+            sbom.add_crate_graph(&metadata, env.cargo().package())?;
+        }
+        let path = crate::sbom::sbom_path(&env.output());
+        sbom.write(&path)?;
+        runner.end_task();
+        Some(path)
+    } else {
+        None
+    };
+
+    let artifact = if let Some(artifact) = artifact_path {
+        runner.start_task("Copy artifact");
+        copy_artifact(&env.output(), &artifact)?;
+        if let Some(sbom) = &sbom_path {
+            std::fs::copy(sbom, crate::sbom::sbom_path(&artifact))?;
+        }
+        runner.end_task();
+        artifact
+    } else {
+        env.output()
+    };
+
+    let sha256 = if env.reproducible() {
+        Some(artifact_hash(&artifact)?)
+    } else {
+        None
+    };
+    if env.message_format() == MessageFormat::Json {
+        let signing = signing(env, &artifact)?;
+        Message::Artifact {
+            path: &artifact,
+            signed: signing.is_some(),
+            debug_signed: signing
+                .as_ref()
+                .map(|(_, debug)| *debug)
+                .unwrap_or_default(),
+            signer: signing
+                .as_ref()
+                .and_then(|(info, _)| info.as_ref())
+                .map(|info| info.sha256.as_str()),
+            sbom: sbom_path.as_deref(),
+            sha256: sha256.as_deref(),
+        }
+        .emit();
+    } else if let Some(sha256) = &sha256 {
+        // Same format as `sha256sum`
+        println!("{}  {}", sha256, artifact.display());
+        if let Some(sbom) = &sbom_path {
+            println!("{}  {}", artifact_hash(sbom)?, sbom.display());
+        }
+    }
+
+    if !post_build.is_empty() {
+        runner.start_verbose_task("Run post-build hooks");
+        run_hooks(env, post_build)?;
+        runner.end_task();
+    }
+
+    runner.write_timings(env)
+}
+
+/// Certificate `artifact` was signed with, if it's known, and whether it's
+/// a debug certificate. `None` if the artifact is unsigned: desktop formats
+/// besides msix are only signed with a signer, apks and msix packages get a
+/// debug certificate without one. Gradle signs debug builds with the debug
+/// keystore and leaves release builds unsigned, packager backends sign on
+/// their own.
+fn signing(env: &BuildEnv, artifact: &Path) -> Result<Option<(Option<CertificateInfo>, bool)>> {
+    if env.target().packager().is_some() {
+        return Ok(None);
+    }
+    let signer = env.target().signer();
+    let configured = signer
+        .map(|signer| CertificateInfo::new(signer.cert()))
+        .transpose()?
+        .map(|info| (Some(info), false));
+    Ok(match (env.target().platform(), env.target().format()) {
+        (Platform::Android, _) if env.target().gradle() => {
+            if env.target().opt() == Opt::Debug {
+                Some((None, true))
+            } else {
+                None
+            }
+        }
+        (Platform::Android, _) => {
+            let cert = Apk::verify(artifact)?
+                .first()
+                .map(CertificateInfo::new)
+                .transpose()?;
+            Some((cert, signer.is_none()))
+        }
+        (Platform::Windows, Format::Msix) => Some(configured.unwrap_or((None, true))),
+        (Platform::Macos | Platform::Ios, _) => configured,
+        _ => None,
+    })
+}
+
+/// Hex sha256 of a file artifact, or of the relative paths and contents of
+/// the files of a directory artifact in sorted order.
+pub(crate) fn artifact_hash(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        let mut dirs = vec![path.to_path_buf()];
+        let mut files = vec![];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        for file in files {
+            let name = file.strip_prefix(path)?.to_slash_lossy();
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update(Sha256::digest(std::fs::read(&file)?));
+        }
+    } else {
+        hasher.update(std::fs::read(path)?);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Copies a file or directory artifact, replacing the previous copy.
+/// Nothing is done if `dest` is the artifact itself.
+fn copy_artifact(source: &Path, dest: &Path) -> Result<()> {
+    if dest.exists() && std::fs::canonicalize(dest)? == std::fs::canonicalize(source)? {
+        return Ok(());
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if dest.is_dir() {
+        std::fs::remove_dir_all(dest)?;
+    } else if dest.exists() {
+        std::fs::remove_file(dest)?;
+    }
+    if source.is_dir() {
+        std::fs::create_dir_all(dest)?;
+        xcommon::copy_dir_all(source, dest)?;
+    } else {
+        std::fs::copy(source, dest)?;
+    }
+    Ok(())
+}
+
+/// Writes the `InfoPlist.strings` of every locale and declares the
+/// locales in `CFBundleLocalizations`.
+fn add_info_plist_strings(app: &mut AppBundle, translations: Option<&Translations>) -> Result<()> {
+    let translations = if let Some(translations) = translations {
+        translations
+    } else {
+        return Ok(());
+    };
+    for (locale, strings) in translations.info_plist_strings() {
+        app.add_strings(&locale, "InfoPlist", &strings)?;
+    }
+    let locales = translations
+        .locales()
+        .map(|locale| plist::Value::String(locale.to_string()))
+        .collect();
+    let mut keys = plist::Dictionary::new();
+    keys.insert("CFBundleLocalizations".into(), plist::Value::Array(locales));
+    app.add_info_plist_keys(keys);
+    Ok(())
+}
+
+/// Starts the stage writing out and, given a signer, signing the artifact.
+/// Artifacts are always written and signed again, so it's a cache miss.
+fn finish_stage(env: &BuildEnv, runner: &mut TaskRunner) {
+    runner.stage(if env.target().signer().is_some() {
+        "Sign"
+    } else {
+        "Write"
+    });
+    runner.cache(Cache::Miss);
+}
+
+/// Runs user provided shell commands in the package root. The build is
+/// described to the commands through `XBUILD_*` environment variables.
+fn run_hooks(env: &BuildEnv, hooks: &[String]) -> Result<()> {
+    let triples = env
+        .target()
+        .compile_targets()
+        .map(|target| target.rust_triple())
+        .collect::<Result<Vec<_>>>()?;
+    for hook in hooks {
+        let mut cmd = crate::task::shell(hook);
+        cmd.current_dir(env.root_dir())
+            .env("XBUILD_PLATFORM", env.target().platform().to_string())
+            .env("XBUILD_FORMAT", env.target().format().to_string())
+            .env("XBUILD_OPT", env.target().opt().to_string())
+            .env("XBUILD_TARGET", triples.join(" "))
+            .env("XBUILD_PLATFORM_DIR", env.platform_dir())
+            .env("XBUILD_OUTPUT", env.output());
+        if let Some(epoch) = env.source_date_epoch() {
+            cmd.env("SOURCE_DATE_EPOCH", epoch.to_string());
+        }
+        crate::task::run(cmd, env.verbose(), env.message_format())
+            .with_context(|| format!("hook `{}` failed", hook))?;
+    }
     Ok(())
 }
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::process::Command;
+
+/// Compiler wrapper used for `cc` crate builds when `native_cache` is
+/// enabled. Object files of single source compilations are stored in
+/// `cache_dir` keyed by the hash of the compiler version, the arguments and
+/// the preprocessed source, everything else is passed through.
+pub fn cc_cache(cache_dir: &Path, compiler: &str, args: &[String]) -> Result<i32> {
+    let output = args.iter().position(|arg| arg == "-o");
+    let cacheable = args.iter().any(|arg| arg == "-c")
+        && !args
+            .iter()
+            .any(|arg| arg.starts_with("-M") || arg.starts_with('@') || arg == "-");
+    let output = match output {
+        Some(i) if cacheable && i + 1 < args.len() => i,
+        _ => return exec(compiler, args),
+    };
+    let out = Path::new(&args[output + 1]);
+    let mut key_args = args.to_vec();
+    key_args.drain(output..output + 2);
+
+    let version = Command::new(compiler).arg("--version").output()?;
+    let mut preprocess_args = key_args.clone();
+    preprocess_args.retain(|arg| arg != "-c");
+    let preprocessed = Command::new(compiler)
+        .args(&preprocess_args)
+        .arg("-E")
+        .output()?;
+    if !version.status.success() || !preprocessed.status.success() {
+        return exec(compiler, args);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(compiler.as_bytes());
+    hasher.update(&version.stdout);
+    for arg in &key_args {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(&preprocessed.stdout);
+    let hash = hasher.finalize();
+    let hash = hash
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    let entry = cache_dir.join(&hash[..2]).join(format!("{}.o", &hash[2..]));
+
+    if entry.exists() {
+        std::fs::copy(&entry, out)?;
+        return Ok(0);
+    }
+    let code = exec(compiler, args)?;
+    if code == 0 {
+        std::fs::create_dir_all(entry.parent().unwrap())?;
+        let tmp = entry.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::copy(out, &tmp)?;
+        std::fs::rename(&tmp, &entry)?;
+    }
+    Ok(code)
+}
+
+fn exec(compiler: &str, args: &[String]) -> Result<i32> {
+    let status = Command::new(compiler).args(args).status()?;
+    Ok(status.code().unwrap_or(1))
+}
@@ -0,0 +1,72 @@
+use crate::CleanArgs;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+pub fn clean(args: CleanArgs) -> Result<()> {
+    let cargo = args.cargo.cargo()?;
+    let build_dir = cargo.target_dir().join("x");
+    let mut paths = vec![];
+    if let Some(platform) = args.platform {
+        // One directory per profile
+        if build_dir.exists() {
+            for entry in std::fs::read_dir(&build_dir)? {
+                paths.push(entry?.path().join(platform.to_string()));
+            }
+        }
+    } else {
+        paths.push(build_dir);
+    }
+    if args.cache {
+        paths.push(crate::cache_dir());
+    }
+
+    let mut total = 0;
+    for path in paths.iter().filter(|path| path.exists()) {
+        let size = dir_size(path)?;
+        total += size;
+        if args.dry_run {
+            println!("Would remove {} ({})", path.display(), format_size(size));
+        } else {
+            println!("Removing {} ({})", path.display(), format_size(size));
+            std::fs::remove_dir_all(path)?;
+        }
+    }
+    if args.dry_run {
+        println!("Would free {}", format_size(total));
+    } else {
+        println!("Freed {}", format_size(total));
+    }
+    Ok(())
+}
+
+/// Size of all files in `path`, without following symlinks.
+pub(super) fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut dirs: Vec<PathBuf> = vec![path.into()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.path().symlink_metadata()?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+pub(super) fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -1,4 +1,11 @@
+use crate::config::Config;
+use crate::devices::Device;
+use crate::download::DownloadManager;
+use crate::task::Cache;
+use crate::{BuildArgs, BuildEnv, Platform};
 use anyhow::Result;
+use clap::Parser;
+use serde::Serialize;
 use std::path::PathBuf;
 use std::process::Command;
 
@@ -13,55 +20,85 @@ impl Default for Doctor {
             groups: vec![
                 Group {
                     name: "clang/llvm toolchain",
+                    platforms: &[],
                     checks: vec![
-                        Check::new("clang", Some(VersionCheck::new("--version", 0, 2))),
-                        Check::new("clang++", Some(VersionCheck::new("--version", 0, 2))),
-                        Check::new("llvm-ar", None),
-                        Check::new("llvm-lib", None),
-                        Check::new("llvm-readobj", Some(VersionCheck::new("--version", 1, 4))),
-                        Check::new("lld", Some(VersionCheck::new("-flavor ld --version", 0, 1))),
-                        Check::new("lld-link", Some(VersionCheck::new("--version", 0, 1))),
-                        Check::new("lldb", Some(VersionCheck::new("--version", 0, 2))),
-                        Check::new("lldb-server", None),
+                        Check::new("clang", Some(VersionCheck::new("--version", 0, 2)))
+                            .hint("install llvm"),
+                        Check::new("clang++", Some(VersionCheck::new("--version", 0, 2)))
+                            .hint("install llvm"),
+                        Check::new("llvm-ar", None).hint("install llvm"),
+                        Check::new("llvm-lib", None).hint("install llvm"),
+                        Check::new("llvm-readobj", Some(VersionCheck::new("--version", 1, 4)))
+                            .hint("install llvm"),
+                        Check::new("lld", Some(VersionCheck::new("-flavor ld --version", 0, 1)))
+                            .hint("install lld"),
+                        Check::new("lld-link", Some(VersionCheck::new("--version", 0, 1)))
+                            .hint("install lld"),
+                        Check::new("lldb", Some(VersionCheck::new("--version", 0, 2)))
+                            .hint("install lldb"),
+                        Check::new("lldb-server", None).hint("install lldb"),
                     ],
                 },
                 Group {
                     name: "rust",
+                    platforms: &[],
                     checks: vec![
-                        Check::new("rustup", Some(VersionCheck::new("--version", 0, 1))),
-                        Check::new("cargo", Some(VersionCheck::new("--version", 0, 1))),
+                        Check::new("rustup", Some(VersionCheck::new("--version", 0, 1)))
+                            .hint("install rustup from https://rustup.rs")
+                            .env_dir("CARGO_HOME", "bin"),
+                        Check::new("cargo", Some(VersionCheck::new("--version", 0, 1)))
+                            .hint("run `rustup default stable`")
+                            .env_dir("CARGO_HOME", "bin"),
                     ],
                 },
                 Group {
                     name: "android",
+                    platforms: &[Platform::Android],
                     checks: vec![
-                        Check::new("adb", Some(VersionCheck::new("--version", 0, 4))),
-                        Check::new("javac", Some(VersionCheck::new("--version", 0, 1))),
-                        Check::new("java", Some(VersionCheck::new("--version", 0, 1))),
-                        Check::new("kotlin", Some(VersionCheck::new("-version", 0, 2))),
-                        Check::new("gradle", Some(VersionCheck::new("--version", 2, 1))),
+                        Check::new("adb", Some(VersionCheck::new("--version", 0, 4)))
+                            .hint("install the android sdk platform-tools")
+                            .env_dir("ANDROID_HOME", "platform-tools"),
+                        Check::new("javac", Some(VersionCheck::new("--version", 0, 1)))
+                            .hint("install a jdk")
+                            .env_dir("JAVA_HOME", "bin"),
+                        Check::new("java", Some(VersionCheck::new("--version", 0, 1)))
+                            .hint("install a jdk")
+                            .env_dir("JAVA_HOME", "bin"),
+                        Check::new("kotlin", Some(VersionCheck::new("-version", 0, 2)))
+                            .hint("install kotlin"),
+                        Check::new("gradle", Some(VersionCheck::new("--version", 2, 1)))
+                            .hint("install gradle"),
                     ],
                 },
                 Group {
                     name: "ios",
+                    platforms: &[Platform::Ios],
                     checks: vec![
-                        Check::new("idevice_id", Some(VersionCheck::new("-v", 0, 1))),
-                        Check::new("ideviceinfo", Some(VersionCheck::new("-v", 0, 1))),
-                        Check::new("ideviceinstaller", Some(VersionCheck::new("-v", 0, 1))),
-                        Check::new("ideviceimagemounter", Some(VersionCheck::new("-v", 0, 1))),
-                        Check::new("idevicedebug", Some(VersionCheck::new("-v", 0, 1))),
+                        Check::new("idevice_id", Some(VersionCheck::new("-v", 0, 1)))
+                            .hint("install libimobiledevice"),
+                        Check::new("ideviceinfo", Some(VersionCheck::new("-v", 0, 1)))
+                            .hint("install libimobiledevice"),
+                        Check::new("ideviceinstaller", Some(VersionCheck::new("-v", 0, 1)))
+                            .hint("install libimobiledevice"),
+                        Check::new("ideviceimagemounter", Some(VersionCheck::new("-v", 0, 1)))
+                            .hint("install libimobiledevice"),
+                        Check::new("idevicedebug", Some(VersionCheck::new("-v", 0, 1)))
+                            .hint("install libimobiledevice"),
                         Check::new(
                             "idevicedebugserverproxy",
                             Some(VersionCheck::new("-v", 0, 1)),
-                        ),
+                        )
+                        .hint("install libimobiledevice"),
                     ],
                 },
                 Group {
                     name: "linux",
+                    platforms: &[Platform::Linux],
                     checks: vec![Check::new(
                         "mksquashfs",
                         Some(VersionCheck::new("-version", 0, 2)),
-                    )],
+                    )
+                    .hint("install squashfs-tools")],
                 },
             ],
         }
@@ -80,6 +117,8 @@ impl std::fmt::Display for Doctor {
 #[derive(Clone, Debug)]
 struct Group {
     name: &'static str,
+    /// Platforms the tools are required for, all if empty
+    platforms: &'static [Platform],
     checks: Vec<Check>,
 }
 
@@ -96,6 +135,8 @@ impl std::fmt::Display for Group {
                 };
                 write!(f, "{:20}", version)?;
                 write!(f, "{}", path.display())?;
+            } else if let Some(hint) = check.hint {
+                write!(f, "not found, {}", hint)?;
             } else {
                 write!(f, "not found")?;
             }
@@ -109,11 +150,32 @@ impl std::fmt::Display for Group {
 struct Check {
     name: &'static str,
     version: Option<VersionCheck>,
+    hint: Option<&'static str>,
+    env_dir: Option<(&'static str, &'static str)>,
 }
 
 impl Check {
     pub const fn new(name: &'static str, version: Option<VersionCheck>) -> Self {
-        Self { name, version }
+        Self {
+            name,
+            version,
+            hint: None,
+            env_dir: None,
+        }
+    }
+
+    /// How to install the tool when it wasn't found.
+    pub const fn hint(mut self, hint: &'static str) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    /// Directory relative to an environment variable the tool is usually
+    /// installed to, like `bin` of `JAVA_HOME`, suggested for `PATH` when
+    /// it's found there.
+    pub const fn env_dir(mut self, var: &'static str, dir: &'static str) -> Self {
+        self.env_dir = Some((var, dir));
+        self
     }
 }
 
@@ -139,6 +201,13 @@ impl Check {
         Ok(which::which(self.name)?)
     }
 
+    /// The tool in its [`Check::env_dir`], if it's there.
+    fn env_path(self) -> Option<PathBuf> {
+        let (var, dir) = self.env_dir?;
+        let dir = PathBuf::from(std::env::var_os(var)?).join(dir);
+        which::which_in(self.name, Some(&dir), &dir).ok()
+    }
+
     fn version(self) -> Result<Option<String>> {
         if let Some(version) = self.version {
             let output = Command::new(self.name)
@@ -162,7 +231,248 @@ impl Check {
     }
 }
 
-pub fn doctor() {
-    let doctor = Doctor::default();
-    print!("{}", doctor);
+/// Machine readable result of a [`Check`].
+#[derive(Debug, Serialize)]
+struct CheckResult {
+    group: &'static str,
+    name: &'static str,
+    found: bool,
+    path: Option<PathBuf>,
+    version: Option<String>,
+    hint: Option<&'static str>,
+}
+
+impl Doctor {
+    /// Only the checks relevant for building for `platform`.
+    fn for_platform(platform: Platform) -> Self {
+        let mut doctor = Self::default();
+        doctor
+            .groups
+            .retain(|group| group.platforms.is_empty() || group.platforms.contains(&platform));
+        doctor
+    }
+
+    fn results(&self) -> Vec<CheckResult> {
+        let mut results = vec![];
+        for group in &self.groups {
+            for check in &group.checks {
+                let path = check.path().ok();
+                results.push(CheckResult {
+                    group: group.name,
+                    name: check.name(),
+                    found: path.is_some(),
+                    version: path.as_ref().and_then(|_| check.version().ok().flatten()),
+                    path,
+                    hint: check.hint,
+                });
+            }
+        }
+        results
+    }
+}
+
+/// The keystore gradle signs debug builds with, created by android studio.
+pub(super) fn debug_keystore() -> Option<PathBuf> {
+    Some(dirs::home_dir()?.join(".android").join("debug.keystore"))
+}
+
+/// Fix applied by `x doctor --fix`, or a hint when it can't be applied
+/// by x, like setting an environment variable.
+#[derive(Debug, Serialize)]
+struct FixResult {
+    name: String,
+    applied: bool,
+    message: String,
+}
+
+impl FixResult {
+    fn applied(name: impl Into<String>, message: String) -> Self {
+        Self {
+            name: name.into(),
+            applied: true,
+            message,
+        }
+    }
+
+    fn hint(name: impl Into<String>, message: String) -> Self {
+        Self {
+            name: name.into(),
+            applied: false,
+            message,
+        }
+    }
+}
+
+/// Applies the fixes that don't require installing system packages: the
+/// components x downloads for `platform` when run in a package, the android
+/// platform-tools and the debug keystore. Tools found outside of `PATH` get
+/// a hint instead.
+fn fix(
+    doctor: &Doctor,
+    platform: Option<Platform>,
+    device: Option<&Device>,
+) -> Result<Vec<FixResult>> {
+    let mut fixes = vec![];
+    if let Some(platform) = platform {
+        if std::path::Path::new("Cargo.toml").exists() {
+            let target = if let Some(device) = device {
+                ["--device".to_string(), device.to_string()]
+            } else {
+                ["--platform".to_string(), platform.to_string()]
+            };
+            let env = BuildEnv::new(BuildArgs::try_parse_from(
+                std::iter::once("doctor".to_string()).chain(target),
+            )?)?;
+            let downloads = DownloadManager::new(&env)?;
+            downloads.prefetch()?;
+            let fetched = downloads
+                .take_fetches()
+                .into_iter()
+                .filter(|(_, _, cache)| *cache == Cache::Miss)
+                .map(|(name, _, _)| name)
+                .collect::<Vec<_>>();
+            if !fetched.is_empty() {
+                fixes.push(FixResult::applied(
+                    "components",
+                    format!("downloaded {}", fetched.join(", ")),
+                ));
+            }
+            if platform == Platform::Android && which::which("adb").is_err() {
+                let adb = env.android_sdk().join("platform-tools");
+                if !adb.exists() {
+                    crate::sdkmanager::install(&downloads, "platform-tools", &[])?;
+                    fixes.push(FixResult::applied(
+                        "adb",
+                        format!("installed the platform-tools to {}", adb.display()),
+                    ));
+                }
+                if std::env::var_os("ANDROID_HOME").is_none() {
+                    fixes.push(FixResult::hint(
+                        "ANDROID_HOME",
+                        format!("set `ANDROID_HOME={}`", env.android_sdk().display()),
+                    ));
+                }
+            }
+        }
+    }
+    for check in doctor.groups.iter().flat_map(|group| &group.checks) {
+        if check.path().is_ok() {
+            continue;
+        }
+        if let Some(path) = check.env_path() {
+            fixes.push(FixResult::hint(
+                check.name(),
+                format!("add {} to `PATH`", path.parent().unwrap().display()),
+            ));
+        }
+    }
+    if let Some(keystore) = debug_keystore() {
+        if !keystore.exists() && which::which("keytool").is_ok() {
+            std::fs::create_dir_all(keystore.parent().unwrap())?;
+            let status = Command::new("keytool")
+                .arg("-genkeypair")
+                .arg("-keystore")
+                .arg(&keystore)
+                .arg("-storepass")
+                .arg("android")
+                .arg("-alias")
+                .arg("androiddebugkey")
+                .arg("-keypass")
+                .arg("android")
+                .arg("-keyalg")
+                .arg("RSA")
+                .arg("-keysize")
+                .arg("2048")
+                .arg("-validity")
+                .arg("10000")
+                .arg("-dname")
+                .arg("CN=Android Debug,O=Android,C=US")
+                .status()?;
+            anyhow::ensure!(status.success(), "failed to create {}", keystore.display());
+            fixes.push(FixResult::applied(
+                "debug keystore",
+                format!("created {}", keystore.display()),
+            ));
+        }
+    }
+    Ok(fixes)
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceCheckResult {
+    name: &'static str,
+    ok: bool,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    tools: Vec<CheckResult>,
+    device: Vec<DeviceCheckResult>,
+    fixes: Vec<FixResult>,
+}
+
+/// Checks the installed tooling, only for `platform` or the platform of
+/// `device` if given, and the requirements on the device itself.
+pub fn doctor(
+    platform: Option<Platform>,
+    device: Option<&Device>,
+    json: bool,
+    apply_fixes: bool,
+) -> Result<()> {
+    let platform = if let Some(device) = device {
+        Some(device.platform()?)
+    } else {
+        platform
+    };
+    let doctor = if let Some(platform) = platform {
+        Doctor::for_platform(platform)
+    } else {
+        Doctor::default()
+    };
+    let fixes = if apply_fixes {
+        fix(&doctor, platform, device)?
+    } else {
+        vec![]
+    };
+    let device_checks = if let Some(device) = device {
+        let min_sdk = Config::parse("manifest.yaml")?.android().min_sdk_version();
+        device
+            .checks(min_sdk)
+            .into_iter()
+            .map(|(name, result)| DeviceCheckResult {
+                name,
+                ok: result.is_ok(),
+                message: result.unwrap_or_else(|err| err.to_string()),
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    if json {
+        let report = Report {
+            tools: doctor.results(),
+            device: device_checks,
+            fixes,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", doctor);
+        if let Some(device) = device {
+            println!("{:-^1$}", device.to_string(), 60);
+            for check in &device_checks {
+                let status = if check.ok { "ok" } else { "failed" };
+                println!("{:20} {:20}{}", check.name, status, check.message);
+            }
+            println!();
+        }
+        if !fixes.is_empty() {
+            println!("{:-^1$}", "fixes", 60);
+            for fix in &fixes {
+                let status = if fix.applied { "applied" } else { "hint" };
+                println!("{:20} {:20}{}", fix.name, status, fix.message);
+            }
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,82 @@
+use crate::devices::{Avd, Simctl, VirtualDevice};
+use crate::Platform;
+use anyhow::Result;
+
+/// Android emulators first, a missing sdk or xcode just means there are
+/// none of that kind.
+fn virtual_devices() -> Result<Vec<VirtualDevice>> {
+    let mut devices = vec![];
+    if let Ok(avd) = Avd::which() {
+        avd.list(&mut devices)?;
+    }
+    if let Ok(simctl) = Simctl::which() {
+        simctl.list(&mut devices)?;
+    }
+    Ok(devices)
+}
+
+fn platform_of(name: &str) -> Result<Platform> {
+    if let Ok(avd) = Avd::which() {
+        if avd.exists(name)? {
+            return Ok(Platform::Android);
+        }
+    }
+    if let Ok(simctl) = Simctl::which() {
+        if simctl.exists(name)? {
+            return Ok(Platform::Ios);
+        }
+    }
+    anyhow::bail!("no emulator or simulator named {}", name);
+}
+
+pub fn list_emulators() -> Result<()> {
+    for device in virtual_devices()? {
+        println!(
+            "{:40}{:10}{:10}{}",
+            device.name,
+            device.platform.to_string(),
+            if device.running { "running" } else { "stopped" },
+            device.id.as_deref().unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+pub fn create_emulator(
+    name: &str,
+    platform: Platform,
+    image: Option<&str>,
+    device_type: Option<&str>,
+) -> Result<()> {
+    match platform {
+        Platform::Android => Avd::create(name, image, device_type)?,
+        Platform::Ios => {
+            anyhow::ensure!(image.is_none(), "--image only applies to android");
+            let udid = Simctl::which()?.create(name, device_type)?;
+            println!("created simulator {}", udid);
+        }
+        _ => anyhow::bail!("emulators are only available for android and ios"),
+    }
+    Ok(())
+}
+
+pub fn start_emulator(name: &str, headless: bool) -> Result<()> {
+    match platform_of(name)? {
+        Platform::Android => {
+            let serial = Avd::which()?.start(name, headless)?;
+            println!("{} is running, use `--device adb:{}`", name, serial);
+        }
+        _ => {
+            let udid = Simctl::which()?.start(name, headless)?;
+            println!("{} is running as {}", name, udid);
+        }
+    }
+    Ok(())
+}
+
+pub fn stop_emulator(name: &str) -> Result<()> {
+    match platform_of(name)? {
+        Platform::Android => Avd::which()?.stop(name),
+        _ => Simctl::which()?.stop(name),
+    }
+}
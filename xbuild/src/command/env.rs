@@ -0,0 +1,31 @@
+use crate::download::DownloadManager;
+use crate::{BuildEnv, MessageFormat};
+use anyhow::Result;
+
+/// Prints the compilers, linker and flags the rust code of the selected
+/// target is built with, as `export` statements or as a json object with
+/// `--message-format json`.
+pub fn env(env: &BuildEnv) -> Result<()> {
+    let targets = env.target().compile_targets().collect::<Vec<_>>();
+    // The variables of different targets can't be exported at once
+    anyhow::ensure!(
+        targets.len() == 1,
+        "{} targets are selected, select one with `--arch`",
+        targets.len()
+    );
+    let target = targets[0];
+    DownloadManager::new(env)?.prefetch()?;
+    let target_dir = env.arch_dir(target.arch()).join("cargo");
+    let vars = env.cargo_build(target, &target_dir)?.into_env()?;
+    if env.message_format() == MessageFormat::Json {
+        let vars = vars
+            .into_iter()
+            .collect::<std::collections::BTreeMap<_, _>>();
+        println!("{}", serde_json::to_string_pretty(&vars)?);
+    } else {
+        for (name, value) in vars {
+            println!("export {}='{}'", name, value.replace('\'', "'\\''"));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use crate::config::{Config, ListingConfig};
+use crate::{MetadataArgs, Store};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Character limits of the listing fields, checked before exporting.
+const PLAY_LIMITS: &[(&str, usize)] = &[
+    ("title", 30),
+    ("short_description", 80),
+    ("description", 4000),
+    ("changelog", 500),
+];
+const APPLE_LIMITS: &[(&str, usize)] = &[
+    ("title", 30),
+    ("subtitle", 30),
+    ("description", 4000),
+    ("keywords", 100),
+    ("promotional_text", 170),
+    ("changelog", 4000),
+];
+
+/// Writes `publish.metadata` as the directory structure fastlane `supply`
+/// (google play) and `deliver` (app store) upload listings from.
+pub fn export_metadata(args: MetadataArgs) -> Result<()> {
+    let cargo = args.cargo.cargo()?;
+    let root = cargo.package_root();
+    let config = Config::parse(root.join("manifest.yaml"))?;
+    let metadata = &config.publish().metadata;
+    anyhow::ensure!(
+        !metadata.is_empty(),
+        "no store listings configured in `publish.metadata`"
+    );
+    let stores = match args.store {
+        Some(store @ (Store::Play | Store::Apple)) => vec![store],
+        Some(store) => anyhow::bail!("no fastlane metadata for the {} store", store),
+        None => vec![Store::Play, Store::Apple],
+    };
+    let out = args.output.unwrap_or_else(|| root.join("fastlane"));
+    for (locale, listing) in metadata {
+        for store in &stores {
+            let limits = match store {
+                Store::Play => PLAY_LIMITS,
+                _ => APPLE_LIMITS,
+            };
+            for (name, limit) in limits {
+                if let Some(text) = field(listing, name) {
+                    let len = text.trim().chars().count();
+                    anyhow::ensure!(
+                        len <= *limit,
+                        "`publish.metadata.{}.{}` has {} characters, the {} store allows {}",
+                        locale,
+                        name,
+                        len,
+                        store,
+                        limit
+                    );
+                }
+            }
+            match store {
+                Store::Play => export_play(root, &out, locale, listing)?,
+                _ => export_apple(root, &out, locale, listing)?,
+            }
+        }
+    }
+    println!("exported store metadata to {}", out.display());
+    Ok(())
+}
+
+fn field<'a>(listing: &'a ListingConfig, name: &str) -> Option<&'a str> {
+    match name {
+        "title" => listing.title.as_deref(),
+        "subtitle" => listing.subtitle.as_deref(),
+        "short_description" => listing.short_description.as_deref(),
+        "description" => listing.description.as_deref(),
+        "keywords" => listing.keywords.as_deref(),
+        "promotional_text" => listing.promotional_text.as_deref(),
+        "changelog" => listing.changelog.as_deref(),
+        _ => None,
+    }
+}
+
+/// `metadata/android/<locale>`, see `fastlane supply init`.
+fn export_play(root: &Path, out: &Path, locale: &str, listing: &ListingConfig) -> Result<()> {
+    let dir = out.join("metadata").join("android").join(locale);
+    recreate_dir(&dir)?;
+    write_text(&dir.join("title.txt"), listing.title.as_deref())?;
+    write_text(
+        &dir.join("short_description.txt"),
+        listing.short_description.as_deref(),
+    )?;
+    write_text(
+        &dir.join("full_description.txt"),
+        listing.description.as_deref(),
+    )?;
+    write_text(
+        &dir.join("changelogs").join("default.txt"),
+        listing.changelog.as_deref(),
+    )?;
+    for (device, screenshots) in &listing.screenshots {
+        let name = match device.as_str() {
+            "phone" => "phoneScreenshots",
+            "tablet" => "tenInchScreenshots",
+            "small-tablet" => "sevenInchScreenshots",
+            "tv" => "tvScreenshots",
+            "wear" => "wearScreenshots",
+            _ => anyhow::bail!("unknown screenshot device `{}`", device),
+        };
+        let images = dir.join("images").join(name);
+        for (i, screenshot) in screenshots.iter().enumerate() {
+            copy_screenshot(root, screenshot, &images, &format!("{:02}", i + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// `metadata/<locale>` and `screenshots/<locale>`, see `fastlane deliver
+/// init`. Deliver detects the device of a screenshot from its size.
+fn export_apple(root: &Path, out: &Path, locale: &str, listing: &ListingConfig) -> Result<()> {
+    let dir = out.join("metadata").join(locale);
+    recreate_dir(&dir)?;
+    write_text(&dir.join("name.txt"), listing.title.as_deref())?;
+    write_text(&dir.join("subtitle.txt"), listing.subtitle.as_deref())?;
+    write_text(&dir.join("description.txt"), listing.description.as_deref())?;
+    write_text(&dir.join("keywords.txt"), listing.keywords.as_deref())?;
+    write_text(
+        &dir.join("promotional_text.txt"),
+        listing.promotional_text.as_deref(),
+    )?;
+    write_text(&dir.join("release_notes.txt"), listing.changelog.as_deref())?;
+    let screenshots_dir = out.join("screenshots").join(locale);
+    recreate_dir(&screenshots_dir)?;
+    for (device, screenshots) in &listing.screenshots {
+        if device == "tv" || device == "wear" {
+            continue;
+        }
+        for (i, screenshot) in screenshots.iter().enumerate() {
+            let name = format!("{:02}_{}", i + 1, device);
+            copy_screenshot(root, screenshot, &screenshots_dir, &name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the output of a previous export, so removed screenshots don't
+/// get uploaded.
+fn recreate_dir(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    std::fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+fn write_text(path: &Path, text: Option<&str>) -> Result<()> {
+    if let Some(text) = text {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, format!("{}\n", text.trim()))?;
+    }
+    Ok(())
+}
+
+fn copy_screenshot(root: &Path, screenshot: &Path, dir: &Path, name: &str) -> Result<()> {
+    let source = root.join(screenshot);
+    let ext = source.extension().unwrap_or_default().to_string_lossy();
+    let mut dest = PathBuf::from(dir).join(name);
+    dest.set_extension(&*ext);
+    std::fs::create_dir_all(dir)?;
+    std::fs::copy(&source, &dest)
+        .with_context(|| format!("failed to copy screenshot {}", source.display()))?;
+    Ok(())
+}
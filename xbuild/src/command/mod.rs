@@ -1,20 +1,57 @@
 use crate::cargo::CrateType;
-use crate::devices::Device;
-use crate::{BuildEnv, CompileTarget, Platform};
-use anyhow::Result;
+use crate::devices::{Device, LogFilter, LogOutput, PortForward, Simctl};
+use crate::error::no_device;
+use crate::message::Message;
+use crate::{BuildEnv, CompileTarget, MessageFormat, Platform};
+use anyhow::{Context, Result};
 use app_store_connect::UnifiedApiKey;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 mod build;
+mod cc_cache;
+mod clean;
 mod doctor;
+mod emulator;
+mod env;
+mod metadata;
 mod new;
+mod profile;
+mod publish;
+mod signers;
+mod symbolicate;
+mod test;
+mod update;
+mod vendor;
+mod version;
+mod watch;
 
-pub use build::build;
+pub use build::{build, bundle, compile};
+pub use cc_cache::cc_cache;
+pub use clean::clean;
 pub use doctor::doctor;
-pub use new::new;
+pub use emulator::{create_emulator, list_emulators, start_emulator, stop_emulator};
+pub use env::env;
+pub use metadata::export_metadata;
+pub use new::{new, Template};
+pub use profile::profile;
+pub use publish::publish;
+pub use signers::{default_signer, signers};
+pub use symbolicate::symbolicate;
+pub use test::test;
+pub use update::update;
+pub use vendor::vendor;
+pub use version::{version, Bump};
+pub use watch::watch;
 
-pub fn devices() -> Result<()> {
-    for device in Device::list()? {
+pub fn devices(json: bool) -> Result<()> {
+    let devices = Device::list()?;
+    if json {
+        let devices = devices.iter().map(Device::info).collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+    for device in devices {
         println!(
             "{:50}{:20}{:20}{}",
             device.to_string(),
@@ -26,22 +63,107 @@ pub fn devices() -> Result<()> {
     Ok(())
 }
 
-pub fn run(env: &BuildEnv) -> Result<()> {
+pub fn pair_device(address: &str, code: &str) -> Result<()> {
+    Device::pair(address, code)?;
+    println!(
+        "paired with {}, connect to the address shown under wireless debugging with `x devices connect`",
+        address
+    );
+    Ok(())
+}
+
+pub fn connect_device(address: &str) -> Result<()> {
+    let device = Device::connect(address)?;
+    println!("connected, use `--device {}`", device);
+    Ok(())
+}
+
+pub fn disconnect_device(address: &str) -> Result<()> {
+    Device::disconnect(address)
+}
+
+/// Runs the app on the selected devices, `forward` adds to the
+/// `forward_ports` of the config.
+pub fn run(env: &BuildEnv, log_filters: &[LogFilter], forward: &[PortForward]) -> Result<()> {
     let out = env.executable();
-    if let Some(device) = env.target().device() {
-        device.run(env, &out)?;
+    let mut ports = env.config().forward_ports(env.target().platform());
+    ports.extend_from_slice(forward);
+    let ports = &ports;
+    let devices = env.target().devices();
+    if env.target().platform() == Platform::Web {
+        crate::web::serve(&out, 8080)?;
+    } else if devices.len() > 1 {
+        // Runs on every device at once, the output of each is prefixed
+        let errors = std::thread::scope(|s| {
+            let handles = devices
+                .iter()
+                .map(|device| {
+                    let out = &out;
+                    s.spawn(move || {
+                        let id = device.to_string();
+                        run_on(env, device, out, log_filters, ports, Some(&id))
+                            .with_context(|| format!("failed to run on {}", id))
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .zip(devices)
+                .filter_map(|(handle, device)| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("running on {} panicked", device)))
+                        .err()
+                })
+                .collect::<Vec<_>>()
+        });
+        for error in &errors {
+            eprintln!("{:?}", error);
+        }
+        anyhow::ensure!(
+            errors.is_empty(),
+            "failed to run on {} of {} devices",
+            errors.len(),
+            devices.len()
+        );
+    } else if let Some(device) = env.target().device() {
+        run_on(env, device, &out, log_filters, ports, None)?;
     } else {
-        anyhow::bail!("no device specified");
+        return Err(no_device());
     }
     Ok(())
 }
 
-pub fn lldb(env: &BuildEnv) -> Result<()> {
+fn run_on(
+    env: &BuildEnv,
+    device: &Device,
+    out: &Path,
+    log_filters: &[LogFilter],
+    ports: &[PortForward],
+    prefix: Option<&str>,
+) -> Result<()> {
+    let id = device.to_string();
+    device.forward_ports(ports)?;
+    let json = env.message_format() == MessageFormat::Json;
+    if json {
+        Message::Launch {
+            device: &id,
+            path: out,
+        }
+        .emit();
+    }
+    device.run(env, out, log_filters, prefix)?;
+    if json {
+        Message::Exit { device: &id }.emit();
+    }
+    Ok(())
+}
+
+pub fn lldb(env: &BuildEnv, attach: Option<&str>) -> Result<()> {
     if let Some(device) = env.target().device() {
         let target = CompileTarget::new(device.platform()?, device.arch()?, env.target().opt());
         let cargo_dir = env
-            .build_dir()
-            .join(target.opt().to_string())
+            .opt_dir()
             .join(target.platform().to_string())
             .join(target.arch().to_string())
             .join("cargo");
@@ -50,19 +172,188 @@ pub fn lldb(env: &BuildEnv) -> Result<()> {
             Platform::Ios => env.output(),
             Platform::Linux => env.output().join(env.name()),
             Platform::Macos => env.executable(),
+            Platform::Web => anyhow::bail!("lldb is not supported on web"),
             Platform::Windows => todo!(),
         };
         let lldb_server = match target.platform() {
             Platform::Android => Some(env.lldb_server(target)?),
             _ => None,
         };
-        device.lldb(env, &executable, lldb_server.as_deref())?;
+        device.lldb(env, &executable, lldb_server.as_deref(), attach)?;
     } else {
-        anyhow::bail!("no device specified");
+        return Err(no_device());
     }
     Ok(())
 }
 
+/// Installs previously built artifacts, by default the output of the last
+/// build for the target.
+pub fn install(env: &BuildEnv, paths: Vec<PathBuf>) -> Result<()> {
+    let paths = if paths.is_empty() {
+        let output = env.output();
+        anyhow::ensure!(
+            output.exists(),
+            "{} doesn't exist, run `x build` first",
+            output.display()
+        );
+        vec![output]
+    } else {
+        paths
+    };
+    if let Some(device) = env.target().device() {
+        device.install(&paths)?;
+    } else {
+        return Err(no_device());
+    }
+    Ok(())
+}
+
+/// Uninstalls the app, by default the one configured in `manifest.yaml`.
+pub fn uninstall(env: &BuildEnv, id: Option<&str>) -> Result<()> {
+    let device = if let Some(device) = env.target().device() {
+        device
+    } else {
+        return Err(no_device());
+    };
+    let config = env.config();
+    let id = match (id, device.platform()?) {
+        (Some(id), _) => id,
+        (None, Platform::Android) => config.android().manifest.package.as_deref().unwrap(),
+        (None, Platform::Ios) => config
+            .ios()
+            .info
+            .cf_bundle_identifier
+            .as_deref()
+            .context("no ios bundle identifier configured")?,
+        (None, Platform::Windows) => config
+            .windows()
+            .manifest
+            .identity
+            .name
+            .as_deref()
+            .context("no msix identity name configured")?,
+        (None, _) => env.name(),
+    };
+    device.uninstall(id)?;
+    Ok(())
+}
+
+/// Streams the logs of the installed app without building or installing it.
+/// With `lldb` the debugger is attached instead, using the symbols of the
+/// last local build.
+pub fn attach(env: &BuildEnv, lldb: bool, log_filters: &[LogFilter]) -> Result<()> {
+    if let Some(device) = env.target().device() {
+        if lldb {
+            device.launch(env)?;
+            let package = env.config().android().manifest.package.as_ref().unwrap();
+            self::lldb(env, Some(package))?;
+        } else {
+            device.attach(env, log_filters)?;
+        }
+    } else {
+        return Err(no_device());
+    }
+    Ok(())
+}
+
+/// Streams the logs of the app on the device or `simulator` without
+/// rebuilding or launching it. Lines not containing `grep` are hidden and
+/// the shown ones are copied to `save`.
+pub fn logs(
+    env: &BuildEnv,
+    simulator: Option<&str>,
+    log_filters: &[LogFilter],
+    grep: Option<String>,
+    save: Option<&Path>,
+) -> Result<()> {
+    let mut output = LogOutput::new(None).grep(grep).save(save)?;
+    if let Some(simulator) = simulator {
+        Simctl::which()?.logs(simulator, env.name(), &mut output)
+    } else if let Some(device) = env.target().device() {
+        device.logs(env, log_filters, &mut output)
+    } else {
+        Err(no_device())
+    }
+}
+
+/// `<app>-<timestamp>.<extension>` in the current directory.
+fn capture_path(env: &BuildEnv, extension: &str) -> PathBuf {
+    let (year, month, day, hour, minute, second) = utc_now();
+    PathBuf::from(format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}.{}",
+        env.name(),
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        extension
+    ))
+}
+
+pub fn screenshot(env: &BuildEnv, simulator: Option<&str>, output: Option<PathBuf>) -> Result<()> {
+    let path = output.unwrap_or_else(|| capture_path(env, "png"));
+    if let Some(simulator) = simulator {
+        Simctl::which()?.screenshot(simulator, &path)?;
+    } else if let Some(device) = env.target().device() {
+        device.screenshot(&path)?;
+    } else {
+        return Err(no_device());
+    }
+    println!("saved {}", path.display());
+    Ok(())
+}
+
+pub fn push(env: &BuildEnv, local: &Path, remote: &str) -> Result<()> {
+    let device = env.target().device().ok_or_else(no_device)?;
+    device.push(env, local, remote)?;
+    println!("pushed {} to {}", local.display(), remote);
+    Ok(())
+}
+
+/// Copies `remote` to `output`, by default its file name in the current
+/// directory.
+pub fn pull(env: &BuildEnv, remote: &str, output: Option<PathBuf>) -> Result<()> {
+    let device = env.target().device().ok_or_else(no_device)?;
+    let name = remote
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(remote);
+    let path = output.unwrap_or_else(|| PathBuf::from(name));
+    device.pull(env, remote, &path)?;
+    println!("saved {}", path.display());
+    Ok(())
+}
+
+pub fn record(
+    env: &BuildEnv,
+    simulator: Option<&str>,
+    output: Option<PathBuf>,
+    duration: Duration,
+) -> Result<()> {
+    let device = env.target().device();
+    // screencapture on macos only writes quicktime movies
+    let extension = match device {
+        Some(device) if device.is_host() && cfg!(target_os = "macos") && simulator.is_none() => {
+            "mov"
+        }
+        _ => "mp4",
+    };
+    let path = output.unwrap_or_else(|| capture_path(env, extension));
+    println!("recording for {}s", duration.as_secs());
+    if let Some(simulator) = simulator {
+        Simctl::which()?.record(simulator, &path, duration)?;
+    } else if let Some(device) = device {
+        device.record(&path, duration)?;
+    } else {
+        return Err(no_device());
+    }
+    println!("saved {}", path.display());
+    Ok(())
+}
+
 pub fn create_apple_api_key(
     issuer_id: &str,
     key_id: &str,
@@ -72,3 +363,23 @@ pub fn create_apple_api_key(
     UnifiedApiKey::from_ecdsa_pem_path(issuer_id, key_id, private_key)?.write_json_file(api_key)?;
     Ok(())
 }
+
+/// Current utc time as `(year, month, day, hour, minute, second)`.
+fn utc_now() -> (i64, i64, i64, i64, i64, i64) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -1,33 +1,112 @@
 use anyhow::Result;
 use std::path::Path;
 
-pub fn new(name: &str) -> Result<()> {
+/// Project scaffold generated by `x new`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Template {
+    /// Cross platform ui app using dioxus
+    Dioxus,
+    /// Plain winit window and event loop
+    Winit,
+    /// Bevy game running fullscreen in landscape on mobile
+    Game,
+    /// Headless background service for desktop platforms
+    Service,
+    /// Library exposing a c abi for use as a plugin
+    Lib,
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Dioxus => write!(f, "dioxus"),
+            Self::Winit => write!(f, "winit"),
+            Self::Game => write!(f, "game"),
+            Self::Service => write!(f, "service"),
+            Self::Lib => write!(f, "lib"),
+        }
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        Ok(match template {
+            "dioxus" => Self::Dioxus,
+            "winit" => Self::Winit,
+            "game" => Self::Game,
+            "service" => Self::Service,
+            "lib" => Self::Lib,
+            _ => anyhow::bail!("unsupported template {}", template),
+        })
+    }
+}
+
+macro_rules! template_file {
+    ($dest:expr, $src:expr) => {
+        ($dest, &include_bytes!(concat!("../../template/", $src))[..])
+    };
+}
+
+impl Template {
+    /// Files of the template relative to the project root.
+    fn files(self) -> Vec<(&'static str, &'static [u8])> {
+        let mut files = match self {
+            Self::Dioxus => vec![
+                template_file!("Cargo.toml", "dioxus/Cargo_toml"),
+                template_file!("manifest.yaml", "dioxus/manifest.yaml"),
+                template_file!("src/lib.rs", "dioxus/lib.rs"),
+                template_file!("src/main.rs", "dioxus/main.rs"),
+                template_file!("kotlin/MainActivity.kt", "dioxus/MainActivity.kt"),
+            ],
+            Self::Winit => vec![
+                template_file!("Cargo.toml", "winit/Cargo_toml"),
+                template_file!("manifest.yaml", "winit/manifest.yaml"),
+                template_file!("icon.png", "icon.png"),
+                template_file!("src/lib.rs", "winit/lib.rs"),
+                template_file!("src/main.rs", "winit/main.rs"),
+            ],
+            Self::Game => vec![
+                template_file!("Cargo.toml", "game/Cargo_toml"),
+                template_file!("manifest.yaml", "game/manifest.yaml"),
+                template_file!("icon.png", "icon.png"),
+                template_file!("src/lib.rs", "game/lib.rs"),
+                template_file!("src/main.rs", "game/main.rs"),
+            ],
+            Self::Service => vec![
+                template_file!("Cargo.toml", "service/Cargo_toml"),
+                template_file!("manifest.yaml", "service/manifest.yaml"),
+                template_file!("icon.png", "icon.png"),
+                template_file!("src/main.rs", "service/main.rs"),
+            ],
+            Self::Lib => vec![
+                template_file!("Cargo.toml", "lib/Cargo_toml"),
+                template_file!("manifest.yaml", "lib/manifest.yaml"),
+                template_file!("src/lib.rs", "lib/lib.rs"),
+            ],
+        };
+        files.push(template_file!(".gitignore", ".gitignore"));
+        files
+    }
+}
+
+pub fn new(name: &str, template: Template) -> Result<()> {
     let root = Path::new(name);
-    let src = root.join("src");
-    let kotlin = root.join("kotlin");
+    let package = root.file_name().unwrap().to_str().unwrap();
+    let crate_name = package.replace('-', "_");
     std::fs::create_dir(root)?;
-    std::fs::create_dir(&src)?;
-    std::fs::create_dir(&kotlin)?;
-    std::fs::write(
-        root.join("Cargo.toml"),
-        include_bytes!("../../template/Cargo_toml"),
-    )?;
-    std::fs::write(
-        root.join(".gitignore"),
-        include_bytes!("../../template/.gitignore"),
-    )?;
-    std::fs::write(
-        root.join("manifest.yaml"),
-        include_bytes!("../../template/manifest.yaml"),
-    )?;
-    std::fs::write(src.join("lib.rs"), include_bytes!("../../template/lib.rs"))?;
-    std::fs::write(
-        src.join("main.rs"),
-        include_bytes!("../../template/main.rs"),
-    )?;
-    std::fs::write(
-        kotlin.join("MainActivity.kt"),
-        include_bytes!("../../template/MainActivity.kt"),
-    )?;
+    for (dest, contents) in template.files() {
+        let path = root.join(dest);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        if dest.ends_with(".png") {
+            std::fs::write(path, contents)?;
+        } else {
+            let contents = std::str::from_utf8(contents)?
+                .replace("{{name}}", package)
+                .replace("{{crate_name}}", &crate_name);
+            std::fs::write(path, contents)?;
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,137 @@
+use crate::error::no_device;
+use crate::{Arch, BuildArgs, BuildEnv, Opt, Platform, Profiler};
+use anyhow::{Context, Result};
+use inferno::collapse::{dtrace, perf, Collapse};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+pub fn profile(
+    args: BuildArgs,
+    profiler: Option<Profiler>,
+    duration: Duration,
+    flamegraph: bool,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    // Keeps symbols and line info in optimized builds
+    std::env::set_var("CARGO_PROFILE_RELEASE_DEBUG", "true");
+    let mut env = BuildEnv::new(args)?;
+    env.enable_profiling();
+    if env.target().opt() == Opt::Debug {
+        log::warn!("profiling a debug build, pass `--release` for representative results");
+    }
+    let device = if let Some(device) = env.target().device() {
+        device
+    } else {
+        return Err(no_device());
+    };
+    let platform = device.platform()?;
+    let profiler = match profiler {
+        Some(profiler) => profiler,
+        None => Profiler::default_for(platform)?,
+    };
+    anyhow::ensure!(
+        profiler.platform() == platform,
+        "{} can't profile {} apps",
+        profiler,
+        platform
+    );
+    let extension = match profiler {
+        Profiler::Simpleperf | Profiler::Perf => "perf.data",
+        Profiler::Perfetto => "perfetto-trace",
+        Profiler::Instruments => "trace",
+        Profiler::Dtrace => "stacks",
+    };
+    let out = output.unwrap_or_else(|| {
+        env.build_dir()
+            .join("profile")
+            .join(super::capture_path(&env, extension))
+    });
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    super::build(&env)?;
+    let path = match platform {
+        Platform::Linux => env.output().join(env.name()),
+        _ => env.executable(),
+    };
+    let simpleperf = match (profiler, simpleperf_dir()) {
+        (Profiler::Simpleperf, Some(dir)) => {
+            let abi = match device.arch()? {
+                Arch::Arm64 => "arm64",
+                Arch::X64 => "x86_64",
+                arch => anyhow::bail!("unsupported arch {}", arch),
+            };
+            Some(dir.join("bin").join("android").join(abi).join("simpleperf"))
+        }
+        _ => None,
+    };
+    println!("profiling for {}s", duration.as_secs());
+    device.profile(&path, profiler, simpleperf.as_deref(), duration, &out)?;
+    println!("saved {}", out.display());
+    if profiler == Profiler::Perfetto {
+        println!("open it in https://ui.perfetto.dev");
+    }
+    if flamegraph {
+        let svg = out.with_extension("svg");
+        write_flamegraph(profiler, &out, &svg)?;
+        println!("saved {}", svg.display());
+    }
+    Ok(())
+}
+
+/// The simpleperf dir of the android ndk at `ANDROID_NDK_HOME` or the newest
+/// one in `ANDROID_HOME`, the ndk downloaded by x only contains the sysroot.
+fn simpleperf_dir() -> Option<PathBuf> {
+    let ndk = std::env::var_os("ANDROID_NDK_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            let ndks = PathBuf::from(std::env::var_os("ANDROID_HOME")?).join("ndk");
+            std::fs::read_dir(ndks)
+                .ok()?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .max()
+        })?;
+    Some(ndk.join("simpleperf")).filter(|dir| dir.exists())
+}
+
+fn write_flamegraph(profiler: Profiler, trace: &Path, svg: &Path) -> Result<()> {
+    let mut folded = vec![];
+    match profiler {
+        Profiler::Simpleperf => {
+            let dir = simpleperf_dir().context(
+                "converting simpleperf traces needs the android ndk, set `ANDROID_NDK_HOME`",
+            )?;
+            // Prints the samples in the format of `perf script`
+            let output = Command::new("python3")
+                .arg(dir.join("report_sample.py"))
+                .arg("-i")
+                .arg(trace)
+                .output()
+                .context("failed to run python3")?;
+            anyhow::ensure!(output.status.success(), "report_sample.py failed");
+            perf::Folder::default().collapse(&output.stdout[..], &mut folded)?;
+        }
+        Profiler::Perf => {
+            let output = Command::new("perf")
+                .arg("script")
+                .arg("-i")
+                .arg(trace)
+                .output()?;
+            anyhow::ensure!(output.status.success(), "perf script failed");
+            perf::Folder::default().collapse(&output.stdout[..], &mut folded)?;
+        }
+        Profiler::Dtrace => {
+            let stacks = std::fs::read(trace)?;
+            dtrace::Folder::default().collapse(&stacks[..], &mut folded)?;
+        }
+        _ => anyhow::bail!("flamegraphs can't be generated from {} traces", profiler),
+    }
+    let folded = std::str::from_utf8(&folded)?;
+    let mut options = inferno::flamegraph::Options::default();
+    options.title = trace.file_name().unwrap().to_string_lossy().to_string();
+    let file = std::io::BufWriter::new(std::fs::File::create(svg)?);
+    inferno::flamegraph::from_lines(&mut options, folded.lines(), file)?;
+    Ok(())
+}
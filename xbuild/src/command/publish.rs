@@ -0,0 +1,153 @@
+use crate::publish::{AppStoreConnect, PartnerCenter, Play, ReleaseNotes, Track};
+use crate::secret::Secret;
+use crate::{BuildEnv, Format, Platform, PublishArgs, Store};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub fn publish(args: PublishArgs) -> Result<()> {
+    let rollout = args
+        .rollout
+        .map(|percent| {
+            anyhow::ensure!(
+                percent > 0.0 && percent <= 100.0,
+                "rollout must be a percentage between 0 and 100"
+            );
+            Ok(percent / 100.0)
+        })
+        .transpose()?;
+    let notes = args
+        .release_notes
+        .iter()
+        .map(|notes| {
+            let (language, path) = notes
+                .split_once('=')
+                .context("expected release notes as `<language>=<path>`")?;
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read release notes `{}`", path))?;
+            Ok(ReleaseNotes {
+                language: language.to_string(),
+                text: text.trim().to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let env = BuildEnv::new(args.build)?;
+    let notes = if notes.is_empty() {
+        env.config()
+            .publish()
+            .metadata
+            .iter()
+            .filter_map(|(locale, listing)| {
+                Some(ReleaseNotes {
+                    language: locale.clone(),
+                    text: listing.changelog.as_ref()?.trim().to_string(),
+                })
+            })
+            .collect()
+    } else {
+        notes
+    };
+    anyhow::ensure!(
+        env.target().signer().is_some(),
+        "store uploads must be signed, pass `--pem` or set `X_PEM`"
+    );
+    let config = env.config().publish();
+    let store = match (env.target().store(), env.target().platform()) {
+        (Some(store), _) => store,
+        (None, Platform::Android) => Store::Play,
+        _ => anyhow::bail!("pass `--store play`, `--store apple` or `--store microsoft`"),
+    };
+    if store != Store::Play && args.track.is_some() {
+        log::warn!("--track only applies to google play");
+    }
+    match store {
+        Store::Play => {
+            let package = env
+                .config()
+                .android()
+                .manifest
+                .package
+                .clone()
+                .context("no android package name configured")?;
+            let service_account = if let Some(secret) = &args.service_account {
+                secret.resolve(Path::new(""))
+            } else if let Some(secret) = &config.play.service_account {
+                secret.resolve(env.root_dir())
+            } else {
+                anyhow::bail!("pass `--service-account` or set `publish.play.service_account`");
+            }
+            .context("failed to read the service account key")?;
+            let track = args.track.or(config.play.track).unwrap_or(Track::Internal);
+            // Authenticate before the build so a bad key fails fast
+            let play = Play::new(&service_account)?;
+            super::build(&env)?;
+            let version_code = play.publish(&package, &env.output(), track, rollout, &notes)?;
+            println!(
+                "published {} version {} on the {} track",
+                package, version_code, track
+            );
+        }
+        Store::Apple => {
+            anyhow::ensure!(
+                env.target().format() == Format::Ipa,
+                "only ipa uploads to the app store are supported"
+            );
+            if rollout.is_some() || !notes.is_empty() {
+                log::warn!("phased releases and release notes are configured in app store connect");
+            }
+            let api_key = if let Some(api_key) = env.target().api_key()? {
+                api_key
+            } else if let Some(secret) = &config.apple.api_key {
+                secret.to_file(env.root_dir())?
+            } else {
+                anyhow::bail!("pass `--api-key` or set `publish.apple.api_key`");
+            };
+            let app_id = config
+                .apple
+                .app_id
+                .as_deref()
+                .context("set `publish.apple.app_id` to the apple id of the app")?;
+            let info = &env.config().ios().info;
+            let version = info.cf_bundle_short_version_string.as_deref().unwrap();
+            let build = info.cf_bundle_version.as_deref().unwrap();
+            let asc = AppStoreConnect::new(api_key.path())?;
+            super::build(&env)?;
+            let id = asc.upload(app_id, &env.output(), version, build)?;
+            println!(
+                "uploaded {} ({}) as build upload {}, it will show up in app store connect after processing",
+                version, build, id
+            );
+        }
+        Store::Microsoft => {
+            anyhow::ensure!(
+                env.target().format() == Format::Msix,
+                "only msix uploads to the microsoft store are supported"
+            );
+            let microsoft = &config.microsoft;
+            let tenant_id = microsoft
+                .tenant_id
+                .as_deref()
+                .context("set `publish.microsoft.tenant_id`")?;
+            let client_id = microsoft
+                .client_id
+                .as_deref()
+                .context("set `publish.microsoft.client_id`")?;
+            let app_id = microsoft
+                .app_id
+                .as_deref()
+                .context("set `publish.microsoft.app_id` to the store id of the app")?;
+            let client_secret = microsoft
+                .client_secret
+                .clone()
+                .unwrap_or_else(|| Secret::Env("X_MICROSOFT_CLIENT_SECRET".into()))
+                .resolve(env.root_dir())
+                .context("set `publish.microsoft.client_secret` or `X_MICROSOFT_CLIENT_SECRET`")?;
+            let partner_center = PartnerCenter::new(tenant_id, client_id, &client_secret)?;
+            super::build(&env)?;
+            let id = partner_center.publish(app_id, &env.output(), rollout, &notes)?;
+            println!("committed submission {} for certification", id);
+        }
+        Store::Sideload => anyhow::bail!("sideloaded apps can't be published"),
+    }
+    Ok(())
+}
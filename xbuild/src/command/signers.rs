@@ -0,0 +1,633 @@
+use super::doctor::debug_keystore;
+use super::version::{replace_line, yaml_find};
+use crate::config::Config;
+use crate::secret::Secret;
+use crate::SignersArgs;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use xcommon::{CertificateBuilder, CertificateInfo, Signer};
+
+/// Where a signing identity is stored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Source {
+    Pem,
+    Pkcs12,
+    DebugKeystore,
+    Keychain,
+    CertStore,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Pem => write!(f, "pem"),
+            Self::Pkcs12 => write!(f, "pkcs12"),
+            Self::DebugKeystore => write!(f, "debug-keystore"),
+            Self::Keychain => write!(f, "keychain"),
+            Self::CertStore => write!(f, "cert-store"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Identity {
+    source: Source,
+    /// File or store the identity was found in.
+    location: String,
+    subject: String,
+    sha256: String,
+    expires: String,
+    expired: bool,
+    default: bool,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Contents of pem identities, which may not be files.
+    #[serde(skip)]
+    pem: Option<String>,
+    #[serde(skip)]
+    password: Option<String>,
+}
+
+impl Identity {
+    fn new(source: Source, location: &str, path: Option<&Path>, info: CertificateInfo) -> Self {
+        Self {
+            source,
+            location: location.to_string(),
+            subject: info.subject.clone(),
+            expires: info.not_after.format("%Y-%m-%d").to_string(),
+            expired: info.is_expired(),
+            sha256: info.sha256,
+            default: false,
+            path: path.map(Path::to_path_buf),
+            pem: None,
+            password: None,
+        }
+    }
+
+    /// Identities xbuild can sign artifacts with, the others are only
+    /// listed.
+    fn can_sign(&self) -> bool {
+        matches!(self.source, Source::Pem | Source::Pkcs12)
+    }
+
+    fn signer(&self) -> Result<Signer> {
+        let signer = match self.source {
+            Source::Pem => Signer::new(self.pem.as_ref().unwrap())?,
+            Source::Pkcs12 => {
+                let path = self.path.as_ref().unwrap();
+                let password = self.password.as_deref().unwrap_or_default();
+                Signer::new(&pkcs12_pem(path, password, true)?)?
+            }
+            _ => anyhow::bail!("{} identities can't sign artifacts", self.source),
+        };
+        Ok(signer)
+    }
+}
+
+pub fn signers(args: SignersArgs) -> Result<()> {
+    let cargo = args.cargo.cargo()?;
+    let root = cargo.package_root();
+    let manifest = root.join("manifest.yaml");
+    let config = Config::parse(&manifest)?;
+    let mut identities = file_identities(&config, root)?;
+    system_identities(&mut identities)?;
+    let default = config.signing().default.as_deref();
+    if let Some(default) = default {
+        for identity in &mut identities {
+            identity.default = fingerprint_eq(&identity.sha256, default);
+        }
+    }
+
+    if let Some(pem) = &args.generate {
+        return generate(
+            &config,
+            root,
+            &manifest,
+            pem,
+            args.validity_days,
+            cargo.package(),
+        );
+    }
+
+    if let Some(query) = &args.set_default {
+        let identity = find(&identities, query)?;
+        anyhow::ensure!(
+            identity.can_sign(),
+            "{} identities can't sign artifacts, export `{}` as a pem and add it to `signing.pem`",
+            identity.source,
+            identity.subject
+        );
+        set_default(&manifest, &identity.sha256)?;
+        println!(
+            "{}: signing.default {} ({})",
+            manifest.display(),
+            identity.sha256,
+            identity.subject
+        );
+        return Ok(());
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&identities)?);
+        return Ok(());
+    }
+    for identity in &identities {
+        let expires = if identity.expired {
+            format!("expired {}", identity.expires)
+        } else {
+            identity.expires.clone()
+        };
+        println!(
+            "{} {:16}{:30}{:20}{:50}{}",
+            if identity.default { "*" } else { " " },
+            identity.source.to_string(),
+            identity.subject,
+            expires,
+            identity.location,
+            identity.sha256,
+        );
+    }
+    if let Some(default) = default {
+        anyhow::ensure!(
+            identities.iter().any(|identity| identity.default),
+            "the default signer {} is not available",
+            default
+        );
+    }
+    Ok(())
+}
+
+/// Signer of the identity selected with `signing.default`, if set. Files
+/// are read until one contains it, pkcs12 files only if no pem does.
+pub fn default_signer(config: &Config, root: &Path) -> Result<Option<Signer>> {
+    let default = if let Some(default) = &config.signing().default {
+        default
+    } else {
+        return Ok(None);
+    };
+    for pem in pem_files(config)? {
+        // Files that can't be read can't be the default either
+        let identities = if let Ok(identities) = pem_identities(root, &pem) {
+            identities
+        } else {
+            continue;
+        };
+        if let Some(identity) = identities
+            .into_iter()
+            .find(|identity| fingerprint_eq(&identity.sha256, default))
+        {
+            return identity.signer().map(Some);
+        }
+    }
+    if !config.signing().pkcs12.is_empty() {
+        let password = pkcs12_password(config, root)?;
+        for pkcs12 in &config.signing().pkcs12 {
+            if let Some(identity) = pkcs12_identities(root, pkcs12, &password)?
+                .into_iter()
+                .find(|identity| fingerprint_eq(&identity.sha256, default))
+            {
+                return identity.signer().map(Some);
+            }
+        }
+    }
+    anyhow::bail!(
+        "no file in `signing.pem` or `signing.pkcs12` matches the default signer {}",
+        default
+    );
+}
+
+/// Identities configured in `manifest.yaml`, including the pem files of
+/// the build types.
+fn file_identities(config: &Config, root: &Path) -> Result<Vec<Identity>> {
+    let mut identities = vec![];
+    for pem in pem_files(config)? {
+        identities.extend(pem_identities(root, &pem)?);
+    }
+    if !config.signing().pkcs12.is_empty() {
+        let password = pkcs12_password(config, root)?;
+        for pkcs12 in &config.signing().pkcs12 {
+            identities.extend(pkcs12_identities(root, pkcs12, &password)?);
+        }
+    }
+    Ok(identities)
+}
+
+/// `signing.pem` followed by the pems of the build types.
+fn pem_files(config: &Config) -> Result<Vec<Secret>> {
+    let mut pems = config.signing().pem.clone();
+    for name in config.build_types() {
+        if let Some(pem) = config.build_type(name)?.pem {
+            if !pems.contains(&pem) {
+                pems.push(pem);
+            }
+        }
+    }
+    Ok(pems)
+}
+
+fn pem_identities(root: &Path, pem: &Secret) -> Result<Vec<Identity>> {
+    let (location, path) = match pem {
+        Secret::File(path) => (path.display().to_string(), Some(root.join(path))),
+        _ => (pem.to_string(), None),
+    };
+    let contents = pem
+        .resolve(root)
+        .with_context(|| format!("failed to read the pem {}", location))?;
+    Ok(CertificateInfo::from_pem(&contents)?
+        .into_iter()
+        .map(|info| {
+            let mut identity = Identity::new(Source::Pem, &location, path.as_deref(), info);
+            identity.pem = Some(contents.clone());
+            identity
+        })
+        .collect())
+}
+
+fn pkcs12_password(config: &Config, root: &Path) -> Result<String> {
+    Ok(match &config.signing().pkcs12_password {
+        Some(secret) => secret.resolve(root)?,
+        // Unencrypted files have an empty password
+        None => std::env::var("X_PKCS12_PASSWORD").unwrap_or_default(),
+    })
+}
+
+fn pkcs12_identities(root: &Path, pkcs12: &Path, password: &str) -> Result<Vec<Identity>> {
+    let path = root.join(pkcs12);
+    Ok(
+        CertificateInfo::from_pem(&pkcs12_pem(&path, password, false)?)?
+            .into_iter()
+            .map(|info| {
+                let mut identity = Identity::new(
+                    Source::Pkcs12,
+                    &pkcs12.display().to_string(),
+                    Some(&path),
+                    info,
+                );
+                identity.password = Some(password.to_string());
+                identity
+            })
+            .collect(),
+    )
+}
+
+/// Identities of the android debug keystore and the keychain on macos or
+/// the certificate store on windows. Sources whose tools aren't installed
+/// are skipped.
+fn system_identities(identities: &mut Vec<Identity>) -> Result<()> {
+    if let Some(keystore) = debug_keystore().filter(|keystore| keystore.exists()) {
+        if which::which("keytool").is_ok() {
+            let output = Command::new("keytool")
+                .arg("-exportcert")
+                .arg("-rfc")
+                .arg("-alias")
+                .arg("androiddebugkey")
+                .arg("-keystore")
+                .arg(&keystore)
+                .arg("-storepass")
+                .arg("android")
+                .output()?;
+            anyhow::ensure!(
+                output.status.success(),
+                "keytool failed to read {}",
+                keystore.display()
+            );
+            for info in CertificateInfo::from_pem(std::str::from_utf8(&output.stdout)?)? {
+                identities.push(Identity::new(
+                    Source::DebugKeystore,
+                    &keystore.display().to_string(),
+                    None,
+                    info,
+                ));
+            }
+        }
+    }
+    if cfg!(target_os = "macos") {
+        let output = Command::new("security")
+            .arg("find-identity")
+            .arg("-v")
+            .arg("-p")
+            .arg("codesigning")
+            .output()?;
+        anyhow::ensure!(output.status.success(), "security find-identity failed");
+        // `  1) <sha1> "Developer ID Application: Name (TEAM)"`
+        let names = std::str::from_utf8(&output.stdout)?
+            .lines()
+            .filter_map(|line| line.split('"').nth(1))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        for name in names {
+            let output = Command::new("security")
+                .arg("find-certificate")
+                .arg("-a")
+                .arg("-p")
+                .arg("-c")
+                .arg(&name)
+                .output()?;
+            anyhow::ensure!(output.status.success(), "security find-certificate failed");
+            for info in CertificateInfo::from_pem(std::str::from_utf8(&output.stdout)?)? {
+                if info.subject == name && !identities.iter().any(|i| i.sha256 == info.sha256) {
+                    identities.push(Identity::new(Source::Keychain, "login", None, info));
+                }
+            }
+        }
+    }
+    if cfg!(target_os = "windows") {
+        let output = Command::new("powershell")
+            .arg("-NoProfile")
+            .arg("-Command")
+            .arg(
+                r"Get-ChildItem Cert:\CurrentUser\My -CodeSigningCert | ForEach-Object { [Convert]::ToBase64String($_.RawData) }",
+            )
+            .output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "listing the certificate store failed"
+        );
+        for line in std::str::from_utf8(&output.stdout)?.lines() {
+            let der = base64::decode(line.trim())?;
+            identities.push(Identity::new(
+                Source::CertStore,
+                r"CurrentUser\My",
+                None,
+                CertificateInfo::from_der(&der)?,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Decrypts a PKCS#12 file with openssl, returning the certificates and,
+/// if `keys` is set, the private key as pem.
+fn pkcs12_pem(path: &Path, password: &str, keys: bool) -> Result<String> {
+    anyhow::ensure!(
+        path.exists(),
+        "pkcs12 file doesn't exist {}",
+        path.display()
+    );
+    which::which("openssl").context("openssl is required to read pkcs12 files")?;
+    let mut cmd = Command::new("openssl");
+    cmd.arg("pkcs12").arg("-in").arg(path);
+    if keys {
+        cmd.arg("-nodes");
+    } else {
+        cmd.arg("-nokeys");
+    }
+    // Passed through the environment to keep it out of the process list
+    cmd.arg("-passin")
+        .arg("env:X_PKCS12_PASSWORD")
+        .env("X_PKCS12_PASSWORD", password);
+    let output = cmd.output()?;
+    anyhow::ensure!(
+        output.status.success(),
+        "failed to read {}, is `signing.pkcs12_password` set? {}",
+        path.display(),
+        std::str::from_utf8(&output.stderr)?.trim()
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Compares fingerprints ignoring case and colons, `prefix` may be
+/// abbreviated.
+fn fingerprint_eq(sha256: &str, prefix: &str) -> bool {
+    let normalize = |s: &str| s.replace(':', "").to_uppercase();
+    let prefix = normalize(prefix);
+    !prefix.is_empty() && normalize(sha256).starts_with(&prefix)
+}
+
+fn find<'a>(identities: &'a [Identity], query: &str) -> Result<&'a Identity> {
+    let mut matches = identities
+        .iter()
+        .filter(|identity| fingerprint_eq(&identity.sha256, query));
+    let identity = matches
+        .next()
+        .with_context(|| format!("no signing identity with fingerprint {}", query))?;
+    anyhow::ensure!(
+        matches.all(|other| other.sha256 == identity.sha256),
+        "fingerprint {} is ambiguous",
+        query
+    );
+    Ok(identity)
+}
+
+/// Writes a new development identity to `pem`, adds it to `signing.pem`
+/// and makes it the default.
+fn generate(
+    config: &Config,
+    root: &Path,
+    manifest: &Path,
+    pem: &Path,
+    validity_days: u32,
+    package: &str,
+) -> Result<()> {
+    let path = root.join(pem);
+    anyhow::ensure!(!path.exists(), "{} already exists", path.display());
+    let yaml = std::fs::read_to_string(manifest).unwrap_or_default();
+    let listed = config
+        .signing()
+        .pem
+        .contains(&Secret::File(pem.to_path_buf()));
+    anyhow::ensure!(
+        listed || yaml_find(&yaml, &["signing", "pem"]).is_none(),
+        "add `{}` to `signing.pem` first",
+        pem.display()
+    );
+    let builder = match &config.windows().manifest.identity.publisher {
+        // Msix packages must be signed by their publisher
+        Some(publisher) => subject(publisher)?,
+        None => CertificateBuilder::new(package),
+    };
+    let signer = builder
+        .validity_days(validity_days)
+        .code_signing(true)
+        .build()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, signer.to_pem()?)?;
+    if !listed {
+        let pem = pem.display().to_string().replace('\\', "/");
+        set_signing(manifest, "pem", &format!("pem: [\"{}\"]", pem))?;
+    }
+    let info = CertificateInfo::from_pem(&std::fs::read_to_string(&path)?)?.remove(0);
+    set_default(manifest, &info.sha256)?;
+    println!(
+        "{}: signing.default {} ({})",
+        manifest.display(),
+        info.sha256,
+        info.subject
+    );
+    Ok(())
+}
+
+/// Certificate builder for a distinguished name like
+/// `CN=Example, O=Example Inc., C=US`.
+fn subject(dn: &str) -> Result<CertificateBuilder> {
+    let mut fields = vec![];
+    for field in dn.split(',') {
+        let (key, value) = field
+            .split_once('=')
+            .with_context(|| format!("invalid publisher `{}`", dn))?;
+        fields.push((key.trim(), value.trim()));
+    }
+    let common_name = fields
+        .iter()
+        .find(|(key, _)| *key == "CN")
+        .with_context(|| format!("publisher `{}` has no CN", dn))?
+        .1;
+    let mut builder = CertificateBuilder::new(common_name);
+    for (key, value) in fields {
+        builder = match key {
+            "CN" => builder,
+            "O" => builder.organization(value),
+            "OU" => builder.organizational_unit(value),
+            "L" => builder.locality(value),
+            "S" | "ST" => builder.state(value),
+            "C" => builder.country(value),
+            _ => anyhow::bail!("unsupported `{}` in publisher `{}`", key, dn),
+        };
+    }
+    Ok(builder)
+}
+
+/// Sets `signing.default` in `manifest.yaml`, keeping the rest of the file
+/// intact.
+fn set_default(manifest: &Path, sha256: &str) -> Result<()> {
+    set_signing(manifest, "default", &format!("default: \"{}\"", sha256))
+}
+
+/// Replaces or adds the `key` of `signing` with the line `value`.
+fn set_signing(manifest: &Path, key: &str, value: &str) -> Result<()> {
+    let yaml = std::fs::read_to_string(manifest).unwrap_or_default();
+    let yaml = if let Some((line, _)) = yaml_find(&yaml, &["signing", key]) {
+        replace_line(&yaml, line, value)
+    } else if let Some((line, _)) = yaml_find(&yaml, &["signing"]) {
+        let mut lines = yaml.lines().map(str::to_string).collect::<Vec<_>>();
+        let indent = lines[line].len() - lines[line].trim_start().len();
+        lines.insert(line + 1, format!("{}  {}", " ".repeat(indent), value));
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    } else {
+        let mut out = yaml;
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&format!("signing:\n  {}\n", value));
+        out
+    };
+    std::fs::write(manifest, yaml)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEM: &str = include_str!("../../../xcommon/assets/test.pem");
+
+    fn parse_config(dir: &Path, default: &str) -> Config {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("test.pem"), PEM).unwrap();
+        let manifest = dir.join("manifest.yaml");
+        std::fs::write(
+            &manifest,
+            format!(
+                "signing:\n  pem: [missing.pem, test.pem]\n  pkcs12: [missing.p12]\n  default: \"{}\"\n",
+                default
+            ),
+        )
+        .unwrap();
+        Config::parse(manifest).unwrap()
+    }
+
+    #[test]
+    fn test_default_signer() {
+        let dir = std::env::temp_dir().join("xbuild-default-signer");
+        let sha256 = CertificateInfo::from_pem(PEM).unwrap().remove(0).sha256;
+        // The pkcs12 file doesn't exist, finding the default in a pem must
+        // not read it
+        let config = parse_config(&dir, &sha256[..11]);
+        assert!(default_signer(&config, &dir).unwrap().is_some());
+
+        let config = parse_config(&dir, "00:00");
+        let err = default_signer(&config, &dir).unwrap_err();
+        assert!(err.to_string().contains("pkcs12 file doesn't exist"));
+
+        std::fs::write(dir.join("manifest.yaml"), "").unwrap();
+        let config = Config::parse(dir.join("manifest.yaml")).unwrap();
+        assert!(default_signer(&config, &dir).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_generate() {
+        let dir = std::env::temp_dir().join("xbuild-generate-signer");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("manifest.yaml");
+        std::fs::write(
+            &manifest,
+            r#"windows:
+  manifest:
+    identity:
+      publisher: "CN=Example, O=Example Inc., C=US"
+    properties: {}
+    resources: { resource: [] }
+    dependencies: { target_device_family: [] }
+    capabilities: []
+    applications: { application: [] }
+signing:
+  pkcs12: []
+"#,
+        )
+        .unwrap();
+        let config = Config::parse(&manifest).unwrap();
+        generate(
+            &config,
+            &dir,
+            &manifest,
+            Path::new("keys/dev.pem"),
+            30,
+            "example",
+        )
+        .unwrap();
+
+        let config = Config::parse(&manifest).unwrap();
+        assert_eq!(
+            config.signing().pem,
+            vec![Secret::File("keys/dev.pem".into())]
+        );
+        let signer = default_signer(&config, &dir).unwrap().unwrap();
+        let info = CertificateInfo::from_pem(&signer.to_pem().unwrap())
+            .unwrap()
+            .remove(0);
+        assert_eq!(info.subject, "Example");
+        assert!(generate(
+            &config,
+            &dir,
+            &manifest,
+            Path::new("keys/dev.pem"),
+            30,
+            "example"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_subject() {
+        assert!(subject("CN=Example, O=Example Inc., S=Some-State, C=US").is_ok());
+        assert!(subject("O=Example Inc.").is_err());
+        assert!(subject("CN=Example, E=mail@example.com").is_err());
+        assert!(subject("Example").is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_eq() {
+        assert!(fingerprint_eq("AB:CD:EF", "abcd"));
+        assert!(fingerprint_eq("AB:CD:EF", "ab:cd:ef"));
+        assert!(!fingerprint_eq("AB:CD:EF", "cd"));
+        assert!(!fingerprint_eq("AB:CD:EF", ":"));
+    }
+}
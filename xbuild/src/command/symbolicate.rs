@@ -0,0 +1,579 @@
+use crate::cargo::CrateType;
+use crate::{BuildEnv, Platform};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Resolves the native frames of a crash report against the unstripped
+/// binaries of the last local build. Supports android tombstones and logcat
+/// crash dumps, apple `.ips` and `.crash` reports and minidumps.
+pub fn symbolicate(env: &BuildEnv, report: &Path) -> Result<()> {
+    let data =
+        std::fs::read(report).with_context(|| format!("failed to read `{}`", report.display()))?;
+    let binaries = binaries(env);
+    anyhow::ensure!(
+        !binaries.is_empty(),
+        "no build output found for {}, build the crashing version first",
+        env.target().platform()
+    );
+    if data.starts_with(b"MDMP") {
+        return minidump(env, report, &binaries);
+    }
+    let text = String::from_utf8_lossy(&data);
+    if let Some(ips) = Ips::parse(&text) {
+        ips.symbolicate(&binaries)
+    } else if text.contains("Binary Images:") {
+        apple_crash(&text, &binaries)
+    } else {
+        tombstone(&text, &binaries)
+    }
+}
+
+/// Unstripped binaries of all targets by file name. Cargo places a dSYM next
+/// to apple binaries when debug info is split.
+fn binaries(env: &BuildEnv) -> HashMap<String, PathBuf> {
+    let mut binaries = HashMap::new();
+    for target in env.target().compile_targets() {
+        let crate_type = match target.platform() {
+            Platform::Android => CrateType::Cdylib,
+            _ => CrateType::Bin,
+        };
+        let cargo_dir = env.arch_dir(target.arch()).join("cargo");
+        if let Ok(binary) = env.cargo_artefact(&cargo_dir, target, crate_type) {
+            let name = binary.file_name().unwrap().to_str().unwrap().to_string();
+            let dsym = binary
+                .with_extension("dSYM")
+                .join("Contents")
+                .join("Resources")
+                .join("DWARF")
+                .join(&name);
+            let binary = if dsym.exists() { dsym } else { binary };
+            binaries.insert(name, binary);
+        }
+    }
+    binaries
+}
+
+/// Parses `#00 pc 000000000004a2b8  /data/app/.../libfoo.so (...)`.
+fn tombstone_frame(line: &str) -> Option<(u64, &str)> {
+    let frame = &line[line.find('#')?..];
+    let mut fields = frame.split_whitespace();
+    fields.next()?;
+    if fields.next()? != "pc" {
+        return None;
+    }
+    let pc = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let path = fields.next()?;
+    Some((pc, path.rsplit('/').next().unwrap()))
+}
+
+/// Parses the `(BuildId: 9f2c...)` at the end of tombstone frames.
+fn tombstone_build_id(line: &str) -> Option<&str> {
+    let start = line.find("(BuildId: ")? + "(BuildId: ".len();
+    let end = start + line[start..].find(')')?;
+    Some(&line[start..end])
+}
+
+fn tombstone(text: &str, binaries: &HashMap<String, PathBuf>) -> Result<()> {
+    let mut frames = vec![];
+    for line in text.lines() {
+        if let Some((pc, name)) = tombstone_frame(line) {
+            if let Some(binary) = binaries.get(name) {
+                check_build_id(name, binary, tombstone_build_id(line))?;
+                frames.push((pc, name));
+            }
+        }
+    }
+    anyhow::ensure!(
+        !frames.is_empty(),
+        "no frames of {} in the crash report",
+        binaries.keys().cloned().collect::<Vec<_>>().join(", ")
+    );
+    let mut resolved = HashMap::new();
+    for (name, binary) in binaries {
+        let pcs = frames
+            .iter()
+            .filter(|(_, frame)| frame == name)
+            .map(|(pc, _)| *pc)
+            .collect::<Vec<_>>();
+        for (pc, symbol) in pcs.iter().zip(llvm_symbolizer(binary, &pcs)?) {
+            resolved.insert((*pc, name.as_str()), symbol);
+        }
+    }
+    for line in text.lines() {
+        println!("{}", line);
+        if let Some(symbol) = tombstone_frame(line).and_then(|frame| resolved.get(&frame)) {
+            for symbol in symbol {
+                println!("        {}", symbol);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses `3   MyApp   0x0000000104a5c3d0 0x104a54000 + 33744`.
+fn apple_frame(line: &str) -> Option<(&str, u64, u64)> {
+    let mut fields = line.split_whitespace();
+    fields.next()?.parse::<u32>().ok()?;
+    let image = fields.next()?;
+    let address = parse_hex(fields.next()?)?;
+    let load_address = parse_hex(fields.next()?)?;
+    Some((image, address, load_address))
+}
+
+fn parse_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex.strip_prefix("0x")?, 16).ok()
+}
+
+/// Parses `0x104a54000 - 0x104a5ffff +MyApp arm64  <1b1f5e0a...> /path`
+/// of the binary images into the image name and its uuid.
+fn apple_image(line: &str) -> Option<(&str, &str)> {
+    let mut fields = line.split_whitespace();
+    parse_hex(fields.next()?)?;
+    if fields.next()? != "-" {
+        return None;
+    }
+    parse_hex(fields.next()?)?;
+    let name = fields.next()?.trim_start_matches('+');
+    fields.next()?;
+    let uuid = fields.next()?.strip_prefix('<')?.strip_suffix('>')?;
+    Some((name, uuid))
+}
+
+fn apple_crash(text: &str, binaries: &HashMap<String, PathBuf>) -> Result<()> {
+    for (name, uuid) in text.lines().filter_map(apple_image) {
+        if let Some(binary) = binaries.get(name) {
+            check_build_id(name, binary, Some(uuid))?;
+        }
+    }
+    let mut frames: HashMap<(&str, u64), Vec<u64>> = HashMap::new();
+    for (image, address, load_address) in text.lines().filter_map(apple_frame) {
+        if binaries.contains_key(image) && address >= load_address {
+            frames
+                .entry((image, load_address))
+                .or_default()
+                .push(address);
+        }
+    }
+    anyhow::ensure!(
+        !frames.is_empty(),
+        "no frames of the app in the crash report"
+    );
+    let mut resolved = HashMap::new();
+    for ((image, load_address), addresses) in &frames {
+        let symbols = atos(&binaries[*image], *load_address, addresses)?;
+        for (address, symbol) in addresses.iter().zip(symbols) {
+            resolved.insert(*address, symbol);
+        }
+    }
+    for line in text.lines() {
+        match apple_frame(line).and_then(|(_, address, _)| resolved.get(&address)) {
+            Some(symbol) => {
+                let (frame, _) = line.split_at(line.find("0x").unwrap());
+                println!("{}{}", frame, symbol);
+            }
+            None => println!("{}", line),
+        }
+    }
+    Ok(())
+}
+
+/// Json crash reports written by ios 15 and macos 12 and later, a json
+/// header line followed by the report.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Ips {
+    used_images: Vec<IpsImage>,
+    threads: Vec<IpsThread>,
+    #[serde(default)]
+    exception: Option<IpsException>,
+}
+
+#[derive(Deserialize)]
+struct IpsImage {
+    #[serde(default)]
+    base: u64,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    uuid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IpsThread {
+    #[serde(default)]
+    triggered: bool,
+    #[serde(default)]
+    name: Option<String>,
+    frames: Vec<IpsFrame>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpsFrame {
+    image_index: usize,
+    image_offset: u64,
+    #[serde(default)]
+    symbol: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IpsException {
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default)]
+    signal: Option<String>,
+}
+
+impl Ips {
+    fn parse(text: &str) -> Option<Self> {
+        let (header, body) = text.split_once('\n')?;
+        serde_json::from_str::<serde_json::Value>(header).ok()?;
+        serde_json::from_str(body).ok()
+    }
+
+    fn symbolicate(&self, binaries: &HashMap<String, PathBuf>) -> Result<()> {
+        let mut resolved = HashMap::new();
+        for (index, image) in self.used_images.iter().enumerate() {
+            let (name, binary) = match image
+                .name
+                .as_ref()
+                .and_then(|name| Some((name, binaries.get(name)?)))
+            {
+                Some(binary) => binary,
+                None => continue,
+            };
+            check_build_id(name, binary, image.uuid.as_deref())?;
+            let addresses = self
+                .threads
+                .iter()
+                .flat_map(|thread| &thread.frames)
+                .filter(|frame| frame.image_index == index)
+                .filter_map(|frame| image.base.checked_add(frame.image_offset))
+                .collect::<Vec<_>>();
+            for (address, symbol) in addresses.iter().zip(atos(binary, image.base, &addresses)?) {
+                resolved.insert(*address, symbol);
+            }
+        }
+        anyhow::ensure!(
+            !resolved.is_empty(),
+            "no frames of the app in the crash report"
+        );
+        if let Some(exception) = &self.exception {
+            println!(
+                "{} ({})",
+                exception.ty,
+                exception.signal.as_deref().unwrap_or_default()
+            );
+        }
+        for (i, thread) in self.threads.iter().enumerate() {
+            print!("\nThread {}", i);
+            if let Some(name) = &thread.name {
+                print!(" {}", name);
+            }
+            println!("{}:", if thread.triggered { " crashed" } else { "" });
+            for (j, frame) in thread.frames.iter().enumerate() {
+                println!("{:<4}{}", j, self.frame(frame, &resolved));
+            }
+        }
+        Ok(())
+    }
+
+    /// Image, address and symbol of a frame. Frames of images missing from
+    /// `usedImages` are printed with their offset.
+    fn frame(&self, frame: &IpsFrame, resolved: &HashMap<u64, String>) -> String {
+        let image = self.used_images.get(frame.image_index);
+        let base = image.map(|image| image.base).unwrap_or_default();
+        let address = base.wrapping_add(frame.image_offset);
+        let name = image
+            .and_then(|image| image.name.as_deref())
+            .unwrap_or("???");
+        let symbol = image
+            .and_then(|_| resolved.get(&address))
+            .cloned()
+            .or_else(|| frame.symbol.clone())
+            .unwrap_or_else(|| format!("0x{:x} + {}", base, frame.image_offset));
+        format!("{:<30}0x{:016x} {}", name, address, symbol)
+    }
+}
+
+/// Fails if the BuildId or UUID `reported` for `name` in the crash report
+/// isn't one of the local binary, whose symbols would be wrong.
+fn check_build_id(name: &str, binary: &Path, reported: Option<&str>) -> Result<()> {
+    let reported = if let Some(reported) = reported {
+        normalize_id(reported)
+    } else {
+        return Ok(());
+    };
+    let local = build_ids(&std::fs::read(binary)?)?;
+    anyhow::ensure!(
+        local.is_empty() || local.contains(&reported),
+        "{} crashed with build id {}, the local build is {}, build the crashing version first",
+        name,
+        reported,
+        local.join(", ")
+    );
+    Ok(())
+}
+
+fn normalize_id(id: &str) -> String {
+    id.replace('-', "").to_lowercase()
+}
+
+/// GNU build id of an elf or the UUIDs of a mach-o, one per arch of
+/// universal binaries.
+fn build_ids(bytes: &[u8]) -> Result<Vec<String>> {
+    use goblin::mach::{Mach, MachO, SingleArch};
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let uuid = |macho: &MachO| {
+        macho
+            .load_commands
+            .iter()
+            .find_map(|command| match &command.command {
+                goblin::mach::load_command::CommandVariant::Uuid(uuid) => Some(hex(&uuid.uuid)),
+                _ => None,
+            })
+    };
+    Ok(match goblin::Object::parse(bytes)? {
+        goblin::Object::Elf(elf) => elf
+            .iter_note_sections(bytes, Some(".note.gnu.build-id"))
+            .into_iter()
+            .flatten()
+            .filter_map(|note| note.ok())
+            .filter(|note| note.n_type == goblin::elf::note::NT_GNU_BUILD_ID)
+            .map(|note| hex(note.desc))
+            .collect(),
+        goblin::Object::Mach(Mach::Binary(macho)) => uuid(&macho).into_iter().collect(),
+        goblin::Object::Mach(Mach::Fat(fat)) => {
+            let mut ids = vec![];
+            for arch in fat.into_iter() {
+                if let SingleArch::MachO(macho) = arch? {
+                    ids.extend(uuid(&macho));
+                }
+            }
+            ids
+        }
+        _ => vec![],
+    })
+}
+
+/// Resolves addresses of a mach-o image loaded at `load_address`. Uses
+/// `atos` on macos, elsewhere `llvm-symbolizer` with the addresses rebased
+/// onto the default `__TEXT` address of executables.
+fn atos(binary: &Path, load_address: u64, addresses: &[u64]) -> Result<Vec<String>> {
+    if addresses.is_empty() {
+        return Ok(vec![]);
+    }
+    if cfg!(not(target_os = "macos")) {
+        const TEXT_VMADDR: u64 = 0x1_0000_0000;
+        let addresses = addresses
+            .iter()
+            .map(|address| {
+                let offset = address.checked_sub(load_address).with_context(|| {
+                    format!(
+                        "0x{:x} is below the load address 0x{:x}",
+                        address, load_address
+                    )
+                })?;
+                Ok(offset + TEXT_VMADDR)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(llvm_symbolizer(binary, &addresses)?
+            .into_iter()
+            .map(|symbol| symbol.join(" inlined into "))
+            .collect());
+    }
+    let output = Command::new("atos")
+        .arg("-o")
+        .arg(binary)
+        .arg("-l")
+        .arg(format!("0x{:x}", load_address))
+        .arg("-i")
+        .args(addresses.iter().map(|address| format!("0x{:x}", address)))
+        .output()
+        .context("atos not found, install the xcode command line tools")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "atos failed: {}",
+        std::str::from_utf8(&output.stderr)?.trim()
+    );
+    // With `-i` inlined frames are listed on consecutive lines and each
+    // address ends with an empty line
+    let stdout = std::str::from_utf8(&output.stdout)?;
+    let symbols = stdout
+        .split("\n\n")
+        .map(|symbol| {
+            symbol
+                .trim()
+                .lines()
+                .collect::<Vec<_>>()
+                .join(" inlined into ")
+        })
+        .filter(|symbol| !symbol.is_empty())
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        symbols.len() == addresses.len(),
+        "unexpected atos output: {}",
+        stdout
+    );
+    Ok(symbols)
+}
+
+/// Resolves addresses to `function at file:line:column`, one entry per
+/// address listing inlined frames first.
+fn llvm_symbolizer(binary: &Path, addresses: &[u64]) -> Result<Vec<Vec<String>>> {
+    if addresses.is_empty() {
+        return Ok(vec![]);
+    }
+    let symbolizer = which::which("llvm-symbolizer")
+        .or_else(|_| which::which("llvm-addr2line"))
+        .context("llvm-symbolizer not found, install llvm")?;
+    let output = Command::new(symbolizer)
+        .arg("--obj")
+        .arg(binary)
+        .arg("--functions=linkage")
+        .arg("--demangle")
+        .arg("--inlines")
+        .arg("--output-style=LLVM")
+        .args(addresses.iter().map(|address| format!("0x{:x}", address)))
+        .output()?;
+    anyhow::ensure!(
+        output.status.success(),
+        "llvm-symbolizer failed: {}",
+        std::str::from_utf8(&output.stderr)?.trim()
+    );
+    let stdout = std::str::from_utf8(&output.stdout)?;
+    let symbols = stdout
+        .split("\n\n")
+        .filter(|symbol| !symbol.trim().is_empty())
+        .map(|symbol| {
+            let lines = symbol.trim().lines().collect::<Vec<_>>();
+            lines
+                .chunks(2)
+                .map(|frame| match frame {
+                    [function, location] => format!("{} at {}", function, location),
+                    [function] => function.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        symbols.len() == addresses.len(),
+        "unexpected llvm-symbolizer output: {}",
+        stdout
+    );
+    Ok(symbols)
+}
+
+/// Minidumps are walked with `minidump-stackwalk` using breakpad symbols
+/// generated from the build output with `dump_syms`.
+fn minidump(env: &BuildEnv, report: &Path, binaries: &HashMap<String, PathBuf>) -> Result<()> {
+    let dump_syms = which::which("dump_syms")
+        .context("dump_syms not found, install it with `cargo install dump_syms`")?;
+    let stackwalk = which::which("minidump-stackwalk").context(
+        "minidump-stackwalk not found, install it with `cargo install minidump-stackwalk`",
+    )?;
+    let symbols_dir = env.build_dir().join("symbols");
+    for binary in binaries.values() {
+        let output = Command::new(&dump_syms).arg(binary).output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "dump_syms failed on `{}`",
+            binary.display()
+        );
+        // `MODULE <os> <arch> <id> <name>` determines the symbol store layout
+        let symbols = std::str::from_utf8(&output.stdout)?;
+        let module = symbols
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("MODULE "))
+            .context("invalid dump_syms output")?;
+        let (id, name) = match module.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, _, id, name] => (id, name),
+            _ => anyhow::bail!("invalid breakpad module {}", module),
+        };
+        let dir = symbols_dir.join(name).join(id);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(format!("{}.sym", name)), symbols)?;
+    }
+    let status = Command::new(stackwalk)
+        .arg("--symbols-path")
+        .arg(&symbols_dir)
+        .arg(report)
+        .status()?;
+    anyhow::ensure!(status.success(), "minidump-stackwalk failed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tombstone_frame() {
+        let line = "      #01 pc 000000000004a2b8  /data/app/~~x==/com.example-y==/lib/arm64/libexample.so (main+24) (BuildId: 9F2C01ab)";
+        assert_eq!(tombstone_frame(line), Some((0x4a2b8, "libexample.so")));
+        assert_eq!(tombstone_build_id(line), Some("9F2C01ab"));
+        assert_eq!(tombstone_frame("backtrace:"), None);
+        assert_eq!(
+            tombstone_build_id("#00 pc 0000 /system/lib64/libc.so"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_apple_crash_lines() {
+        assert_eq!(
+            apple_frame("3   MyApp   0x0000000104a5c3d0 0x104a54000 + 33744"),
+            Some(("MyApp", 0x104a5c3d0, 0x104a54000))
+        );
+        assert_eq!(
+            apple_image("       0x104a54000 -        0x104a5ffff +MyApp arm64  <1b1f5e0a0c1b19ba8aca47dbf2917f7f> /private/var/MyApp"),
+            Some(("MyApp", "1b1f5e0a0c1b19ba8aca47dbf2917f7f"))
+        );
+        assert_eq!(apple_image("Binary Images:"), None);
+    }
+
+    #[test]
+    fn test_ips_missing_image() {
+        let ips = Ips::parse(
+            "{\"app_name\":\"MyApp\"}\n{\"usedImages\":[{\"base\":4096,\"name\":\"MyApp\",\"uuid\":\"1B1F5E0A-0C1B-19BA-8ACA-47DBF2917F7F\"}],\"threads\":[{\"frames\":[{\"imageIndex\":3,\"imageOffset\":16}]}]}",
+        )
+        .unwrap();
+        assert_eq!(
+            normalize_id(ips.used_images[0].uuid.as_deref().unwrap()),
+            "1b1f5e0a0c1b19ba8aca47dbf2917f7f"
+        );
+        let resolved = [(4096 + 16, "main".to_string())].into_iter().collect();
+        // The frame refers to an image that isn't listed
+        let frame = &ips.threads[0].frames[0];
+        assert_eq!(
+            ips.frame(frame, &resolved),
+            format!("{:<30}0x{:016x} 0x0 + 16", "???", 16)
+        );
+    }
+
+    #[test]
+    fn test_check_build_id() {
+        let exe = std::env::current_exe().unwrap();
+        let ids = build_ids(&std::fs::read(&exe).unwrap()).unwrap();
+        for id in &ids {
+            check_build_id("test", &exe, Some(&id.to_uppercase())).unwrap();
+        }
+        assert_eq!(
+            check_build_id("test", &exe, Some("00")).is_err(),
+            !ids.is_empty()
+        );
+        check_build_id("test", &exe, None).unwrap();
+    }
+
+    #[test]
+    fn test_atos_below_load_address() {
+        if cfg!(not(target_os = "macos")) {
+            assert!(atos(Path::new("MyApp"), 0x2000, &[0x1000]).is_err());
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::devices::{Device, Simctl};
+use crate::download::DownloadManager;
+use crate::error::no_device;
+use crate::task::TaskRunner;
+use crate::{Arch, BuildEnv, CompileTarget, Platform};
+use anyhow::Result;
+
+/// Cross compiles the test harnesses of the package and runs them on the
+/// selected device, or the ios `simulator`, exiting with the first non zero
+/// exit code.
+pub fn test(env: &BuildEnv, simulator: Option<&str>, args: &[String]) -> Result<()> {
+    if let Some(simulator) = simulator {
+        return test_simulator(env, simulator, args);
+    }
+    let device = if let Some(device) = env.target().device() {
+        device.clone()
+    } else if env
+        .target()
+        .compile_targets()
+        .all(|target| target.is_host().unwrap_or(false))
+    {
+        Device::host()
+    } else {
+        return Err(no_device());
+    };
+    let target = CompileTarget::new(device.platform()?, device.arch()?, env.target().opt());
+
+    let mut runner = TaskRunner::new(3, env.verbose(), env.message_format());
+
+    runner.start_task("Fetch precompiled artifacts");
+    DownloadManager::new(env)?.prefetch()?;
+    runner.end_task();
+
+    runner.start_verbose_task(format!("Build tests `{}`", env.name()));
+    let cargo_dir = env.arch_dir(target.arch()).join("cargo");
+    let executables = env
+        .cargo_test(target, &cargo_dir)?
+        .test_executables(env.message_format())?;
+    runner.end_task();
+
+    runner.start_verbose_task(format!("Run tests on {}", device));
+    let mut code = 0;
+    for executable in &executables {
+        let status = device.run_executable(executable, args)?;
+        if code == 0 {
+            code = status;
+        }
+    }
+    runner.end_task();
+    runner.write_timings(env)?;
+
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Runs the test harnesses in the ios simulator `simulator`, booting it if
+/// it isn't running.
+fn test_simulator(env: &BuildEnv, simulator: &str, args: &[String]) -> Result<()> {
+    anyhow::ensure!(
+        env.target().device().is_none(),
+        "`--simulator` can't be combined with `--device`"
+    );
+    let simctl = Simctl::which()?;
+    let target = CompileTarget::new(Platform::Ios, Arch::host()?, env.target().opt());
+
+    let mut runner = TaskRunner::new(3, env.verbose(), env.message_format());
+
+    runner.start_task("Fetch precompiled artifacts");
+    DownloadManager::new(env)?.ios_simulator_target(target)?;
+    runner.end_task();
+
+    runner.start_verbose_task(format!("Build tests `{}`", env.name()));
+    let cargo_dir = env.build_dir().join("ios-simulator").join("cargo");
+    let executables = env
+        .cargo_test_simulator(target, &cargo_dir)?
+        .test_executables(env.message_format())?;
+    runner.end_task();
+
+    runner.start_verbose_task(format!("Run tests on {}", simulator));
+    let udid = simctl.start(simulator, true)?;
+    let mut code = 0;
+    for executable in &executables {
+        let status = simctl.spawn(&udid, executable, args)?;
+        if code == 0 {
+            code = status;
+        }
+    }
+    runner.end_task();
+    runner.write_timings(env)?;
+
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
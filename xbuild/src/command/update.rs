@@ -0,0 +1,222 @@
+use super::clean::{dir_size, format_size};
+use crate::download::{version_file, DownloadManager, WorkItem};
+use crate::BuildEnv;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Status {
+    /// Matches the pinned version.
+    Current,
+    /// Downloaded from an older xbuild release, replaced on update.
+    Stale,
+    /// Downloaded before releases were recorded, kept as is.
+    Unknown,
+    /// Not used by the project, removed with `--prune` from a vendor dir,
+    /// or with `--all` from the cache shared with other projects.
+    Unused,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Current => write!(f, "current"),
+            Self::Stale => write!(f, "stale"),
+            Self::Unknown => write!(f, "unknown"),
+            Self::Unused => write!(f, "unused"),
+        }
+    }
+}
+
+struct Component {
+    name: String,
+    version: String,
+    status: Status,
+    path: PathBuf,
+}
+
+/// Git repository `x update --self` installs x from.
+const REPOSITORY: &str = "https://github.com/BraymatterOrg/xbuild-fork";
+
+/// Lists the downloaded components, replaces the stale ones and, with
+/// `prune`, removes the ones the project doesn't use. Components in the
+/// shared cache may be used by other projects, they're only pruned with
+/// `all`. `self_update` reinstalls x with cargo.
+pub fn update(
+    env: &BuildEnv,
+    dry_run: bool,
+    prune: bool,
+    all: bool,
+    self_update: bool,
+) -> Result<()> {
+    let components = components(env)?;
+    let shared = env.components_dir() == env.cache_dir();
+    let mut remove = vec![];
+    let mut kept = 0;
+    for component in &components {
+        let size = if component.path.is_dir() {
+            dir_size(&component.path)?
+        } else {
+            component.path.metadata()?.len()
+        };
+        println!(
+            "{:40}{:20}{:10}{}",
+            component.name,
+            component.version,
+            component.status.to_string(),
+            format_size(size)
+        );
+        match component.status {
+            Status::Stale => remove.push(component),
+            Status::Unused if prune && (!shared || all) => remove.push(component),
+            Status::Unused if prune => kept += 1,
+            _ => {}
+        }
+    }
+    if components.is_empty() {
+        println!("No components in {}", env.components_dir().display());
+    }
+    if kept > 0 {
+        println!(
+            "Kept {} unused components other projects may use, pass `--all` to remove them",
+            kept
+        );
+    }
+
+    for component in &remove {
+        if dry_run {
+            println!("Would remove {}", component.path.display());
+            continue;
+        }
+        println!("Removing {}", component.path.display());
+        if component.path.is_dir() {
+            std::fs::remove_dir_all(&component.path)?;
+        } else {
+            std::fs::remove_file(&component.path)?;
+        }
+        std::fs::remove_file(version_file(&component.path)).ok();
+    }
+    if remove
+        .iter()
+        .any(|component| component.status == Status::Stale)
+        && !dry_run
+    {
+        DownloadManager::new(env)?.prefetch()?;
+    }
+
+    if self_update {
+        let mut cargo = Command::new("cargo");
+        cargo
+            .arg("install")
+            .arg("--locked")
+            .arg("--git")
+            .arg(REPOSITORY)
+            .arg("xbuild");
+        if dry_run {
+            println!("Would run {:?}", cargo);
+        } else {
+            anyhow::ensure!(cargo.status()?.success(), "failed to update x");
+        }
+    }
+    Ok(())
+}
+
+/// Components in the components dir with their status relative to the
+/// versions pinned by the project and the xbuild release.
+fn components(env: &BuildEnv) -> Result<Vec<Component>> {
+    let dir = env.components_dir();
+    let android = env.config().android();
+    let mut components = vec![];
+    let release_components = [
+        "Android.ndk",
+        "Windows.sdk",
+        "MacOSX.sdk",
+        "iPhoneOS.sdk",
+        "Linux-x64.sysroot",
+        "Linux-arm64.sysroot",
+        "runtime-aarch64",
+        "runtime-armhf",
+    ];
+    for name in release_components {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        let version = std::fs::read_to_string(version_file(&path)).ok();
+        let status = if name == "Android.ndk" && android.ndk_version.is_some() {
+            Status::Unused
+        } else if version.is_none() {
+            Status::Unknown
+        } else if version.as_deref() == Some(WorkItem::VERSION) {
+            Status::Current
+        } else {
+            Status::Stale
+        };
+        components.push(Component {
+            name: name.to_string(),
+            version: version.unwrap_or_else(|| "unknown".into()),
+            status,
+            path,
+        });
+    }
+
+    let sdk = env.android_sdk();
+    let platform = format!("android-{}", env.compile_sdk_version());
+    let pinned = [
+        ("platforms", Some(platform.as_str())),
+        ("ndk", android.ndk_version.as_deref()),
+        ("build-tools", android.build_tools_version.as_deref()),
+    ];
+    for (kind, pinned) in pinned {
+        for (version, path) in entries(&sdk.join(kind))? {
+            let status = if Some(version.as_str()) == pinned {
+                Status::Current
+            } else {
+                Status::Unused
+            };
+            components.push(Component {
+                name: format!("Android.sdk/{}", kind),
+                version,
+                status,
+                path,
+            });
+        }
+    }
+
+    let disk_images = dir.join("iPhoneOS.platform").join("DeviceSupport");
+    for (version, path) in entries(&disk_images)? {
+        // Needed by the connected devices, which aren't known here
+        components.push(Component {
+            name: "DeveloperDiskImage".into(),
+            version,
+            status: Status::Current,
+            path,
+        });
+    }
+
+    // Archives are only needed while extracting
+    for (name, path) in entries(&dir.join("download"))? {
+        components.push(Component {
+            name: format!("download/{}", name),
+            version: "-".into(),
+            status: Status::Unused,
+            path,
+        });
+    }
+    Ok(components)
+}
+
+/// Entries of `dir` by name, empty if it doesn't exist.
+fn entries(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut entries = vec![];
+    if dir.exists() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            entries.push((name, path));
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
@@ -0,0 +1,12 @@
+use crate::download::DownloadManager;
+use crate::BuildEnv;
+use anyhow::Result;
+
+/// Downloads all sdk components required to build for the selected target
+/// into the vendor dir, for use with `--offline --vendor-dir`.
+pub fn vendor(env: &BuildEnv) -> Result<()> {
+    anyhow::ensure!(!env.offline(), "vendoring requires network access");
+    DownloadManager::new(env)?.prefetch()?;
+    println!("vendored components in {}", env.components_dir().display());
+    Ok(())
+}
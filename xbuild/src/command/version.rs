@@ -0,0 +1,417 @@
+use crate::cargo::manifest::Inheritable;
+use crate::config::msix_version;
+use crate::VersionArgs;
+use anyhow::{Context, Result};
+use apk::VersionCode;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+}
+
+/// Edits of a single file, applied together.
+struct FileEdit {
+    path: PathBuf,
+    contents: String,
+    changes: Vec<String>,
+}
+
+pub fn version(args: VersionArgs) -> Result<()> {
+    let bump = args.bump();
+    let cargo = args.cargo.cargo()?;
+    let package = cargo.manifest().package.as_ref().unwrap();
+    let (toml_path, section) = match &package.version {
+        Inheritable::Value(_) => (cargo.package_root().join("Cargo.toml"), "package"),
+        Inheritable::Inherited { .. } => (
+            cargo
+                .workspace_manifest_path()
+                .context("`version.workspace = true` requires a workspace")?
+                .to_path_buf(),
+            "workspace.package",
+        ),
+    };
+    let toml = std::fs::read_to_string(&toml_path)?;
+    let (line, old) = toml_find(&toml, section, "version")
+        .with_context(|| format!("no `{}.version` in `{}`", section, toml_path.display()))?;
+    let bump = if let Some(bump) = bump {
+        bump
+    } else {
+        println!("{}", old);
+        return Ok(());
+    };
+    let new = bump_semver(&old, bump)?;
+
+    let current = toml.lines().nth(line).unwrap();
+    let comment = current
+        .find(" #")
+        .map(|i| &current[i..])
+        .unwrap_or_default();
+    let updated = format!("version = \"{}\"{}", new, comment);
+    let mut edits = vec![FileEdit {
+        contents: replace_line(&toml, line, &updated),
+        changes: vec![format!("version {} -> {}", old, new)],
+        path: toml_path,
+    }];
+    let manifest_path = cargo.package_root().join("manifest.yaml");
+    let yaml = std::fs::read_to_string(&manifest_path).unwrap_or_default();
+    let mut edit = FileEdit {
+        path: manifest_path,
+        contents: yaml,
+        changes: vec![],
+    };
+    let mut derived = vec![];
+
+    let old_code = VersionCode::from_semver(&old)
+        .ok()
+        .map(|code| code.to_code(1));
+    let new_code = VersionCode::from_semver(&new)?.to_code(1);
+    if !edit.set(&["android", "manifest", "version_code"], |code| {
+        let code = code.parse::<u32>().ok()?;
+        Some((code + 1).to_string())
+    }) {
+        let old_code = old_code.map(|code| code.to_string()).unwrap_or_default();
+        derived.push(format!("android versionCode {} -> {}", old_code, new_code));
+    }
+    edit.set(&["android", "manifest", "version_name"], |_| {
+        Some(new.clone())
+    });
+    for platform in ["ios", "macos"] {
+        edit.set(
+            &[platform, "info", "cf_bundle_short_version_string"],
+            |_| Some(new.clone()),
+        );
+        // Build numbers are incremented, otherwise they follow the version
+        let explicit = edit.set(&[platform, "info", "cf_bundle_version"], |build| {
+            Some(match build.parse::<u64>() {
+                Ok(build) => (build + 1).to_string(),
+                Err(_) => new.clone(),
+            })
+        });
+        if !explicit && platform == "ios" {
+            derived.push(format!("ios CFBundleVersion {} -> {}", old, new));
+        }
+    }
+    if !edit.set(&["windows", "manifest", "identity", "version"], |_| {
+        Some(msix_version(&new))
+    }) {
+        derived.push(format!(
+            "msix version {} -> {}",
+            msix_version(&old),
+            msix_version(&new)
+        ));
+    }
+    if !edit.changes.is_empty() {
+        edits.push(edit);
+    }
+
+    for path in metainfo_files(cargo.package_root())? {
+        let xml = std::fs::read_to_string(&path)?;
+        if let Some(contents) = add_appstream_release(&xml, &new, &today()) {
+            edits.push(FileEdit {
+                path,
+                contents,
+                changes: vec![format!("appstream release {}", new)],
+            });
+        }
+    }
+
+    for edit in &edits {
+        for change in &edit.changes {
+            println!("{}: {}", edit.path.display(), change);
+        }
+        if !args.dry_run {
+            std::fs::write(&edit.path, &edit.contents)?;
+        }
+    }
+    for change in &derived {
+        println!("{} (derived)", change);
+    }
+    Ok(())
+}
+
+fn bump_semver(version: &str, bump: Bump) -> Result<String> {
+    // Pre-release and build metadata are dropped
+    let core = version.split(['-', '+']).next().unwrap();
+    let mut parts = core
+        .split('.')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|parts| parts.len() == 3)
+        .with_context(|| format!("invalid semver {}", version))?;
+    match bump {
+        Bump::Major => parts = vec![parts[0] + 1, 0, 0],
+        Bump::Minor => parts = vec![parts[0], parts[1] + 1, 0],
+        Bump::Patch => parts[2] += 1,
+    }
+    Ok(format!("{}.{}.{}", parts[0], parts[1], parts[2]))
+}
+
+impl FileEdit {
+    /// Replaces the value of the yaml key at `path` if it is set, returns
+    /// if it was.
+    fn set(&mut self, path: &[&str], value: impl FnOnce(&str) -> Option<String>) -> bool {
+        let (line, old) = if let Some(found) = yaml_find(&self.contents, path) {
+            found
+        } else {
+            return false;
+        };
+        if let Some(new) = value(&old) {
+            let current = self.contents.lines().nth(line).unwrap();
+            let (key, rest) = current.split_once(':').unwrap();
+            let comment = rest.find(" #").map(|i| &rest[i..]).unwrap_or_default();
+            let quote = if rest.trim_start().starts_with('"') {
+                "\""
+            } else {
+                ""
+            };
+            let updated = format!("{}: {}{}{}{}", key, quote, new, quote, comment);
+            self.contents = replace_line(&self.contents, line, &updated);
+            self.changes
+                .push(format!("{} {} -> {}", path.join("."), old, new));
+        }
+        true
+    }
+}
+
+/// Finds `key = "value"` in the `[section]` table, ignoring a trailing
+/// comment.
+fn toml_find(toml: &str, section: &str, key: &str) -> Option<(usize, String)> {
+    let header = format!("[{}]", section);
+    let mut in_section = false;
+    for (i, line) in toml.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_section = line == header;
+        } else if in_section {
+            if let Some((k, v)) = line.split_once('=') {
+                if k.trim() == key {
+                    let v = v.trim();
+                    let value = match v.chars().next() {
+                        Some(quote @ ('"' | '\'')) => v[1..].split(quote).next().unwrap(),
+                        _ => v.split('#').next().unwrap().trim(),
+                    };
+                    return Some((i, value.to_string()));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Line based lookup of a nested mapping key in block style yaml, which is
+/// enough for `manifest.yaml` and keeps comments and formatting intact when
+/// editing.
+pub(super) fn yaml_find(yaml: &str, path: &[&str]) -> Option<(usize, String)> {
+    let mut stack: Vec<(usize, &str)> = vec![];
+    for (i, line) in yaml.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let (key, value) = if let Some(kv) = trimmed.split_once(':') {
+            kv
+        } else {
+            continue;
+        };
+        while stack.last().map(|(i, _)| *i >= indent).unwrap_or_default() {
+            stack.pop();
+        }
+        stack.push((indent, key.trim()));
+        if stack.iter().map(|(_, key)| *key).eq(path.iter().copied()) {
+            let value = value.split(" #").next().unwrap().trim();
+            return Some((i, value.trim_matches(['"', '\'']).to_string()));
+        }
+    }
+    None
+}
+
+pub(super) fn replace_line(contents: &str, line: usize, replacement: &str) -> String {
+    let indent = contents
+        .lines()
+        .nth(line)
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .unwrap_or_default();
+    let replacement = if replacement.starts_with(char::is_whitespace) {
+        replacement.to_string()
+    } else {
+        format!("{}{}", indent, replacement.trim_start())
+    };
+    let mut lines = contents.lines().map(str::to_string).collect::<Vec<_>>();
+    lines[line] = replacement;
+    let mut out = lines.join("\n");
+    if contents.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+/// AppStream metadata files in the package root.
+fn metainfo_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(root)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        if name.ends_with(".metainfo.xml") || name.ends_with(".appdata.xml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Adds `<release version="" date=""/>` as the newest release, creating the
+/// `<releases>` element if needed. Returns `None` if the release is already
+/// listed or the file has no `<component>`.
+fn add_appstream_release(xml: &str, version: &str, date: &str) -> Option<String> {
+    if xml.contains(&format!("<release version=\"{}\"", version)) {
+        return None;
+    }
+    let release = format!("<release version=\"{}\" date=\"{}\"/>", version, date);
+    if let Some(start) = xml.find("<releases>") {
+        let end = start + "<releases>".len();
+        let line_start = xml[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let indent = &xml[line_start..start];
+        Some(format!(
+            "{}\n{}  {}{}",
+            &xml[..end],
+            indent,
+            release,
+            &xml[end..]
+        ))
+    } else {
+        let end = xml.rfind("</component>")?;
+        Some(format!(
+            "{}  <releases>\n    {}\n  </releases>\n{}",
+            &xml[..end],
+            release,
+            &xml[end..]
+        ))
+    }
+}
+
+/// Current utc date as `YYYY-MM-DD`.
+fn today() -> String {
+    let (year, month, day, ..) = super::utc_now();
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump_semver() {
+        assert_eq!(bump_semver("1.2.3", Bump::Major).unwrap(), "2.0.0");
+        assert_eq!(bump_semver("1.2.3", Bump::Minor).unwrap(), "1.3.0");
+        assert_eq!(bump_semver("1.2.3", Bump::Patch).unwrap(), "1.2.4");
+        assert_eq!(bump_semver("1.2.3-rc.1+abc", Bump::Patch).unwrap(), "1.2.4");
+        assert!(bump_semver("1.2", Bump::Patch).is_err());
+        assert!(bump_semver("1.2.x", Bump::Patch).is_err());
+    }
+
+    #[test]
+    fn test_toml_find() {
+        let toml = r#"[package]
+name = "app"
+version = "0.1.0" # comment
+
+[workspace.package]
+version='0.2.0'
+
+[dependencies]
+version = { version = "1.0" }
+"#;
+        assert_eq!(
+            toml_find(toml, "package", "version"),
+            Some((2, "0.1.0".into()))
+        );
+        assert_eq!(
+            toml_find(toml, "workspace.package", "version"),
+            Some((5, "0.2.0".into()))
+        );
+        assert_eq!(toml_find(toml, "package", "edition"), None);
+        assert_eq!(
+            toml_find("[package]\nversion = 1 # comment", "package", "version"),
+            Some((1, "1".into()))
+        );
+    }
+
+    #[test]
+    fn test_yaml_find() {
+        let yaml = r#"# comment
+android:
+  manifest:
+    version_code: 3 # bumped by ci
+    version_name: "1.0.0"
+ios:
+  info:
+    cf_bundle_version: '7'
+  manifest:
+    version_code: 1
+"#;
+        let find = |path: &[&str]| yaml_find(yaml, path);
+        assert_eq!(
+            find(&["android", "manifest", "version_code"]),
+            Some((3, "3".into()))
+        );
+        assert_eq!(
+            find(&["android", "manifest", "version_name"]),
+            Some((4, "1.0.0".into()))
+        );
+        assert_eq!(
+            find(&["ios", "info", "cf_bundle_version"]),
+            Some((7, "7".into()))
+        );
+        assert_eq!(find(&["ios", "info", "version_code"]), None);
+        assert_eq!(find(&["macos", "info", "cf_bundle_version"]), None);
+    }
+
+    #[test]
+    fn test_file_edit_keeps_comments_and_quotes() {
+        let mut edit = FileEdit {
+            path: PathBuf::new(),
+            contents: "android:\n  manifest:\n    version_name: \"1.0.0\" # name\n".into(),
+            changes: vec![],
+        };
+        assert!(edit.set(&["android", "manifest", "version_name"], |_| {
+            Some("1.0.1".into())
+        }));
+        assert_eq!(
+            edit.contents,
+            "android:\n  manifest:\n    version_name: \"1.0.1\" # name\n"
+        );
+        assert!(!edit.set(&["ios", "info", "cf_bundle_version"], |_| None));
+    }
+
+    #[test]
+    fn test_add_appstream_release() {
+        let xml = r#"<component>
+  <releases>
+    <release version="0.1.0" date="2024-01-01"/>
+  </releases>
+</component>
+"#;
+        assert_eq!(
+            add_appstream_release(xml, "0.2.0", "2024-02-01").unwrap(),
+            r#"<component>
+  <releases>
+    <release version="0.2.0" date="2024-02-01"/>
+    <release version="0.1.0" date="2024-01-01"/>
+  </releases>
+</component>
+"#
+        );
+        assert_eq!(add_appstream_release(xml, "0.1.0", "2024-02-01"), None);
+        let xml = "<component>\n  <id>app</id>\n</component>\n";
+        assert_eq!(
+            add_appstream_release(xml, "0.2.0", "2024-02-01").unwrap(),
+            "<component>\n  <id>app</id>\n  <releases>\n    <release version=\"0.2.0\" date=\"2024-02-01\"/>\n  </releases>\n</component>\n"
+        );
+        assert_eq!(add_appstream_release("<xml/>", "0.2.0", "2024-02-01"), None);
+    }
+}
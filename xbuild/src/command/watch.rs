@@ -0,0 +1,208 @@
+use super::build::artifact_hash;
+use crate::devices::{LogFilter, LogOutput, PortForward};
+use crate::error::no_device;
+use crate::{BuildEnv, Platform};
+use anyhow::Result;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::process::Child;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+/// Time without file events after which a change is considered complete,
+/// editors save in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Builds and runs the app on the selected devices, then rebuilds and
+/// redeploys it whenever a file of the workspace changes. Android apps are
+/// only reinstalled if the apk changed and their logs are streamed for the
+/// whole session, desktop and ios apps are restarted.
+pub fn watch(env: &BuildEnv, log_filters: &[LogFilter], forward: &[PortForward]) -> Result<()> {
+    anyhow::ensure!(
+        env.target().platform() != Platform::Web,
+        "`--watch` is not supported on web"
+    );
+    let devices = env.target().devices();
+    if devices.is_empty() {
+        return Err(no_device());
+    }
+    let mut ports = env.config().forward_ports(env.target().platform());
+    ports.extend_from_slice(forward);
+    for device in devices {
+        device.forward_ports(&ports)?;
+    }
+
+    let cargo = env.cargo();
+    let root = cargo
+        .workspace_manifest_path()
+        .and_then(Path::parent)
+        .unwrap_or_else(|| cargo.package_root());
+    let ignored = [cargo.target_dir(), env.build_dir()];
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watch_sources(&mut watcher, root, &ignored)?;
+
+    let out = env.executable();
+    let mut children = Vec::<Child>::new();
+    let mut deployed = None;
+    let mut streaming = false;
+    std::thread::scope(|s| loop {
+        // Stopped before the build, running executables can't be replaced
+        // on windows
+        let restart = !children.is_empty();
+        for mut child in children.drain(..) {
+            child.kill().ok();
+            child.wait().ok();
+        }
+        match crate::command::build(env).and_then(|()| artifact_hash(&out)) {
+            Ok(hash) if !restart && deployed.as_ref() == Some(&hash) => {
+                println!("{} is up to date", out.display());
+            }
+            Ok(hash) => {
+                for device in devices {
+                    match device.relaunch(env, &out) {
+                        Ok(child) => children.extend(child),
+                        Err(err) => eprintln!("failed to run on {}: {:?}", device, err),
+                    }
+                }
+                deployed = Some(hash);
+                // The app keeps its uid when it is reinstalled, so logcat is
+                // streamed for the whole session
+                if !streaming && env.target().platform() == Platform::Android {
+                    streaming = true;
+                    for device in devices {
+                        let prefix = (devices.len() > 1).then(|| device.to_string());
+                        s.spawn(move || {
+                            let mut output = LogOutput::new(prefix.as_deref());
+                            if let Err(err) = device.logs(env, log_filters, &mut output) {
+                                eprintln!("failed to stream the logs of {}: {:?}", device, err);
+                            }
+                        });
+                    }
+                }
+            }
+            Err(err) => eprintln!("{:?}", err),
+        }
+        println!("watching {} for changes", root.display());
+        wait_for_change(&rx, root, &ignored);
+    })
+}
+
+/// Watches the files of `root` and its directories except hidden and
+/// `ignored` ones, so the build output doesn't flood the watcher.
+fn watch_sources(watcher: &mut impl Watcher, root: &Path, ignored: &[&Path]) -> Result<()> {
+    watcher.watch(root, RecursiveMode::NonRecursive)?;
+    for entry in std::fs::read_dir(root)? {
+        let path = entry?.path();
+        let hidden = path.file_name().unwrap().to_string_lossy().starts_with('.');
+        if path.is_dir() && !hidden && !ignored.iter().any(|dir| path.starts_with(dir)) {
+            watcher.watch(&path, RecursiveMode::Recursive)?;
+        }
+    }
+    Ok(())
+}
+
+/// Blocks until a file outside of the build directories changed and no
+/// further events arrived for [`DEBOUNCE`].
+fn wait_for_change(rx: &Receiver<notify::Result<Event>>, root: &Path, ignored: &[&Path]) {
+    // The watcher outlives the receiver
+    while let Ok(event) = rx.recv() {
+        match event {
+            Ok(event) if is_source_change(&event, root, ignored) => break,
+            Ok(_) => {}
+            Err(err) => eprintln!("failed to watch for changes: {}", err),
+        }
+    }
+    while rx.recv_timeout(DEBOUNCE).is_ok() {}
+}
+
+fn is_source_change(event: &Event, root: &Path, ignored: &[&Path]) -> bool {
+    if !matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        // Like `.git`, relative to the root which may be in a hidden directory
+        let hidden = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+        // Backup and swap files of editors
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let temporary = name.ends_with('~') || name.ends_with(".swp") || name == "4913";
+        !hidden && !temporary && !ignored.iter().any(|dir| path.starts_with(dir))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(Path::new("/project").join(path))
+    }
+
+    #[test]
+    fn test_is_source_change() {
+        let root = Path::new("/project");
+        let target = root.join("target");
+        let ignored = [target.as_path()];
+        let modify = EventKind::Modify(ModifyKind::Any);
+        assert!(is_source_change(
+            &event(modify, "src/main.rs"),
+            root,
+            &ignored
+        ));
+        assert!(is_source_change(
+            &event(EventKind::Create(CreateKind::File), "manifest.yaml"),
+            root,
+            &ignored
+        ));
+        assert!(!is_source_change(
+            &event(EventKind::Access(AccessKind::Any), "src/main.rs"),
+            root,
+            &ignored
+        ));
+        for path in [
+            "target/debug/app",
+            ".git/index",
+            "src/.main.rs.swp",
+            "src/main.rs~",
+            "src/4913",
+        ] {
+            assert!(
+                !is_source_change(&event(modify, path), root, &ignored),
+                "{}",
+                path
+            );
+        }
+        // Hidden directories above the root don't hide its files
+        let root = Path::new("/home/user/.projects/app");
+        let event = Event::new(modify).add_path(root.join("src/lib.rs"));
+        assert!(is_source_change(&event, root, &ignored));
+    }
+
+    #[test]
+    fn test_watch_sources() {
+        let root = std::env::temp_dir().join("xbuild-watch-sources");
+        for dir in ["src", "target/debug", ".git"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let target = root.join("target");
+        let (tx, rx) = channel();
+        let mut watcher = notify::recommended_watcher(tx).unwrap();
+        watch_sources(&mut watcher, &root, &[&target]).unwrap();
+        std::fs::write(target.join("debug").join("app"), "").unwrap();
+        std::fs::write(root.join("src").join("main.rs"), "").unwrap();
+        let event = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        // Paths may be canonicalized
+        assert!(event.paths[0].ends_with("src/main.rs"), "{:?}", event);
+    }
+}
@@ -1,14 +1,23 @@
 use crate::cargo::manifest::{Inheritable, Manifest, Package};
-use crate::{Opt, Platform};
+use crate::devices::PortForward;
+use crate::publish::Track;
+use crate::secret::Secret;
+use crate::{Categorize, ErrorKind, Opt, Platform};
 use anyhow::{Context, Result};
-use apk::manifest::{Activity, AndroidManifest, IntentFilter, MetaData};
+use apk::manifest::{Activity, AndroidManifest, IntentFilter, MetaData, Profileable};
 use apk::VersionCode;
 use appbundle::InfoPlist;
+use msix::manifest::Capability;
 use msix::AppxManifest;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use xcommon::ZipFileOptions;
+
+/// Api level of the `minSdkVersion` unless configured.
+pub const DEFAULT_MIN_SDK_VERSION: u32 = 21;
+/// Api level of the `targetSdkVersion` unless configured.
+pub const DEFAULT_TARGET_SDK_VERSION: u32 = 33;
+use xcommon::{Compression, ZipFileOptions};
 
 #[derive(Clone, Debug, Default)]
 pub struct Config {
@@ -17,7 +26,17 @@ pub struct Config {
     ios: IosConfig,
     linux: LinuxConfig,
     macos: MacosConfig,
+    web: WebConfig,
     windows: WindowsConfig,
+    publish: PublishConfig,
+    uniffi: Option<UniffiConfig>,
+    localization: Option<LocalizationConfig>,
+    splash: Option<SplashConfig>,
+    build_types: BTreeMap<String, BuildTypeConfig>,
+    signing: SigningConfig,
+    targets: BTreeMap<String, TargetConfig>,
+    packagers: BTreeMap<String, PackagerConfig>,
+    version: String,
 }
 
 impl Config {
@@ -26,17 +45,102 @@ impl Config {
             return Ok(Default::default());
         }
         let contents = std::fs::read_to_string(path.as_ref())?;
-        let config: RawConfig = serde_yaml::from_str(&contents)?;
+        let config: RawConfig = serde_yaml::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.as_ref().display()))
+            .categorize(
+                ErrorKind::Configuration,
+                "check the key and value at the reported line, unknown keys are rejected",
+            )?;
         Ok(Self {
             generic: config.generic.unwrap_or_default(),
             android: config.android.unwrap_or_default(),
             ios: config.ios.unwrap_or_default(),
             linux: config.linux.unwrap_or_default(),
             macos: config.macos.unwrap_or_default(),
+            web: config.web.unwrap_or_default(),
             windows: config.windows.unwrap_or_default(),
+            publish: config.publish.unwrap_or_default(),
+            uniffi: config.uniffi,
+            localization: config.localization,
+            splash: config.splash,
+            build_types: config.build_types,
+            signing: config.signing.unwrap_or_default(),
+            targets: config.targets,
+            packagers: config.packagers,
+            version: String::new(),
         })
     }
 
+    /// Resolves the build type `name` by merging it with the types it
+    /// inherits from. `debug`, `release` and `profile` are built in and can
+    /// be customized, other types inherit from `debug` by default.
+    pub fn build_type(&self, name: &str) -> Result<BuildTypeConfig> {
+        let mut chain = vec![];
+        let mut name = name.to_string();
+        loop {
+            anyhow::ensure!(
+                chain.len() <= self.build_types.len() + 3,
+                "build type inheritance cycle"
+            );
+            let builtin = BuildTypeConfig::builtin(&name);
+            let ty = match (self.build_types.get(&name), &builtin) {
+                (Some(ty), Some(builtin)) => ty.clone().inherit(builtin.clone()),
+                (Some(ty), None) => ty.clone(),
+                (None, Some(builtin)) => builtin.clone(),
+                (None, None) => anyhow::bail!("unknown build type `{}`", name),
+            };
+            let parent = if builtin.is_some() {
+                ty.inherits.clone()
+            } else {
+                Some(ty.inherits.clone().unwrap_or_else(|| "debug".into()))
+            };
+            chain.push(ty);
+            if let Some(parent) = parent {
+                name = parent;
+            } else {
+                break;
+            }
+        }
+        Ok(chain
+            .into_iter()
+            .rev()
+            .fold(BuildTypeConfig::default(), |parent, ty| ty.inherit(parent)))
+    }
+
+    /// Applies the manifest tweaks of a build type, after
+    /// [`Self::apply_rust_package`].
+    pub fn apply_build_type(&mut self, ty: &BuildTypeConfig) {
+        self.generic.features.extend(ty.features.iter().cloned());
+        if let Some(debuggable) = ty.debuggable {
+            self.android.manifest.application.debuggable = Some(debuggable);
+        }
+        if ty.profileable == Some(true) {
+            self.enable_profiling();
+        }
+        if let Some(suffix) = &ty.application_id_suffix {
+            let ids = [
+                self.android.manifest.package.as_mut(),
+                self.ios.info.cf_bundle_identifier.as_mut(),
+                self.macos.info.cf_bundle_identifier.as_mut(),
+                self.windows.manifest.identity.name.as_mut(),
+            ];
+            for id in ids.into_iter().flatten() {
+                id.push_str(suffix);
+            }
+        }
+    }
+
+    fn platform_generic(&self, platform: Platform) -> &GenericConfig {
+        match platform {
+            Platform::Android => &self.android.generic,
+            Platform::Ios => &self.ios.generic,
+            Platform::Macos => &self.macos.generic,
+            Platform::Web => &self.web.generic,
+            Platform::Linux => &self.linux.generic,
+            Platform::Windows => &self.windows.generic,
+        }
+    }
+
     /// Selects a generic config value from [`GenericConfig`], platform-specific
     /// overrides first and otherwise falls back to a shared option in the root.
     pub fn select_generic<T: ?Sized>(
@@ -44,30 +148,71 @@ impl Config {
         platform: Platform,
         select: impl Fn(&GenericConfig) -> Option<&T>,
     ) -> Option<&T> {
-        let generic = match platform {
-            Platform::Android => &self.android.generic,
-            Platform::Ios => &self.ios.generic,
-            Platform::Macos => &self.macos.generic,
-            Platform::Linux => &self.linux.generic,
-            Platform::Windows => &self.windows.generic,
-        };
-        select(generic).or_else(|| select(&self.generic))
+        select(self.platform_generic(platform)).or_else(|| select(&self.generic))
     }
 
     pub fn icon(&self, platform: Platform) -> Option<&Path> {
         self.select_generic(platform, |g| g.icon.as_deref())
     }
 
-    pub fn runtime_libs(&self, platform: Platform) -> Vec<PathBuf> {
-        let generic = match platform {
-            Platform::Android => &self.android.generic,
-            Platform::Ios => &self.ios.generic,
-            Platform::Macos => &self.macos.generic,
-            Platform::Linux => &self.linux.generic,
-            Platform::Windows => &self.windows.generic,
-        };
+    pub fn icon_background(&self, platform: Platform) -> Option<&str> {
+        self.select_generic(platform, |g| g.icon_background.as_deref())
+    }
+
+    pub fn icon_padding(&self, platform: Platform) -> Option<f32> {
+        self.select_generic(platform, |g| g.icon_padding.as_ref())
+            .copied()
+    }
+
+    /// User visible app name, if configured.
+    pub fn name(&self, platform: Platform) -> Option<&str> {
+        self.select_generic(platform, |g| g.name.as_deref())
+    }
+
+    /// Compression of zip based artifacts, if configured.
+    pub fn compression(&self, platform: Platform) -> Option<Compression> {
+        self.select_generic(platform, |g| g.compression.as_ref())
+            .map(|c| c.to_compression())
+    }
+
+    /// Whether zip based artifacts should be reproducible.
+    pub fn deterministic(&self, platform: Platform) -> bool {
+        self.select_generic(platform, |g| g.deterministic.as_ref())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Version of the cargo package.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Directory the artifact is copied to, if configured.
+    pub fn artifact_dir(&self, platform: Platform) -> Option<&Path> {
+        self.select_generic(platform, |g| g.artifact_dir.as_deref())
+    }
+
+    /// Name template of the copied artifact, if configured.
+    pub fn artifact_name(&self, platform: Platform) -> Option<&str> {
+        self.select_generic(platform, |g| g.artifact_name.as_deref())
+    }
+
+    /// Whether `cc` crate compilations are cached between builds.
+    pub fn native_cache(&self, platform: Platform) -> bool {
+        self.select_generic(platform, |g| g.native_cache.as_ref())
+            .copied()
+            .unwrap_or_default()
+    }
 
-        generic
+    /// Whether a software bill of materials is written next to the artifact.
+    pub fn sbom(&self, platform: Platform) -> bool {
+        self.select_generic(platform, |g| g.sbom.as_ref())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn runtime_libs(&self, platform: Platform) -> Vec<PathBuf> {
+        self.platform_generic(platform)
             .runtime_libs
             .iter()
             .chain(&self.generic.runtime_libs)
@@ -75,12 +220,156 @@ impl Config {
             .collect()
     }
 
+    /// Foreign language bindings generated with `uniffi-bindgen`, if
+    /// configured.
+    pub fn uniffi(&self) -> Option<&UniffiConfig> {
+        self.uniffi.as_ref()
+    }
+
+    pub fn localization(&self) -> Option<&LocalizationConfig> {
+        self.localization.as_ref()
+    }
+
+    pub fn splash(&self) -> Option<&SplashConfig> {
+        self.splash.as_ref()
+    }
+
+    /// Cargo features enabled in addition to the ones passed on the command line.
+    pub fn features(&self, platform: Platform) -> Vec<String> {
+        self.generic
+            .features
+            .iter()
+            .chain(&self.platform_generic(platform).features)
+            .cloned()
+            .collect()
+    }
+
+    /// Environment variables of cargo invocations, platform-specific values
+    /// override shared ones.
+    pub fn env(&self, platform: Platform) -> BTreeMap<String, String> {
+        let mut env = self.generic.env.clone();
+        env.extend(self.platform_generic(platform).env.clone());
+        env
+    }
+
+    /// Additional rustc flags of cargo invocations, shared ones first and
+    /// the ones of the rust `triple` last.
+    pub fn rustflags(&self, platform: Platform, triple: &str) -> Vec<String> {
+        let target = self.targets.get(triple);
+        self.generic
+            .rustflags
+            .iter()
+            .chain(&self.platform_generic(platform).rustflags)
+            .chain(target.into_iter().flat_map(|target| &target.rustflags))
+            .cloned()
+            .collect()
+    }
+
+    /// Options of the rust `triple`, like the toolchain it's built with.
+    pub fn target(&self, triple: &str) -> Option<&TargetConfig> {
+        self.targets.get(triple)
+    }
+
+    /// Packaging backend selected with `x build --packager <name>`.
+    pub fn packager(&self, name: &str) -> Option<&PackagerConfig> {
+        self.packagers.get(name)
+    }
+
+    /// Linker replacing the default one, the one of the rust `triple` takes
+    /// precedence over the platform and shared ones.
+    pub fn linker(&self, platform: Platform, triple: &str) -> Option<&str> {
+        self.targets
+            .get(triple)
+            .and_then(|target| target.linker.as_deref())
+            .or_else(|| self.select_generic(platform, |g| g.linker.as_deref()))
+    }
+
+    /// Linker arguments in the same order as [`Self::rustflags`].
+    pub fn link_args(&self, platform: Platform, triple: &str) -> Vec<String> {
+        let target = self.targets.get(triple);
+        self.generic
+            .link_args
+            .iter()
+            .chain(&self.platform_generic(platform).link_args)
+            .chain(target.into_iter().flat_map(|target| &target.link_args))
+            .cloned()
+            .collect()
+    }
+
+    /// Host ports made reachable from the device by `x run`, shared ones
+    /// first.
+    pub fn forward_ports(&self, platform: Platform) -> Vec<PortForward> {
+        self.generic
+            .forward_ports
+            .iter()
+            .chain(&self.platform_generic(platform).forward_ports)
+            .copied()
+            .collect()
+    }
+
+    /// Commands run before cargo is invoked, shared ones first.
+    pub fn pre_build(&self, platform: Platform) -> Vec<String> {
+        self.generic
+            .pre_build
+            .iter()
+            .chain(&self.platform_generic(platform).pre_build)
+            .cloned()
+            .collect()
+    }
+
+    /// Commands run after the artifact was created, shared ones first.
+    pub fn post_build(&self, platform: Platform) -> Vec<String> {
+        self.generic
+            .post_build
+            .iter()
+            .chain(&self.platform_generic(platform).post_build)
+            .cloned()
+            .collect()
+    }
+
+    /// Allows profiling the app from the shell on android 10 and later,
+    /// unless the manifest configures profiling explicitly.
+    pub fn enable_profiling(&mut self) {
+        self.android
+            .manifest
+            .application
+            .profileable
+            .get_or_insert_with(|| Profileable {
+                shell: Some(true),
+                ..Default::default()
+            });
+    }
+
+    /// Loads the native activity from `lib_name` instead of the `[lib]`
+    /// target, after [`Self::apply_rust_package`].
+    pub fn set_android_lib_name(&mut self, lib_name: &str) {
+        for activity in &mut self.android.manifest.application.activities {
+            for meta_data in &mut activity.meta_data {
+                if meta_data.name == "android.app.lib_name" {
+                    meta_data.value = lib_name.replace('-', "_");
+                }
+            }
+        }
+    }
+
     pub fn apply_rust_package(
         &mut self,
         manifest_package: &Package,
         workspace_manifest: Option<&Manifest>,
         opt: Opt,
     ) -> Result<()> {
+        let name = |platform| {
+            self.name(platform)
+                .unwrap_or(&manifest_package.name)
+                .to_string()
+        };
+        let android_name = name(Platform::Android);
+        let ios_name = name(Platform::Ios);
+        let linux_name = name(Platform::Linux);
+        let macos_name = name(Platform::Macos);
+        let web_name = name(Platform::Web);
+        let windows_name = name(Platform::Windows);
+
         // android
         let wry = self.android.wry;
         if wry {
@@ -88,6 +377,26 @@ impl Config {
                 .dependencies
                 .push("androidx.appcompat:appcompat:1.4.1".into());
         }
+        for (name, short, sdk) in [
+            (
+                "min",
+                self.android.min_sdk_version,
+                self.android.manifest.sdk.min_sdk_version,
+            ),
+            (
+                "target",
+                self.android.target_sdk_version,
+                self.android.manifest.sdk.target_sdk_version,
+            ),
+        ] {
+            if let (Some(short), Some(sdk)) = (short, sdk) {
+                anyhow::ensure!(
+                    short == sdk,
+                    "`android.{0}_sdk_version` and `android.manifest.sdk.{0}_sdk_version` differ",
+                    name
+                );
+            }
+        }
         let manifest = &mut self.android.manifest;
         manifest.package.get_or_insert_with(|| {
             format!("com.example.{}", manifest_package.name.replace('-', "_"))
@@ -127,37 +436,44 @@ impl Config {
             None => "".into(),
         };
 
+        self.version = package_version.clone();
         manifest
             .version_name
             .get_or_insert_with(|| package_version.clone());
         if let Ok(code) = VersionCode::from_semver(&package_version) {
             manifest.version_code.get_or_insert_with(|| code.to_code(1));
         }
-        let target_sdk_version = 33;
-        let target_sdk_codename = 13;
-        let min_sdk_version = 21;
-        manifest
+        let min_sdk_version = self.android.min_sdk_version();
+        let target_sdk_version = self.android.target_sdk_version();
+        let manifest = &mut self.android.manifest;
+        manifest.sdk.min_sdk_version = Some(min_sdk_version);
+        manifest.sdk.target_sdk_version = Some(target_sdk_version);
+        let compile_sdk_version = *manifest
             .compile_sdk_version
             .get_or_insert(target_sdk_version);
+        anyhow::ensure!(
+            min_sdk_version <= target_sdk_version,
+            "android min sdk version {} is newer than the target sdk version {}",
+            min_sdk_version,
+            target_sdk_version
+        );
+        anyhow::ensure!(
+            target_sdk_version <= compile_sdk_version,
+            "android target sdk version {} is newer than the compile sdk version {}",
+            target_sdk_version,
+            compile_sdk_version
+        );
+        let codename = android_version(compile_sdk_version);
         manifest
             .platform_build_version_code
-            .get_or_insert(target_sdk_version);
+            .get_or_insert(compile_sdk_version);
         manifest
             .compile_sdk_version_codename
-            .get_or_insert(target_sdk_codename);
-        manifest
-            .platform_build_version_name
-            .get_or_insert(target_sdk_codename);
-        manifest
-            .sdk
-            .target_sdk_version
-            .get_or_insert(target_sdk_version);
-        manifest.sdk.min_sdk_version.get_or_insert(min_sdk_version);
+            .get_or_insert(codename);
+        manifest.platform_build_version_name.get_or_insert(codename);
 
         let application = &mut manifest.application;
-        application
-            .label
-            .get_or_insert_with(|| manifest_package.name.clone());
+        application.label.get_or_insert(android_name);
         if wry {
             application
                 .theme
@@ -166,7 +482,9 @@ impl Config {
         application
             .debuggable
             .get_or_insert_with(|| opt == Opt::Debug);
-        application.has_code.get_or_insert(wry);
+        application
+            .has_code
+            .get_or_insert(wry || self.android.jni.enabled() || self.uniffi.is_some());
 
         if application.activities.is_empty() {
             application.activities.push(Activity::default());
@@ -220,8 +538,7 @@ impl Config {
         let info = &mut self.ios.info;
         info.cf_bundle_identifier
             .get_or_insert_with(|| manifest_package.name.clone());
-        info.cf_bundle_name
-            .get_or_insert_with(|| manifest_package.name.clone());
+        info.cf_bundle_name.get_or_insert(ios_name);
         info.cf_bundle_package_type
             .get_or_insert_with(|| "APPL".into());
         info.cf_bundle_short_version_string
@@ -266,30 +583,40 @@ impl Config {
 
         // macos
         let info = &mut self.macos.info;
-        info.cf_bundle_name
-            .get_or_insert_with(|| manifest_package.name.clone());
+        info.cf_bundle_name.get_or_insert(macos_name);
         info.cf_bundle_short_version_string
             .get_or_insert_with(|| package_version.clone());
         info.ls_minimum_system_version
             .get_or_insert_with(|| "10.11".to_string());
 
+        // linux
+        self.linux
+            .desktop
+            .entry("Name".into())
+            .or_insert(linux_name);
+
         // windows
         self.windows
             .manifest
             .properties
             .display_name
-            .get_or_insert_with(|| manifest_package.name.clone());
+            .get_or_insert(windows_name);
+        let capabilities = std::mem::take(&mut self.windows.capabilities);
+        self.windows.manifest.capabilities.extend(capabilities);
         self.windows
             .manifest
             .identity
             .version
-            .get_or_insert(package_version);
+            .get_or_insert_with(|| msix_version(&package_version));
         self.windows
             .manifest
             .properties
             .description
             .get_or_insert(package_description);
 
+        // web
+        self.web.title.get_or_insert(web_name);
+
         Ok(())
     }
 
@@ -309,6 +636,23 @@ impl Config {
         &self.macos
     }
 
+    pub fn web(&self) -> &WebConfig {
+        &self.web
+    }
+
+    pub fn publish(&self) -> &PublishConfig {
+        &self.publish
+    }
+
+    /// Names of the build types configured in `build_types`.
+    pub fn build_types(&self) -> impl Iterator<Item = &str> {
+        self.build_types.keys().map(String::as_str)
+    }
+
+    pub fn signing(&self) -> &SigningConfig {
+        &self.signing
+    }
+
     pub fn windows(&self) -> &WindowsConfig {
         &self.windows
     }
@@ -351,6 +695,29 @@ impl ZipAlignmentOptions {
     }
 }
 
+/// Compression method and level of zip based artifacts (`apk`, `msix`, `ipa`).
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case", deny_unknown_fields)]
+pub enum CompressionConfig {
+    /// Don't compress, fastest to build
+    Stored,
+    /// Deflate with a level between 0 and 9
+    Deflated { level: Option<i32> },
+    /// Zstd with a level between 1 and 21, not supported by `apk`, `ipa` or
+    /// `msix`
+    Zstd { level: Option<i32> },
+}
+
+impl CompressionConfig {
+    pub fn to_compression(self) -> Compression {
+        match self {
+            Self::Stored => Compression::Stored,
+            Self::Deflated { level } => Compression::Deflated(level),
+            Self::Zstd { level } => Compression::Zstd(level),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged, deny_unknown_fields)]
 pub enum AssetPath {
@@ -390,6 +757,13 @@ impl AssetPath {
     }
 }
 
+/// Msix versions have four numeric parts, semver pre-release and build
+/// metadata are dropped.
+pub fn msix_version(semver: &str) -> String {
+    let core = semver.split(['-', '+']).next().unwrap();
+    format!("{}.0", core)
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 struct RawConfig {
@@ -399,15 +773,185 @@ struct RawConfig {
     linux: Option<LinuxConfig>,
     ios: Option<IosConfig>,
     macos: Option<MacosConfig>,
+    web: Option<WebConfig>,
     windows: Option<WindowsConfig>,
+    publish: Option<PublishConfig>,
+    uniffi: Option<UniffiConfig>,
+    localization: Option<LocalizationConfig>,
+    splash: Option<SplashConfig>,
+    #[serde(default)]
+    build_types: BTreeMap<String, BuildTypeConfig>,
+    signing: Option<SigningConfig>,
+    #[serde(default)]
+    targets: BTreeMap<String, TargetConfig>,
+    #[serde(default)]
+    packagers: BTreeMap<String, PackagerConfig>,
+}
+
+/// Toolchain and linker options of a rust target triple, like
+/// `aarch64-linux-android`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    /// Rust toolchain to build with, like `nightly` or `nightly-2024-06-01`,
+    /// invoked as `cargo +<toolchain>`
+    pub toolchain: Option<String>,
+    /// Standard library crates built from source with `-Z build-std`, like
+    /// `[std, panic_abort]`. Needs a nightly toolchain
+    #[serde(default)]
+    pub build_std: Vec<String>,
+    /// Features of the standard library built with `build_std`, like
+    /// `[panic_immediate_abort]`
+    #[serde(default)]
+    pub build_std_features: Vec<String>,
+    /// Target spec json passed to `--target` instead of the triple, relative
+    /// to the package root. Named after the triple, like
+    /// `aarch64-linux-android.json`
+    pub spec: Option<PathBuf>,
+    /// Image `x build --container` runs cargo in, with the rust target,
+    /// compilers and linker of the triple installed. Defaults to the image
+    /// published with xbuild for android, linux and windows triples
+    pub container: Option<String>,
+    /// Linker rustc invokes instead of the default one
+    pub linker: Option<String>,
+    /// Arguments passed to the linker, like `-Wl,-z,max-page-size=16384`
+    #[serde(default)]
+    pub link_args: Vec<String>,
+    /// Flags passed to rustc
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+}
+
+/// Packaging backend besides the built-in formats, see
+/// [`crate::packager`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackagerConfig {
+    /// Platform whose compiled artifacts are packaged
+    pub platform: Platform,
+    /// Extension of the package, like `flatpak` or `deb`
+    pub extension: String,
+    /// Shell command creating the package when no backend of this name is
+    /// registered, defaults to running `xbuild-<name>`
+    pub command: Option<String>,
+    /// Backend specific options, passed to commands as json in
+    /// `XBUILD_OPTIONS`
+    #[serde(default)]
+    pub options: serde_yaml::Value,
+}
+
+/// Named build configuration selected with `x build --type`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildTypeConfig {
+    /// Build type that unset options are taken from
+    pub inherits: Option<String>,
+    /// Optimizations, `debug` or `release`
+    pub opt: Option<Opt>,
+    /// Cargo profile to build with instead of the one matching `opt`
+    pub cargo_profile: Option<String>,
+    /// Overrides `android:debuggable`
+    pub debuggable: Option<bool>,
+    /// Allows profiling the app from the shell on android
+    pub profileable: Option<bool>,
+    /// Appended to the android package, bundle identifier and msix identity,
+    /// like `.debug` to install the build type next to the release app
+    pub application_id_suffix: Option<String>,
+    /// PEM encoded signing key and certificate, a path relative to the
+    /// package root or an `env:`, `keychain:`, `file:` or `command:` secret
+    pub pem: Option<Secret>,
+    /// Cargo features to enable
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl BuildTypeConfig {
+    fn builtin(name: &str) -> Option<Self> {
+        Some(match name {
+            "debug" => Self {
+                opt: Some(Opt::Debug),
+                ..Default::default()
+            },
+            "release" => Self {
+                opt: Some(Opt::Release),
+                ..Default::default()
+            },
+            "profile" => Self {
+                inherits: Some("release".into()),
+                profileable: Some(true),
+                ..Default::default()
+            },
+            _ => return None,
+        })
+    }
+
+    /// Fills the unset options from `parent`.
+    fn inherit(self, parent: Self) -> Self {
+        Self {
+            inherits: self.inherits.or(parent.inherits),
+            opt: self.opt.or(parent.opt),
+            cargo_profile: self.cargo_profile.or(parent.cargo_profile),
+            debuggable: self.debuggable.or(parent.debuggable),
+            profileable: self.profileable.or(parent.profileable),
+            application_id_suffix: self.application_id_suffix.or(parent.application_id_suffix),
+            pem: self.pem.or(parent.pem),
+            features: parent.features.into_iter().chain(self.features).collect(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct GenericConfig {
+    /// Png or svg app icon, the png is at least 512x512 px
     icon: Option<PathBuf>,
+    /// `#rrggbb` color the icon is drawn on, also the background layer of
+    /// android adaptive icons
+    icon_background: Option<String>,
+    /// Part of the icon size left empty around the icon, like `0.1`
+    icon_padding: Option<f32>,
+    /// User visible app name, defaults to the cargo package name.
+    name: Option<String>,
+    compression: Option<CompressionConfig>,
+    /// Normalize timestamps, permissions and entry order of zip based artifacts.
+    deterministic: Option<bool>,
     #[serde(default)]
     runtime_libs: Vec<PathBuf>,
+    /// Cargo features to enable
+    #[serde(default)]
+    features: Vec<String>,
+    /// Environment variables passed to cargo
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Flags passed to rustc
+    #[serde(default)]
+    rustflags: Vec<String>,
+    /// Linker rustc invokes instead of the default one, like `clang`
+    linker: Option<String>,
+    /// Arguments passed to the linker, like `-fuse-ld=mold`
+    #[serde(default)]
+    link_args: Vec<String>,
+    /// Cache object files of native dependencies built with the `cc` crate
+    native_cache: Option<bool>,
+    /// Write a CycloneDX sbom of the crate graph and bundled native libraries
+    sbom: Option<bool>,
+    /// Shell commands run before building
+    #[serde(default)]
+    pre_build: Vec<String>,
+    /// Shell commands run after packaging
+    #[serde(default)]
+    post_build: Vec<String>,
+    /// Ports of dev servers on the host the app connects to on `localhost`,
+    /// as `port` or `device:host`. Reversed with adb on android
+    #[serde(default)]
+    forward_ports: Vec<PortForward>,
+    /// Directory the artifact is copied to after building, relative to the
+    /// package root
+    artifact_dir: Option<PathBuf>,
+    /// Name of the copied artifact, `{name}`, `{version}`, `{platform}`,
+    /// `{arch}`, `{profile}`, `{format}` and `{ext}` are replaced. Defaults
+    /// to `{name}-{version}-{platform}-{arch}.{ext}`
+    artifact_name: Option<String>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -421,6 +965,90 @@ pub struct AndroidDebugConfig {
     pub reverse: HashMap<String, String>,
 }
 
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UniffiConfig {
+    /// Interface definition relative to the package root, bindings are
+    /// generated from the compiled library if unset
+    pub udl: Option<PathBuf>,
+    /// `uniffi.toml` relative to the package root
+    pub config: Option<PathBuf>,
+    /// Command running the bindgen, like `cargo run --bin uniffi-bindgen --`,
+    /// defaults to `uniffi-bindgen`
+    pub bindgen: Option<String>,
+}
+
+/// Translations of the app name and permission strings, see
+/// [`crate::l10n`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalizationConfig {
+    /// `.csv` file with a `key` column and a column per locale, or a
+    /// directory of `<locale>.ftl` or `<locale>.po` files, relative to the
+    /// package root
+    pub source: PathBuf,
+    /// Locale of the unqualified resources, defaults to `en`
+    pub default_locale: Option<String>,
+}
+
+/// Screen shown while the app launches on android, ios and windows.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplashConfig {
+    /// Square `.png` or `.svg` centered on the background, relative to the
+    /// package root
+    pub image: Option<PathBuf>,
+    /// `#rrggbb` color, defaults to white
+    pub background: Option<String>,
+    /// Variant shown in dark mode
+    pub dark: Option<SplashVariantConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplashVariantConfig {
+    /// Defaults to the light image
+    pub image: Option<PathBuf>,
+    /// Defaults to the light background
+    pub background: Option<String>,
+}
+
+/// Signing identities of the project, listed with `x signers`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningConfig {
+    /// PEM encoded signing keys and certificates, paths relative to the
+    /// package root or secrets like `keychain:SERVICE`
+    #[serde(default)]
+    pub pem: Vec<Secret>,
+    /// PKCS#12 files relative to the package root
+    #[serde(default)]
+    pub pkcs12: Vec<PathBuf>,
+    /// Password of the PKCS#12 files, defaults to `env:X_PKCS12_PASSWORD`
+    pub pkcs12_password: Option<Secret>,
+    /// SHA-256 fingerprint of the identity artifacts are signed with unless
+    /// `--pem`, `X_PEM` or the build type select one, set with
+    /// `x signers --set-default`
+    pub default: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JniConfig {
+    /// Generate java classes declaring the `Java_*` functions exported by
+    /// the rust sources as `native` methods
+    #[serde(default)]
+    pub bindings: bool,
+    /// Directory of java sources relative to the package root
+    pub sources: Option<PathBuf>,
+}
+
+impl JniConfig {
+    pub fn enabled(&self) -> bool {
+        self.bindings || self.sources.is_some()
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct AndroidConfig {
@@ -430,10 +1058,45 @@ pub struct AndroidConfig {
     pub manifest: AndroidManifest,
     #[serde(default)]
     pub dependencies: Vec<String>,
+    /// Png or svg silhouette of the icon, tinted by android 13 for themed
+    /// icons. Launchers don't theme the icon if it's not set.
+    pub monochrome_icon: Option<PathBuf>,
+    /// Oldest android version the app runs on, defaults to
+    /// [`DEFAULT_MIN_SDK_VERSION`]. Native code is compiled for this api level.
+    pub min_sdk_version: Option<u32>,
+    /// Android version the app is tested against, defaults to
+    /// [`DEFAULT_TARGET_SDK_VERSION`]
+    pub target_sdk_version: Option<u32>,
     #[serde(default)]
     pub gradle: bool,
+    /// Pins the ndk, like `26.1.10909125`, to the version of the sdkmanager
+    /// package instead of the ndk shipped by xbuild
+    pub ndk_version: Option<String>,
+    /// Pins the build-tools providing `d8`, like `34.0.0`, instead of using
+    /// the newest installed ones
+    pub build_tools_version: Option<String>,
+    /// Extra `gradle.properties` of the gradle project
+    #[serde(default)]
+    pub gradle_properties: BTreeMap<String, String>,
+    /// Plugins applied to the app module as `id:version`
+    #[serde(default)]
+    pub gradle_plugins: Vec<String>,
+    /// Lines added verbatim to the dependencies of the app module, like
+    /// `implementation platform('com.google.firebase:firebase-bom:32.7.0')`
+    #[serde(default)]
+    pub gradle_dependencies: Vec<String>,
     #[serde(default)]
     pub wry: bool,
+    /// Java glue compiled into the app
+    #[serde(default)]
+    pub jni: JniConfig,
+    /// Shrinks the dex code of release builds with r8
+    #[serde(default)]
+    pub shrink: bool,
+    /// Proguard rules used when shrinking in addition to xbuild's keep rules,
+    /// relative to the package root
+    #[serde(default)]
+    pub proguard_rules: Vec<PathBuf>,
     #[serde(default)]
     pub assets: Vec<AssetPath>,
     /// Debug configuration for `x run`
@@ -441,13 +1104,51 @@ pub struct AndroidConfig {
     pub debug: AndroidDebugConfig,
 }
 
+impl AndroidConfig {
+    /// `minSdkVersion` of the `<uses-sdk>` element.
+    pub fn min_sdk_version(&self) -> u32 {
+        self.manifest
+            .sdk
+            .min_sdk_version
+            .or(self.min_sdk_version)
+            .unwrap_or(DEFAULT_MIN_SDK_VERSION)
+    }
+
+    /// `targetSdkVersion` of the `<uses-sdk>` element.
+    pub fn target_sdk_version(&self) -> u32 {
+        self.manifest
+            .sdk
+            .target_sdk_version
+            .or(self.target_sdk_version)
+            .unwrap_or(DEFAULT_TARGET_SDK_VERSION)
+    }
+}
+
+/// Major android version of an api level, as in `platformBuildVersionName`.
+fn android_version(api_level: u32) -> u32 {
+    match api_level {
+        0..=22 => 5,
+        23 => 6,
+        24 | 25 => 7,
+        26 | 27 => 8,
+        28 => 9,
+        29..=31 => api_level - 19,
+        32 => 12,
+        _ => api_level - 20,
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct IosConfig {
     #[serde(flatten)]
     generic: GenericConfig,
     pub assets_car: Option<PathBuf>,
+    #[serde(default)]
     pub info: InfoPlist,
+    /// Additional `Info.plist` keys not covered by `info`
+    #[serde(default)]
+    pub info_plist: plist::Dictionary,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -455,7 +1156,13 @@ pub struct IosConfig {
 pub struct MacosConfig {
     #[serde(flatten)]
     generic: GenericConfig,
+    #[serde(default)]
     pub info: InfoPlist,
+    /// Additional `Info.plist` keys not covered by `info`
+    #[serde(default)]
+    pub info_plist: plist::Dictionary,
+    /// Path to an entitlements plist used when signing
+    pub entitlements: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -463,6 +1170,93 @@ pub struct MacosConfig {
 pub struct LinuxConfig {
     #[serde(flatten)]
     generic: GenericConfig,
+    /// Desktop entry keys, overriding the generated defaults
+    #[serde(default)]
+    pub desktop: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebConfig {
+    #[serde(flatten)]
+    generic: GenericConfig,
+    /// Page title, defaults to the app name
+    pub title: Option<String>,
+    /// Html page used instead of the generated `index.html`
+    pub index_html: Option<PathBuf>,
+}
+
+/// Store credentials used by `x publish`. Secrets are passed through
+/// environment variables instead.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublishConfig {
+    #[serde(default)]
+    pub play: PlayPublishConfig,
+    #[serde(default)]
+    pub apple: ApplePublishConfig,
+    #[serde(default)]
+    pub microsoft: MicrosoftPublishConfig,
+    /// Store listings by locale like `en-US`, exported with
+    /// `x metadata export`
+    #[serde(default)]
+    pub metadata: BTreeMap<String, ListingConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListingConfig {
+    /// App name shown in the store
+    pub title: Option<String>,
+    /// Subtitle in the app store
+    pub subtitle: Option<String>,
+    /// Short description on google play
+    pub short_description: Option<String>,
+    pub description: Option<String>,
+    /// Comma separated search keywords in the app store
+    pub keywords: Option<String>,
+    /// Promotional text in the app store, can be changed without a release
+    pub promotional_text: Option<String>,
+    /// Release notes of the current version, also used by `x publish`
+    /// without `--release-notes`
+    pub changelog: Option<String>,
+    /// Screenshots relative to the package root by device, one of `phone`,
+    /// `tablet`, `small-tablet`, `tv` or `wear`
+    #[serde(default)]
+    pub screenshots: BTreeMap<String, Vec<PathBuf>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayPublishConfig {
+    /// Json key of a google cloud service account, a path relative to the
+    /// package root or an `env:`, `keychain:`, `file:` or `command:` secret
+    pub service_account: Option<Secret>,
+    /// Default release track
+    pub track: Option<Track>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApplePublishConfig {
+    /// Unified app store connect api key, a path relative to the package
+    /// root or an `env:`, `keychain:`, `file:` or `command:` secret
+    pub api_key: Option<Secret>,
+    /// Apple id of the app in app store connect
+    pub app_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MicrosoftPublishConfig {
+    /// Azure ad tenant associated with the partner center account
+    pub tenant_id: Option<String>,
+    /// Azure ad application with access to the partner center account
+    pub client_id: Option<String>,
+    /// Store id of the app
+    pub app_id: Option<String>,
+    /// Azure ad client secret, defaults to `env:X_MICROSOFT_CLIENT_SECRET`
+    pub client_secret: Option<Secret>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -470,5 +1264,60 @@ pub struct LinuxConfig {
 pub struct WindowsConfig {
     #[serde(flatten)]
     generic: GenericConfig,
+    #[serde(default)]
     pub manifest: AppxManifest,
+    /// Capabilities added to the manifest
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, yaml: &str) -> Config {
+        let path = std::env::temp_dir().join(format!("xbuild-config-{}.yaml", name));
+        std::fs::write(&path, yaml).unwrap();
+        Config::parse(path).unwrap()
+    }
+
+    #[test]
+    fn test_build_type() {
+        let config = parse(
+            "build-types",
+            r#"build_types:
+  release:
+    pem: release.pem
+    features: [release]
+  staging:
+    inherits: release
+    debuggable: true
+    features: [staging]
+  dev:
+    application_id_suffix: .dev
+  a:
+    inherits: b
+  b:
+    inherits: a
+"#,
+        );
+        let staging = config.build_type("staging").unwrap();
+        assert_eq!(staging.opt, Some(Opt::Release));
+        assert_eq!(staging.debuggable, Some(true));
+        assert_eq!(staging.pem, Some(Secret::File("release.pem".into())));
+        assert_eq!(staging.features, vec!["release", "staging"]);
+
+        // Custom types inherit from debug
+        let dev = config.build_type("dev").unwrap();
+        assert_eq!(dev.opt, Some(Opt::Debug));
+        assert_eq!(dev.application_id_suffix.as_deref(), Some(".dev"));
+
+        let profile = config.build_type("profile").unwrap();
+        assert_eq!(profile.opt, Some(Opt::Release));
+        assert_eq!(profile.profileable, Some(true));
+        assert_eq!(profile.pem, Some(Secret::File("release.pem".into())));
+
+        assert!(config.build_type("a").is_err());
+        assert!(config.build_type("unknown").is_err());
+    }
 }
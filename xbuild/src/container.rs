@@ -0,0 +1,202 @@
+//! Builds in a docker or podman container with the toolchain of the target
+//! preinstalled, so the ndk, sdks and cross linkers aren't needed on the
+//! host.
+
+use crate::download::WorkItem;
+use crate::{Categorize, CompileTarget, ErrorKind, Platform};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Cargo home in the container, the registry and git checkouts of the host
+/// are mounted into it.
+const CARGO_HOME: &str = "/xbuild/cargo";
+
+/// Image with the toolchain of `target` the sdk workflow publishes with the
+/// xbuild release [`WorkItem::VERSION`], `None` for platforms without one.
+pub fn default_image(target: CompileTarget) -> Option<String> {
+    let name = match target.platform() {
+        Platform::Android => "android",
+        Platform::Linux => "linux",
+        Platform::Windows => "windows",
+        _ => return None,
+    };
+    // Docker tags can't contain `+`
+    let tag = WorkItem::VERSION.trim_start_matches('v').replace('+', "-");
+    Some(format!("ghcr.io/rust-mobile/xbuild-{}:{}", name, tag))
+}
+
+/// Finds docker or podman.
+pub fn engine() -> Result<PathBuf> {
+    anyhow::ensure!(
+        cfg!(unix),
+        "container builds need a linux or macos host, the paths are mounted as is"
+    );
+    which::which("docker")
+        .or_else(|_| which::which("podman"))
+        .context("docker or podman not found")
+        .categorize(
+            ErrorKind::Environment,
+            "install docker or podman, or build without `--container`",
+        )
+}
+
+/// Container a command is run in. Mounted directories have the same path
+/// inside the container, so paths passed to the command stay valid.
+#[derive(Clone, Debug)]
+pub struct Container {
+    engine: PathBuf,
+    image: String,
+    mounts: Vec<PathBuf>,
+}
+
+impl Container {
+    pub fn new(engine: &Path, image: &str) -> Self {
+        Self {
+            engine: engine.to_path_buf(),
+            image: image.to_string(),
+            mounts: vec![],
+        }
+    }
+
+    pub fn mount(&mut self, dir: &Path) {
+        if !self.mounts.iter().any(|mount| dir.starts_with(mount)) {
+            self.mounts.push(dir.to_path_buf());
+        }
+    }
+
+    /// `cmd` run in the container, with its working directory and
+    /// environment.
+    pub fn wrap(&self, cmd: &Command) -> Result<Command> {
+        let mut run = Command::new(&self.engine);
+        run.arg("run").arg("--rm");
+        for dir in &self.mounts {
+            // Created by the engine as root otherwise
+            std::fs::create_dir_all(dir)?;
+        }
+        let podman = self.engine.file_stem() == Some("podman".as_ref());
+        if podman {
+            // Rootless podman maps the user of the host to root otherwise
+            run.arg("--userns=keep-id");
+        } else if let Some(dir) = self.mounts.first() {
+            // Files in the mounts are owned by the user of the host
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                let metadata = std::fs::metadata(dir)?;
+                run.arg("--user")
+                    .arg(format!("{}:{}", metadata.uid(), metadata.gid()));
+            }
+        }
+        for dir in &self.mounts {
+            run.arg("-v").arg(format!("{0}:{0}", dir.display()));
+        }
+        if let Some(cargo_home) = host_cargo_home() {
+            for dir in ["registry", "git"] {
+                let host = cargo_home.join(dir);
+                std::fs::create_dir_all(&host)?;
+                run.arg("-v")
+                    .arg(format!("{}:{}/{}", host.display(), CARGO_HOME, dir));
+            }
+            run.arg("-e").arg(format!("CARGO_HOME={}", CARGO_HOME));
+        }
+        if let Some(dir) = cmd.get_current_dir() {
+            run.arg("-w").arg(dir);
+        }
+        for (name, value) in cmd.get_envs() {
+            if let Some(value) = value {
+                let mut env = name.to_os_string();
+                env.push("=");
+                env.push(value);
+                run.arg("-e").arg(env);
+            }
+        }
+        run.arg(&self.image)
+            .arg(cmd.get_program())
+            .args(cmd.get_args());
+        Ok(run)
+    }
+
+    /// Cargo home in the container, for remapping the paths of reproducible
+    /// builds.
+    pub fn cargo_home() -> &'static Path {
+        Path::new(CARGO_HOME)
+    }
+}
+
+fn host_cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arch, Opt};
+
+    fn run_args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_default_image() {
+        let target = CompileTarget::new(Platform::Android, Arch::Arm64, Opt::Debug);
+        let tag = WorkItem::VERSION.trim_start_matches('v').replace('+', "-");
+        assert_eq!(
+            default_image(target),
+            Some(format!("ghcr.io/rust-mobile/xbuild-android:{}", tag))
+        );
+        let target = CompileTarget::new(Platform::Macos, Arch::Arm64, Opt::Debug);
+        assert_eq!(default_image(target), None);
+    }
+
+    #[test]
+    fn test_wrap() -> Result<()> {
+        let dir = std::env::temp_dir().join("xbuild-container-test");
+        let target = dir.join("target");
+        let mut container = Container::new(Path::new("/usr/bin/docker"), "example/image:1");
+        container.mount(&dir);
+        // Already mounted with the workspace
+        container.mount(&target);
+        let mut cargo = Command::new("cargo");
+        cargo
+            .arg("build")
+            .current_dir(&dir)
+            .env("CC_aarch64_linux_android", "clang");
+        let args = run_args(&container.wrap(&cargo)?);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = std::fs::metadata(&dir)?;
+            let user = format!("{}:{}", metadata.uid(), metadata.gid());
+            assert!(args.windows(2).any(|arg| arg == ["--user", user.as_str()]));
+        }
+        let mount = format!("{0}:{0}", dir.display());
+        let mounts = args
+            .windows(2)
+            .filter(|arg| arg[0] == "-v" && !arg[1].contains(CARGO_HOME))
+            .map(|arg| arg[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(mounts, [mount]);
+        let workdir = dir.display().to_string();
+        assert!(args.windows(2).any(|arg| arg == ["-w", workdir.as_str()]));
+        assert!(args
+            .windows(2)
+            .any(|arg| arg == ["-e", "CC_aarch64_linux_android=clang"]));
+        assert!(args.ends_with(&[
+            "example/image:1".to_string(),
+            "cargo".to_string(),
+            "build".to_string()
+        ]));
+
+        let podman = Container::new(Path::new("/usr/bin/podman"), "example/image:1");
+        let args = run_args(&podman.wrap(&cargo)?);
+        assert!(args.contains(&"--userns=keep-id".to_string()));
+        assert!(!args.contains(&"--user".to_string()));
+        Ok(())
+    }
+}
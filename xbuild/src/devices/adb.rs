@@ -1,19 +1,28 @@
 use crate::config::AndroidDebugConfig;
-use crate::devices::{Backend, Device};
-use crate::{Arch, Platform};
+use crate::devices::logcat::{LogEntry, LogFilter, Logcat};
+use crate::devices::{sh_quote, Backend, Device, DeviceFeatures, DeviceInfo, LogOutput};
+use crate::{Arch, Categorize, Error, ErrorKind, Platform, Profiler};
 use anyhow::{Context, Result};
 use apk::Apk;
-use std::io::{BufRead, BufReader};
+use std::collections::HashSet;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::{Child, ChildStdout, Command, Stdio};
-use std::time::Duration;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Initial delay before restarting a logcat that exited while the app runs.
+const LOGCAT_BACKOFF: Duration = Duration::from_millis(250);
 
 #[derive(Clone, Debug)]
 pub(crate) struct Adb(PathBuf);
 
 impl Adb {
     pub fn which() -> Result<Self> {
-        Ok(Self(which::which(exe!("adb"))?))
+        let adb = which::which(exe!("adb")).context("adb not found").categorize(
+            ErrorKind::Environment,
+            "install the android platform-tools and add them to `PATH`, `x doctor` shows what's missing",
+        )?;
+        Ok(Self(adb))
     }
 
     fn adb(&self, device: &str) -> Command {
@@ -43,7 +52,101 @@ impl Adb {
         cmd
     }
 
+    /// Pairs with a device that has wireless debugging enabled, using the
+    /// address and code shown in the pairing dialog.
+    pub fn pair(&self, address: &str, code: &str) -> Result<()> {
+        let output = Command::new(&self.0)
+            .arg("pair")
+            .arg(address)
+            .arg(code)
+            .output()?;
+        let stdout = std::str::from_utf8(&output.stdout)?.trim();
+        anyhow::ensure!(
+            output.status.success() && stdout.contains("Successfully paired"),
+            "adb pair failed: {}{}",
+            stdout,
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        Ok(())
+    }
+
+    /// Connects to a device over the network and remembers it, so it is
+    /// reconnected when listing devices.
+    pub fn connect(&self, address: &str) -> Result<()> {
+        let output = Command::new(&self.0).arg("connect").arg(address).output()?;
+        // adb connect exits successfully even if connecting failed
+        let stdout = std::str::from_utf8(&output.stdout)?.trim();
+        anyhow::ensure!(
+            output.status.success() && stdout.contains("connected to"),
+            "adb connect failed: {}{}",
+            stdout,
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        let mut known = known_devices()?;
+        if !known.iter().any(|known| known == address) {
+            known.push(address.to_string());
+            write_known_devices(&known)?;
+        }
+        Ok(())
+    }
+
+    /// Disconnects a network device and forgets it.
+    pub fn disconnect(&self, address: &str) -> Result<()> {
+        Command::new(&self.0)
+            .arg("disconnect")
+            .arg(address)
+            .stdout(Stdio::null())
+            .status()?;
+        let mut known = known_devices()?;
+        anyhow::ensure!(
+            known.iter().any(|known| known == address),
+            "{} is not a known device",
+            address
+        );
+        known.retain(|known| known != address);
+        write_known_devices(&known)
+    }
+
+    /// Tries to reconnect all remembered network devices, devices that
+    /// don't answer within a few seconds are skipped.
+    fn connect_known(&self) -> Result<()> {
+        let mut children = vec![];
+        for address in known_devices()? {
+            let child = Command::new(&self.0)
+                .arg("connect")
+                .arg(address)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()?;
+            children.push(child);
+        }
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            children.retain_mut(|child| child.try_wait().ok().flatten().is_none());
+            if children.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        for mut child in children {
+            child.kill().ok();
+        }
+        Ok(())
+    }
+
     pub fn devices(&self, devices: &mut Vec<Device>) -> Result<()> {
+        self.connect_known()?;
+        for id in self.serials()? {
+            devices.push(Device {
+                backend: Backend::Adb(self.clone()),
+                id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Serials of the devices known to the adb server.
+    pub fn serials(&self) -> Result<Vec<String>> {
         let output = Command::new(&self.0).arg("devices").output()?;
         anyhow::ensure!(
             output.status.success(),
@@ -53,25 +156,43 @@ impl Adb {
         );
         let mut lines = std::str::from_utf8(&output.stdout)?.lines();
         lines.next();
-        for line in lines {
-            if let Some(id) = line.split_whitespace().next() {
-                devices.push(Device {
-                    backend: Backend::Adb(self.clone()),
-                    id: id.to_string(),
-                });
-            }
-        }
-        Ok(())
+        Ok(lines
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|id| id.to_string())
+            .collect())
     }
 
-    fn getprop(&self, device: &str, prop: &str) -> Result<String> {
-        let output = self.shell(device, None).arg("getprop").arg(prop).output()?;
+    /// Sends a console command to an emulator.
+    pub fn emu(&self, device: &str, args: &[&str]) -> Result<String> {
+        let output = self.adb(device).arg("emu").args(args).output()?;
         anyhow::ensure!(
             output.status.success(),
-            "adb getprop exited with code {:?}: {}",
+            "adb emu exited with code {:?}: {}",
             output.status.code(),
             std::str::from_utf8(&output.stderr)?.trim()
         );
+        Ok(std::str::from_utf8(&output.stdout)?.to_string())
+    }
+
+    pub fn getprop(&self, device: &str, prop: &str) -> Result<String> {
+        let output = self.shell(device, None).arg("getprop").arg(prop).output()?;
+        if !output.status.success() {
+            let stderr = std::str::from_utf8(&output.stderr)?.trim();
+            let msg = format!(
+                "adb getprop exited with code {:?}: {}",
+                output.status.code(),
+                stderr
+            );
+            // Usually the first command run on a device when building for it
+            let hint = if stderr.contains("unauthorized") {
+                "device unauthorized, accept the usb debugging prompt on the device"
+            } else if stderr.contains("offline") || stderr.contains("not found") {
+                "reconnect the device and check that it's listed by `x devices`"
+            } else {
+                return Err(anyhow::anyhow!(msg));
+            };
+            return Err(Error::new(ErrorKind::Device, msg).hint(hint).into());
+        }
         Ok(std::str::from_utf8(&output.stdout)?.trim().to_string())
     }
 
@@ -92,6 +213,35 @@ impl Adb {
         Ok(())
     }
 
+    /// Installs an apk or the split apks of an app with `install-multiple`,
+    /// replacing an installed version.
+    pub fn install_apks(&self, device: &str, paths: &[PathBuf]) -> Result<()> {
+        let status = self
+            .adb(device)
+            .arg(if paths.len() > 1 {
+                "install-multiple"
+            } else {
+                "install"
+            })
+            .arg("-r")
+            .args(paths)
+            .status()?;
+        anyhow::ensure!(status.success(), "adb install failed");
+        Ok(())
+    }
+
+    pub fn uninstall(&self, device: &str, package: &str) -> Result<()> {
+        let output = self.adb(device).arg("uninstall").arg(package).output()?;
+        let stdout = std::str::from_utf8(&output.stdout)?.trim();
+        anyhow::ensure!(
+            output.status.success() && stdout.contains("Success"),
+            "failed to uninstall {}: {}",
+            package,
+            stdout
+        );
+        Ok(())
+    }
+
     /// To run a native activity use "android.app.NativeActivity" as the activity name
     fn start(&self, device: &str, package: &str, activity: &str) -> Result<()> {
         let status = self
@@ -231,6 +381,8 @@ impl Adb {
         let child = self
             .shell(device, None)
             .arg("logcat")
+            .arg("-v")
+            .arg("threadtime")
             .arg("-T")
             .arg(format!("'{}'", last_timestamp))
             .arg(format!("--uid={}", uid))
@@ -240,6 +392,80 @@ impl Adb {
         Ok(Logcat::new(child))
     }
 
+    /// Prints the log entries of `package` matching `filters` since
+    /// `last_timestamp`. The logs of restarted processes are picked up by
+    /// filtering on the uid of the app, and logcat is restarted where it left
+    /// off when the device reconnects. Returns when logcat exits after the
+    /// app did.
+    fn stream_logs(
+        &self,
+        device: &str,
+        package: &str,
+        mut last_timestamp: String,
+        filters: &[LogFilter],
+        output: &mut LogOutput,
+    ) -> Result<()> {
+        let mut pids = HashSet::new();
+        let mut backoff = LOGCAT_BACKOFF;
+        loop {
+            let uid = self.uidof(device, package)?;
+            for line in self.logcat(device, uid, &last_timestamp)? {
+                let entry = if let Some(entry) = LogEntry::parse(&line) {
+                    entry
+                } else {
+                    continue;
+                };
+                if pids.insert(entry.pid) && pids.len() > 1 {
+                    output.line(format_args!(
+                        "--- {} started process {}",
+                        package, entry.pid
+                    ))?;
+                }
+                if LogFilter::matches(filters, &entry) {
+                    output.line(&entry)?;
+                }
+                last_timestamp = entry.timestamp;
+            }
+            if self.is_online(device) {
+                if !self.is_running(device, package) {
+                    output.line(format_args!("--- {} exited", package))?;
+                    return Ok(());
+                }
+                // Logcat exited while the app runs, don't restart it in a busy loop
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(LOGCAT_BACKOFF * 32);
+                continue;
+            }
+            output.line(format_args!(
+                "--- lost connection to {}, waiting for it to reconnect",
+                device
+            ))?;
+            let status = self.adb(device).arg("wait-for-device").status()?;
+            anyhow::ensure!(status.success(), "adb wait-for-device failed");
+            backoff = LOGCAT_BACKOFF;
+        }
+    }
+
+    fn is_online(&self, device: &str) -> bool {
+        self.adb(device)
+            .arg("get-state")
+            .output()
+            .map(|output| {
+                output.status.success()
+                    && String::from_utf8_lossy(&output.stdout).trim() == "device"
+            })
+            .unwrap_or_default()
+    }
+
+    fn is_running(&self, device: &str, package: &str) -> bool {
+        self.shell(device, None)
+            .arg("pidof")
+            .arg(package)
+            .output()
+            .map(|output| !String::from_utf8_lossy(&output.stdout).trim().is_empty())
+            .unwrap_or_default()
+    }
+
     pub fn forward(&self, device: &str, port: u16) -> Result<u16> {
         let output = self
             .adb(device)
@@ -256,6 +482,24 @@ impl Adb {
         Ok(std::str::from_utf8(&output.stdout)?.trim().parse()?)
     }
 
+    /// Connections to `localhost:<device_port>` on the device reach
+    /// `host_port` on the host.
+    pub fn reverse(&self, device: &str, device_port: u16, host_port: u16) -> Result<()> {
+        let output = self
+            .adb(device)
+            .arg("reverse")
+            .arg(format!("tcp:{}", device_port))
+            .arg(format!("tcp:{}", host_port))
+            .output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "adb reverse exited with code {:?}: {}",
+            output.status.code(),
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        Ok(())
+    }
+
     /*fn app_dir(&self, device: &str, package: &str) -> Result<PathBuf> {
         let output = self
             .shell(device, Some(package))
@@ -325,12 +569,145 @@ impl Adb {
         Ok(())
     }
 
+    fn pidof(&self, device: &str, name: &str) -> Result<u32> {
+        let output = self.shell(device, None).arg("pidof").arg(name).output()?;
+        let output = std::str::from_utf8(&output.stdout)?;
+        let mut pids = output.split_whitespace();
+        let pid = pids
+            .next()
+            .with_context(|| format!("no process named `{}` is running", name))?;
+        anyhow::ensure!(
+            pids.next().is_none(),
+            "multiple processes named `{}` are running, pass a pid",
+            name
+        );
+        Ok(pid.parse()?)
+    }
+
+    /// Resolves the launcher activity of an installed package.
+    fn launcher_activity(&self, device: &str, package: &str) -> Result<String> {
+        let output = self
+            .shell(device, None)
+            .arg("cmd")
+            .arg("package")
+            .arg("resolve-activity")
+            .arg("--brief")
+            .arg("-c")
+            .arg("android.intent.category.LAUNCHER")
+            .arg(package)
+            .output()?;
+        let output = std::str::from_utf8(&output.stdout)?;
+        let component = output
+            .lines()
+            .rev()
+            .find_map(|line| line.trim().strip_prefix(&format!("{}/", package)))
+            .with_context(|| format!("`{}` is not installed on {}", package, device))?;
+        Ok(component.to_string())
+    }
+
+    /// Starts the installed `package` unless it is already running.
+    pub fn launch(&self, device: &str, package: &str) -> Result<()> {
+        if self.pidof(device, package).is_err() {
+            let activity = self.launcher_activity(device, package)?;
+            self.start(device, package, &activity)?;
+        }
+        Ok(())
+    }
+
+    /// Streams the logs of the installed `package`, launching it first if it
+    /// isn't running.
+    pub fn attach_logs(&self, device: &str, package: &str, filters: &[LogFilter]) -> Result<()> {
+        let last_timestamp = self.logcat_last_timestamp(device)?;
+        self.launch(device, package)?;
+        self.stream_logs(
+            device,
+            package,
+            last_timestamp,
+            filters,
+            &mut LogOutput::new(None),
+        )
+    }
+
+    /// Streams the logs of `package` from now on, without launching it.
+    pub fn logs(
+        &self,
+        device: &str,
+        package: &str,
+        filters: &[LogFilter],
+        output: &mut LogOutput,
+    ) -> Result<()> {
+        let last_timestamp = self.logcat_last_timestamp(device)?;
+        self.stream_logs(device, package, last_timestamp, filters, output)
+    }
+
+    /// Attaches lldb to the running `process`, given as a pid or name, of the
+    /// debuggable app `package`. The lldb-server runs as the app user so it is
+    /// allowed to ptrace the process.
+    pub fn attach(
+        &self,
+        device: &str,
+        package: &str,
+        lldb_server: &Path,
+        executable: &Path,
+        process: &str,
+    ) -> Result<()> {
+        let pid = match process.parse::<u32>() {
+            Ok(pid) => pid,
+            Err(_) => self.pidof(device, process)?,
+        };
+        self.push(device, lldb_server)?;
+        // The app user can't execute files in /data/local/tmp, so the server
+        // is copied into the data dir of the app
+        let status = self
+            .shell(device, Some(package))
+            .arg("sh")
+            .arg("-c")
+            .arg("'cp /data/local/tmp/lldb-server ./lldb-server && chmod 700 ./lldb-server'")
+            .status()?;
+        anyhow::ensure!(
+            status.success(),
+            "failed to copy lldb-server into the app dir, is `{}` debuggable?",
+            package
+        );
+        let mut lldb_server = self
+            .shell(device, Some(package))
+            .arg("./lldb-server")
+            .arg("platform")
+            .arg("--listen")
+            .arg("*:10086")
+            .arg("--server")
+            .stdin(Stdio::null())
+            .spawn()?;
+        std::thread::sleep(Duration::from_millis(100));
+        self.forward(device, 10086)?;
+        let symbols = executable.parent().unwrap();
+        let status = Command::new("lldb")
+            .arg("-O")
+            .arg("platform select remote-android")
+            .arg("-O")
+            .arg(format!("platform connect connect://{}:10086", device))
+            .arg("-O")
+            .arg(format!(
+                "settings append target.exec-search-paths {}",
+                symbols.display()
+            ))
+            .arg("-o")
+            .arg(format!("process attach --pid {}", pid))
+            .arg(executable)
+            .status()?;
+        lldb_server.kill()?;
+        anyhow::ensure!(status.success(), "lldb exited with nonzero exit code.");
+        Ok(())
+    }
+
     pub fn run(
         &self,
         device: &str,
         path: &Path,
         debug_config: &AndroidDebugConfig,
         debug: bool,
+        filters: &[LogFilter],
+        prefix: Option<&str>,
     ) -> Result<()> {
         let entry_point = Apk::entry_point(path)?;
         let package = &entry_point.package;
@@ -345,14 +722,235 @@ impl Adb {
         self.forward_reverse(device, debug_config)?;
         let last_timestamp = self.logcat_last_timestamp(device)?;
         self.start(device, package, activity)?;
-        let uid = self.uidof(device, package)?;
-        let logcat = self.logcat(device, uid, &last_timestamp)?;
-        for line in logcat {
-            println!("{}", line);
-        }
+        self.stream_logs(
+            device,
+            package,
+            last_timestamp,
+            filters,
+            &mut LogOutput::new(prefix),
+        )
+    }
+
+    /// Installs and starts the app without waiting for a debugger or
+    /// streaming its logs, returning the package name.
+    pub fn install_and_start(&self, device: &str, path: &Path) -> Result<String> {
+        let entry_point = Apk::entry_point(path)?;
+        let package = entry_point.package;
+        self.stop(device, &package)?;
+        self.clear_debug_app(device)?;
+        self.install(device, path)?;
+        self.start(device, &package, &entry_point.activity)?;
+        Ok(package)
+    }
+
+    /// Runs the app and records a cpu profile with simpleperf, or a system
+    /// trace with perfetto, for `duration` and pulls it to `out`. Without
+    /// `simpleperf` the binary shipped with the os is used.
+    pub fn profile(
+        &self,
+        device: &str,
+        path: &Path,
+        profiler: Profiler,
+        simpleperf: Option<&Path>,
+        duration: Duration,
+        out: &Path,
+    ) -> Result<()> {
+        let package = self.install_and_start(device, path)?;
+        let secs = duration.as_secs();
+        let remote = match profiler {
+            Profiler::Simpleperf => {
+                let simpleperf = if let Some(simpleperf) = simpleperf {
+                    self.push(device, simpleperf)?;
+                    "/data/local/tmp/simpleperf"
+                } else {
+                    "simpleperf"
+                };
+                let remote = "/data/local/tmp/x-perf.data";
+                let status = self
+                    .shell(device, None)
+                    .arg(simpleperf)
+                    .arg("record")
+                    .arg("--app")
+                    .arg(&package)
+                    .arg("-g")
+                    .arg("--duration")
+                    .arg(secs.to_string())
+                    .arg("-o")
+                    .arg(remote)
+                    .status()?;
+                anyhow::ensure!(status.success(), "simpleperf record failed");
+                remote
+            }
+            Profiler::Perfetto => {
+                // perfetto may only write to its own trace dir
+                let remote = "/data/misc/perfetto-traces/x.perfetto-trace";
+                let status = self
+                    .shell(device, None)
+                    .arg("perfetto")
+                    .arg("-o")
+                    .arg(remote)
+                    .arg("-t")
+                    .arg(format!("{}s", secs))
+                    .arg("--app")
+                    .arg(&package)
+                    .args(["sched", "freq", "idle", "am", "wm", "gfx", "view", "input"])
+                    .status()?;
+                anyhow::ensure!(status.success(), "perfetto failed");
+                remote
+            }
+            _ => anyhow::bail!("{} is not available on android", profiler),
+        };
+        let status = self.adb(device).arg("pull").arg(remote).arg(out).status()?;
+        anyhow::ensure!(status.success(), "adb pull failed");
+        self.shell(device, None).arg("rm").arg(remote).status()?;
+        Ok(())
+    }
+
+    /// Pushes an executable to `/data/local/tmp` and runs it, returning its
+    /// exit code.
+    pub fn run_executable(&self, device: &str, path: &Path, args: &[String]) -> Result<i32> {
+        self.push(device, path)?;
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let status = self
+            .shell(device, None)
+            .arg(executable_command(name, args))
+            .status()?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    pub fn screenshot(&self, device: &str, path: &Path) -> Result<()> {
+        let output = self
+            .adb(device)
+            .arg("exec-out")
+            .arg("screencap")
+            .arg("-p")
+            .output()?;
+        anyhow::ensure!(
+            output.status.success() && !output.stdout.is_empty(),
+            "adb screencap failed: {}",
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        std::fs::write(path, output.stdout)?;
+        Ok(())
+    }
+
+    pub fn record(&self, device: &str, path: &Path, duration: Duration) -> Result<()> {
+        // screenrecord stops after at most three minutes
+        anyhow::ensure!(
+            duration <= Duration::from_secs(180),
+            "android screen recordings are limited to 180 seconds"
+        );
+        let remote = "/data/local/tmp/x-record.mp4";
+        let status = self
+            .shell(device, None)
+            .arg("screenrecord")
+            .arg("--time-limit")
+            .arg(duration.as_secs().to_string())
+            .arg(remote)
+            .status()?;
+        anyhow::ensure!(status.success(), "adb screenrecord failed");
+        let status = self
+            .adb(device)
+            .arg("pull")
+            .arg(remote)
+            .arg(path)
+            .status()?;
+        anyhow::ensure!(status.success(), "adb pull failed");
+        self.shell(device, None).arg("rm").arg(remote).status()?;
+        Ok(())
+    }
+
+    /// Copies `local` to `remote` in the data dir of `package`. adb push
+    /// can't write to the sandbox, so a tar archive is piped to `run-as`.
+    pub fn push_app_data(
+        &self,
+        device: &str,
+        package: &str,
+        local: &Path,
+        remote: &str,
+    ) -> Result<()> {
+        let (parent, name) = split_remote(remote)?;
+        let archive = super::tar_path(local, name)?;
+        let status = self
+            .shell(device, Some(package))
+            .arg("mkdir")
+            .arg("-p")
+            .arg(parent)
+            .status()?;
+        anyhow::ensure!(status.success(), "failed to create {}", parent);
+        let mut child = self
+            .adb(device)
+            .arg("exec-in")
+            .arg("run-as")
+            .arg(package)
+            .arg("tar")
+            .arg("-xf")
+            .arg("-")
+            .arg("-C")
+            .arg(parent)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(&archive)?;
+        anyhow::ensure!(child.wait()?.success(), "failed to push {}", remote);
         Ok(())
     }
 
+    /// Copies `remote` in the data dir of `package` to `local`.
+    pub fn pull_app_data(
+        &self,
+        device: &str,
+        package: &str,
+        remote: &str,
+        local: &Path,
+    ) -> Result<()> {
+        let (parent, name) = split_remote(remote)?;
+        let output = self
+            .adb(device)
+            .arg("exec-out")
+            .arg("run-as")
+            .arg(package)
+            .arg("tar")
+            .arg("-cf")
+            .arg("-")
+            .arg("-C")
+            .arg(parent)
+            .arg(name)
+            .output()?;
+        anyhow::ensure!(
+            output.status.success() && !output.stdout.is_empty(),
+            "failed to pull {}: {}",
+            remote,
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        super::untar_path(&output.stdout, name, local)
+    }
+
+    pub fn checks(&self, device: &str, min_sdk: u32) -> Vec<(&'static str, Result<String>)> {
+        let authorized = (|| {
+            let output = self.adb(device).arg("get-state").output()?;
+            let state = std::str::from_utf8(&output.stdout)?.trim();
+            let error = std::str::from_utf8(&output.stderr)?.trim();
+            anyhow::ensure!(
+                output.status.success() && state == "device",
+                "{}{}, accept the usb debugging prompt on the device",
+                state,
+                error
+            );
+            Ok("yes".to_string())
+        })();
+        let api_level = (|| {
+            let sdk: u32 = self.getprop(device, "ro.build.version.sdk")?.parse()?;
+            anyhow::ensure!(
+                sdk >= min_sdk,
+                "api level {} is lower than the min sdk {}",
+                sdk,
+                min_sdk
+            );
+            Ok(format!("{} (min sdk {})", sdk, min_sdk))
+        })();
+        vec![("authorized", authorized), ("api level", api_level)]
+    }
+
     pub fn name(&self, device: &str) -> Result<String> {
         self.getprop(device, "ro.product.device")
     }
@@ -372,6 +970,49 @@ impl Adb {
         Ok(arch)
     }
 
+    pub fn info(&self, device: &str) -> DeviceInfo {
+        let transport = if device.starts_with("emulator-") {
+            "emulator"
+        } else if device.contains(':') || device.contains("._adb-tls-connect.") {
+            "network"
+        } else {
+            "usb"
+        };
+        let state = self
+            .adb(device)
+            .arg("get-state")
+            .output()
+            .ok()
+            .and_then(|output| {
+                let stdout = String::from_utf8(output.stdout).ok()?;
+                let stderr = String::from_utf8(output.stderr).ok()?;
+                // Errors like `device unauthorized` are printed to stderr
+                let state = stdout.trim().to_string();
+                Some(if state.is_empty() {
+                    stderr.trim().trim_start_matches("error: ").to_string()
+                } else {
+                    state
+                })
+            })
+            .unwrap_or_default();
+        let ready = state == "device";
+        let getprop = |prop| self.getprop(device, prop).ok().filter(|_| ready);
+        DeviceInfo {
+            transport,
+            os_version: getprop("ro.build.version.release"),
+            model: getprop("ro.product.model"),
+            features: DeviceFeatures {
+                debugging: ready,
+                // Set on android 11 and later when the incremental file system is available
+                incremental_install: getprop("ro.incremental.enable")
+                    .map(|enabled| !enabled.is_empty())
+                    .unwrap_or_default(),
+            },
+            state,
+            ..Default::default()
+        }
+    }
+
     pub fn details(&self, device: &str) -> Result<String> {
         let release = self.getprop(device, "ro.build.version.release")?;
         let sdk = self.getprop(device, "ro.build.version.sdk")?;
@@ -379,51 +1020,82 @@ impl Adb {
     }
 }
 
-pub struct Logcat {
-    child: Child,
-    reader: BufReader<ChildStdout>,
-    line: String,
+fn known_devices_path() -> PathBuf {
+    dirs::config_dir().unwrap().join("x").join("adb_devices")
 }
 
-impl Logcat {
-    fn new(mut child: Child) -> Self {
-        let stdout = child.stdout.take().expect("child missing stdout");
-        let reader = BufReader::new(stdout);
-        Self {
-            child,
-            reader,
-            line: String::with_capacity(1024),
-        }
+/// Addresses of the network devices added with `x devices connect`.
+fn known_devices() -> Result<Vec<String>> {
+    let path = known_devices_path();
+    if !path.exists() {
+        return Ok(vec![]);
     }
+    let known = std::fs::read_to_string(path)?;
+    Ok(known
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
 }
 
-impl Iterator for Logcat {
-    type Item = String;
+fn write_known_devices(known: &[String]) -> Result<()> {
+    let path = known_devices_path();
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let mut contents = known.join("\n");
+    contents.push('\n');
+    std::fs::write(path, contents)?;
+    Ok(())
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            self.line.clear();
-            match self.reader.read_line(&mut self.line) {
-                Ok(0) => return None,
-                Ok(_) => {
-                    let line = self.line.trim();
-                    if let Some((date, line)) = line.split_once(' ') {
-                        if let Some((time, line)) = line.split_once(' ') {
-                            if date.len() == 5 && time.len() == 12 {
-                                return Some(line.to_string());
-                            }
-                        }
-                    }
-                    return Some(self.line.clone());
-                }
-                Err(_) => {}
-            }
-        }
+/// Splits a path relative to the data dir into its parent and file name.
+fn split_remote(remote: &str) -> Result<(&str, &str)> {
+    let remote = remote.trim_end_matches('/');
+    let (parent, name) = remote.rsplit_once('/').unwrap_or((".", remote));
+    anyhow::ensure!(
+        !name.is_empty() && name != "." && name != "..",
+        "invalid device path `{}`",
+        remote
+    );
+    Ok((if parent.is_empty() { "/" } else { parent }, name))
+}
+
+/// Command line running the executable `name` pushed to `/data/local/tmp`.
+/// `adb shell` joins its arguments and the device shell splits them again,
+/// so they're quoted.
+fn executable_command(name: &str, args: &[String]) -> String {
+    let mut command = format!("cd /data/local/tmp && {}", sh_quote(&format!("./{}", name)));
+    for arg in args {
+        command.push(' ');
+        command.push_str(&sh_quote(arg));
     }
+    command
 }
 
-impl Drop for Logcat {
-    fn drop(&mut self) {
-        self.child.kill().ok();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executable_command() {
+        assert_eq!(
+            executable_command("tests-1234", &[]),
+            "cd /data/local/tmp && './tests-1234'"
+        );
+        let args = ["my test".to_string(), "it's|*;".to_string()];
+        assert_eq!(
+            executable_command("tests", &args),
+            r"cd /data/local/tmp && './tests' 'my test' 'it'\''s|*;'"
+        );
+    }
+
+    #[test]
+    fn test_split_remote() {
+        assert_eq!(split_remote("files/app.db").unwrap(), ("files", "app.db"));
+        assert_eq!(split_remote("files/dir/").unwrap(), ("files", "dir"));
+        assert_eq!(split_remote("app.db").unwrap(), (".", "app.db"));
+        assert_eq!(split_remote("/app.db").unwrap(), ("/", "app.db"));
+        assert!(split_remote("").is_err());
+        assert!(split_remote("files/..").is_err());
+        assert!(split_remote(".").is_err());
     }
 }
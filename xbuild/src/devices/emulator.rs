@@ -0,0 +1,378 @@
+use crate::devices::adb::Adb;
+use crate::devices::{stream_output, LogOutput};
+use crate::{Arch, Categorize, ErrorKind, Platform};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Android emulator or ios simulator.
+#[derive(Debug)]
+pub struct VirtualDevice {
+    pub name: String,
+    pub platform: Platform,
+    /// Adb serial of a running emulator or udid of a simulator.
+    pub id: Option<String>,
+    pub running: bool,
+}
+
+/// Finds a tool in the android sdk at `ANDROID_HOME`, falling back to `PATH`.
+fn sdk_tool(dir: &str, name: &str) -> Result<PathBuf> {
+    let home = std::env::var_os("ANDROID_HOME").or_else(|| std::env::var_os("ANDROID_SDK_ROOT"));
+    if let Some(home) = home {
+        let path = PathBuf::from(home).join(dir).join(name);
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+    which::which(name)
+        .with_context(|| format!("{} not found", name))
+        .categorize(
+            ErrorKind::Environment,
+            "install it with the android sdkmanager and set `ANDROID_HOME`",
+        )
+}
+
+/// Android virtual devices managed with avdmanager and the emulator.
+pub(crate) struct Avd {
+    adb: Adb,
+    emulator: PathBuf,
+}
+
+impl Avd {
+    pub fn which() -> Result<Self> {
+        Ok(Self {
+            adb: Adb::which()?,
+            emulator: sdk_tool("emulator", exe!("emulator"))?,
+        })
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        let output = Command::new(&self.emulator).arg("-list-avds").output()?;
+        anyhow::ensure!(output.status.success(), "emulator -list-avds failed");
+        Ok(std::str::from_utf8(&output.stdout)?
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty() && !line.starts_with("INFO"))
+            .collect())
+    }
+
+    /// Adb serials of the running emulators by avd name.
+    fn running(&self) -> Result<HashMap<String, String>> {
+        let mut running = HashMap::new();
+        for serial in self.adb.serials()? {
+            if !serial.starts_with("emulator-") {
+                continue;
+            }
+            if let Ok(name) = self.adb.emu(&serial, &["avd", "name"]) {
+                if let Some(name) = name.lines().next() {
+                    running.insert(name.trim().to_string(), serial);
+                }
+            }
+        }
+        Ok(running)
+    }
+
+    pub fn list(&self, devices: &mut Vec<VirtualDevice>) -> Result<()> {
+        let running = self.running()?;
+        for name in self.names()? {
+            let id = running.get(&name).cloned();
+            devices.push(VirtualDevice {
+                running: id.is_some(),
+                platform: Platform::Android,
+                name,
+                id,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.names()?.iter().any(|avd| avd == name))
+    }
+
+    /// Creates an avd from a system image installed with the sdkmanager, by
+    /// default the google apis image of android 33 for the host arch.
+    pub fn create(name: &str, image: Option<&str>, device_type: Option<&str>) -> Result<()> {
+        let avdmanager = sdk_tool("cmdline-tools/latest/bin", "avdmanager")?;
+        let default_image;
+        let image = if let Some(image) = image {
+            image
+        } else {
+            let abi = match Arch::host()? {
+                Arch::Arm64 => "arm64-v8a",
+                _ => "x86_64",
+            };
+            default_image = format!("system-images;android-33;google_apis;{}", abi);
+            &default_image
+        };
+        let mut child = Command::new(avdmanager)
+            .arg("create")
+            .arg("avd")
+            .arg("--name")
+            .arg(name)
+            .arg("--package")
+            .arg(image)
+            .arg("--device")
+            .arg(device_type.unwrap_or("pixel"))
+            .stdin(Stdio::piped())
+            .spawn()?;
+        // Declines creating a custom hardware profile
+        child.stdin.take().unwrap().write_all(b"no\n")?;
+        anyhow::ensure!(
+            child.wait()?.success(),
+            "avdmanager failed, `sdkmanager \"{}\"` installs the system image",
+            image
+        );
+        Ok(())
+    }
+
+    /// Boots the avd unless it's running already and waits for it to finish
+    /// booting, returning its adb serial.
+    pub fn start(&self, name: &str, headless: bool) -> Result<String> {
+        if let Some(serial) = self.running()?.remove(name) {
+            return Ok(serial);
+        }
+        anyhow::ensure!(self.exists(name)?, "no avd named {}", name);
+        let mut emulator = Command::new(&self.emulator);
+        emulator.arg("-avd").arg(name).arg("-no-snapshot-save");
+        if headless {
+            emulator.arg("-no-window").arg("-no-audio");
+        }
+        // The emulator keeps running after x exits
+        emulator
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let start = Instant::now();
+        let timeout = Duration::from_secs(300);
+        let serial = loop {
+            anyhow::ensure!(start.elapsed() < timeout, "timed out starting {}", name);
+            if let Some(serial) = self.running()?.remove(name) {
+                break serial;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        };
+        while self
+            .adb
+            .getprop(&serial, "sys.boot_completed")
+            .ok()
+            .as_deref()
+            != Some("1")
+        {
+            anyhow::ensure!(start.elapsed() < timeout, "timed out booting {}", name);
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        Ok(serial)
+    }
+
+    pub fn stop(&self, name: &str) -> Result<()> {
+        let serial = self
+            .running()?
+            .remove(name)
+            .with_context(|| format!("{} is not running", name))?;
+        self.adb.emu(&serial, &["kill"])?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct SimctlList {
+    devices: HashMap<String, Vec<Simulator>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Simulator {
+    name: String,
+    udid: String,
+    state: String,
+    is_available: Option<bool>,
+}
+
+/// Ios simulators managed with `xcrun simctl`.
+pub(crate) struct Simctl;
+
+impl Simctl {
+    pub fn which() -> Result<Self> {
+        anyhow::ensure!(
+            cfg!(target_os = "macos"),
+            "ios simulators are only available on macos"
+        );
+        which::which("xcrun")?;
+        Ok(Self)
+    }
+
+    fn simctl(&self) -> Command {
+        let mut cmd = Command::new("xcrun");
+        cmd.arg("simctl");
+        cmd
+    }
+
+    fn simulators(&self) -> Result<Vec<Simulator>> {
+        let output = self
+            .simctl()
+            .arg("list")
+            .arg("devices")
+            .arg("--json")
+            .output()?;
+        anyhow::ensure!(output.status.success(), "simctl list failed");
+        let list: SimctlList = serde_json::from_slice(&output.stdout)?;
+        let mut simulators = list
+            .devices
+            .into_values()
+            .flatten()
+            .filter(|simulator| simulator.is_available.unwrap_or(true))
+            .collect::<Vec<_>>();
+        simulators.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(simulators)
+    }
+
+    /// Udid of the simulator with `name` or udid `name`.
+    fn find(&self, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .simulators()?
+            .into_iter()
+            .find(|simulator| simulator.name == name || simulator.udid == name)
+            .map(|simulator| simulator.udid))
+    }
+
+    pub fn list(&self, devices: &mut Vec<VirtualDevice>) -> Result<()> {
+        for simulator in self.simulators()? {
+            devices.push(VirtualDevice {
+                name: simulator.name,
+                platform: Platform::Ios,
+                running: simulator.state == "Booted",
+                id: Some(simulator.udid),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.find(name)?.is_some())
+    }
+
+    /// Creates a simulator with the newest installed runtime.
+    pub fn create(&self, name: &str, device_type: Option<&str>) -> Result<String> {
+        let output = self
+            .simctl()
+            .arg("create")
+            .arg(name)
+            .arg(device_type.unwrap_or("iPhone 14"))
+            .output()?;
+        anyhow::ensure!(
+            output.status.success(),
+            "simctl create failed: {}",
+            std::str::from_utf8(&output.stderr)?.trim()
+        );
+        Ok(std::str::from_utf8(&output.stdout)?.trim().to_string())
+    }
+
+    /// Boots the simulator and waits until it finished booting, returning
+    /// its udid.
+    pub fn start(&self, name: &str, headless: bool) -> Result<String> {
+        let udid = self
+            .find(name)?
+            .with_context(|| format!("no simulator named {}", name))?;
+        // Boots the simulator if needed and blocks until it's ready
+        let status = self
+            .simctl()
+            .arg("bootstatus")
+            .arg(&udid)
+            .arg("-b")
+            .stdout(Stdio::null())
+            .status()?;
+        anyhow::ensure!(status.success(), "failed to boot {}", name);
+        if !headless {
+            Command::new("open").arg("-a").arg("Simulator").status()?;
+        }
+        Ok(udid)
+    }
+
+    /// Runs `executable` in the booted simulator with its output relayed,
+    /// returning its exit code.
+    pub fn spawn(&self, name: &str, executable: &Path, args: &[String]) -> Result<i32> {
+        let udid = self
+            .find(name)?
+            .with_context(|| format!("no simulator named {}", name))?;
+        let status = self
+            .simctl()
+            .arg("spawn")
+            .arg(udid)
+            .arg(executable)
+            .args(args)
+            .status()?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    pub fn screenshot(&self, name: &str, path: &Path) -> Result<()> {
+        let udid = self
+            .find(name)?
+            .with_context(|| format!("no simulator named {}", name))?;
+        let status = self
+            .simctl()
+            .arg("io")
+            .arg(udid)
+            .arg("screenshot")
+            .arg(path)
+            .status()?;
+        anyhow::ensure!(status.success(), "simctl screenshot failed");
+        Ok(())
+    }
+
+    /// Streams the unified log entries of the process `process`.
+    pub fn logs(&self, name: &str, process: &str, output: &mut LogOutput) -> Result<()> {
+        let udid = self
+            .find(name)?
+            .with_context(|| format!("no simulator named {}", name))?;
+        let mut cmd = self.simctl();
+        cmd.arg("spawn")
+            .arg(udid)
+            .arg("log")
+            .arg("stream")
+            .arg("--style")
+            .arg("compact")
+            .arg("--predicate")
+            .arg(format!("process == \"{}\"", process));
+        let status = stream_output(&mut cmd, output)?;
+        anyhow::ensure!(status.success(), "simctl log stream failed");
+        Ok(())
+    }
+
+    pub fn record(&self, name: &str, path: &Path, duration: Duration) -> Result<()> {
+        let udid = self
+            .find(name)?
+            .with_context(|| format!("no simulator named {}", name))?;
+        let mut child = self
+            .simctl()
+            .arg("io")
+            .arg(udid)
+            .arg("recordVideo")
+            .arg("--force")
+            .arg(path)
+            .spawn()?;
+        std::thread::sleep(duration);
+        // recordVideo finishes writing the file on SIGINT
+        let status = Command::new("kill")
+            .arg("-INT")
+            .arg(child.id().to_string())
+            .status()?;
+        anyhow::ensure!(status.success(), "failed to stop recording");
+        anyhow::ensure!(child.wait()?.success(), "simctl recordVideo failed");
+        Ok(())
+    }
+
+    pub fn stop(&self, name: &str) -> Result<()> {
+        let udid = self
+            .find(name)?
+            .with_context(|| format!("no simulator named {}", name))?;
+        let status = self.simctl().arg("shutdown").arg(udid).status()?;
+        anyhow::ensure!(status.success(), "failed to shut down {}", name);
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
-use crate::{Arch, Platform};
-use anyhow::Result;
-use std::path::Path;
-use std::process::Command;
+use crate::devices::{stream_output, DeviceFeatures, DeviceInfo, LogOutput};
+use crate::{Arch, Platform, Profiler};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub(crate) struct Host;
@@ -26,6 +28,19 @@ impl Host {
         Arch::host()
     }
 
+    pub fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            transport: "local",
+            state: "device".to_string(),
+            os_version: self.details().ok().filter(|details| !details.is_empty()),
+            features: DeviceFeatures {
+                debugging: which::which("lldb").is_ok(),
+                incremental_install: false,
+            },
+            ..Default::default()
+        }
+    }
+
     pub fn details(&self) -> Result<String> {
         if cfg!(target_os = "linux") {
             let os_release = std::fs::read_to_string("/etc/os-release")?;
@@ -51,8 +66,262 @@ impl Host {
         Ok(())
     }
 
+    /// Starts the app without waiting for it to exit.
+    pub fn spawn(&self, path: &Path) -> Result<Child> {
+        Ok(Command::new(path).spawn()?)
+    }
+
+    /// Installs an app bundle to `/Applications` on macos, an appimage to
+    /// `~/.local/bin` on linux or an msix package on windows.
+    pub fn install(&self, path: &Path) -> Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        match extension {
+            "app" if cfg!(target_os = "macos") => {
+                let dest = Path::new("/Applications").join(path.file_name().unwrap());
+                if dest.exists() {
+                    std::fs::remove_dir_all(&dest)?;
+                }
+                std::fs::create_dir_all(&dest)?;
+                xcommon::copy_dir_all(path, &dest)?;
+                println!("installed {}", dest.display());
+            }
+            "AppImage" if cfg!(target_os = "linux") => {
+                let dir = appimage_dir()?;
+                std::fs::create_dir_all(&dir)?;
+                let dest = dir.join(path.file_name().unwrap());
+                std::fs::copy(path, &dest)?;
+                println!("installed {}", dest.display());
+            }
+            "msix" if cfg!(target_os = "windows") => {
+                let status = Command::new("powershell")
+                    .arg("-NoProfile")
+                    .arg("-Command")
+                    .arg("Add-AppxPackage")
+                    .arg("-Path")
+                    .arg(path)
+                    .status()?;
+                anyhow::ensure!(status.success(), "Add-AppxPackage failed");
+            }
+            _ => anyhow::bail!(
+                "can't install {} on the host, build an appbundle on macos, an appimage on linux or an msix on windows",
+                path.display()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Removes an app installed with [`Self::install`], `name` is the app
+    /// name or the msix identity name on windows.
+    pub fn uninstall(&self, name: &str) -> Result<()> {
+        if cfg!(target_os = "windows") {
+            let status = Command::new("powershell")
+                .arg("-NoProfile")
+                .arg("-Command")
+                .arg(format!(
+                    "Get-AppxPackage -Name '{}' | Remove-AppxPackage",
+                    name
+                ))
+                .status()?;
+            anyhow::ensure!(status.success(), "Remove-AppxPackage failed");
+            return Ok(());
+        }
+        let path = if cfg!(target_os = "macos") {
+            Path::new("/Applications").join(format!("{}.app", name))
+        } else {
+            appimage_dir()?.join(format!("{}.AppImage", name))
+        };
+        anyhow::ensure!(path.exists(), "{} is not installed", name);
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else {
+            std::fs::remove_file(&path)?;
+        }
+        println!("removed {}", path.display());
+        Ok(())
+    }
+
+    pub fn run_executable(&self, path: &Path, args: &[String]) -> Result<i32> {
+        let status = Command::new(path).args(args).status()?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    /// Streams the log entries of the running app without launching it,
+    /// from the unified log of the process `name` on macos and the user
+    /// journal of `executable` on linux, which desktop sessions route the
+    /// output of launched apps to.
+    pub fn logs(&self, name: &str, executable: &Path, output: &mut LogOutput) -> Result<()> {
+        let mut cmd = if cfg!(target_os = "macos") {
+            let mut cmd = Command::new("log");
+            cmd.arg("stream")
+                .arg("--style")
+                .arg("compact")
+                .arg("--process")
+                .arg(name);
+            cmd
+        } else if cfg!(target_os = "linux") {
+            which::which("journalctl")
+                .context("journalctl not found, run the app with `x run` to see its output")?;
+            let identifier = executable.file_name().unwrap().to_string_lossy();
+            let mut cmd = Command::new("journalctl");
+            cmd.arg("--user")
+                .arg("--follow")
+                .arg("--lines")
+                .arg("0")
+                .arg("--output")
+                .arg("cat")
+                .arg("--identifier")
+                .arg(identifier.as_ref());
+            cmd
+        } else {
+            anyhow::bail!(
+                "the output of windows apps isn't logged, run the app with `x run` to see it"
+            );
+        };
+        stream_output(&mut cmd, output)?;
+        Ok(())
+    }
+
+    pub fn screenshot(&self, path: &Path) -> Result<()> {
+        let mut cmd = if cfg!(target_os = "macos") {
+            let mut cmd = Command::new("screencapture");
+            cmd.arg("-x");
+            cmd
+        } else if cfg!(target_os = "linux") {
+            if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                Command::new("grim")
+            } else {
+                let mut cmd = Command::new("import");
+                cmd.arg("-window").arg("root");
+                cmd
+            }
+        } else {
+            anyhow::bail!("screenshots are not supported on this host");
+        };
+        cmd.arg(path);
+        let program = cmd.get_program().to_string_lossy().to_string();
+        let status = cmd
+            .status()
+            .with_context(|| format!("failed to run {}", program))?;
+        anyhow::ensure!(status.success(), "failed to take screenshot");
+        Ok(())
+    }
+
+    pub fn record(&self, path: &Path, duration: Duration) -> Result<()> {
+        let secs = duration.as_secs().to_string();
+        let status = if cfg!(target_os = "macos") {
+            Command::new("screencapture")
+                .arg("-x")
+                .arg("-v")
+                .arg("-V")
+                .arg(secs)
+                .arg(path)
+                .status()
+                .context("failed to run screencapture")?
+        } else if cfg!(target_os = "linux") {
+            let display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".into());
+            Command::new("ffmpeg")
+                .arg("-y")
+                .arg("-loglevel")
+                .arg("error")
+                .arg("-f")
+                .arg("x11grab")
+                .arg("-t")
+                .arg(secs)
+                .arg("-i")
+                .arg(display)
+                .arg("-pix_fmt")
+                .arg("yuv420p")
+                .arg(path)
+                .status()
+                .context("failed to run ffmpeg")?
+        } else {
+            anyhow::bail!("screen recording is not supported on this host");
+        };
+        anyhow::ensure!(status.success(), "failed to record the screen");
+        Ok(())
+    }
+
+    /// Runs `executable` and profiles it for `duration`, writing the trace
+    /// to `out`.
+    pub fn profile(
+        &self,
+        executable: &Path,
+        profiler: Profiler,
+        duration: Duration,
+        out: &Path,
+    ) -> Result<()> {
+        let secs = duration.as_secs().to_string();
+        if profiler == Profiler::Instruments {
+            let status = Command::new("xcrun")
+                .arg("xctrace")
+                .arg("record")
+                .arg("--template")
+                .arg("Time Profiler")
+                .arg("--time-limit")
+                .arg(format!("{}s", secs))
+                .arg("--output")
+                .arg(out)
+                .arg("--launch")
+                .arg("--")
+                .arg(executable)
+                .status()
+                .context("failed to run xctrace")?;
+            anyhow::ensure!(status.success(), "xctrace record failed");
+            return Ok(());
+        }
+        let mut app = Command::new(executable).spawn()?;
+        let pid = app.id().to_string();
+        let status = match profiler {
+            Profiler::Perf => Command::new("perf")
+                .arg("record")
+                .arg("-F")
+                .arg("997")
+                .arg("-g")
+                .arg("-o")
+                .arg(out)
+                .arg("-p")
+                .arg(&pid)
+                .arg("--")
+                .arg("sleep")
+                .arg(&secs)
+                .status()
+                .context("failed to run perf"),
+            // dtrace needs root
+            Profiler::Dtrace => Command::new("sudo")
+                .arg("dtrace")
+                .arg("-x")
+                .arg("ustackframes=100")
+                .arg("-p")
+                .arg(&pid)
+                .arg("-n")
+                .arg(format!(
+                    "profile-997 /pid == $target/ {{ @[ustack()] = count(); }} tick-{}s {{ exit(0); }}",
+                    secs
+                ))
+                .arg("-o")
+                .arg(out)
+                .status()
+                .context("failed to run dtrace"),
+            _ => Err(anyhow::anyhow!("{} is not available on the host", profiler)),
+        };
+        app.kill().ok();
+        app.wait().ok();
+        anyhow::ensure!(status?.success(), "{} failed", profiler);
+        Ok(())
+    }
+
     pub fn lldb(&self, executable: &Path) -> Result<()> {
         Command::new("lldb").arg(executable).status()?;
         Ok(())
     }
 }
+
+fn appimage_dir() -> Result<PathBuf> {
+    Ok(dirs::home_dir()
+        .context("no home directory")?
+        .join(".local")
+        .join("bin"))
+}
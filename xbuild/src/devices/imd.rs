@@ -1,8 +1,11 @@
-use crate::devices::{Backend, Device};
+use crate::devices::{
+    print_line, stream_output, Backend, Device, DeviceFeatures, DeviceInfo, LogOutput,
+};
 use crate::{Arch, BuildEnv, Platform};
 use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, Stdio};
 
 #[derive(Clone, Debug)]
 pub(crate) struct IMobileDevice {
@@ -12,6 +15,8 @@ pub(crate) struct IMobileDevice {
     ideviceinstaller: PathBuf,
     idevicedebug: PathBuf,
     idevicedebugserverproxy: PathBuf,
+    /// Talk to the device over the network instead of usb.
+    network: bool,
 }
 
 impl IMobileDevice {
@@ -23,13 +28,48 @@ impl IMobileDevice {
             ideviceinstaller: which::which(exe!("ideviceinstaller"))?,
             idevicedebug: which::which(exe!("idevicedebug"))?,
             idevicedebugserverproxy: which::which(exe!("idevicedebugserverproxy"))?,
+            network: false,
         })
     }
 
+    /// Selects the network transport if the device is only reachable over
+    /// wi-fi.
+    pub fn for_device(mut self, device: &str) -> Result<Self> {
+        let usb = self.list(false)?;
+        if !usb.iter().any(|udid| udid == device)
+            && self.list(true)?.iter().any(|udid| udid == device)
+        {
+            self.network = true;
+        }
+        Ok(self)
+    }
+
+    fn command(&self, tool: &Path, device: &str) -> Command {
+        let mut cmd = Command::new(tool);
+        cmd.arg("--udid").arg(device);
+        if self.network {
+            cmd.arg("--network");
+        }
+        cmd
+    }
+
+    /// Udids of the paired devices connected over usb or the network.
+    fn list(&self, network: bool) -> Result<Vec<String>> {
+        let output = Command::new(&self.idevice_id)
+            .arg("-l")
+            .arg(if network { "-n" } else { "-d" })
+            .output()?;
+        anyhow::ensure!(output.status.success(), "failed to run idevice_id");
+        Ok(std::str::from_utf8(&output.stdout)?
+            .lines()
+            .map(|udid| udid.trim().to_string())
+            .filter(|udid| !udid.is_empty())
+            .collect())
+    }
+
     fn getkey(&self, device: &str, key: &str) -> Result<String> {
-        let output = Command::new(&self.ideviceinfo)
-            .arg("--udid")
-            .arg(device)
+        let output = self
+            .command(&self.ideviceinfo, device)
             .arg("--key")
             .arg(key)
             .output()?;
@@ -37,10 +77,9 @@ impl IMobileDevice {
         Ok(std::str::from_utf8(&output.stdout)?.trim().to_string())
     }
 
-    fn install(&self, device: &str, path: &Path) -> Result<()> {
-        let status = Command::new(&self.ideviceinstaller)
-            .arg("--udid")
-            .arg(device)
+    pub fn install(&self, device: &str, path: &Path) -> Result<()> {
+        let status = self
+            .command(&self.ideviceinstaller, device)
             .arg("--install")
             .arg(path)
             .status()?;
@@ -48,21 +87,36 @@ impl IMobileDevice {
         Ok(())
     }
 
-    fn start(&self, device: &str, bundle_identifier: &str) -> Result<()> {
-        let status = Command::new(&self.idevicedebug)
-            .arg("--udid")
-            .arg(device)
-            .arg("run")
+    pub fn uninstall(&self, device: &str, bundle_identifier: &str) -> Result<()> {
+        let status = self
+            .command(&self.ideviceinstaller, device)
+            .arg("--uninstall")
             .arg(bundle_identifier)
             .status()?;
+        anyhow::ensure!(status.success(), "failed to run ideviceinstaller");
+        Ok(())
+    }
+
+    fn start(&self, device: &str, bundle_identifier: &str, prefix: Option<&str>) -> Result<()> {
+        let mut cmd = self.command(&self.idevicedebug, device);
+        cmd.arg("run").arg(bundle_identifier);
+        let status = if let Some(prefix) = prefix {
+            let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+            let stdout = BufReader::new(child.stdout.take().unwrap());
+            for line in stdout.lines() {
+                print_line(Some(prefix), line?);
+            }
+            child.wait()?
+        } else {
+            cmd.status()?
+        };
         anyhow::ensure!(status.success(), "failed to run idevicedebug");
         Ok(())
     }
 
     fn disk_image_mounted(&self, device: &str) -> Result<bool> {
-        let output = Command::new(&self.ideviceimagemounter)
-            .arg("--udid")
-            .arg(device)
+        let output = self
+            .command(&self.ideviceimagemounter, device)
             .arg("-l")
             .output()?;
         anyhow::ensure!(output.status.success(), "failed to run ideviceimagemounter");
@@ -83,39 +137,192 @@ impl IMobileDevice {
         if self.disk_image_mounted(device)? {
             return Ok(());
         }
-        let status = Command::new(&self.ideviceimagemounter)
-            .arg("--udid")
-            .arg(device)
+        let status = self
+            .command(&self.ideviceimagemounter, device)
             .arg(disk_image)
             .status()?;
         anyhow::ensure!(status.success(), "failed to run ideviceimagemounter");
         Ok(())
     }
 
-    pub fn run(&self, env: &BuildEnv, device: &str, path: &Path) -> Result<()> {
+    pub fn run(
+        &self,
+        env: &BuildEnv,
+        device: &str,
+        path: &Path,
+        prefix: Option<&str>,
+    ) -> Result<()> {
         let bundle_identifier = appbundle::app_bundle_identifier(path)?;
         self.mount_disk_image(env, device)?;
         self.install(device, path)?;
-        self.start(device, &bundle_identifier)?;
+        self.start(device, &bundle_identifier, prefix)?;
         Ok(())
     }
 
+    /// Installs and launches the app without waiting for it, the returned
+    /// idevicedebug process prints its output until it is killed.
+    pub fn spawn(&self, env: &BuildEnv, device: &str, path: &Path) -> Result<Child> {
+        let bundle_identifier = appbundle::app_bundle_identifier(path)?;
+        self.mount_disk_image(env, device)?;
+        self.install(device, path)?;
+        let mut cmd = self.command(&self.idevicedebug, device);
+        Ok(cmd.arg("run").arg(bundle_identifier).spawn()?)
+    }
+
+    /// Launches the installed app, idevicedebug stays attached and prints
+    /// its output.
+    pub fn attach(&self, env: &BuildEnv, device: &str, bundle_identifier: &str) -> Result<()> {
+        self.mount_disk_image(env, device)?;
+        self.start(device, bundle_identifier, None)
+    }
+
     pub fn devices(&self, devices: &mut Vec<Device>) -> Result<()> {
-        let output = Command::new(&self.idevice_id)
-            .arg("-l")
-            .arg("-d")
-            .output()?;
-        anyhow::ensure!(output.status.success(), "failed to run idevice_id");
-        let lines = std::str::from_utf8(&output.stdout)?.lines();
-        for uuid in lines {
-            devices.push(Device {
-                backend: Backend::Imd(self.clone()),
-                id: uuid.trim().to_string(),
-            });
+        let usb = self.list(false)?;
+        // Devices with wi-fi sync enabled show up on both transports
+        let network = self
+            .list(true)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|udid| !usb.contains(udid))
+            .collect::<Vec<_>>();
+        for (udids, network) in [(usb, false), (network, true)] {
+            for udid in udids {
+                devices.push(Device {
+                    backend: Backend::Imd(Self {
+                        network,
+                        ..self.clone()
+                    }),
+                    id: udid,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Requires the developer disk image to be mounted.
+    pub fn screenshot(&self, device: &str, path: &Path) -> Result<()> {
+        let status = self
+            .command(&which::which(exe!("idevicescreenshot"))?, device)
+            .arg(path)
+            .status()?;
+        anyhow::ensure!(status.success(), "failed to run idevicescreenshot");
+        Ok(())
+    }
+
+    /// Copies files to or from the container of an app installed with a
+    /// development profile, paths on the device are relative to it.
+    fn afc(&self, device: &str, bundle_identifier: &str, args: &[&std::ffi::OsStr]) -> Result<()> {
+        let status = self
+            .command(
+                &which::which(exe!("afcclient"))
+                    .context("afcclient is required, install a recent libimobiledevice")?,
+                device,
+            )
+            .arg("--container")
+            .arg(bundle_identifier)
+            .args(args)
+            .status()?;
+        anyhow::ensure!(status.success(), "afcclient {:?} failed", args);
+        Ok(())
+    }
+
+    pub fn push(
+        &self,
+        device: &str,
+        bundle_identifier: &str,
+        local: &Path,
+        remote: &str,
+    ) -> Result<()> {
+        let mut args = vec!["put".as_ref()];
+        if local.is_dir() {
+            args.push("-r".as_ref());
         }
+        args.extend([local.as_os_str(), remote.as_ref()]);
+        self.afc(device, bundle_identifier, &args)
+    }
+
+    pub fn pull(
+        &self,
+        device: &str,
+        bundle_identifier: &str,
+        remote: &str,
+        local: &Path,
+    ) -> Result<()> {
+        self.afc(
+            device,
+            bundle_identifier,
+            &[
+                "get".as_ref(),
+                "-r".as_ref(),
+                remote.as_ref(),
+                local.as_os_str(),
+            ],
+        )
+    }
+
+    /// Streams the syslog entries of `process`.
+    pub fn logs(&self, device: &str, process: &str, output: &mut LogOutput) -> Result<()> {
+        let mut cmd = self.command(&which::which(exe!("idevicesyslog"))?, device);
+        cmd.arg("--process").arg(process).arg("--no-colors");
+        let status = stream_output(&mut cmd, output)?;
+        anyhow::ensure!(status.success(), "failed to run idevicesyslog");
         Ok(())
     }
 
+    pub fn checks(&self, device: &str) -> Vec<(&'static str, Result<String>)> {
+        let paired = (|| {
+            // Pairing happens over usb, network devices are listed once paired
+            if self.network {
+                return Ok("yes (wi-fi)".to_string());
+            }
+            let status = Command::new(which::which(exe!("idevicepair"))?)
+                .arg("--udid")
+                .arg(device)
+                .arg("validate")
+                .output()?
+                .status;
+            anyhow::ensure!(status.success(), "not paired, run `idevicepair pair`");
+            Ok("yes".to_string())
+        })();
+        let developer_mode = (|| {
+            let output = self
+                .command(&self.ideviceinfo, device)
+                .arg("--domain")
+                .arg("com.apple.security.mac.amfi")
+                .arg("--key")
+                .arg("DeveloperModeStatus")
+                .output()?;
+            let status = std::str::from_utf8(&output.stdout)?.trim();
+            anyhow::ensure!(
+                status != "false",
+                "disabled, enable it in Settings > Privacy & Security"
+            );
+            Ok("enabled".to_string())
+        })();
+        let provisioning = (|| {
+            let output = self
+                .command(&which::which(exe!("ideviceprovision"))?, device)
+                .arg("list")
+                .output()?;
+            anyhow::ensure!(output.status.success(), "failed to run ideviceprovision");
+            let profiles = std::str::from_utf8(&output.stdout)?
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            Ok(profiles)
+        })();
+        let version = self
+            .product_version(device)
+            .map(|(major, minor)| format!("{}.{}", major, minor));
+        vec![
+            ("paired", paired),
+            ("ios version", version),
+            ("developer mode", developer_mode),
+            ("provisioning profiles", provisioning),
+        ]
+    }
+
     pub fn name(&self, device: &str) -> Result<String> {
         self.getkey(device, "DeviceName")
     }
@@ -138,16 +345,37 @@ impl IMobileDevice {
         Ok((major.parse()?, minor.parse()?))
     }
 
+    pub fn info(&self, device: &str) -> DeviceInfo {
+        let version = self.getkey(device, "ProductVersion").ok();
+        DeviceInfo {
+            transport: if self.network { "network" } else { "usb" },
+            // Lockdown only answers for paired devices
+            state: if version.is_some() {
+                "device"
+            } else {
+                "unpaired"
+            }
+            .to_string(),
+            features: DeviceFeatures {
+                debugging: version.is_some(),
+                incremental_install: false,
+            },
+            os_version: version,
+            model: self.getkey(device, "ProductType").ok(),
+            ..Default::default()
+        }
+    }
+
     pub fn details(&self, device: &str) -> Result<String> {
         let name = self.getkey(device, "ProductName")?;
         let version = self.getkey(device, "ProductVersion")?;
-        Ok(format!("{} {}", name, version))
+        let transport = if self.network { " (wi-fi)" } else { "" };
+        Ok(format!("{} {}{}", name, version, transport))
     }
 
     pub fn bundle_path_device(&self, device: &str, bundle_identifier: &str) -> Result<PathBuf> {
-        let output = Command::new(&self.ideviceinstaller)
-            .arg("--udid")
-            .arg(device)
+        let output = self
+            .command(&self.ideviceinstaller, device)
             .arg("-l")
             .arg("-o")
             .arg("xml")
@@ -176,11 +404,8 @@ impl IMobileDevice {
     }
 
     pub fn start_debug_server_proxy(&self, device: &str, port: u16) -> Result<()> {
-        let mut cmd = Command::new(&self.idevicedebugserverproxy);
-        cmd.arg("--udid")
-            .arg(device)
-            .arg("--lldb")
-            .arg(port.to_string());
+        let mut cmd = self.command(&self.idevicedebugserverproxy, device);
+        cmd.arg("--lldb").arg(port.to_string());
         std::thread::spawn(move || {
             cmd.status().unwrap();
         });
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use console::style;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStdout};
+
+/// Log priority of a logcat entry, ordered from least to most severe.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Priority {
+    Verbose,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Verbose => write!(f, "V"),
+            Self::Debug => write!(f, "D"),
+            Self::Info => write!(f, "I"),
+            Self::Warn => write!(f, "W"),
+            Self::Error => write!(f, "E"),
+            Self::Fatal => write!(f, "F"),
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = anyhow::Error;
+
+    fn from_str(priority: &str) -> Result<Self> {
+        Ok(match priority.to_lowercase().as_str() {
+            "v" | "verbose" => Self::Verbose,
+            "d" | "debug" => Self::Debug,
+            "i" | "info" => Self::Info,
+            "w" | "warn" => Self::Warn,
+            "e" | "error" => Self::Error,
+            "f" | "fatal" => Self::Fatal,
+            _ => anyhow::bail!("unsupported log priority {}", priority),
+        })
+    }
+}
+
+/// Shows entries of a tag at or above a priority, parsed from `tag:level`.
+/// The tag `*` matches all tags without a more specific filter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogFilter {
+    tag: String,
+    priority: Priority,
+}
+
+impl std::str::FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(filter: &str) -> Result<Self> {
+        let (tag, priority) = filter
+            .rsplit_once(':')
+            .context("expected log filter as `tag:level`")?;
+        anyhow::ensure!(!tag.is_empty(), "empty tag in log filter {}", filter);
+        Ok(Self {
+            tag: tag.to_string(),
+            priority: priority.parse()?,
+        })
+    }
+}
+
+impl LogFilter {
+    /// Without filters all entries are shown, otherwise entries of tags
+    /// without a filter are hidden.
+    pub fn matches(filters: &[Self], entry: &LogEntry) -> bool {
+        if filters.is_empty() {
+            return true;
+        }
+        filters
+            .iter()
+            .find(|filter| filter.tag == entry.tag)
+            .or_else(|| filters.iter().find(|filter| filter.tag == "*"))
+            .map(|filter| entry.priority >= filter.priority)
+            .unwrap_or_default()
+    }
+}
+
+/// Entry of `logcat -v threadtime`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub pid: u32,
+    pub priority: Priority,
+    pub tag: String,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// Parses `MM-DD HH:MM:SS.mmm  PID  TID P TAG: MESSAGE`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (date, rest) = next_field(line)?;
+        let (time, rest) = next_field(rest)?;
+        if date.len() != 5 || time.len() != 12 {
+            return None;
+        }
+        let (pid, rest) = next_field(rest)?;
+        let (_tid, rest) = next_field(rest)?;
+        let (priority, rest) = next_field(rest)?;
+        let (tag, message) = rest
+            .split_once(": ")
+            .unwrap_or((rest.trim_end_matches(':'), ""));
+        Some(Self {
+            timestamp: format!("{} {}", date, time),
+            pid: pid.parse().ok()?,
+            priority: priority.parse().ok()?,
+            tag: tag.trim().to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+/// Splits off the next whitespace separated field, columns are padded.
+fn next_field(line: &str) -> Option<(&str, &str)> {
+    line.trim_start().split_once(char::is_whitespace)
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prefix = format!("{}/{}:", self.priority, self.tag);
+        let (prefix, message) = match self.priority {
+            Priority::Verbose => (style(prefix).dim(), style(&self.message).dim()),
+            Priority::Debug => (style(prefix).cyan(), style(&self.message)),
+            Priority::Info => (style(prefix).green(), style(&self.message)),
+            Priority::Warn => (style(prefix).yellow(), style(&self.message).yellow()),
+            Priority::Error => (style(prefix).red(), style(&self.message).red()),
+            Priority::Fatal => (
+                style(prefix).red().bold(),
+                style(&self.message).red().bold(),
+            ),
+        };
+        write!(f, "{} {}", prefix, message)
+    }
+}
+
+pub struct Logcat {
+    child: Child,
+    reader: BufReader<ChildStdout>,
+    line: String,
+}
+
+impl Logcat {
+    pub fn new(mut child: Child) -> Self {
+        let stdout = child.stdout.take().expect("child missing stdout");
+        let reader = BufReader::new(stdout);
+        Self {
+            child,
+            reader,
+            line: String::with_capacity(1024),
+        }
+    }
+}
+
+impl Iterator for Logcat {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => return Some(self.line.trim_end().to_string()),
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+impl Drop for Logcat {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let entry =
+            LogEntry::parse("01-25 11:30:42.123  1234  1250 W RustStdoutStderr: hello: world")
+                .unwrap();
+        assert_eq!(
+            entry,
+            LogEntry {
+                timestamp: "01-25 11:30:42.123".into(),
+                pid: 1234,
+                priority: Priority::Warn,
+                tag: "RustStdoutStderr".into(),
+                message: "hello: world".into(),
+            }
+        );
+        let entry = LogEntry::parse("01-25 11:30:42.123   987   987 I MyTag   :").unwrap();
+        assert_eq!(entry.tag, "MyTag");
+        assert_eq!(entry.message, "");
+        assert!(LogEntry::parse("--------- beginning of main").is_none());
+        assert!(LogEntry::parse("01-25 11:30:42.123  pid  1250 W tag: message").is_none());
+    }
+
+    #[test]
+    fn test_filters() {
+        let entry = |tag: &str, priority| LogEntry {
+            timestamp: String::new(),
+            pid: 1,
+            priority,
+            tag: tag.into(),
+            message: String::new(),
+        };
+        assert!(LogFilter::matches(&[], &entry("any", Priority::Verbose)));
+        let filters = ["app:d".parse().unwrap(), "*:error".parse().unwrap()];
+        assert!(LogFilter::matches(&filters, &entry("app", Priority::Debug)));
+        assert!(!LogFilter::matches(
+            &filters,
+            &entry("app", Priority::Verbose)
+        ));
+        assert!(!LogFilter::matches(
+            &filters,
+            &entry("other", Priority::Warn)
+        ));
+        assert!(LogFilter::matches(
+            &filters,
+            &entry("other", Priority::Fatal)
+        ));
+        let filters = ["app:i".parse().unwrap()];
+        assert!(!LogFilter::matches(
+            &filters,
+            &entry("other", Priority::Fatal)
+        ));
+        // Tags may contain colons
+        let filter: LogFilter = "a:b:w".parse().unwrap();
+        assert_eq!(filter.tag, "a:b");
+        assert!(":w".parse::<LogFilter>().is_err());
+        assert!("app:loud".parse::<LogFilter>().is_err());
+    }
+}
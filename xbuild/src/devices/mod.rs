@@ -1,19 +1,102 @@
 use crate::devices::adb::Adb;
 use crate::devices::host::Host;
 use crate::devices::imd::IMobileDevice;
-use crate::{Arch, BuildEnv, Platform};
-use anyhow::Result;
-use std::path::Path;
+use crate::devices::ssh::Ssh;
+use crate::{Arch, BuildEnv, Platform, Profiler};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
 
 mod adb;
+mod emulator;
 mod host;
 mod imd;
+mod logcat;
+mod ssh;
+
+pub(crate) use emulator::{Avd, Simctl, VirtualDevice};
+
+pub use logcat::LogFilter;
+
+/// Lets the app reach a server on the host at `localhost:<device>`, parsed
+/// from `port` or `device:host`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PortForward {
+    pub device: u16,
+    pub host: u16,
+}
+
+impl std::str::FromStr for PortForward {
+    type Err = anyhow::Error;
+
+    fn from_str(forward: &str) -> Result<Self> {
+        let (device, host) = forward.split_once(':').unwrap_or((forward, forward));
+        let port = |port: &str| {
+            port.parse::<u16>().with_context(|| {
+                format!(
+                    "expected port forward as `port` or `device:host`, got {}",
+                    forward
+                )
+            })
+        };
+        Ok(Self {
+            device: port(device)?,
+            host: port(host)?,
+        })
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PortForward {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Port(u16),
+            Mapping(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Port(port) => Ok(Self {
+                device: port,
+                host: port,
+            }),
+            Raw::Mapping(forward) => forward.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 enum Backend {
     Adb(Adb),
     Imd(IMobileDevice),
     Host(Host),
+    Ssh(Ssh),
+}
+
+/// Device metadata for external tooling, values that can't be queried, for
+/// example because the device is unauthorized, are `None`.
+#[derive(Debug, Default, Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: Option<String>,
+    /// `usb`, `network`, `emulator` or `local`
+    pub transport: &'static str,
+    /// `device` when ready, otherwise the state reported by the backend
+    pub state: String,
+    pub platform: Option<String>,
+    pub arch: Option<String>,
+    pub os_version: Option<String>,
+    pub model: Option<String>,
+    pub features: DeviceFeatures,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DeviceFeatures {
+    pub debugging: bool,
+    pub incremental_install: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -31,8 +114,25 @@ impl std::str::FromStr for Device {
         }
         if let Some((backend, id)) = device.split_once(':') {
             let backend = match backend {
-                "adb" => Backend::Adb(Adb::which()?),
-                "imd" => Backend::Imd(IMobileDevice::which()?),
+                "adb" => {
+                    let adb = Adb::which()?;
+                    // Network devices are identified by `ip:port`
+                    if id.contains(':') {
+                        adb.connect(id)?;
+                    }
+                    Backend::Adb(adb)
+                }
+                "imd" => Backend::Imd(IMobileDevice::which()?.for_device(id)?),
+                "ssh" => Backend::Ssh(Ssh::which()?),
+                "emulator" => {
+                    // Boots the avd if needed, without a window on ci
+                    let headless = std::env::var_os("CI").is_some();
+                    let serial = Avd::which()?.start(id, headless)?;
+                    return Ok(Self {
+                        backend: Backend::Adb(Adb::which()?),
+                        id: serial,
+                    });
+                }
                 _ => anyhow::bail!("unsupported backend {}", backend),
             };
             Ok(Self {
@@ -51,6 +151,7 @@ impl std::fmt::Display for Device {
             Backend::Adb(_) => write!(f, "adb:{}", &self.id),
             Backend::Host(_) => write!(f, "{}", &self.id),
             Backend::Imd(_) => write!(f, "imd:{}", &self.id),
+            Backend::Ssh(_) => write!(f, "ssh:{}", &self.id),
         }
     }
 }
@@ -67,6 +168,26 @@ impl Device {
         Ok(devices)
     }
 
+    /// Pairs with an android device over wireless debugging.
+    pub fn pair(address: &str, code: &str) -> Result<()> {
+        Adb::which()?.pair(address, code)
+    }
+
+    /// Connects to an android device over the network and remembers it.
+    pub fn connect(address: &str) -> Result<Self> {
+        let adb = Adb::which()?;
+        adb.connect(address)?;
+        Ok(Self {
+            backend: Backend::Adb(adb),
+            id: address.to_string(),
+        })
+    }
+
+    /// Disconnects and forgets an android network device.
+    pub fn disconnect(address: &str) -> Result<()> {
+        Adb::which()?.disconnect(address)
+    }
+
     pub fn host() -> Self {
         Self {
             backend: Backend::Host(Host),
@@ -83,6 +204,7 @@ impl Device {
             Backend::Adb(adb) => adb.name(&self.id),
             Backend::Host(host) => host.name(),
             Backend::Imd(imd) => imd.name(&self.id),
+            Backend::Ssh(ssh) => ssh.name(&self.id),
         }
     }
 
@@ -91,6 +213,7 @@ impl Device {
             Backend::Adb(adb) => adb.platform(&self.id),
             Backend::Host(host) => host.platform(),
             Backend::Imd(imd) => imd.platform(&self.id),
+            Backend::Ssh(ssh) => ssh.platform(&self.id),
         }
     }
 
@@ -99,42 +222,275 @@ impl Device {
             Backend::Adb(adb) => adb.arch(&self.id),
             Backend::Host(host) => host.arch(),
             Backend::Imd(imd) => imd.arch(&self.id),
+            Backend::Ssh(ssh) => ssh.arch(&self.id),
         }
     }
 
+    pub fn info(&self) -> DeviceInfo {
+        let mut info = match &self.backend {
+            Backend::Adb(adb) => adb.info(&self.id),
+            Backend::Host(host) => host.info(),
+            Backend::Imd(imd) => imd.info(&self.id),
+            Backend::Ssh(ssh) => ssh.info(&self.id),
+        };
+        info.id = self.to_string();
+        info.name = self.name().ok();
+        info.platform = self.platform().ok().map(|platform| platform.to_string());
+        info.arch = self.arch().ok().map(|arch| arch.to_string());
+        info
+    }
+
     pub fn details(&self) -> Result<String> {
         match &self.backend {
             Backend::Adb(adb) => adb.details(&self.id),
             Backend::Host(host) => host.details(),
             Backend::Imd(imd) => imd.details(&self.id),
+            Backend::Ssh(ssh) => ssh.details(&self.id),
         }
     }
 
-    pub fn run(&self, env: &BuildEnv, path: &Path) -> Result<()> {
+    /// Installs and runs the app, printing its output. With `prefix` every
+    /// line is prefixed, to tell apart several devices running at once.
+    pub fn run(
+        &self,
+        env: &BuildEnv,
+        path: &Path,
+        log_filters: &[LogFilter],
+        prefix: Option<&str>,
+    ) -> Result<()> {
         match &self.backend {
-            Backend::Adb(adb) => adb.run(&self.id, path, &env.config.android().debug, false),
+            Backend::Adb(adb) => {
+                let debug_config = &env.config.android().debug;
+                adb.run(&self.id, path, debug_config, false, log_filters, prefix)
+            }
             Backend::Host(host) => host.run(path),
-            Backend::Imd(imd) => imd.run(env, &self.id, path),
+            Backend::Imd(imd) => imd.run(env, &self.id, path, prefix),
+            Backend::Ssh(ssh) => ssh.run(env, &self.id, path, prefix),
         }?;
         Ok(())
     }
 
+    /// Reinstalls and restarts the app without waiting for it, for
+    /// `x run --watch`. Returns the process to kill before the next deploy,
+    /// android apps are stopped by the reinstall.
+    pub fn relaunch(&self, env: &BuildEnv, path: &Path) -> Result<Option<Child>> {
+        match &self.backend {
+            Backend::Adb(adb) => {
+                adb.install_and_start(&self.id, path)?;
+                Ok(None)
+            }
+            Backend::Host(host) => host.spawn(path).map(Some),
+            Backend::Imd(imd) => imd.spawn(env, &self.id, path).map(Some),
+            Backend::Ssh(ssh) => ssh.spawn(env, &self.id, path).map(Some),
+        }
+    }
+
+    /// Installs a built app without launching it. Split apks of an android
+    /// app are installed together.
+    pub fn install(&self, paths: &[PathBuf]) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.install_apks(&self.id, paths),
+            Backend::Host(host) => match paths {
+                [path] => host.install(path),
+                _ => anyhow::bail!("expected a single artifact to install"),
+            },
+            Backend::Imd(imd) => match paths {
+                [path] => imd.install(&self.id, path),
+                _ => anyhow::bail!("expected a single artifact to install"),
+            },
+            Backend::Ssh(ssh) => match paths {
+                [path] => ssh.install(&self.id, path),
+                _ => anyhow::bail!("expected a single artifact to install"),
+            },
+        }
+    }
+
+    /// Removes the app with the package name, bundle identifier or app name
+    /// `id`.
+    pub fn uninstall(&self, id: &str) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.uninstall(&self.id, id),
+            Backend::Host(host) => host.uninstall(id),
+            Backend::Imd(imd) => imd.uninstall(&self.id, id),
+            Backend::Ssh(_) => anyhow::bail!("uninstalling over ssh is not supported"),
+        }
+    }
+
+    /// Streams the logs of the installed app, launching it if it isn't
+    /// running already.
+    pub fn attach(&self, env: &BuildEnv, log_filters: &[LogFilter]) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.attach_logs(&self.id, android_package(env), log_filters),
+            Backend::Host(_) => anyhow::bail!("attaching is not supported on the host"),
+            Backend::Ssh(_) => anyhow::bail!("attaching is not supported over ssh"),
+            Backend::Imd(imd) => {
+                let bundle_identifier = env.config().ios().info.cf_bundle_identifier.as_ref();
+                imd.attach(env, &self.id, bundle_identifier.unwrap())
+            }
+        }
+    }
+
+    /// Streams the logs of the app without launching it: logcat on android,
+    /// the syslog on ios, the unified log on macos and the output of the
+    /// built executable on linux and windows. `filters` apply to logcat.
+    pub(crate) fn logs(
+        &self,
+        env: &BuildEnv,
+        filters: &[LogFilter],
+        output: &mut LogOutput,
+    ) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.logs(&self.id, android_package(env), filters, output),
+            Backend::Host(host) => host.logs(env.name(), &env.executable(), output),
+            Backend::Imd(imd) => imd.logs(&self.id, env.name(), output),
+            Backend::Ssh(_) => anyhow::bail!("use `x run` to stream the output over ssh"),
+        }
+    }
+
+    /// Makes the host ports reachable from the device. Desktop apps and
+    /// simulators share the network of the host already.
+    pub fn forward_ports(&self, ports: &[PortForward]) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => {
+                for port in ports {
+                    adb.reverse(&self.id, port.device, port.host)?;
+                }
+            }
+            Backend::Host(_) => {
+                for port in ports.iter().filter(|port| port.device != port.host) {
+                    log::warn!(
+                        "host apps reach port {} directly, it can't be mapped to {}",
+                        port.host,
+                        port.device
+                    );
+                }
+            }
+            Backend::Imd(_) if !ports.is_empty() => {
+                log::warn!("ios devices can't reach the host on localhost, use its lan address");
+            }
+            Backend::Imd(_) => {}
+            Backend::Ssh(_) if !ports.is_empty() => {
+                log::warn!("ports aren't forwarded over ssh, use the lan address of the host");
+            }
+            Backend::Ssh(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Launches the installed app unless it is running already.
+    pub fn launch(&self, env: &BuildEnv) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.launch(&self.id, android_package(env)),
+            _ => anyhow::bail!("launching installed apps is only supported on android"),
+        }
+    }
+
+    /// Saves a png screenshot of the device screen to `path`.
+    pub fn screenshot(&self, path: &Path) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.screenshot(&self.id, path),
+            Backend::Host(host) => host.screenshot(path),
+            Backend::Imd(imd) => imd.screenshot(&self.id, path),
+            Backend::Ssh(_) => anyhow::bail!("screenshots over ssh are not supported"),
+        }
+    }
+
+    /// Copies `local` into the sandbox of the app, `remote` is relative to
+    /// the data dir on android and the app container on ios.
+    pub fn push(&self, env: &BuildEnv, local: &Path, remote: &str) -> Result<()> {
+        anyhow::ensure!(local.exists(), "{} doesn't exist", local.display());
+        match &self.backend {
+            Backend::Adb(adb) => adb.push_app_data(&self.id, android_package(env), local, remote),
+            Backend::Host(_) => {
+                anyhow::bail!("the host has no app sandbox, copy the files directly")
+            }
+            Backend::Imd(imd) => imd.push(&self.id, ios_bundle_identifier(env)?, local, remote),
+            Backend::Ssh(_) => anyhow::bail!("ssh hosts have no app sandbox, use scp"),
+        }
+    }
+
+    /// Copies `remote` out of the sandbox of the app to `local`.
+    pub fn pull(&self, env: &BuildEnv, remote: &str, local: &Path) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.pull_app_data(&self.id, android_package(env), remote, local),
+            Backend::Host(_) => {
+                anyhow::bail!("the host has no app sandbox, copy the files directly")
+            }
+            Backend::Imd(imd) => imd.pull(&self.id, ios_bundle_identifier(env)?, remote, local),
+            Backend::Ssh(_) => anyhow::bail!("ssh hosts have no app sandbox, use scp"),
+        }
+    }
+
+    /// Records the device screen for `duration` and saves the video to `path`.
+    pub fn record(&self, path: &Path, duration: Duration) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.record(&self.id, path, duration),
+            Backend::Host(host) => host.record(path, duration),
+            Backend::Imd(_) => anyhow::bail!("recording ios devices is not supported"),
+            Backend::Ssh(_) => anyhow::bail!("recording over ssh is not supported"),
+        }
+    }
+
+    /// Runs the app at `path` and records a profile of it with `profiler`
+    /// for `duration`, writing the trace to `out`.
+    pub fn profile(
+        &self,
+        path: &Path,
+        profiler: Profiler,
+        simpleperf: Option<&Path>,
+        duration: Duration,
+        out: &Path,
+    ) -> Result<()> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.profile(&self.id, path, profiler, simpleperf, duration, out),
+            Backend::Host(host) => host.profile(path, profiler, duration, out),
+            Backend::Imd(_) => anyhow::bail!("profiling on ios devices is not supported"),
+            Backend::Ssh(_) => anyhow::bail!("profiling over ssh is not supported"),
+        }
+    }
+
+    /// Device side requirements for running apps, the name of each check
+    /// with a description of the state or the reason it failed.
+    pub fn checks(&self, min_sdk: u32) -> Vec<(&'static str, Result<String>)> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.checks(&self.id, min_sdk),
+            Backend::Host(_) | Backend::Ssh(_) => vec![],
+            Backend::Imd(imd) => imd.checks(&self.id),
+        }
+    }
+
+    /// Runs a plain executable like a test harness on the device and returns
+    /// its exit code.
+    pub fn run_executable(&self, path: &Path, args: &[String]) -> Result<i32> {
+        match &self.backend {
+            Backend::Adb(adb) => adb.run_executable(&self.id, path, args),
+            Backend::Host(host) => host.run_executable(path, args),
+            Backend::Imd(_) => anyhow::bail!("running executables on ios devices is not supported"),
+            Backend::Ssh(ssh) => ssh.run_executable(&self.id, path, args),
+        }
+    }
+
     pub fn lldb(
         &self,
         env: &BuildEnv,
         executable: &Path,
         lldb_server: Option<&Path>,
+        attach: Option<&str>,
     ) -> Result<()> {
         match &self.backend {
             Backend::Adb(adb) => {
-                if let Some(lldb_server) = lldb_server {
-                    adb.lldb(&self.id, executable, lldb_server)
+                let lldb_server = lldb_server.context("lldb-server required on android")?;
+                if let Some(process) = attach {
+                    let package = android_package(env);
+                    adb.attach(&self.id, package, lldb_server, executable, process)
                 } else {
-                    anyhow::bail!("lldb-server required on android");
+                    adb.lldb(&self.id, lldb_server, executable)
                 }
             }
+            _ if attach.is_some() => anyhow::bail!("--attach is only supported on android"),
             Backend::Host(host) => host.lldb(executable),
             Backend::Imd(imd) => imd.lldb(env, &self.id, executable),
+            Backend::Ssh(_) => anyhow::bail!("debugging over ssh is not supported"),
         }
     }
 
@@ -146,3 +502,239 @@ impl Device {
         }
     }
 }
+
+fn android_package(env: &BuildEnv) -> &str {
+    env.config().android().manifest.package.as_ref().unwrap()
+}
+
+fn ios_bundle_identifier(env: &BuildEnv) -> Result<&str> {
+    env.config()
+        .ios()
+        .info
+        .cf_bundle_identifier
+        .as_deref()
+        .context("missing `ios.info.cf_bundle_identifier`")
+}
+
+/// Archives the file or directory `path` as `name`.
+fn tar_path(path: &Path, name: &str) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(vec![]);
+    if path.is_dir() {
+        builder.append_dir_all(name, path)?;
+    } else {
+        builder.append_path_with_name(path, name)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Unpacks an archive of `name` to `path`, the inverse of `tar_path`. Only
+/// files and directories below `name` are unpacked, links or entries
+/// escaping `path` are rejected.
+fn untar_path(archive: &[u8], name: &str, path: &Path) -> Result<()> {
+    anyhow::ensure!(
+        matches!(
+            Path::new(name).components().collect::<Vec<_>>()[..],
+            [std::path::Component::Normal(_)]
+        ),
+        "invalid archive name `{}`",
+        name
+    );
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        anyhow::ensure!(
+            matches!(
+                entry.header().entry_type(),
+                tar::EntryType::Regular | tar::EntryType::Directory
+            ),
+            "unexpected {:?} entry {}",
+            entry.header().entry_type(),
+            entry_path.display()
+        );
+        let rest = entry_path
+            .strip_prefix(name)
+            .with_context(|| format!("unexpected entry {}", entry_path.display()))?;
+        anyhow::ensure!(
+            rest.components()
+                .all(|c| matches!(c, std::path::Component::Normal(_))),
+            "unexpected entry {}",
+            entry_path.display()
+        );
+        // Joining an empty path would append a separator
+        let dest = if rest.as_os_str().is_empty() {
+            path.to_path_buf()
+        } else {
+            path.join(rest)
+        };
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&dest)?;
+    }
+    Ok(())
+}
+
+/// Destination of streamed app logs. Lines are printed, prefixed with the
+/// device when running on several, and appended to the save file without
+/// styling. Lines not containing `grep` are dropped.
+pub(crate) struct LogOutput {
+    prefix: Option<String>,
+    grep: Option<String>,
+    file: Option<File>,
+}
+
+impl LogOutput {
+    pub fn new(prefix: Option<&str>) -> Self {
+        Self {
+            prefix: prefix.map(str::to_string),
+            grep: None,
+            file: None,
+        }
+    }
+
+    pub fn grep(mut self, pattern: Option<String>) -> Self {
+        self.grep = pattern;
+        self
+    }
+
+    pub fn save(mut self, path: Option<&Path>) -> Result<Self> {
+        if let Some(path) = path {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            self.file = Some(file);
+        }
+        Ok(self)
+    }
+
+    pub fn line(&mut self, line: impl std::fmt::Display) -> Result<()> {
+        let line = line.to_string();
+        let plain = console::strip_ansi_codes(&line);
+        if let Some(grep) = &self.grep {
+            if !plain.contains(grep.as_str()) {
+                return Ok(());
+            }
+        }
+        print_line(self.prefix.as_deref(), &line);
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", plain)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `cmd`, passing the lines it writes to stdout and stderr to
+/// `output` as they arrive.
+fn stream_output(cmd: &mut Command, output: &mut LogOutput) -> Result<ExitStatus> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {}", program))?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let readers = [
+        Box::new(stdout) as Box<dyn std::io::Read + Send>,
+        Box::new(stderr),
+    ]
+    .map(|pipe| {
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    drop(tx);
+    for line in rx {
+        output.line(line)?;
+    }
+    for reader in readers {
+        reader.join().ok();
+    }
+    Ok(child.wait()?)
+}
+
+/// Quotes `arg` for a posix shell.
+fn sh_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Prints a line of app output, prefixed with the device it came from.
+fn print_line(prefix: Option<&str>, line: impl std::fmt::Display) {
+    if let Some(prefix) = prefix {
+        println!(
+            "{} {}",
+            console::style(format!("[{}]", prefix)).bold(),
+            line
+        );
+    } else {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Archive with a single entry, written without the path checks of
+    /// `tar::Builder`.
+    fn archive(path: &str, ty: tar::EntryType) -> Vec<u8> {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(ty);
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(vec![]);
+        builder.append(&header, std::io::empty()).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_tar_path() {
+        let dir = std::env::temp_dir().join("xbuild-tar-path");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        std::fs::write(dir.join("src/nested/file"), "contents").unwrap();
+        std::fs::write(dir.join("single"), "single").unwrap();
+
+        let tar = tar_path(&dir.join("src"), "files").unwrap();
+        untar_path(&tar, "files", &dir.join("dest")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("dest/nested/file")).unwrap(),
+            "contents"
+        );
+        let tar = tar_path(&dir.join("single"), "name").unwrap();
+        untar_path(&tar, "name", &dir.join("copy")).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("copy")).unwrap(), "single");
+
+        assert!(untar_path(&tar, "other", &dir.join("copy")).is_err());
+        assert!(untar_path(&tar, "..", &dir.join("copy")).is_err());
+    }
+
+    #[test]
+    fn test_untar_path_rejects_escapes() {
+        let dir = std::env::temp_dir().join("xbuild-untar-path");
+        for (path, ty) in [
+            ("files/../escape", tar::EntryType::Regular),
+            ("/files/abs", tar::EntryType::Regular),
+            ("files/link", tar::EntryType::Symlink),
+            ("files/hard", tar::EntryType::Link),
+        ] {
+            assert!(
+                untar_path(&archive(path, ty), "files", &dir).is_err(),
+                "{}",
+                path
+            );
+        }
+        assert!(!dir.parent().unwrap().join("escape").exists());
+        untar_path(&archive("files/ok", tar::EntryType::Regular), "files", &dir).unwrap();
+        assert!(dir.join("ok").exists());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::devices::{sh_quote, stream_output, DeviceFeatures, DeviceInfo, LogOutput};
+use crate::task::powershell_quote;
+use crate::{Arch, BuildEnv, Categorize, ErrorKind, Format, Platform};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Directory in the home of the remote user apps are copied to.
+const REMOTE_DIR: &str = ".xbuild";
+
+/// Desktop machine reachable over ssh, identified by `user@host`. Apps are
+/// copied with scp and run in the ssh session, so their output is streamed
+/// back.
+#[derive(Clone, Debug)]
+pub(crate) struct Ssh {
+    ssh: PathBuf,
+    scp: PathBuf,
+    /// Queried on first use, every query is a connection.
+    remote: Arc<Mutex<Option<Remote>>>,
+}
+
+/// Platform and arch of a host and the kind of its login shell.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Remote {
+    platform: Platform,
+    arch: Arch,
+    /// The login shell is a posix shell, also on windows with git bash,
+    /// msys2 or cygwin. Otherwise it is `cmd`.
+    posix: bool,
+}
+
+impl Remote {
+    /// Quotes `arg` for the login shell.
+    fn quote(&self, arg: &str) -> String {
+        if self.posix {
+            sh_quote(arg)
+        } else {
+            cmd_quote(arg)
+        }
+    }
+
+    /// Joins `segments` with the separator of the login shell.
+    fn path(&self, segments: &[&str]) -> String {
+        segments.join(if self.posix { "/" } else { "\\" })
+    }
+
+    /// Command running the powershell `script`.
+    fn powershell(&self, script: &str) -> String {
+        let script = if self.posix {
+            sh_quote(script)
+        } else {
+            // Powershell strings are single quoted, so the script has no `"`
+            format!("\"{}\"", script)
+        };
+        format!("powershell -NoProfile -Command {}", script)
+    }
+}
+
+impl Ssh {
+    pub fn which() -> Result<Self> {
+        let hint = "install an openssh client and add it to `PATH`";
+        let ssh = which::which(exe!("ssh"))
+            .context("ssh not found")
+            .categorize(ErrorKind::Environment, hint)?;
+        let scp = which::which(exe!("scp"))
+            .context("scp not found")
+            .categorize(ErrorKind::Environment, hint)?;
+        Ok(Self {
+            ssh,
+            scp,
+            remote: Default::default(),
+        })
+    }
+
+    fn ssh(&self, host: &str) -> Command {
+        let mut cmd = Command::new(&self.ssh);
+        cmd.arg("-o").arg("ConnectTimeout=10").arg(host);
+        cmd
+    }
+
+    /// Output of `command` run in the login shell of `host`.
+    fn output(&self, host: &str, command: &str) -> Result<String> {
+        let output = self
+            .ssh(host)
+            .arg(command)
+            .stdin(Stdio::null())
+            .output()
+            .context("failed to run ssh")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "`{}` failed on {}: {}",
+            command,
+            host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        Ok(std::str::from_utf8(&output.stdout)?.trim().to_string())
+    }
+
+    /// Platform and arch of `host`, from `uname` or on windows, where the
+    /// default shell is `cmd`, the processor architecture. Only the first
+    /// call connects.
+    fn remote(&self, host: &str) -> Result<Remote> {
+        let mut cached = self.remote.lock().unwrap();
+        if let Some(remote) = *cached {
+            return Ok(remote);
+        }
+        let remote = if let Ok(uname) = self.output(host, "uname -sm") {
+            parse_uname(&uname).with_context(|| format!("unsupported host {}", host))?
+        } else {
+            let arch = self
+                .output(host, "echo %PROCESSOR_ARCHITECTURE%")
+                .with_context(|| format!("failed to query the os of {}", host))
+                .categorize(
+                    ErrorKind::Device,
+                    "check that `ssh <user>@<host>` logs in without prompting",
+                )?;
+            let arch = match arch.as_str() {
+                "AMD64" => Arch::X64,
+                "ARM64" => Arch::Arm64,
+                _ => anyhow::bail!("unsupported arch {} on {}", arch, host),
+            };
+            Remote {
+                platform: Platform::Windows,
+                arch,
+                posix: false,
+            }
+        };
+        *cached = Some(remote);
+        Ok(remote)
+    }
+
+    pub fn name(&self, host: &str) -> Result<String> {
+        self.output(host, "hostname")
+    }
+
+    pub fn platform(&self, host: &str) -> Result<Platform> {
+        Ok(self.remote(host)?.platform)
+    }
+
+    pub fn arch(&self, host: &str) -> Result<Arch> {
+        Ok(self.remote(host)?.arch)
+    }
+
+    pub fn info(&self, host: &str) -> DeviceInfo {
+        let online = self.remote(host).is_ok();
+        DeviceInfo {
+            transport: "network",
+            state: if online {
+                "device".to_string()
+            } else {
+                "offline".to_string()
+            },
+            os_version: online.then(|| self.details(host).ok()).flatten(),
+            features: DeviceFeatures {
+                debugging: false,
+                incremental_install: false,
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn details(&self, host: &str) -> Result<String> {
+        if self.remote(host)?.posix {
+            self.output(host, "uname -sr")
+        } else {
+            self.output(host, "ver")
+        }
+    }
+
+    /// Copies the file or directory `path` into [`REMOTE_DIR`], replacing
+    /// it, and returns the remote path.
+    fn copy(&self, host: &str, remote: Remote, path: &Path) -> Result<String> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("invalid artifact path")?;
+        let dest = remote.path(&[REMOTE_DIR, name]);
+        let quoted = remote.quote(&dest);
+        let prepare = if remote.posix {
+            format!("rm -rf {} && mkdir -p {}", quoted, REMOTE_DIR)
+        } else {
+            // A trailing backslash only exists for directories
+            format!(
+                "(if exist \"{0}\\\" (rmdir /s /q {1}) else if exist {1} (del /f /q {1})) & (if not exist {2} mkdir {2})",
+                dest, quoted, REMOTE_DIR
+            )
+        };
+        self.output(host, &prepare)?;
+        let status = Command::new(&self.scp)
+            .arg("-q")
+            .arg("-r")
+            .arg(path)
+            .arg(format!("{}:{}/", host, REMOTE_DIR))
+            .status()
+            .context("failed to run scp")?;
+        anyhow::ensure!(status.success(), "failed to copy {} to {}", name, host);
+        Ok(dest)
+    }
+
+    /// Copies the built app to `host` and returns the command running it.
+    /// Msix packages are installed and launched through the shell, their
+    /// output can't be streamed.
+    fn deploy(&self, env: &BuildEnv, host: &str, path: &Path) -> Result<String> {
+        let remote = self.remote(host)?;
+        let output = env.output();
+        let dest = self.copy(host, remote, &output)?;
+        if env.target().format() == Format::Msix {
+            let app = env
+                .config()
+                .windows()
+                .manifest
+                .applications
+                .application
+                .first()
+                .and_then(|app| app.id.clone())
+                .context("missing `windows.manifest.applications`")?;
+            let name = env
+                .config()
+                .windows()
+                .manifest
+                .identity
+                .name
+                .clone()
+                .context("missing `windows.manifest.identity.name`")?;
+            return Ok(remote.powershell(&format!(
+                "Add-AppxPackage -ForceUpdateFromAnyVersion -Path {}; Start-Process ('shell:AppsFolder\\' + (Get-AppxPackage -Name {}).PackageFamilyName + '!' + {})",
+                powershell_quote(&dest),
+                powershell_quote(&name),
+                powershell_quote(&app)
+            )));
+        }
+        let relative = path.strip_prefix(&output).unwrap_or(Path::new(""));
+        let mut segments = vec![dest.as_str()];
+        segments.extend(relative.iter().filter_map(|segment| segment.to_str()));
+        let executable = remote.quote(&remote.path(&segments));
+        Ok(match remote.platform {
+            Platform::Windows => executable,
+            // Graphical apps open on the display of the remote session
+            Platform::Linux => format!("chmod +x {0} && DISPLAY=${{DISPLAY:-:0}} {0}", executable),
+            _ => format!("chmod +x {0} && {0}", executable),
+        })
+    }
+
+    pub fn run(&self, env: &BuildEnv, host: &str, path: &Path, prefix: Option<&str>) -> Result<()> {
+        let command = self.deploy(env, host, path)?;
+        let mut output = LogOutput::new(prefix);
+        stream_output(self.ssh(host).arg(command), &mut output)?;
+        Ok(())
+    }
+
+    /// Redeploys the app and starts it without waiting. The session has a
+    /// terminal so killing the ssh client hangs up the remote app.
+    pub fn spawn(&self, env: &BuildEnv, host: &str, path: &Path) -> Result<Child> {
+        let command = self.deploy(env, host, path)?;
+        Ok(self.ssh(host).arg("-tt").arg(command).spawn()?)
+    }
+
+    /// Copies the artifact to the remote user's [`REMOTE_DIR`] and installs
+    /// msix packages.
+    pub fn install(&self, host: &str, path: &Path) -> Result<()> {
+        let remote = self.remote(host)?;
+        let dest = self.copy(host, remote, path)?;
+        if path.extension() == Some("msix".as_ref()) {
+            self.output(
+                host,
+                &remote.powershell(&format!(
+                    "Add-AppxPackage -ForceUpdateFromAnyVersion -Path {}",
+                    powershell_quote(&dest)
+                )),
+            )?;
+        }
+        log::info!("installed {} on {}", dest, host);
+        Ok(())
+    }
+
+    pub fn run_executable(&self, host: &str, path: &Path, args: &[String]) -> Result<i32> {
+        let remote = self.remote(host)?;
+        let dest = remote.quote(&self.copy(host, remote, path)?);
+        let mut command = if remote.platform == Platform::Windows {
+            dest
+        } else {
+            format!("chmod +x {0} && {0}", dest)
+        };
+        for arg in args {
+            command.push(' ');
+            command.push_str(&remote.quote(arg));
+        }
+        let status = self.ssh(host).arg(command).status()?;
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+/// Remote of a posix login shell from the output of `uname -sm`.
+fn parse_uname(uname: &str) -> Result<Remote> {
+    let (os, machine) = uname.split_once(' ').unwrap_or((uname, ""));
+    let platform = match os {
+        "Linux" => Platform::Linux,
+        "Darwin" => Platform::Macos,
+        // Git bash and msys2 report `MINGW64_NT-10.0-22631` or similar
+        _ if ["MINGW", "MSYS", "CYGWIN"]
+            .iter()
+            .any(|prefix| os.starts_with(prefix)) =>
+        {
+            Platform::Windows
+        }
+        _ => anyhow::bail!("unsupported os {}", os),
+    };
+    let arch = match machine {
+        "x86_64" | "amd64" => Arch::X64,
+        "aarch64" | "arm64" => Arch::Arm64,
+        "armv7l" => Arch::Arm,
+        _ => anyhow::bail!("unsupported arch {}", machine),
+    };
+    Ok(Remote {
+        platform,
+        arch,
+        posix: true,
+    })
+}
+
+/// Quotes `arg` like the windows c runtime parses command lines.
+fn cmd_quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote are escapes
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_uname() {
+        let remote = parse_uname("Linux aarch64").unwrap();
+        assert_eq!(remote.platform, Platform::Linux);
+        assert_eq!(remote.arch, Arch::Arm64);
+        let remote = parse_uname("MINGW64_NT-10.0-22631 x86_64").unwrap();
+        assert_eq!(remote.platform, Platform::Windows);
+        assert_eq!(remote.arch, Arch::X64);
+        assert!(remote.posix);
+        assert_eq!(remote.path(&[REMOTE_DIR, "app.exe"]), ".xbuild/app.exe");
+        assert!(parse_uname("FreeBSD amd64").is_err());
+        assert!(parse_uname("Linux riscv64").is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(sh_quote("it's a file"), r"'it'\''s a file'");
+        assert_eq!(cmd_quote("a b"), r#""a b""#);
+        assert_eq!(cmd_quote(r#"say "hi" & exit"#), r#""say \"hi\" & exit""#);
+        assert_eq!(cmd_quote(r"C:\dir\"), r#""C:\dir\\""#);
+        assert_eq!(cmd_quote(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(powershell_quote(r"C:\it's"), r"'C:\it''s'");
+    }
+}
@@ -1,23 +1,36 @@
-use crate::{BuildEnv, Platform};
+use crate::sdkmanager::Filter;
+use crate::task::Cache;
+use crate::{
+    Arch, BuildEnv, Categorize, CompileTarget, Error, ErrorKind, Format, MessageFormat, Platform,
+};
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use indicatif::{ProgressBar, ProgressStyle};
 use mvn::Download;
 use reqwest::blocking::Client;
+use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tar::{Archive, EntryType};
 use zstd::Decoder;
 
 pub struct DownloadManager<'a> {
     env: &'a BuildEnv,
     client: Client,
+    /// Name, duration and cache outcome of every fetched component.
+    fetches: Mutex<Vec<(String, Duration, Cache)>>,
 }
 
 impl<'a> Download for DownloadManager<'a> {
     fn download(&self, url: &str, dest: &Path) -> Result<()> {
-        let pb = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::stdout())
+        let pb = if self.env.message_format() == MessageFormat::Json {
+            ProgressBar::hidden()
+        } else {
+            crate::task::progress().add(ProgressBar::new(0))
+        }
         .with_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}")?
@@ -54,9 +67,18 @@ impl<'a> Download for &'a DownloadManager<'a> {
 impl<'a> DownloadManager<'a> {
     pub fn new(env: &'a BuildEnv) -> Result<Self> {
         let client = Client::new();
-        let download_dir = env.cache_dir().join("download");
+        let download_dir = env.components_dir().join("download");
         std::fs::create_dir_all(download_dir)?;
-        Ok(Self { env, client })
+        Ok(Self {
+            env,
+            client,
+            fetches: Default::default(),
+        })
+    }
+
+    /// Components fetched so far, for the `--timings` report.
+    pub(crate) fn take_fetches(&self) -> Vec<(String, Duration, Cache)> {
+        std::mem::take(&mut self.fetches.lock().unwrap())
     }
 
     pub(crate) fn env(&self) -> &BuildEnv {
@@ -64,14 +86,26 @@ impl<'a> DownloadManager<'a> {
     }
 
     pub(crate) fn fetch(&self, item: WorkItem) -> Result<()> {
+        let start = Instant::now();
+        let component = item
+            .output
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
         if item.output.exists() {
+            self.fetches
+                .lock()
+                .unwrap()
+                .push((component, start.elapsed(), Cache::Hit));
             return Ok(());
         }
+        ensure_online(self.env, &item.output)?;
         let name = item.url.rsplit_once('/').unwrap().1;
         let result: Result<()> = (|| {
             if name.ends_with(".tar.zst") {
-                let archive = self.env().cache_dir().join("download").join(name);
-                self.download(&item.url, &archive)?;
+                let archive = self.env().components_dir().join("download").join(name);
+                self.download_verified(&item, &archive)?;
                 let archive = BufReader::new(File::open(&archive)?);
                 let mut archive = Archive::new(Decoder::new(archive)?);
                 let dest = item.output.parent().unwrap();
@@ -87,61 +121,146 @@ impl<'a> DownloadManager<'a> {
                     entry.unpack_in(dest)?;
                 }
             } else if name.ends_with(".framework.zip") {
-                let download_dir = self.env().cache_dir().join("download");
+                let download_dir = self.env().components_dir().join("download");
                 let archive = download_dir.join(name);
-                self.download(&item.url, &archive)?;
+                self.download_verified(&item, &archive)?;
                 let framework_dir = download_dir.join("framework");
                 xcommon::extract_zip(&archive, &framework_dir)?;
                 let archive = framework_dir.join(name);
                 std::fs::create_dir_all(&item.output)?;
                 xcommon::extract_zip(&archive, &item.output)?;
             } else if name.ends_with(".zip") {
-                let archive = self.env().cache_dir().join("download").join(name);
-                self.download(&item.url, &archive)?;
+                let archive = self.env().components_dir().join("download").join(name);
+                self.download_verified(&item, &archive)?;
                 xcommon::extract_zip(&archive, item.output.parent().unwrap())?;
             } else {
-                self.download(&item.url, &item.output)?;
+                self.download_verified(&item, &item.output)?;
             }
             Ok(())
         })();
+        if let (Ok(()), Some(release)) = (&result, item.release) {
+            std::fs::write(version_file(&item.output), release)?;
+        }
         if result.is_err() {
             if item.output.is_dir() {
                 std::fs::remove_dir_all(&item.output).ok();
             } else {
                 std::fs::remove_file(&item.output).ok();
             }
+            return result;
         }
-        result
+        self.fetches
+            .lock()
+            .unwrap()
+            .push((component, start.elapsed(), Cache::Miss));
+        Ok(())
     }
 
-    fn rustup_target(&self, target: &str) -> Result<()> {
-        let status = Command::new("rustup")
-            .arg("target")
-            .arg("add")
-            .arg(target)
-            .status()?;
-        anyhow::ensure!(status.success(), "failure running rustup target add");
+    /// Downloads `item` to `dest` and checks it against the pinned sha256.
+    fn download_verified(&self, item: &WorkItem, dest: &Path) -> Result<()> {
+        if item.sha256.is_none() && !item.unverified {
+            // Hard error once `sdk.sha256` has the digests of the release
+            log::warn!("no sha256 pinned for {}, using it unverified", item.url);
+        }
+        self.download(&item.url, dest)?;
+        if let Some(expected) = item.sha256.as_deref() {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut File::open(dest)?, &mut hasher)?;
+            let actual = hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            if actual != expected {
+                std::fs::remove_file(dest).ok();
+                anyhow::bail!(
+                    "checksum mismatch for {}\n  expected sha256 {}\n  got sha256      {}\nthe download is corrupted or was tampered with",
+                    item.url,
+                    expected,
+                    actual
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs the std of `target`, or the `rust-src` component if the std
+    /// is built from source with `build_std`.
+    fn rustup_target(&self, target: CompileTarget) -> Result<()> {
+        self.rustup_triple(target, target.rust_triple()?)
+    }
+
+    /// Installs the std of the ios simulator of `target`.
+    pub fn ios_simulator_target(&self, target: CompileTarget) -> Result<()> {
+        self.rustup_triple(target, target.ios_simulator_triple()?)
+    }
+
+    fn rustup_triple(&self, target: CompileTarget, triple: &str) -> Result<()> {
+        if self.env.offline() {
+            return Ok(());
+        }
+        let build_std = self
+            .env
+            .config()
+            .target(triple)
+            .map(|config| !config.build_std.is_empty())
+            .unwrap_or_default();
+        let mut cmd = Command::new("rustup");
+        if build_std {
+            cmd.arg("component").arg("add").arg("rust-src");
+        } else {
+            cmd.arg("target").arg("add").arg(triple);
+        }
+        if let Some(toolchain) = self.env.toolchain(target)? {
+            cmd.arg("--toolchain").arg(toolchain);
+        }
+        let status = cmd
+            .status()
+            .categorize(
+                ErrorKind::Toolchain,
+                "install rustup from https://rustup.rs or pass `--offline` to use the installed targets",
+            )?;
+        if !status.success() {
+            return Err(Error::new(
+                ErrorKind::Toolchain,
+                format!("failure running rustup for {}", triple),
+            )
+            .hint("check the output of rustup above, `x doctor` shows the installed toolchain")
+            .into());
+        }
         Ok(())
     }
 
     pub fn prefetch(&self) -> Result<()> {
-        for target in self.env().target().compile_targets() {
-            self.rustup_target(target.rust_triple()?)?;
+        // Container images have the rust targets and sdks installed
+        let containerized = self.env().containerized();
+        if !containerized {
+            for target in self.env().target().compile_targets() {
+                self.rustup_target(target)?;
+            }
         }
 
         match self.env().target().platform() {
-            Platform::Linux if Platform::host()? != Platform::Linux => {
-                anyhow::bail!("cross compiling to linux is not yet supported");
+            Platform::Linux => {
+                for target in self.env().target().compile_targets() {
+                    if !target.is_host()? && !containerized {
+                        self.linux_sysroot(target.arch())?;
+                    }
+                    if self.env().target().format() == Format::Appimage {
+                        self.appimage_runtime(target)?;
+                    }
+                }
             }
-            Platform::Windows if Platform::host()? != Platform::Windows => {
+            Platform::Windows if Platform::host()? != Platform::Windows && !containerized => {
                 self.windows_sdk()?;
             }
-            Platform::Macos if Platform::host()? != Platform::Macos => {
+            Platform::Macos if Platform::host()? != Platform::Macos && !containerized => {
                 self.macos_sdk()?;
             }
             Platform::Android => {
                 self.android_ndk()?;
                 self.android_jar()?;
+                self.android_build_tools()?;
             }
             Platform::Ios => {
                 self.ios_sdk()?;
@@ -156,11 +275,58 @@ impl<'a> DownloadManager<'a> {
     }
 }
 
+/// File next to a component downloaded from an xbuild release recording
+/// the release, used by `x update` to find stale components.
+pub fn version_file(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap().to_os_string();
+    name.push(".version");
+    output.with_file_name(name)
+}
+
+/// Digest of `artifact` in the output of `sha256sum`.
+fn sha256sum<'a>(sums: &'a str, artifact: &str) -> Option<&'a str> {
+    sums.lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim_start().trim_start_matches('*') == artifact)
+        .map(|(digest, _)| digest)
+}
+
+fn ensure_online(env: &BuildEnv, missing: &Path) -> Result<()> {
+    anyhow::ensure!(
+        !env.offline(),
+        "`{}` is missing, run `x vendor` before building offline",
+        missing.display()
+    );
+    Ok(())
+}
+
+/// Fails if `artifact` isn't published by the xbuild release
+/// [`WorkItem::VERSION`], unless `output` was put in place by hand.
+fn ensure_released(output: &Path, artifact: &str, hint: String) -> Result<()> {
+    if output.exists() || WorkItem::ARTIFACTS.contains(&artifact) {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::Environment,
+        format!(
+            "`{}` isn't published by the xbuild release {}",
+            artifact,
+            WorkItem::VERSION
+        ),
+    )
+    .hint(hint)
+    .into())
+}
+
 pub struct WorkItem {
     url: String,
     output: PathBuf,
+    sha256: Option<String>,
     no_symlinks: bool,
     no_colons: bool,
+    unverified: bool,
+    /// Xbuild release the item is part of, recorded in its [`version_file`].
+    release: Option<&'static str>,
 }
 
 impl WorkItem {
@@ -168,11 +334,28 @@ impl WorkItem {
         Self {
             url,
             output,
+            sha256: None,
             no_symlinks: false,
             no_colons: false,
+            unverified: false,
+            release: None,
         }
     }
 
+    /// Expected sha256 digest of the downloaded file, a mismatch fails the
+    /// download.
+    pub fn sha256(&mut self, digest: &str) -> &mut Self {
+        self.sha256 = Some(digest.to_lowercase());
+        self
+    }
+
+    /// Downloads the item without a pinned sha256 and without a warning,
+    /// for files that are verified otherwise.
+    pub fn unverified(&mut self) -> &mut Self {
+        self.unverified = true;
+        self
+    }
+
     /// The windows sdk contains symlinks for case sensitive
     /// filesystems. on case sensitive file systems skip the
     /// symlinks
@@ -193,10 +376,31 @@ impl WorkItem {
 impl WorkItem {
     const ORG: &'static str = "rust-mobile";
     const REPO: &'static str = "xbuild";
-    const VERSION: &'static str = "v0.1.0+3";
+    pub const VERSION: &'static str = "v0.1.0+3";
+
+    /// Artifacts published by the release [`Self::VERSION`]. The linux
+    /// sysroots and the appimage runtimes are built by the sdk workflow
+    /// since, and only downloadable once `VERSION` points at a release that
+    /// has them.
+    const ARTIFACTS: &'static [&'static str] = &[
+        "Android.ndk.tar.zst",
+        "MacOSX.sdk.tar.zst",
+        "Windows.sdk.tar.zst",
+        "iPhoneOS.sdk.tar.zst",
+    ];
+
+    /// Digests of the artifacts of the xbuild release [`Self::VERSION`], in
+    /// the `SHA256SUMS` the sdk workflow uploads to the release. Artifacts
+    /// missing from it are downloaded unverified with a warning.
+    const SHA256SUMS: &'static str = include_str!("../sdk.sha256");
 
     pub fn xbuild_release(output: PathBuf, artifact: &str) -> Self {
-        Self::github_release(output, Self::ORG, Self::REPO, Self::VERSION, artifact)
+        let mut item = Self::github_release(output, Self::ORG, Self::REPO, Self::VERSION, artifact);
+        if let Some(digest) = sha256sum(Self::SHA256SUMS, artifact) {
+            item.sha256(digest);
+        }
+        item.release = Some(Self::VERSION);
+        item
     }
 
     pub fn github_release(
@@ -236,19 +440,16 @@ impl WorkItem {
 impl<'a> DownloadManager<'a> {
     pub fn android_jar(&self) -> Result<()> {
         let dir = self.env.android_sdk();
-        let sdk = self.env.target_sdk_version();
+        let sdk = self.env.compile_sdk_version();
         let path = dir
             .join("platforms")
             .join(format!("android-{}", sdk))
             .join("android.jar");
         if !path.exists() {
+            ensure_online(self.env, &path)?;
             let package = format!("platforms;android-{}", sdk);
-            android_sdkmanager::download_and_extract_packages(
-                dir.to_str().unwrap(),
-                android_sdkmanager::HostOs::Linux,
-                &[&package],
-                Some(&[android_sdkmanager::MatchType::EntireName("android.jar")]),
-            )
+            crate::sdkmanager::install(self, &package, &[Filter::Name("android.jar")])?;
+            anyhow::ensure!(path.exists(), "{} not found", package);
         }
         Ok(())
     }
@@ -262,6 +463,42 @@ impl<'a> DownloadManager<'a> {
         self.fetch(item)
     }
 
+    /// Symlinks in the sysroot are resolved when it's packaged, so it
+    /// extracts on windows too.
+    pub fn linux_sysroot(&self, arch: Arch) -> Result<()> {
+        let output = self.env.linux_sysroot(arch);
+        let artifact = format!("Linux-{}.sysroot.tar.zst", arch);
+        ensure_released(
+            &output,
+            &artifact,
+            format!(
+                "build on a linux {} host, pass `--container`, or extract a glibc sysroot to `{}`",
+                arch,
+                output.display()
+            ),
+        )?;
+        self.fetch(WorkItem::xbuild_release(output, &artifact))
+    }
+
+    /// Downloads the runtime of non x86_64 appimages, a copy of the one of
+    /// the AppImage project in the xbuild release.
+    pub fn appimage_runtime(&self, target: CompileTarget) -> Result<()> {
+        if let Some(output) = self.env.appimage_runtime(target) {
+            let name = output.file_name().unwrap().to_str().unwrap().to_string();
+            ensure_released(
+                &output,
+                &name,
+                format!(
+                    "download https://github.com/AppImage/type2-runtime/releases/download/continuous/{} to `{}`",
+                    name,
+                    output.display()
+                ),
+            )?;
+            self.fetch(WorkItem::xbuild_release(output, &name))?;
+        }
+        Ok(())
+    }
+
     pub fn macos_sdk(&self) -> Result<()> {
         let output = self.env.macos_sdk();
         let mut item = WorkItem::xbuild_release(output, "MacOSX.sdk.tar.zst");
@@ -273,10 +510,42 @@ impl<'a> DownloadManager<'a> {
 
     pub fn android_ndk(&self) -> Result<()> {
         let output = self.env.android_ndk();
+        if let Some(version) = self.env.config().android().ndk_version.as_ref() {
+            if !output.exists() {
+                ensure_online(self.env, &output)?;
+                // Only the sysroot is needed, the toolchain is taken from `PATH`
+                crate::sdkmanager::install(
+                    self,
+                    &format!("ndk;{}", version),
+                    &[
+                        Filter::Folder("sysroot/"),
+                        Filter::Name("source.properties"),
+                    ],
+                )?;
+                anyhow::ensure!(output.exists(), "ndk {} not found", version);
+            }
+            return Ok(());
+        }
         let item = WorkItem::xbuild_release(output, "Android.ndk.tar.zst");
         self.fetch(item)
     }
 
+    pub fn android_build_tools(&self) -> Result<()> {
+        let output = if let Some(output) = self.env.android_build_tools() {
+            output
+        } else {
+            return Ok(());
+        };
+        if !output.exists() {
+            ensure_online(self.env, &output)?;
+            let version = self.env.config().android().build_tools_version.as_ref();
+            let package = format!("build-tools;{}", version.unwrap());
+            crate::sdkmanager::install(self, &package, &[])?;
+            anyhow::ensure!(output.exists(), "{} not found", package);
+        }
+        Ok(())
+    }
+
     pub fn ios_sdk(&self) -> Result<()> {
         let output = self.env.ios_sdk();
         let mut item = WorkItem::xbuild_release(output, "iPhoneOS.sdk.tar.zst");
@@ -288,13 +557,50 @@ impl<'a> DownloadManager<'a> {
 
     pub fn developer_disk_image(&self, major: u32, minor: u32) -> Result<()> {
         let output = self.env.developer_disk_image(major, minor);
-        let item = WorkItem::github_release(
+        let mut item = WorkItem::github_release(
             output.parent().unwrap().into(),
             "mspvirajpatel",
             "Xcode_Developer_Disk_Images",
             &format!("{}.{}", major, minor),
             &format!("{}.{}.zip", major, minor),
         );
+        // Mounting fails unless the image matches its apple signature
+        item.unverified();
         self.fetch(item)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256sum() {
+        let sums = "ab01  Android.ndk.tar.zst\ncd23 *Windows.sdk.tar.zst\n";
+        assert_eq!(sha256sum(sums, "Android.ndk.tar.zst"), Some("ab01"));
+        assert_eq!(sha256sum(sums, "Windows.sdk.tar.zst"), Some("cd23"));
+        assert_eq!(sha256sum(sums, "MacOSX.sdk.tar.zst"), None);
+    }
+
+    #[test]
+    #[ignore = "sdk.sha256 is empty until it has the SHA256SUMS of WorkItem::VERSION"]
+    fn test_artifacts_pinned() {
+        for artifact in WorkItem::ARTIFACTS {
+            assert!(
+                sha256sum(WorkItem::SHA256SUMS, artifact).is_some(),
+                "no sha256 pinned for {}",
+                artifact
+            );
+        }
+    }
+
+    #[test]
+    fn test_ensure_released() {
+        let missing = Path::new("/nonexistent/Linux-x64.sysroot");
+        assert!(ensure_released(missing, "Android.ndk.tar.zst", String::new()).is_ok());
+        assert!(ensure_released(missing, "Linux-x64.sysroot.tar.zst", String::new()).is_err());
+        assert!(ensure_released(missing, "runtime-aarch64", String::new()).is_err());
+        let dir = std::env::temp_dir();
+        assert!(ensure_released(&dir, "Linux-x64.sysroot.tar.zst", String::new()).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+//! Errors with a category that selects the exit code of `x` and a hint how
+//! to fix them. They're passed around as [`anyhow::Error`] like every other
+//! error and found again with [`Error::find`].
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// Missing or misconfigured sdks and tools.
+    Environment,
+    /// Invalid `manifest.yaml` or `Cargo.toml`.
+    Configuration,
+    /// Missing rust targets or toolchains.
+    Toolchain,
+    /// Missing, offline or unauthorized devices.
+    Device,
+    /// Missing or unreadable signing identities.
+    Signing,
+}
+
+impl ErrorKind {
+    /// Exit code of `x`, uncategorized errors exit with 1 and invalid
+    /// arguments with 2.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Environment => 3,
+            Self::Configuration => 4,
+            Self::Toolchain => 5,
+            Self::Device => 6,
+            Self::Signing => 7,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Environment => write!(f, "environment"),
+            Self::Configuration => write!(f, "configuration"),
+            Self::Toolchain => write!(f, "toolchain"),
+            Self::Device => write!(f, "device"),
+            Self::Signing => write!(f, "signing"),
+        }
+    }
+}
+
+/// Categorized error, displayed like the error it wraps.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    hint: Option<String>,
+    error: anyhow::Error,
+}
+
+impl Error {
+    pub fn new(
+        kind: ErrorKind,
+        msg: impl fmt::Display + fmt::Debug + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            kind,
+            hint: None,
+            error: anyhow::Error::msg(msg),
+        }
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Suggestion how to fix the error, like a command to run.
+    pub fn remediation(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    /// Outermost categorized error of `error`, including the ones context
+    /// was added to.
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref::<Self>()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Categorizes the error of a result, keeping its message and causes.
+pub trait Categorize<T> {
+    fn categorize(self, kind: ErrorKind, hint: &str) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> Categorize<T> for Result<T, E> {
+    fn categorize(self, kind: ErrorKind, hint: &str) -> anyhow::Result<T> {
+        self.map_err(|error| {
+            Error {
+                kind,
+                hint: Some(hint.to_string()),
+                error: error.into(),
+            }
+            .into()
+        })
+    }
+}
+
+/// Error of commands that need a device when none was passed.
+pub(crate) fn no_device() -> anyhow::Error {
+    Error::new(ErrorKind::Device, "no device specified")
+        .hint(
+            "connect a device or start an emulator, list them with `x devices` and pass `--device`",
+        )
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_find_through_context() {
+        let err = Err::<(), _>(no_device())
+            .context("failed to run")
+            .unwrap_err();
+        let error = Error::find(&err).unwrap();
+        assert_eq!(error.kind().exit_code(), 6);
+        assert_eq!(format!("{:#}", err), "failed to run: no device specified");
+        assert!(Error::find(&anyhow::anyhow!("uncategorized")).is_none());
+    }
+}
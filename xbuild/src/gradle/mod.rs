@@ -1,8 +1,11 @@
 use crate::cargo::CrateType;
+use crate::uniffi::Language;
 use crate::{task, BuildEnv, Format, Opt};
 use anyhow::Result;
+use apk::AndroidManifest;
 use std::path::Path;
 use std::process::Command;
+use xcommon::{Icon, Splash};
 
 static BUILD_GRADLE: &[u8] = include_bytes!("./build.gradle");
 static GRADLE_PROPERTIES: &[u8] = include_bytes!("./gradle.properties");
@@ -11,6 +14,17 @@ static IC_LAUNCHER: &[u8] = include_bytes!("./ic_launcher.xml");
 static DUMMY_CPP: &[u8] = include_bytes!("./dummy.cpp");
 static DUMMY_CMAKE_LISTS: &[u8] = include_bytes!("./CMakeLists.txt");
 
+const DPIS: [(&str, f32); 5] = [
+    ("m", 1.0),
+    ("h", 1.5),
+    ("xh", 2.0),
+    ("xxh", 3.0),
+    ("xxxh", 4.0),
+];
+
+/// Version of androidx.core:core-splashscreen declaring `postSplashScreenTheme`.
+const SPLASHSCREEN_VERSION: &str = "1.0.1";
+
 pub fn prepare(env: &BuildEnv) -> Result<()> {
     let config = env.config().android();
     if config.wry {
@@ -35,7 +49,7 @@ pub fn prepare(env: &BuildEnv) -> Result<()> {
     Ok(())
 }
 
-pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
+pub fn build(env: &BuildEnv, icon: Option<&Icon>, out: &Path) -> Result<()> {
     let platform_dir = env.platform_dir();
     let gradle = platform_dir.join("gradle");
     let app = gradle.join("app");
@@ -45,9 +59,38 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
     let jnilibs = main.join("jniLibs");
     let res = main.join("res");
 
+    let config = env.config().android();
+    let mut root_plugins = String::new();
+    let mut plugins = String::new();
+    for plugin in &config.gradle_plugins {
+        // Plugins without a version are expected on the classpath already
+        let id = if let Some((id, version)) = plugin.split_once(':') {
+            root_plugins.push_str(&format!(
+                "    id '{}' version '{}' apply false\n",
+                id, version
+            ));
+            id
+        } else {
+            plugin.as_str()
+        };
+        plugins.push_str(&format!("id '{}'\n", id));
+    }
+    let build_gradle = std::str::from_utf8(BUILD_GRADLE)?;
+    let end = build_gradle.find("}\n").unwrap();
+    let build_gradle = format!(
+        "{}{}{}",
+        &build_gradle[..end],
+        root_plugins,
+        &build_gradle[end..]
+    );
+    let mut gradle_properties = std::str::from_utf8(GRADLE_PROPERTIES)?.to_string();
+    for (key, value) in &config.gradle_properties {
+        gradle_properties.push_str(&format!("\n{}={}", key, value));
+    }
+
     std::fs::create_dir_all(&kotlin)?;
-    std::fs::write(gradle.join("build.gradle"), BUILD_GRADLE)?;
-    std::fs::write(gradle.join("gradle.properties"), GRADLE_PROPERTIES)?;
+    std::fs::write(gradle.join("build.gradle"), build_gradle)?;
+    std::fs::write(gradle.join("gradle.properties"), gradle_properties)?;
     std::fs::write(gradle.join("settings.gradle"), SETTINGS_GRADLE)?;
 
     // this is so gradle includes libc++_shared.so
@@ -55,8 +98,10 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
     std::fs::write(dummy_cpp.join("dummy.cpp"), DUMMY_CPP)?;
     std::fs::write(app.join("CMakeLists.txt"), DUMMY_CMAKE_LISTS)?;
 
-    let config = env.config().android();
     let mut manifest = config.manifest.clone();
+    if let Some(translations) = env.translations()? {
+        translations.write_android_values(&res, &mut manifest)?;
+    }
 
     let package = manifest.package.take().unwrap_or_default();
     let target_sdk = manifest.sdk.target_sdk_version.take().unwrap();
@@ -71,20 +116,69 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
     manifest.platform_build_version_name = None;
     manifest.application.debuggable = None;
 
+    let mut versions = String::new();
+    if let Some(version) = &config.ndk_version {
+        versions.push_str(&format!("ndkVersion '{}'\n", version));
+    }
+    if let Some(version) = &config.build_tools_version {
+        versions.push_str(&format!("buildToolsVersion '{}'\n", version));
+    }
+
+    let mut build_types = String::new();
+    if crate::jni::shrink(env) {
+        let mut rules = "'proguard-rules.pro'".to_string();
+        for path in crate::jni::user_rules(env)? {
+            rules.push_str(&format!(
+                ", '{}'",
+                path.display().to_string().replace('\\', "/")
+            ));
+        }
+        build_types = format!(
+            r#"
+                buildTypes {{
+                    release {{
+                        minifyEnabled true
+                        proguardFiles getDefaultProguardFile('proguard-android-optimize.txt'), {}
+                    }}
+                }}
+            "#,
+            rules
+        );
+    }
+
     let mut dependencies = String::new();
     for dep in &config.dependencies {
         dependencies.push_str(&format!("implementation '{}'\n", dep));
     }
+    if env.config().uniffi().is_some() {
+        dependencies.push_str(&format!(
+            "implementation 'net.java.dev.jna:jna:{}@aar'\n",
+            crate::uniffi::JNA_VERSION
+        ));
+    }
+    let splash = env.splash()?;
+    if splash.is_some() {
+        dependencies.push_str(&format!(
+            "implementation 'androidx.core:core-splashscreen:{}'\n",
+            SPLASHSCREEN_VERSION
+        ));
+    }
+    for dep in &config.gradle_dependencies {
+        dependencies.push_str(dep);
+        dependencies.push('\n');
+    }
 
     let app_build_gradle = format!(
         r#"
             plugins {{
                 id 'com.android.application'
                 id 'org.jetbrains.kotlin.android'
+                {plugins}
             }}
             android {{
                 namespace '{package}'
-                compileSdk {target_sdk}
+                compileSdk {compile_sdk}
+                {versions}
                 defaultConfig {{
                     applicationId '{package}'
                     minSdk {min_sdk}
@@ -110,12 +204,17 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
                         path "CMakeLists.txt"
                     }}
                 }}
+                {build_types}
             }}
             dependencies {{
                 {dependencies}
             }}
         "#,
         package = package,
+        plugins = plugins,
+        compile_sdk = env.compile_sdk_version(),
+        versions = versions,
+        build_types = build_types,
         target_sdk = target_sdk,
         min_sdk = min_sdk,
         version_code = version_code,
@@ -124,35 +223,42 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
         assets_folder = assets_folder,
     );
 
-    if let Some(icon_path) = env.icon.as_ref() {
-        let mut scaler = xcommon::Scaler::open(icon_path)?;
-        scaler.optimize();
+    if let Some(icon) = icon {
         let anydpi = res.join("mipmap-anydpi-v26");
         std::fs::create_dir_all(&anydpi)?;
-        std::fs::write(anydpi.join("ic_launcher.xml"), IC_LAUNCHER)?;
-        let dpis = [
-            ("m", 48),
-            ("h", 72),
-            ("xh", 96),
-            ("xxh", 144),
-            ("xxxh", 192),
+        let mut ic_launcher = std::str::from_utf8(IC_LAUNCHER)?.to_string();
+        let mut layers = vec![
+            ("ic_launcher", icon.path(), 48),
+            ("ic_launcher_foreground", icon.foreground(), 108),
+            ("ic_launcher_background", icon.background(), 108),
         ];
-        for (name, size) in dpis {
-            let dir_name = format!("mipmap-{}dpi", name);
-            let dir = res.join(dir_name);
-            std::fs::create_dir_all(&dir)?;
-            for variant in ["foreground", "monochrome"] {
-                let mut icon =
-                    std::fs::File::create(dir.join(format!("ic_launcher_{}.png", variant)))?;
-                scaler.write(
-                    &mut icon,
-                    xcommon::ScalerOptsBuilder::new(size, size).build(),
-                )?;
+        if let Some(monochrome) = icon.monochrome() {
+            let end = ic_launcher.find("</adaptive-icon>").unwrap();
+            ic_launcher.insert_str(
+                end,
+                "  <monochrome android:drawable=\"@mipmap/ic_launcher_monochrome\"/>\n",
+            );
+            layers.push(("ic_launcher_monochrome", monochrome, 108));
+        }
+        std::fs::write(anydpi.join("ic_launcher.xml"), ic_launcher)?;
+        for (name, path, dp) in layers {
+            let mut scaler = xcommon::Scaler::open(path)?.with_cache(&env.icon_cache_dir());
+            scaler.optimize();
+            for (dpi, scale) in DPIS {
+                let dir = res.join(format!("mipmap-{}dpi", dpi));
+                std::fs::create_dir_all(&dir)?;
+                let size = (dp as f32 * scale) as u32;
+                let mut file = std::fs::File::create(dir.join(format!("{}.png", name)))?;
+                scaler.write(&mut file, xcommon::ScalerOpts::new(size))?;
             }
         }
         manifest.application.icon = Some("@mipmap/ic_launcher".into());
     }
 
+    if let Some(splash) = splash {
+        write_splash(env, &splash, &res, &mut manifest)?;
+    }
+
     std::fs::write(app.join("build.gradle"), app_build_gradle)?;
     std::fs::write(
         main.join("AndroidManifest.xml"),
@@ -173,6 +279,24 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
         }
     }
 
+    let target = env.target().compile_targets().next().unwrap();
+    let cargo_dir = platform_dir.join(target.arch().to_string()).join("cargo");
+    if let Ok(lib) = env.cargo_artefact(&cargo_dir, target, CrateType::Cdylib) {
+        if let Some(bindings) = crate::uniffi::generate(env, Language::Kotlin, &lib)? {
+            xcommon::copy_dir_all(&bindings, &kotlin)?;
+        }
+    }
+    if let Some(java) = crate::jni::generate(env)? {
+        let java_dir = main.join("java");
+        std::fs::create_dir_all(&java_dir)?;
+        xcommon::copy_dir_all(&java, &java_dir)?;
+    }
+
+    if crate::jni::shrink(env) {
+        let rules = crate::jni::write_keep_rules(env, &[main.join("java"), kotlin.clone()])?;
+        std::fs::copy(rules, app.join("proguard-rules.pro"))?;
+    }
+
     for target in env.target().compile_targets() {
         let arch_dir = platform_dir.join(target.arch().to_string());
         let lib = env.cargo_artefact(&arch_dir.join("cargo"), target, CrateType::Cdylib)?;
@@ -192,7 +316,7 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
         _ => unreachable!(),
     });
     cmd.arg("--warning-mode").arg("all");
-    task::run(cmd, true)?;
+    task::run(cmd, true, env.message_format())?;
     let output = gradle
         .join("app")
         .join("build")
@@ -213,3 +337,96 @@ pub fn build(env: &BuildEnv, out: &Path) -> Result<()> {
     std::fs::copy(output, out)?;
     Ok(())
 }
+
+/// Writes the `splash_background` color, the `splash_icon` drawable and
+/// the `Theme.Splash` style, which inherits from the manifest theme and
+/// switches back to it with `postSplashScreenTheme`.
+fn write_splash(
+    env: &BuildEnv,
+    splash: &Splash,
+    res: &Path,
+    manifest: &mut AndroidManifest,
+) -> Result<()> {
+    let color = |[r, g, b, a]: [u8; 4]| format!("#{:02x}{:02x}{:02x}{:02x}", a, r, g, b);
+    let resources = |body: String| {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<resources>\n{}</resources>\n",
+            body
+        )
+    };
+    let write = |dir: &str, file: &str, contents: String| -> Result<()> {
+        let dir = res.join(dir);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(file), contents)?;
+        Ok(())
+    };
+    write(
+        "values",
+        "splash_colors.xml",
+        resources(format!(
+            "    <color name=\"splash_background\">{}</color>\n",
+            color(splash.background)
+        )),
+    )?;
+    if let Some(dark) = splash.dark_background {
+        write(
+            "values-night",
+            "splash_colors.xml",
+            resources(format!(
+                "    <color name=\"splash_background\">{}</color>\n",
+                color(dark)
+            )),
+        )?;
+    }
+    let images = [
+        ("drawable", splash.image.as_ref()),
+        (
+            "drawable-night",
+            splash.image.as_ref().and(splash.dark_image.as_ref()),
+        ),
+    ];
+    for (prefix, image) in images {
+        let image = if let Some(image) = image {
+            image
+        } else {
+            continue;
+        };
+        let mut scaler = xcommon::Scaler::open(image)?.with_cache(&env.icon_cache_dir());
+        scaler.optimize();
+        for (dpi, scale) in DPIS {
+            let dir = res.join(format!("{}-{}dpi", prefix, dpi));
+            std::fs::create_dir_all(&dir)?;
+            let size = (288.0 * scale) as u32;
+            let opts = xcommon::ScalerOptsBuilder::new(size, size)
+                .padding(0.53)
+                .build();
+            let mut file = std::fs::File::create(dir.join("splash_icon.png"))?;
+            scaler.write(&mut file, opts)?;
+        }
+    }
+    let parent = manifest
+        .application
+        .theme
+        .as_deref()
+        .unwrap_or("@android:style/Theme.DeviceDefault.NoActionBar");
+    let style = |items: &str| {
+        resources(format!(
+            "    <style name=\"Theme.Splash\" parent=\"{}\">\n        <item name=\"android:windowBackground\">@color/splash_background</item>\n        <item name=\"postSplashScreenTheme\">{}</item>\n{}    </style>\n",
+            parent, parent, items
+        ))
+    };
+    write("values", "splash_styles.xml", style(""))?;
+    if env.compile_sdk_version() >= 31 {
+        let mut items =
+            "        <item name=\"android:windowSplashScreenBackground\">@color/splash_background</item>\n"
+                .to_string();
+        if splash.image.is_some() {
+            items.push_str(
+                "        <item name=\"android:windowSplashScreenAnimatedIcon\">@drawable/splash_icon</item>\n",
+            );
+        }
+        write("values-v31", "splash_styles.xml", style(&items))?;
+    }
+    manifest.application.theme = Some("@style/Theme.Splash".into());
+    Ok(())
+}
@@ -0,0 +1,574 @@
+use crate::task::Cache;
+use crate::{BuildEnv, Categorize, ErrorKind};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Native method of a generated class, declared by a `Java_*` export.
+#[derive(Debug, Eq, PartialEq)]
+struct NativeMethod {
+    name: String,
+    is_static: bool,
+    params: Vec<&'static str>,
+    ret: &'static str,
+}
+
+/// Writes the java glue of the app to `<platform dir>/java`, returns `None`
+/// if the app has no java code. With `android.jni.bindings` a class with
+/// matching `native` methods is generated for every class the rust sources
+/// export `Java_<class>_<method>` functions for. Sources in `android.jni.sources`
+/// are copied alongside.
+pub fn generate(env: &BuildEnv) -> Result<Option<PathBuf>> {
+    let config = &env.config().android().jni;
+    if !config.enabled() {
+        return Ok(None);
+    }
+    let java_dir = env.platform_dir().join("java");
+    if java_dir.exists() {
+        std::fs::remove_dir_all(&java_dir)?;
+    }
+    std::fs::create_dir_all(&java_dir)?;
+    if let Some(sources) = &config.sources {
+        let sources = env.cargo().package_root().join(sources);
+        anyhow::ensure!(
+            sources.exists(),
+            "java sources `{}` don't exist",
+            sources.display()
+        );
+        xcommon::copy_dir_all(&sources, &java_dir)?;
+    }
+    if config.bindings {
+        let mut rust_files = vec![];
+        find_files(
+            &env.cargo().package_root().join("src"),
+            "rs",
+            &mut rust_files,
+        )?;
+        let mut classes: BTreeMap<String, Vec<NativeMethod>> = BTreeMap::new();
+        for path in rust_files {
+            let source = std::fs::read_to_string(&path)?;
+            for (class, method) in exports(&source)
+                .with_context(|| format!("invalid jni export in `{}`", path.display()))?
+            {
+                classes.entry(class).or_default().push(method);
+            }
+        }
+        let library = env.android_lib_name().replace('-', "_");
+        for (class, methods) in &classes {
+            let path = java_dir.join(format!("{}.java", class.replace('.', "/")));
+            anyhow::ensure!(
+                !path.exists(),
+                "`{}` is generated from the jni exports, remove it from the java sources",
+                class
+            );
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, java_class(class, &library, methods))?;
+        }
+    }
+    Ok(Some(java_dir))
+}
+
+/// Compiles the java glue with `javac` and `d8`, together with the kotlin
+/// sources in `kotlin` and the jars they depend on. Returns the `classes.dex`
+/// and whether it was reused from the previous build, which happens if none
+/// of the inputs changed.
+pub fn build(env: &BuildEnv, kotlin: Option<&Path>) -> Result<Option<(PathBuf, Cache)>> {
+    let mut sources = vec![];
+    let java_dir = generate(env)?;
+    if let Some(java_dir) = &java_dir {
+        find_files(java_dir, "java", &mut sources)?;
+        anyhow::ensure!(!sources.is_empty(), "no java sources or jni exports found");
+    } else if kotlin.is_none() {
+        return Ok(None);
+    }
+    let android_jar = env.android_jar();
+    let classes_dir = env.platform_dir().join("classes");
+    let dex_dir = env.platform_dir().join("dex");
+    let mut kotlin_sources = vec![];
+    let mut jars = vec![];
+    if let Some(kotlin) = kotlin {
+        find_files(kotlin, "kt", &mut kotlin_sources)?;
+        jars.push(crate::uniffi::jna(env)?);
+        jars.push(kotlin_stdlib()?);
+    }
+    let d8_path = d8(env)?;
+    let mut rules = vec![];
+    if shrink(env) {
+        let sources = java_dir
+            .iter()
+            .cloned()
+            .chain(kotlin.map(Path::to_path_buf))
+            .collect::<Vec<_>>();
+        rules.push(write_keep_rules(env, &sources)?);
+        rules.extend(user_rules(env)?);
+    }
+
+    let dex = dex_dir.join("classes.dex");
+    let stamp = dex_dir.join("inputs.sha256");
+    let inputs = inputs_hash(
+        env,
+        sources.iter().chain(&kotlin_sources).chain(&rules),
+        [&android_jar, &d8_path].into_iter().chain(&jars),
+    )?;
+    if dex.exists() && std::fs::read_to_string(&stamp).ok().as_ref() == Some(&inputs) {
+        return Ok(Some((dex, Cache::Hit)));
+    }
+    for dir in [&classes_dir, &dex_dir] {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        std::fs::create_dir_all(dir)?;
+    }
+    if kotlin.is_some() {
+        let classpath = std::env::join_paths([&android_jar, &jars[0]])?;
+        let status = Command::new("kotlinc")
+            .arg("-classpath")
+            .arg(classpath)
+            .arg("-jvm-target")
+            .arg("1.8")
+            .arg("-d")
+            .arg(&classes_dir)
+            // Java sources are passed so kotlin can refer to them
+            .args(&kotlin_sources)
+            .args(&sources)
+            .status()
+            .context("kotlinc not found, install kotlin")?;
+        anyhow::ensure!(status.success(), "kotlinc failed");
+    }
+    if !sources.is_empty() {
+        let classpath = std::env::join_paths(std::iter::once(&classes_dir).chain(&jars))?;
+        let status = Command::new("javac")
+            .arg("-source")
+            .arg("8")
+            .arg("-target")
+            .arg("8")
+            .arg("-Xlint:-options")
+            .arg("-bootclasspath")
+            .arg(&android_jar)
+            .arg("-classpath")
+            .arg(classpath)
+            .arg("-d")
+            .arg(&classes_dir)
+            .args(&sources)
+            .status()
+            .context("javac not found, install a jdk")?;
+        anyhow::ensure!(status.success(), "javac failed");
+    }
+    let mut classes = vec![];
+    find_files(&classes_dir, "class", &mut classes)?;
+    let mut d8 = if shrink(env) {
+        // R8 ships in the same jar as d8
+        let d8_jar = std::fs::canonicalize(&d8_path)?
+            .parent()
+            .unwrap()
+            .join("lib")
+            .join("d8.jar");
+        anyhow::ensure!(d8_jar.exists(), "{} not found", d8_jar.display());
+        let mut r8 = Command::new("java");
+        r8.arg("-cp")
+            .arg(d8_jar)
+            .arg("com.android.tools.r8.R8")
+            // Libraries commonly refer to classes android doesn't have
+            .arg("--map-diagnostics:MissingDefinitionsDiagnostic")
+            .arg("error")
+            .arg("warning");
+        for rules in &rules {
+            r8.arg("--pg-conf").arg(rules);
+        }
+        r8
+    } else {
+        Command::new(&d8_path)
+    };
+    d8.arg("--lib")
+        .arg(&android_jar)
+        .arg("--min-api")
+        .arg(env.min_sdk_version().to_string())
+        .arg("--output")
+        .arg(&dex_dir);
+    if env.target().opt() == crate::Opt::Release {
+        d8.arg("--release");
+    }
+    let status = d8
+        .args(&classes)
+        .args(&jars)
+        .status()
+        .context("failed to run d8")?;
+    anyhow::ensure!(status.success(), "d8 failed");
+    anyhow::ensure!(
+        !dex_dir.join("classes2.dex").exists(),
+        "the java glue exceeds the method limit of a single dex file"
+    );
+    std::fs::write(&stamp, inputs)?;
+    Ok(Some((dex, Cache::Miss)))
+}
+
+/// Hash of the paths and contents of `files`, the paths, sizes and
+/// modification times of the `tools` and the d8 options.
+fn inputs_hash<'a>(
+    env: &BuildEnv,
+    files: impl Iterator<Item = &'a PathBuf>,
+    tools: impl Iterator<Item = &'a PathBuf>,
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{} {} {}\n",
+        env.min_sdk_version(),
+        env.target().opt(),
+        shrink(env)
+    ));
+    for file in files {
+        hasher.update(format!("{}\n", file.display()));
+        hasher.update(std::fs::read(file)?);
+    }
+    for tool in tools {
+        let metadata = std::fs::metadata(tool)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        hasher.update(format!(
+            "{} {} {}\n",
+            tool.display(),
+            metadata.len(),
+            modified.as_nanos()
+        ));
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// If the dex code is shrunk with r8, only done for release builds.
+pub fn shrink(env: &BuildEnv) -> bool {
+    env.config().android().shrink && env.target().opt() == crate::Opt::Release
+}
+
+/// `android.proguard_rules` resolved against the package root.
+pub fn user_rules(env: &BuildEnv) -> Result<Vec<PathBuf>> {
+    env.config()
+        .android()
+        .proguard_rules
+        .iter()
+        .map(|path| {
+            let path = env.cargo().package_root().join(path);
+            anyhow::ensure!(
+                path.exists(),
+                "proguard rules `{}` don't exist",
+                path.display()
+            );
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Writes the keep rules for the java and kotlin sources in `sources` to
+/// `<platform dir>/proguard-rules.pro`. Native code looks up classes and
+/// members by name, so the app's own code is kept whole and the rules only
+/// strip unused library code. Names aren't obfuscated for the same reason.
+pub fn write_keep_rules(env: &BuildEnv, sources: &[PathBuf]) -> Result<PathBuf> {
+    let mut keep = BTreeSet::new();
+    for dir in sources.iter().filter(|dir| dir.exists()) {
+        let mut files = vec![];
+        find_files(dir, "java", &mut files)?;
+        find_files(dir, "kt", &mut files)?;
+        for file in files {
+            let source = std::fs::read_to_string(&file)?;
+            let package = source.lines().find_map(|line| {
+                let package = line.trim().strip_prefix("package ")?;
+                Some(package.trim_end_matches(';').trim().to_string())
+            });
+            keep.insert(if let Some(package) = package {
+                format!("{}.**", package)
+            } else {
+                // Classes in the default package are named after the file
+                file.file_stem().unwrap().to_str().unwrap().to_string()
+            });
+        }
+    }
+    let manifest = &env.config().android().manifest;
+    for activity in &manifest.application.activities {
+        if let Some(name) = &activity.name {
+            if !name.starts_with("android.") {
+                keep.insert(name.clone());
+            }
+        }
+    }
+    let mut rules = String::from(KEEP_RULES);
+    for class in keep {
+        rules.push_str(&format!("-keep class {} {{ *; }}\n", class));
+    }
+    let path = env.platform_dir().join("proguard-rules.pro");
+    std::fs::write(&path, rules)?;
+    Ok(path)
+}
+
+const KEEP_RULES: &str = "\
+-dontobfuscate
+-keepattributes *Annotation*,Signature,InnerClasses,EnclosingMethod
+-keepclasseswithmembernames,includedescriptorclasses class * {
+    native <methods>;
+}
+-keep class com.sun.jna.** { *; }
+-keep class * implements com.sun.jna.** { *; }
+-dontwarn java.awt.**
+";
+
+/// The kotlin standard library shipped with `kotlinc`.
+fn kotlin_stdlib() -> Result<PathBuf> {
+    let kotlinc = which::which("kotlinc").context("kotlinc not found, install kotlin")?;
+    let stdlib = std::fs::canonicalize(kotlinc)?
+        .parent()
+        .and_then(Path::parent)
+        .map(|home| home.join("lib").join("kotlin-stdlib.jar"))
+        .filter(|stdlib| stdlib.exists())
+        .context("kotlin-stdlib.jar not found next to kotlinc")?;
+    Ok(stdlib)
+}
+
+/// Finds `d8` in the pinned build-tools or the newest build-tools of the
+/// android sdk at `ANDROID_HOME`, falling back to `PATH`.
+fn d8(env: &BuildEnv) -> Result<PathBuf> {
+    let name = if cfg!(target_os = "windows") {
+        "d8.bat"
+    } else {
+        "d8"
+    };
+    if let Some(build_tools) = env.android_build_tools() {
+        let d8 = build_tools.join(name);
+        anyhow::ensure!(d8.exists(), "{} not found", d8.display());
+        return Ok(d8);
+    }
+    let home = std::env::var_os("ANDROID_HOME").or_else(|| std::env::var_os("ANDROID_SDK_ROOT"));
+    if let Some(home) = home {
+        let build_tools = PathBuf::from(home).join("build-tools");
+        if let Ok(entries) = std::fs::read_dir(build_tools) {
+            let mut versions = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().join(name))
+                .filter(|path| path.exists())
+                .collect::<Vec<_>>();
+            versions.sort();
+            if let Some(d8) = versions.pop() {
+                return Ok(d8);
+            }
+        }
+    }
+    which::which(name).context("d8 not found").categorize(
+        ErrorKind::Environment,
+        "install the android build-tools with the sdkmanager and set `ANDROID_HOME`",
+    )
+}
+
+fn find_files(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, extension, files)?;
+        } else if path
+            .extension()
+            .map(|ext| ext == extension)
+            .unwrap_or_default()
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(())
+}
+
+/// Finds `fn Java_<class>_<method>(env: JNIEnv, this: JClass, ...) -> T`.
+fn exports(source: &str) -> Result<Vec<(String, NativeMethod)>> {
+    let mut exports = vec![];
+    let mut rest = source;
+    while let Some(start) = rest.find("fn Java_") {
+        let line = &rest[rest[..start].rfind('\n').map(|i| i + 1).unwrap_or(0)..start];
+        rest = &rest[start + "fn ".len()..];
+        // Only exported functions, not mentions in comments
+        if !line.contains("extern") || line.contains("//") {
+            continue;
+        }
+        let (name, signature) = rest.split_once('(').context("expected a parameter list")?;
+        let (params, ret) = signature
+            .split_once(')')
+            .context("expected a parameter list")?;
+        let ret = ret
+            .split(['{', ';'])
+            .next()
+            .unwrap()
+            .split("where")
+            .next()
+            .unwrap()
+            .trim();
+        let name = name.split('<').next().unwrap().trim();
+        let (class, method) =
+            demangle(name).with_context(|| format!("can't demangle jni function {}", name))?;
+        anyhow::ensure!(
+            !class.contains('$'),
+            "{} belongs to a nested class, which can't be generated",
+            name
+        );
+        let mut params = split_params(params).into_iter().map(|param| {
+            param
+                .split_once(':')
+                .map(|(_, ty)| ty.trim())
+                .unwrap_or(param)
+        });
+        params
+            .next()
+            .with_context(|| format!("{} is missing the `JNIEnv` parameter", name))?;
+        let this = params
+            .next()
+            .with_context(|| format!("{} is missing the class or object parameter", name))?;
+        let is_static = matches!(rust_type(this), "JClass" | "jclass");
+        let params = params
+            .map(|ty| {
+                java_type(ty).with_context(|| format!("unsupported jni type {} in {}", ty, name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let ret = match ret.strip_prefix("->") {
+            Some(ty) => java_type(ty.trim())
+                .with_context(|| format!("unsupported jni type {} in {}", ty.trim(), name))?,
+            None => "void",
+        };
+        exports.push((
+            class,
+            NativeMethod {
+                name: method,
+                is_static,
+                params,
+                ret,
+            },
+        ));
+    }
+    Ok(exports)
+}
+
+/// Splits parameters at commas outside of generic arguments.
+fn split_params(params: &str) -> Vec<&str> {
+    let mut split = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in params.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(params[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(params[start..].trim());
+    split.retain(|param| !param.is_empty());
+    split
+}
+
+/// Reverses the jni name mangling of `Java_<class>_<method>`, overloaded
+/// methods with a mangled signature aren't supported.
+fn demangle(name: &str) -> Option<(String, String)> {
+    let mangled = name.strip_prefix("Java_")?;
+    let mut segments = vec![String::new()];
+    let mut chars = mangled.chars();
+    while let Some(c) = chars.next() {
+        if c != '_' {
+            segments.last_mut().unwrap().push(c);
+            continue;
+        }
+        match chars.clone().next() {
+            Some('1') => {
+                chars.next();
+                segments.last_mut().unwrap().push('_');
+            }
+            Some('0') => {
+                chars.next();
+                let code = chars.by_ref().take(4).collect::<String>();
+                let c = char::from_u32(u32::from_str_radix(&code, 16).ok()?)?;
+                segments.last_mut().unwrap().push(c);
+            }
+            Some(c) if c.is_ascii_digit() || c == '_' => return None,
+            _ => segments.push(String::new()),
+        }
+    }
+    let method = segments.pop()?;
+    if segments.is_empty() || segments.iter().chain([&method]).any(String::is_empty) {
+        return None;
+    }
+    Some((segments.join("."), method))
+}
+
+/// Last path segment without lifetimes and generic arguments.
+fn rust_type(ty: &str) -> &str {
+    let ty = ty.trim().trim_start_matches('&').trim_start();
+    let ty = ty.strip_prefix("mut ").unwrap_or(ty);
+    let ty = ty.split('<').next().unwrap().trim();
+    ty.rsplit("::").next().unwrap()
+}
+
+fn java_type(ty: &str) -> Option<&'static str> {
+    Some(match rust_type(ty) {
+        "jboolean" | "bool" => "boolean",
+        "jbyte" | "i8" => "byte",
+        "jchar" | "u16" => "char",
+        "jshort" | "i16" => "short",
+        "jint" | "i32" => "int",
+        "jlong" | "i64" => "long",
+        "jfloat" | "f32" => "float",
+        "jdouble" | "f64" => "double",
+        "JString" | "jstring" => "String",
+        "JClass" | "jclass" => "Class<?>",
+        "JThrowable" | "jthrowable" => "Throwable",
+        "JObject" | "jobject" => "Object",
+        "JBooleanArray" | "jbooleanArray" => "boolean[]",
+        "JByteArray" | "jbyteArray" => "byte[]",
+        "JCharArray" | "jcharArray" => "char[]",
+        "JShortArray" | "jshortArray" => "short[]",
+        "JIntArray" | "jintArray" => "int[]",
+        "JLongArray" | "jlongArray" => "long[]",
+        "JFloatArray" | "jfloatArray" => "float[]",
+        "JDoubleArray" | "jdoubleArray" => "double[]",
+        "JObjectArray" | "jobjectArray" => "Object[]",
+        _ => return None,
+    })
+}
+
+fn java_class(class: &str, library: &str, methods: &[NativeMethod]) -> String {
+    let mut java = format!(
+        "// Generated by xbuild from the jni exports of {}, do not edit.\n",
+        library
+    );
+    let name = if let Some((package, name)) = class.rsplit_once('.') {
+        java.push_str(&format!("package {};\n", package));
+        name
+    } else {
+        class
+    };
+    java.push_str(&format!(
+        "\npublic class {} {{\n    static {{\n        System.loadLibrary(\"{}\");\n    }}\n",
+        name, library
+    ));
+    for method in methods {
+        let params = method
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| format!("{} arg{}", ty, i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let modifiers = if method.is_static {
+            "public static native"
+        } else {
+            "public native"
+        };
+        java.push_str(&format!(
+            "\n    {} {} {}({});\n",
+            modifiers, method.ret, method.name, params
+        ));
+    }
+    java.push_str("}\n");
+    java
+}
@@ -0,0 +1,641 @@
+//! Translations of the strings the platforms show outside of the app, like
+//! the app name and the permission prompts, loaded from one source and
+//! written in the format of each platform.
+//!
+//! Keys starting with an uppercase letter, like `NSCameraUsageDescription`,
+//! are `Info.plist` keys localized on ios and macos. The other keys become
+//! android string resources and msix resources, `app-name` and
+//! `app-description` name and describe the app on every platform. `-` and
+//! `_` in keys are equivalent.
+use crate::config::LocalizationConfig;
+use anyhow::{Context, Result};
+use apk::manifest::AndroidManifest;
+use apk::StringResource;
+use msix::AppxManifest;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const APP_NAME: &str = "app-name";
+const APP_DESCRIPTION: &str = "app-description";
+
+#[derive(Clone, Debug, Default)]
+pub struct Translations {
+    default_locale: String,
+    /// Messages by locale and key.
+    locales: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Translations {
+    pub fn load(root: &Path, config: &LocalizationConfig) -> Result<Self> {
+        let source = root.join(&config.source);
+        let mut translations = Self {
+            default_locale: config.default_locale.clone().unwrap_or_else(|| "en".into()),
+            locales: Default::default(),
+        };
+        if source.is_dir() {
+            for entry in std::fs::read_dir(&source)
+                .with_context(|| format!("failed to read {}", source.display()))?
+            {
+                let path = entry?.path();
+                let locale = path.file_stem().unwrap().to_string_lossy().to_string();
+                let files = if path.is_dir() {
+                    // `<locale>/main.ftl` or `<locale>/LC_MESSAGES/app.po`
+                    walk(&path)?
+                } else {
+                    vec![path]
+                };
+                for file in files {
+                    translations.load_file(&locale, &file)?;
+                }
+            }
+        } else {
+            let csv = std::fs::read_to_string(&source)
+                .with_context(|| format!("failed to read {}", source.display()))?;
+            let rows = parse_csv(&csv).with_context(|| format!("in {}", source.display()))?;
+            let mut rows = rows.into_iter();
+            let header = rows.next().unwrap_or_default();
+            anyhow::ensure!(
+                header.len() > 1,
+                "{} needs a key column and a column per locale",
+                source.display()
+            );
+            for row in rows {
+                let key = match row.first() {
+                    Some(key) if !key.is_empty() => key,
+                    _ => continue,
+                };
+                for (locale, value) in header.iter().zip(&row).skip(1) {
+                    translations.insert(locale.trim(), key, value);
+                }
+            }
+        }
+        anyhow::ensure!(
+            translations
+                .locales
+                .contains_key(&translations.default_locale),
+            "{} has no translations for the default locale `{}`",
+            source.display(),
+            translations.default_locale
+        );
+        Ok(translations)
+    }
+
+    fn load_file(&mut self, locale: &str, path: &Path) -> Result<()> {
+        let parse = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ftl") => parse_fluent,
+            Some("po") => parse_po,
+            _ => return Ok(()),
+        };
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let messages = parse(&contents).with_context(|| format!("in {}", path.display()))?;
+        for (key, value) in messages {
+            self.insert(locale, &key, &value);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, locale: &str, key: &str, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        self.locales
+            .entry(locale.to_string())
+            .or_default()
+            .insert(key.trim().replace('_', "-"), value.to_string());
+    }
+
+    /// Locales with translations, the default locale first.
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.default_locale.as_str()).chain(
+            self.locales
+                .keys()
+                .map(String::as_str)
+                .filter(move |locale| *locale != self.default_locale),
+        )
+    }
+
+    fn messages(&self, locale: &str) -> impl Iterator<Item = (&str, &str)> {
+        self.locales
+            .get(locale)
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    fn get(&self, locale: &str, key: &str) -> Option<&str> {
+        self.locales.get(locale)?.get(key).map(String::as_str)
+    }
+
+    /// Keys of the resources shared by android and msix.
+    fn resource_keys(&self) -> Vec<&str> {
+        let mut keys = self
+            .locales
+            .values()
+            .flat_map(|messages| messages.keys())
+            .map(String::as_str)
+            .filter(|key| !is_info_plist_key(key))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// String resources compiled into the apk, untranslated keys fall back
+    /// to the default locale. Points the application label at `app_name`.
+    pub fn android_strings(&self, manifest: &mut AndroidManifest) -> Result<Vec<StringResource>> {
+        let mut strings = vec![];
+        for key in self.resource_keys() {
+            let name = key.replace('-', "_");
+            anyhow::ensure!(
+                name.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+                "`{}` is not a valid android resource name",
+                key
+            );
+            let default = match (self.get(&self.default_locale, key), key) {
+                (Some(value), _) => value.to_string(),
+                (None, APP_NAME) => manifest.application.label.clone().unwrap_or_default(),
+                (None, _) => anyhow::bail!(
+                    "`{}` has no translation for the default locale `{}`",
+                    key,
+                    self.default_locale
+                ),
+            };
+            let mut values = vec![(None, default)];
+            for locale in self.locales().skip(1) {
+                if let Some(value) = self.get(locale, key) {
+                    values.push((Some(android_locale(locale)), value.to_string()));
+                }
+            }
+            strings.push(StringResource { name, values });
+        }
+        if strings.iter().any(|string| string.name == "app_name") {
+            manifest.application.label = Some("@string/app_name".into());
+        }
+        Ok(strings)
+    }
+
+    /// Writes `values[-<locale>]/strings.xml` for gradle builds.
+    pub fn write_android_values(&self, res: &Path, manifest: &mut AndroidManifest) -> Result<()> {
+        let mut files = BTreeMap::<String, String>::new();
+        for string in self.android_strings(manifest)? {
+            for (locale, value) in &string.values {
+                let dir = match locale {
+                    Some(locale) => format!("values-{}", locale),
+                    None => "values".to_string(),
+                };
+                files.entry(dir).or_default().push_str(&format!(
+                    "    <string name=\"{}\">{}</string>\n",
+                    string.name,
+                    escape_android(value)
+                ));
+            }
+        }
+        for (dir, strings) in files {
+            let dir = res.join(dir);
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(
+                dir.join("strings.xml"),
+                format!(
+                    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<resources>\n{}</resources>\n",
+                    strings
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Contents of `InfoPlist.strings` by locale.
+    pub fn info_plist_strings(&self) -> BTreeMap<String, BTreeMap<String, String>> {
+        let mut tables = BTreeMap::new();
+        for locale in self.locales() {
+            let mut strings = BTreeMap::new();
+            for (key, value) in self.messages(locale) {
+                if key == APP_NAME {
+                    strings.insert("CFBundleDisplayName".to_string(), value.to_string());
+                    strings.insert("CFBundleName".to_string(), value.to_string());
+                } else if is_info_plist_key(key) {
+                    strings.insert(key.to_string(), value.to_string());
+                }
+            }
+            if !strings.is_empty() {
+                tables.insert(locale.to_string(), strings);
+            }
+        }
+        tables
+    }
+
+    /// Sets `Name` and `Comment` and adds their `Name[<locale>]` and
+    /// `Comment[<locale>]` translations to the desktop entries.
+    pub fn desktop_entries(&self, entries: &mut BTreeMap<String, String>) {
+        for (key, entry) in [(APP_NAME, "Name"), (APP_DESCRIPTION, "Comment")] {
+            for locale in self.locales() {
+                if let Some(value) = self.get(locale, key) {
+                    let name = if locale == self.default_locale {
+                        entry.to_string()
+                    } else {
+                        format!("{}[{}]", entry, locale.replace('-', "_"))
+                    };
+                    entries.insert(name, value.replace('\n', "\\n"));
+                }
+            }
+        }
+    }
+
+    /// Writes the PRI inputs `Strings/<locale>/Resources.resw` to `dir` and
+    /// indexes them into `resources.pri` with `makepri` from the windows
+    /// sdk. Returns the pri file, after declaring the languages and
+    /// pointing the display name and description at the resources.
+    pub fn msix_resources(
+        &self,
+        dir: &Path,
+        manifest: &mut AppxManifest,
+    ) -> Result<Option<PathBuf>> {
+        let keys = self.resource_keys();
+        std::fs::remove_dir_all(dir).ok();
+        for locale in self.locales() {
+            let mut resw = String::from(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<root>\n  \
+                 <resheader name=\"resmimetype\"><value>text/microsoft-resx</value></resheader>\n",
+            );
+            for (key, value) in self.messages(locale) {
+                if keys.contains(&key) {
+                    resw.push_str(&format!(
+                        "  <data name=\"{}\" xml:space=\"preserve\"><value>{}</value></data>\n",
+                        key,
+                        escape_xml(value)
+                    ));
+                }
+            }
+            resw.push_str("</root>\n");
+            let strings = dir.join("Strings").join(locale);
+            std::fs::create_dir_all(&strings)?;
+            std::fs::write(strings.join("Resources.resw"), resw)?;
+        }
+
+        let makepri = if let Ok(makepri) = which::which("makepri") {
+            makepri
+        } else {
+            log::warn!(
+                "makepri not found, the msix isn't localized. The resources are in {}",
+                dir.display()
+            );
+            return Ok(None);
+        };
+        let config = dir.join("priconfig.xml");
+        let pri = dir.join("resources.pri");
+        let status = Command::new(&makepri)
+            .arg("createconfig")
+            .arg("/cf")
+            .arg(&config)
+            .arg("/dq")
+            .arg(&self.default_locale)
+            .arg("/pv")
+            .arg("10.0.0")
+            .arg("/o")
+            .status()?;
+        anyhow::ensure!(status.success(), "makepri createconfig failed");
+        let status = Command::new(&makepri)
+            .arg("new")
+            .arg("/pr")
+            .arg(dir)
+            .arg("/cf")
+            .arg(&config)
+            .arg("/in")
+            .arg(manifest.identity.name.as_deref().unwrap_or_default())
+            .arg("/of")
+            .arg(&pri)
+            .arg("/o")
+            .status()?;
+        anyhow::ensure!(status.success(), "makepri new failed");
+
+        manifest.resources.resource = self
+            .locales()
+            .map(|locale| msix::manifest::Resource {
+                language: locale.to_string(),
+            })
+            .collect();
+        let resource = |key: &str| format!("ms-resource:{}", key);
+        if keys.contains(&APP_NAME) {
+            manifest.properties.display_name = Some(resource(APP_NAME));
+            for application in &mut manifest.applications.application {
+                application.visual_elements.display_name = Some(resource(APP_NAME));
+            }
+        }
+        if keys.contains(&APP_DESCRIPTION) {
+            manifest.properties.description = Some(resource(APP_DESCRIPTION));
+        }
+        Ok(Some(pri))
+    }
+}
+
+fn is_info_plist_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+/// `pt-BR` as the `pt-rBR` android resource qualifier.
+fn android_locale(locale: &str) -> String {
+    match locale.split_once(['-', '_']) {
+        Some((language, region)) => format!("{}-r{}", language, region.to_uppercase()),
+        None => locale.to_string(),
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_android(s: &str) -> String {
+    escape_xml(&s.replace('\\', "\\\\"))
+        .replace('\'', "\\'")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn walk(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(walk(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Messages of a fluent file. Attributes, selectors and variables aren't
+/// supported, term references like `{ -brand }` are substituted.
+fn parse_fluent(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut entries: Vec<(String, String)> = vec![];
+    let mut continues = false;
+    for (i, line) in contents.lines().enumerate() {
+        if line.starts_with([' ', '\t']) && continues {
+            let line = line.trim();
+            if line.starts_with('.') {
+                continues = false;
+            } else if !line.is_empty() {
+                let value = &mut entries.last_mut().unwrap().1;
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line);
+            }
+            continue;
+        }
+        continues = false;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with([' ', '\t']) {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {}: expected `key = value`", i + 1))?;
+        entries.push((key.trim().to_string(), value.trim().to_string()));
+        continues = true;
+    }
+    let terms = entries
+        .iter()
+        .filter(|(key, _)| key.starts_with('-'))
+        .cloned()
+        .collect::<Vec<_>>();
+    Ok(entries
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with('-'))
+        .map(|(key, mut value)| {
+            for (term, term_value) in &terms {
+                value = value.replace(&format!("{{ {} }}", term), term_value);
+                value = value.replace(&format!("{{{}}}", term), term_value);
+            }
+            (key, value)
+        })
+        .collect())
+}
+
+/// Messages of a gettext file, keyed by `msgctxt` or `msgid`. Untranslated
+/// and fuzzy messages are skipped.
+fn parse_po(contents: &str) -> Result<Vec<(String, String)>> {
+    #[derive(Default)]
+    struct Message {
+        ctxt: Option<String>,
+        id: Option<String>,
+        str: Option<String>,
+        fuzzy: bool,
+    }
+    impl Message {
+        fn finish(&mut self, entries: &mut Vec<(String, String)>) {
+            let message = std::mem::take(self);
+            let key = message.ctxt.or(message.id).unwrap_or_default();
+            let value = message.str.unwrap_or_default();
+            if !key.is_empty() && !value.is_empty() && !message.fuzzy {
+                entries.push((key, value));
+            }
+        }
+    }
+    let mut entries = vec![];
+    let mut message = Message::default();
+    // Field continuation lines are appended to, `msgid_plural` and the
+    // other plural forms are ignored
+    let mut ignored = String::new();
+    let mut field: Option<&mut String> = None;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('"') {
+            let value = unquote(line).with_context(|| format!("line {}", i + 1))?;
+            field
+                .as_mut()
+                .with_context(|| format!("line {}: unexpected string", i + 1))?
+                .push_str(&value);
+            continue;
+        }
+        field = None;
+        if line.starts_with('#') {
+            // Comments start the next message
+            if message.str.is_some() {
+                message.finish(&mut entries);
+            }
+            if line.starts_with("#,") && line.contains("fuzzy") {
+                message.fuzzy = true;
+            }
+            continue;
+        }
+        let (keyword, value) = line.split_once(' ').unwrap_or((line, "\"\""));
+        let value = unquote(value.trim()).with_context(|| format!("line {}", i + 1))?;
+        if (keyword == "msgctxt" && (message.id.is_some() || message.str.is_some()))
+            || (keyword == "msgid" && (message.id.is_some() || message.str.is_some()))
+        {
+            message.finish(&mut entries);
+        }
+        let slot = match keyword {
+            "msgctxt" => &mut message.ctxt,
+            "msgid" => &mut message.id,
+            "msgstr" | "msgstr[0]" => &mut message.str,
+            _ => {
+                ignored.clear();
+                field = Some(&mut ignored);
+                continue;
+            }
+        };
+        field = Some(slot.insert(value));
+    }
+    message.finish(&mut entries);
+    Ok(entries)
+}
+
+fn unquote(s: &str) -> Result<String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .with_context(|| format!("expected a quoted string, found `{}`", s))?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => anyhow::bail!("trailing `\\` in `{}`", s),
+        }
+    }
+    Ok(out)
+}
+
+/// Rows of a csv file, fields may be quoted with `"` and contain `""`,
+/// commas and newlines.
+fn parse_csv(contents: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    anyhow::ensure!(!quoted, "unterminated quoted field");
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_fluent() {
+        let ftl = r#"# Comment
+-brand = Example
+hello = Hello from { -brand }!
+multiline =
+    First line
+    Second line
+login = Log in
+    .title = Opens the login form
+bye = Bye {-brand}
+"#;
+        assert_eq!(
+            parse_fluent(ftl).unwrap(),
+            pairs(&[
+                ("hello", "Hello from Example!"),
+                ("multiline", "First line\nSecond line"),
+                ("login", "Log in"),
+                ("bye", "Bye Example"),
+            ])
+        );
+        assert!(parse_fluent("no value").is_err());
+    }
+
+    #[test]
+    fn test_parse_po() {
+        let po = r#"msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+# Translator comment
+msgid "greeting"
+msgstr "Hallo \"Welt\"\tTab"
+
+msgid "multiline"
+msgstr ""
+"Erste Zeile\n"
+"Zweite Zeile"
+
+msgctxt "menu"
+msgid "Open"
+msgstr "Öffnen"
+
+msgid "file"
+msgid_plural "files"
+msgstr[0] "Datei"
+msgstr[1] "Dateien"
+
+#, fuzzy
+msgid "fuzzy"
+msgstr "Unscharf"
+
+msgid "untranslated"
+msgstr ""
+"#;
+        assert_eq!(
+            parse_po(po).unwrap(),
+            pairs(&[
+                ("greeting", "Hallo \"Welt\"\tTab"),
+                ("multiline", "Erste Zeile\nZweite Zeile"),
+                ("menu", "Öffnen"),
+                ("file", "Datei"),
+            ])
+        );
+        assert!(parse_po("msgid \"unterminated").is_err());
+        assert!(parse_po("\"orphan\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "\u{feff}key,en,de\r\nhello,Hello,Hallo\r\nquote,\"Say \"\"hi\"\"\",\"Sag \"\"hallo\"\"\"\nmultiline,\"One,\nTwo\",\nlast,a,b";
+        assert_eq!(
+            parse_csv(csv).unwrap(),
+            vec![
+                vec!["key", "en", "de"],
+                vec!["hello", "Hello", "Hallo"],
+                vec!["quote", "Say \"hi\"", "Sag \"hallo\""],
+                vec!["multiline", "One,\nTwo", ""],
+                vec!["last", "a", "b"],
+            ]
+        );
+        assert!(parse_csv("key,\"open").is_err());
+    }
+}
@@ -1,10 +1,12 @@
-use crate::cargo::{Cargo, CargoBuild, CrateType};
-use crate::config::Config;
+use crate::cargo::{Artifact, Cargo, CargoBuild, CrateType};
+use crate::config::{BuildTypeConfig, Config, PackagerConfig};
 use crate::devices::Device;
-use anyhow::Result;
+use crate::publish::Track;
+use crate::secret::{Secret, SecretFile};
+use anyhow::{Context, Result};
 use clap::Parser;
 use std::path::{Path, PathBuf};
-use xcommon::Signer;
+use xcommon::{Compression, Signer};
 
 #[macro_export]
 macro_rules! exe {
@@ -20,12 +22,29 @@ macro_rules! exe {
 pub mod cargo;
 pub mod command;
 mod config;
+mod container;
 mod devices;
 mod download;
+mod error;
 mod gradle;
+mod jni;
+mod l10n;
+mod message;
+pub mod packager;
+mod publish;
+mod sbom;
+mod sdkmanager;
+mod secret;
 mod task;
+mod uniffi;
+mod web;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub use container::Container;
+pub use devices::{LogFilter, PortForward};
+pub use error::{Categorize, Error, ErrorKind};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Opt {
     Debug,
     Release,
@@ -40,12 +59,70 @@ impl std::fmt::Display for Opt {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl std::fmt::Display for MessageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Human => write!(f, "human"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl std::str::FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        Ok(match format {
+            "human" => Self::Human,
+            "json" => Self::Json,
+            _ => anyhow::bail!("unsupported message format {}", format),
+        })
+    }
+}
+
+/// Format of the `--timings` report.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Timings {
+    Html,
+    Json,
+}
+
+impl std::fmt::Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Html => write!(f, "html"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl std::str::FromStr for Timings {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        Ok(match format {
+            "html" => Self::Html,
+            "json" => Self::Json,
+            _ => anyhow::bail!("unsupported timings format {}", format),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Platform {
     Android,
     Ios,
     Linux,
     Macos,
+    Web,
     Windows,
 }
 
@@ -70,6 +147,7 @@ impl std::fmt::Display for Platform {
             Self::Ios => write!(f, "ios"),
             Self::Linux => write!(f, "linux"),
             Self::Macos => write!(f, "macos"),
+            Self::Web => write!(f, "web"),
             Self::Windows => write!(f, "windows"),
         }
     }
@@ -84,6 +162,7 @@ impl std::str::FromStr for Platform {
             "ios" => Self::Ios,
             "linux" => Self::Linux,
             "macos" => Self::Macos,
+            "web" => Self::Web,
             "windows" => Self::Windows,
             _ => anyhow::bail!("unsupported platform {}", platform),
         })
@@ -94,6 +173,7 @@ impl std::str::FromStr for Platform {
 pub enum Arch {
     Arm,
     Arm64,
+    Wasm32,
     X64,
     //X86,
 }
@@ -115,6 +195,7 @@ impl std::fmt::Display for Arch {
         match self {
             Self::Arm => write!(f, "arm"),
             Self::Arm64 => write!(f, "arm64"),
+            Self::Wasm32 => write!(f, "wasm32"),
             Self::X64 => write!(f, "x64"),
             //Self::X86 => write!(f, "x86"),
         }
@@ -128,6 +209,7 @@ impl std::str::FromStr for Arch {
         Ok(match arch {
             "arm" => Self::Arm,
             "arm64" => Self::Arm64,
+            "wasm32" => Self::Wasm32,
             "x64" => Self::X64,
             //"x86" => Self::X86,
             _ => anyhow::bail!("unsupported arch {}", arch),
@@ -146,6 +228,7 @@ pub enum Format {
     Exe,
     Ipa,
     Msix,
+    Web,
 }
 
 impl std::fmt::Display for Format {
@@ -160,6 +243,7 @@ impl std::fmt::Display for Format {
             Self::Exe => write!(f, "exe"),
             Self::Ipa => write!(f, "ipa"),
             Self::Msix => write!(f, "msix"),
+            Self::Web => write!(f, "web"),
         }
     }
 }
@@ -177,6 +261,7 @@ impl std::str::FromStr for Format {
             "dmg" => Self::Dmg,
             "ipa" => Self::Ipa,
             "msix" => Self::Msix,
+            "web" => Self::Web,
             _ => anyhow::bail!("unsupported arch {}", arch),
         })
     }
@@ -193,6 +278,7 @@ impl Format {
             (Platform::Linux, Opt::Release) => Self::Appimage,
             (Platform::Macos, Opt::Debug) => Self::Appbundle,
             (Platform::Macos, Opt::Release) => Self::Dmg,
+            (Platform::Web, _) => Self::Web,
             (Platform::Windows, Opt::Debug) => Self::Exe,
             (Platform::Windows, Opt::Release) => Self::Exe, // TODO: Msix
         }
@@ -209,6 +295,7 @@ impl Format {
             Self::Exe => "exe",
             Self::Ipa => "ipa",
             Self::Msix => "msix",
+            Self::Web => "web",
         }
     }
 
@@ -250,6 +337,66 @@ impl std::str::FromStr for Store {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Profiler {
+    /// Sampling cpu profiler on android
+    Simpleperf,
+    /// System trace on android
+    Perfetto,
+    /// Time profiler template of instruments on macos
+    Instruments,
+    /// Stack sampling with dtrace on macos
+    Dtrace,
+    /// Linux perf on the host
+    Perf,
+}
+
+impl Profiler {
+    pub fn default_for(platform: Platform) -> Result<Self> {
+        Ok(match platform {
+            Platform::Android => Self::Simpleperf,
+            Platform::Macos => Self::Instruments,
+            Platform::Linux => Self::Perf,
+            _ => anyhow::bail!("profiling {} apps is not supported", platform),
+        })
+    }
+
+    pub fn platform(self) -> Platform {
+        match self {
+            Self::Simpleperf | Self::Perfetto => Platform::Android,
+            Self::Instruments | Self::Dtrace => Platform::Macos,
+            Self::Perf => Platform::Linux,
+        }
+    }
+}
+
+impl std::fmt::Display for Profiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Simpleperf => write!(f, "simpleperf"),
+            Self::Perfetto => write!(f, "perfetto"),
+            Self::Instruments => write!(f, "instruments"),
+            Self::Dtrace => write!(f, "dtrace"),
+            Self::Perf => write!(f, "perf"),
+        }
+    }
+}
+
+impl std::str::FromStr for Profiler {
+    type Err = anyhow::Error;
+
+    fn from_str(profiler: &str) -> Result<Self> {
+        Ok(match profiler {
+            "simpleperf" => Self::Simpleperf,
+            "perfetto" => Self::Perfetto,
+            "instruments" => Self::Instruments,
+            "dtrace" => Self::Dtrace,
+            "perf" => Self::Perf,
+            _ => anyhow::bail!("unsupported profiler {}", profiler),
+        })
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct CompileTarget {
     platform: Platform,
@@ -284,6 +431,30 @@ impl CompileTarget {
             Arch::Arm => apk::Target::ArmV7a,
             Arch::Arm64 => apk::Target::Arm64V8a,
             Arch::X64 => apk::Target::X86_64,
+            Arch::Wasm32 => unreachable!(),
+        }
+    }
+
+    /// Architecture in appimage and runtime names. Linux targets without a
+    /// rust triple, like wasm32, are rejected by `--arch`.
+    pub fn appimage_arch(self) -> &'static str {
+        assert_eq!(self.platform(), Platform::Linux);
+        match self.arch() {
+            Arch::Arm => "armhf",
+            Arch::Arm64 => "aarch64",
+            Arch::X64 => "x86_64",
+            Arch::Wasm32 => unreachable!(),
+        }
+    }
+
+    /// `ProcessorArchitecture` of the msix identity.
+    pub fn msix_architecture(self) -> &'static str {
+        assert_eq!(self.platform(), Platform::Windows);
+        match self.arch() {
+            Arch::Arm => "arm",
+            Arch::Arm64 => "arm64",
+            Arch::X64 => "x64",
+            Arch::Wasm32 => unreachable!(),
         }
     }
 
@@ -295,6 +466,7 @@ impl CompileTarget {
             Arch::Arm => "arm-linux-androideabi",
             //Arch::X86 => "i686-linux-android",
             Arch::X64 => "x86_64-linux-android",
+            Arch::Wasm32 => unreachable!(),
         }
     }
 
@@ -305,6 +477,8 @@ impl CompileTarget {
             (Arch::Arm64, Platform::Ios) => "aarch64-apple-ios",
             (Arch::Arm64, Platform::Linux) => "aarch64-unknown-linux-gnu",
             (Arch::Arm64, Platform::Macos) => "aarch64-apple-darwin",
+            (Arch::Arm64, Platform::Windows) => "aarch64-pc-windows-msvc",
+            (Arch::Wasm32, Platform::Web) => "wasm32-unknown-unknown",
             (Arch::X64, Platform::Android) => "x86_64-linux-android",
             (Arch::X64, Platform::Linux) => "x86_64-unknown-linux-gnu",
             (Arch::X64, Platform::Macos) => "x86_64-apple-darwin",
@@ -317,6 +491,20 @@ impl CompileTarget {
         })
     }
 
+    /// Triple of the ios simulator running on `arch`.
+    pub fn ios_simulator_triple(self) -> Result<&'static str> {
+        anyhow::ensure!(
+            self.platform == Platform::Ios,
+            "{} has no ios simulator",
+            self.platform
+        );
+        Ok(match self.arch {
+            Arch::Arm64 => "aarch64-apple-ios-sim",
+            Arch::X64 => "x86_64-apple-ios",
+            arch => anyhow::bail!("unsupported ios simulator arch {}", arch),
+        })
+    }
+
     pub fn is_host(self) -> Result<bool> {
         Ok(self.platform() == Platform::host()? && self.arch() == Arch::host()?)
     }
@@ -334,9 +522,158 @@ pub struct BuildArgs {
     build_target: BuildTargetArgs,
     #[clap(flatten)]
     cargo: CargoArgs,
+    /// Build and package only the specified binary
+    #[clap(long, conflicts_with = "example")]
+    bin: Option<String>,
+    /// Build and package only the specified example, on android it needs
+    /// `crate-type = ["cdylib"]`
+    #[clap(long)]
+    example: Option<String>,
     /// Use verbose output
     #[clap(long, short)]
     verbose: bool,
+    /// Output format, `human` or `json`
+    #[clap(long, default_value = "human")]
+    message_format: MessageFormat,
+    /// Report how long each build stage took and which were cached, as
+    /// `html` (default) or `json`
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "html"
+    )]
+    timings: Option<Timings>,
+    /// Use the sdk components in a directory created by `x vendor` instead
+    /// of the download cache
+    #[clap(long)]
+    vendor_dir: Option<PathBuf>,
+    /// Copy the artifact to this directory, named after `artifact_name` in
+    /// `manifest.yaml`
+    #[clap(long)]
+    out_dir: Option<PathBuf>,
+    /// Build bit-identical artifacts for attestation: remaps source paths,
+    /// sets `SOURCE_DATE_EPOCH`, normalizes the packaging and prints the
+    /// artifact hashes
+    #[clap(long)]
+    reproducible: bool,
+    /// Build rust in a docker or podman container with the toolchain of the
+    /// target, the image published with xbuild or `targets.<triple>.container`
+    #[clap(long)]
+    container: bool,
+}
+
+impl BuildArgs {
+    pub fn with_vendor_dir(mut self, vendor_dir: PathBuf) -> Self {
+        self.vendor_dir = Some(vendor_dir);
+        self
+    }
+}
+
+#[derive(Parser)]
+pub struct CleanArgs {
+    #[clap(flatten)]
+    cargo: CargoArgs,
+    /// Only remove the artifacts of this platform
+    #[clap(long)]
+    platform: Option<Platform>,
+    /// Also remove the downloaded sdks, ndk and other cached components
+    #[clap(long)]
+    cache: bool,
+    /// List what would be removed without removing anything
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+pub struct MetadataArgs {
+    #[clap(flatten)]
+    cargo: CargoArgs,
+    /// Only export the listings of `play` or `apple`
+    #[clap(long)]
+    store: Option<Store>,
+    /// Fastlane directory to write to, defaults to `fastlane` in the
+    /// package root
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+pub struct SignersArgs {
+    #[clap(flatten)]
+    cargo: CargoArgs,
+    /// Print source, location, subject, expiry and fingerprint of each
+    /// identity as json
+    #[clap(long)]
+    json: bool,
+    /// Sign artifacts with the identity with this SHA-256 fingerprint or a
+    /// unique prefix of it by default
+    #[clap(long)]
+    set_default: Option<String>,
+    /// Write a new development key and code signing certificate to this
+    /// pem, relative to the package root, and sign artifacts with it by
+    /// default. The subject is the publisher of `windows.manifest.identity`
+    /// or the package name
+    #[clap(long, conflicts_with = "set_default")]
+    generate: Option<PathBuf>,
+    /// Days the generated certificate is valid for
+    #[clap(long, requires = "generate", default_value = "3650")]
+    validity_days: u32,
+}
+
+#[derive(Parser)]
+pub struct VersionArgs {
+    #[clap(flatten)]
+    cargo: CargoArgs,
+    /// Bump the major version
+    #[clap(long, conflicts_with_all = ["minor", "patch"])]
+    major: bool,
+    /// Bump the minor version
+    #[clap(long, conflicts_with = "patch")]
+    minor: bool,
+    /// Bump the patch version
+    #[clap(long)]
+    patch: bool,
+    /// Print the changes without writing them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+impl VersionArgs {
+    fn bump(&self) -> Option<command::Bump> {
+        if self.major {
+            Some(command::Bump::Major)
+        } else if self.minor {
+            Some(command::Bump::Minor)
+        } else if self.patch {
+            Some(command::Bump::Patch)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct PublishArgs {
+    #[clap(flatten)]
+    build: BuildArgs,
+    /// Json key of a service account with access to the play console, a
+    /// path or an `env:`, `keychain:`, `file:` or `command:` secret.
+    /// Overrides `publish.play.service_account`
+    #[clap(long)]
+    service_account: Option<Secret>,
+    /// Google play release track, one of `internal`, `alpha`, `beta` or
+    /// `production`. Defaults to `publish.play.track` or `internal`
+    #[clap(long)]
+    track: Option<Track>,
+    /// Percentage of users to roll the release out to
+    #[clap(long)]
+    rollout: Option<f64>,
+    /// Release notes as `<language>=<path>`, for example `en-US=notes.txt`.
+    /// Defaults to the changelogs in `publish.metadata`
+    #[clap(long)]
+    release_notes: Vec<String>,
 }
 
 #[derive(Parser)]
@@ -356,6 +693,9 @@ pub struct CargoArgs {
     /// Space or comma separated list of features to activate
     #[clap(long, short = 'F')]
     features: Vec<String>,
+    /// Build artifacts with the specified cargo profile
+    #[clap(long)]
+    profile: Option<String>,
 }
 
 impl CargoArgs {
@@ -363,6 +703,7 @@ impl CargoArgs {
         Cargo::new(
             self.package.as_deref(),
             self.features,
+            self.profile,
             self.manifest_path,
             self.target_dir,
             self.offline,
@@ -373,11 +714,19 @@ impl CargoArgs {
 #[derive(Parser)]
 pub struct BuildTargetArgs {
     /// Build artifacts in debug mode, without optimizations
-    #[clap(long, conflicts_with = "release")]
+    #[clap(long, conflicts_with = "release", conflicts_with = "profile")]
     debug: bool,
     /// Build artifacts in release mode, with optimizations
-    #[clap(long, short, conflicts_with = "debug")]
+    #[clap(long, short, conflicts_with = "debug", conflicts_with = "profile")]
     release: bool,
+    /// Build type, `debug`, `release`, `profile` or one of `build_types`
+    /// in the manifest.
+    #[clap(
+        long = "type",
+        value_name = "TYPE",
+        conflicts_with_all = ["debug", "release", "profile"]
+    )]
+    build_type: Option<String>,
     /// Build artifacts for target platform. Can be one of
     /// `android`, `ios`, `linux`, `macos` or `windows`.
     #[clap(long, conflicts_with = "device")]
@@ -387,49 +736,124 @@ pub struct BuildTargetArgs {
     #[clap(long, requires = "platform")]
     arch: Option<Arch>,
     /// Build artifacts for target device. To find the device
-    /// identifier of a connected device run `x devices`.
+    /// identifier of a connected device run `x devices`. Can be
+    /// repeated to build once for several devices.
     #[clap(long, conflicts_with = "store")]
-    device: Option<String>,
+    device: Vec<String>,
+    /// Build artifacts for all connected devices, or the ones of
+    /// `--platform`.
+    #[clap(long, conflicts_with = "device", conflicts_with = "store")]
+    all_devices: bool,
     /// Build artifacts with format. Can be one of `aab`,
     /// `apk`, `appbundle`, `appdir`, `appimage`, `dmg`,
-    /// `exe`, `ipa`, `msix`.
+    /// `exe`, `ipa`, `msix`, `web`.
     #[clap(long, conflicts_with = "store")]
     format: Option<Format>,
+    /// Package the artifacts with a backend configured in `packagers`,
+    /// like `flatpak`, instead of a built-in format.
+    #[clap(long, conflicts_with = "format", conflicts_with = "store")]
+    packager: Option<String>,
     /// Build artifacts for target app store. Can be one of
     /// `apple`, `microsoft`, `play` or `sideload`.
     #[clap(long, conflicts_with = "device", conflicts_with = "format")]
     store: Option<Store>,
-    /// Path to a PEM encoded RSA2048 signing key and certificate
-    /// used to sign artifacts.
+    /// PEM encoded signing key and certificate used to sign artifacts, a
+    /// path or an `env:`, `keychain:`, `file:` or `command:` secret.
     #[clap(long)]
-    pem: Option<PathBuf>,
+    pem: Option<Secret>,
     /// Path to an apple provisioning profile.
     #[clap(long)]
     provisioning_profile: Option<PathBuf>,
-    /// Path to an api key.
+    /// App store connect api key, a path or an `env:`, `keychain:`,
+    /// `file:` or `command:` secret.
+    #[clap(long)]
+    api_key: Option<Secret>,
+    /// Package android apps with a generated gradle project instead of the
+    /// native backend, same as setting `android.gradle`.
     #[clap(long)]
-    api_key: Option<PathBuf>,
+    gradle: bool,
 }
 
 impl BuildTargetArgs {
-    pub fn build_target(self, config: &Config) -> Result<BuildTarget> {
-        let signer = if let Some(pem) = self.pem.as_ref() {
-            anyhow::ensure!(pem.exists(), "pem file doesn't exist {}", pem.display());
-            Some(Signer::from_path(pem)?)
-        } else if let Ok(pem) = std::env::var("X_PEM") {
-            Some(Signer::new(&pem)?)
-        } else {
+    /// Name of the selected build type, `None` with a custom `--profile`.
+    fn build_type_name(&self, profile: Option<&str>) -> Option<String> {
+        if let Some(name) = &self.build_type {
+            Some(name.clone())
+        } else if profile.is_some() {
             None
-        };
+        } else if self.release || (!self.debug && self.store.is_some()) {
+            Some("release".into())
+        } else {
+            Some("debug".into())
+        }
+    }
+
+    /// `profile_opt` is the built-in profile of a custom `--profile`.
+    pub fn build_target(
+        self,
+        config: &Config,
+        profile_opt: Option<Opt>,
+        build_type: Option<(String, &BuildTypeConfig)>,
+    ) -> Result<BuildTarget> {
+        let signer = (|| {
+            let signer = if let Some(pem) = self.pem.as_ref() {
+                let pem = pem
+                    .resolve(Path::new(""))
+                    .with_context(|| format!("failed to read the pem {}", pem))?;
+                Some(Signer::new(&pem)?)
+            } else if let Ok(pem) = std::env::var("X_PEM") {
+                Some(Signer::new(&pem).context("failed to read the pem in `X_PEM`")?)
+            } else if let Some(pem) = build_type.as_ref().and_then(|(_, ty)| ty.pem.as_ref()) {
+                let pem = pem
+                    .resolve(Path::new(""))
+                    .with_context(|| format!("failed to read the pem {}", pem))?;
+                Some(Signer::new(&pem)?)
+            } else {
+                None
+            };
+            Ok::<_, anyhow::Error>(signer)
+        })()
+        .categorize(
+            ErrorKind::Signing,
+            "the pem needs a private key and a certificate, `x signers` lists the usable identities",
+        )?;
         let store = self.store;
-        let device = if self.platform.is_none() && store.is_none() && self.device.is_none() {
-            Some(Device::host())
+        let devices = if self.all_devices {
+            let devices = Device::list()?
+                .into_iter()
+                .filter(|device| !device.is_host())
+                .filter(|device| self.platform.is_none() || device.platform().ok() == self.platform)
+                .collect::<Vec<_>>();
+            anyhow::ensure!(!devices.is_empty(), "no devices connected");
+            devices
+        } else if self.platform.is_none() && store.is_none() && self.device.is_empty() {
+            vec![Device::host()]
         } else {
             self.device
-                .as_ref()
+                .iter()
                 .map(|device| device.parse())
-                .transpose()?
+                .collect::<Result<Vec<Device>>>()?
         };
+        let device = devices.first();
+        if let Some(device) = device {
+            for other in &devices[1..] {
+                anyhow::ensure!(
+                    other.platform()? == device.platform()?,
+                    "{} and {} are different platforms, select one with `--platform`",
+                    device,
+                    other
+                );
+            }
+            // The host also runs simulators and browsers
+            if let Some(platform) = self.platform.filter(|_| !device.is_host()) {
+                anyhow::ensure!(
+                    device.platform()? == platform,
+                    "{} is not a {} device",
+                    device,
+                    platform
+                );
+            }
+        }
         let platform = if let Some(platform) = self.platform {
             platform
         } else if let Some(store) = store {
@@ -439,37 +863,83 @@ impl BuildTargetArgs {
                 Store::Play => Platform::Android,
                 Store::Sideload => anyhow::bail!("sideload store requires platform arg"),
             }
-        } else if let Some(device) = device.as_ref() {
+        } else if let Some(device) = device {
             device.platform()?
         } else {
             anyhow::bail!("--platform, --store or --device must be provided");
         };
         let archs = if let Some(arch) = self.arch {
+            // Rejects like wasm32 linux before anything is built
+            CompileTarget::new(platform, arch, Opt::Debug).rust_triple()?;
             vec![arch]
+        } else if platform == Platform::Web {
+            vec![Arch::Wasm32]
         } else if let Some(store) = store {
             match store {
                 Store::Apple => vec![Arch::X64, Arch::Arm64],
+                // The prebuilt windows sdk of the current release is x64 only
                 Store::Microsoft => vec![Arch::X64],
                 Store::Play => vec![Arch::Arm, Arch::Arm64],
                 Store::Sideload => anyhow::bail!("sideload store requires arch arg"),
             }
-        } else if let Some(device) = device.as_ref() {
-            vec![device.arch()?]
+        } else if !devices.is_empty() {
+            let mut archs = vec![];
+            for device in &devices {
+                let arch = device.arch()?;
+                if !archs.contains(&arch) {
+                    archs.push(arch);
+                }
+            }
+            archs
         } else {
             anyhow::bail!("--arch, --store or --device must be provided");
         };
-        let opt = if self.release || (!self.debug && self.store.is_some()) {
+        let opt = if let Some(opt) = profile_opt {
+            opt
+        } else if let Some(opt) = build_type.as_ref().and_then(|(_, ty)| ty.opt) {
+            opt
+        } else if self.release || (!self.debug && self.store.is_some()) {
             Opt::Release
         } else {
             Opt::Debug
         };
+        let gradle = self.gradle || config.android().gradle;
         let format = if let Some(format) = self.format {
             format
         } else if store == Some(Store::Play) {
             Format::Aab
         } else {
-            Format::platform_default(platform, opt, config.android().gradle)
+            Format::platform_default(platform, opt, gradle)
         };
+        anyhow::ensure!(
+            archs.len() == 1 || format.supports_multiarch() || devices.is_empty(),
+            "{} supports a single arch, the devices are {}",
+            format,
+            archs
+                .iter()
+                .map(|arch| arch.to_string())
+                .collect::<Vec<_>>()
+                .join(" and ")
+        );
+        anyhow::ensure!(
+            format != Format::Aab || gradle,
+            "the native android backend can't package app bundles yet, pass `--gradle` or set `android.gradle: true`"
+        );
+        if let Some(name) = &self.packager {
+            let packager = config
+                .packager(name)
+                .with_context(|| format!("unknown packager `{}`", name))
+                .categorize(
+                    ErrorKind::Configuration,
+                    "configure the packager in `packagers` of `manifest.yaml`",
+                )?;
+            anyhow::ensure!(
+                packager.platform == platform,
+                "packager `{}` packages {} apps",
+                name,
+                packager.platform
+            );
+        }
         let provisioning_profile = if let Some(profile) = self.provisioning_profile {
             anyhow::ensure!(
                 profile.exists(),
@@ -485,33 +955,44 @@ impl BuildTargetArgs {
         };
         let api_key = self.api_key;
         Ok(BuildTarget {
+            build_type: build_type.map(|(name, _)| name),
             opt,
             platform,
             archs,
             format,
-            device,
+            packager: self.packager,
+            devices,
             store,
             signer,
             provisioning_profile,
             api_key,
+            gradle,
         })
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct BuildTarget {
+    build_type: Option<String>,
     opt: Opt,
     platform: Platform,
     archs: Vec<Arch>,
     format: Format,
-    device: Option<Device>,
+    packager: Option<String>,
+    devices: Vec<Device>,
     store: Option<Store>,
     signer: Option<Signer>,
     provisioning_profile: Option<Vec<u8>>,
-    api_key: Option<PathBuf>,
+    api_key: Option<Secret>,
+    gradle: bool,
 }
 
 impl BuildTarget {
+    /// Name of the build type, `None` when building a custom cargo profile.
+    pub fn build_type(&self) -> Option<&str> {
+        self.build_type.as_deref()
+    }
+
     pub fn opt(&self) -> Opt {
         self.opt
     }
@@ -528,14 +1009,29 @@ impl BuildTarget {
         self.format
     }
 
+    /// Packaging backend selected with `--packager`, see [`packager`].
+    pub fn packager(&self) -> Option<&str> {
+        self.packager.as_deref()
+    }
+
     pub fn device(&self) -> Option<&Device> {
-        self.device.as_ref()
+        self.devices.first()
+    }
+
+    /// All devices selected with `--device` or `--all-devices`.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
     }
 
     pub fn store(&self) -> Option<Store> {
         self.store
     }
 
+    /// Packages android apps with gradle.
+    pub fn gradle(&self) -> bool {
+        self.gradle
+    }
+
     pub fn compile_targets(&self) -> impl Iterator<Item = CompileTarget> + '_ {
         self.archs
             .iter()
@@ -543,8 +1039,7 @@ impl BuildTarget {
     }
 
     pub fn is_host(&self) -> bool {
-        self.device
-            .as_ref()
+        self.device()
             .map(|device| device.is_host())
             .unwrap_or_default()
     }
@@ -557,9 +1052,45 @@ impl BuildTarget {
         self.provisioning_profile.as_deref()
     }
 
-    pub fn api_key(&self) -> Option<&Path> {
-        self.api_key.as_deref()
+    /// `--api-key` as a file, removed again when it's dropped unless it is
+    /// a path.
+    pub fn api_key(&self) -> Result<Option<SecretFile>> {
+        self.api_key
+            .as_ref()
+            .map(|secret| secret.to_file(Path::new("")))
+            .transpose()
+    }
+}
+
+/// Directory shared by all projects for downloaded and cached components.
+fn cache_dir() -> PathBuf {
+    dirs::cache_dir().unwrap().join("x")
+}
+
+/// Object files of native dependencies, keyed by content hash.
+pub fn native_cache_dir() -> PathBuf {
+    cache_dir().join("native")
+}
+
+/// `SOURCE_DATE_EPOCH` from the environment or the time of the last git
+/// commit, zero outside of git repositories.
+fn source_date_epoch(root: &Path) -> Result<u64> {
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid SOURCE_DATE_EPOCH `{}`", epoch));
     }
+    let output = std::process::Command::new("git")
+        .current_dir(root)
+        .args(["log", "-1", "--format=%ct"])
+        .output();
+    Ok(match output {
+        Ok(output) if output.status.success() => {
+            std::str::from_utf8(&output.stdout)?.trim().parse()?
+        }
+        _ => 0,
+    })
 }
 
 pub struct BuildEnv {
@@ -567,28 +1098,100 @@ pub struct BuildEnv {
     build_target: BuildTarget,
     build_dir: PathBuf,
     cache_dir: PathBuf,
+    vendor_dir: Option<PathBuf>,
     icon: Option<PathBuf>,
     cargo: Cargo,
     config: Config,
     verbose: bool,
     offline: bool,
+    message_format: MessageFormat,
+    timings: Option<Timings>,
+    out_dir: Option<PathBuf>,
+    /// `SOURCE_DATE_EPOCH` of reproducible builds.
+    source_date_epoch: Option<u64>,
+    /// Docker or podman with `--container`.
+    container_engine: Option<PathBuf>,
 }
 
 impl BuildEnv {
     pub fn new(args: BuildArgs) -> Result<Self> {
         let verbose = args.verbose;
+        let message_format = args.message_format;
+        let timings = args.timings;
         let offline = args.cargo.offline;
-        let cargo = args.cargo.cargo()?;
+        let build_type_name = args
+            .build_target
+            .build_type_name(args.cargo.profile.as_deref());
+        let mut cargo = args.cargo.cargo()?;
+        if let Some(bin) = args.bin {
+            cargo.set_artifact(Artifact::Root(bin));
+        } else if let Some(example) = args.example {
+            cargo.set_artifact(Artifact::Example(example));
+        }
         let build_dir = cargo.target_dir().join("x");
-        let cache_dir = dirs::cache_dir().unwrap().join("x");
-        let package = cargo.manifest().package.as_ref().unwrap(); // Caller should guarantee that this is a valid package
+        let cache_dir = cache_dir();
+        let vendor_dir = args
+            .vendor_dir
+            .map(|dir| std::env::current_dir().map(|cwd| cwd.join(dir)))
+            .transpose()?;
+        let out_dir = args
+            .out_dir
+            .map(|dir| std::env::current_dir().map(|cwd| cwd.join(dir)))
+            .transpose()?;
         let manifest = cargo.package_root().join("manifest.yaml");
         let mut config = Config::parse(manifest)?;
-        let build_target = args.build_target.build_target(&config)?;
+        let build_type = if let Some(name) = build_type_name {
+            let mut ty = config.build_type(&name)?;
+            if let Some(Secret::File(pem)) = ty.pem.as_mut() {
+                *pem = cargo.package_root().join(&pem);
+            }
+            if let Some(profile) = ty.cargo_profile.clone() {
+                cargo.set_profile(profile);
+            }
+            Some((name, ty))
+        } else {
+            None
+        };
+        let mut build_target = args.build_target.build_target(
+            &config,
+            cargo.profile_opt()?,
+            build_type.as_ref().map(|(name, ty)| (name.clone(), ty)),
+        )?;
+        if build_target.signer.is_none() {
+            build_target.signer = command::default_signer(&config, cargo.package_root())
+                .categorize(
+                    ErrorKind::Signing,
+                    "`x signers` lists the usable identities, change `signing.default` with `x signers --set-default`",
+                )?;
+        }
+        if let Some(Artifact::Root(bin)) = cargo.selected_artifact() {
+            anyhow::ensure!(
+                build_target.platform() != Platform::Android,
+                "android apps load a library, use `--example` with a `cdylib` example instead of `--bin {}`",
+                bin
+            );
+        }
+        let package = cargo.manifest().package.as_ref().unwrap(); // Caller should guarantee that this is a valid package
         config.apply_rust_package(package, cargo.workspace_manifest(), build_target.opt())?;
+        if let Some(Artifact::Example(example)) = cargo.selected_artifact() {
+            config.set_android_lib_name(example);
+        }
+        if let Some((_, ty)) = &build_type {
+            config.apply_build_type(ty);
+        }
         let icon = config
             .icon(build_target.platform())
             .map(|icon| cargo.package_root().join(icon));
+        let source_date_epoch = if args.reproducible {
+            Some(source_date_epoch(cargo.package_root())?)
+        } else {
+            None
+        };
+        let container_engine = if args.container {
+            Some(container::engine()?)
+        } else {
+            None
+        };
         Ok(Self {
             name: package.name.clone(),
             build_target,
@@ -597,8 +1200,14 @@ impl BuildEnv {
             config,
             build_dir,
             cache_dir,
+            vendor_dir,
             verbose,
             offline,
+            message_format,
+            timings,
+            out_dir,
+            source_date_epoch,
+            container_engine,
         })
     }
 
@@ -606,6 +1215,12 @@ impl BuildEnv {
         &self.name
     }
 
+    /// Marks the app as profileable, so profilers can attach to release
+    /// builds.
+    pub fn enable_profiling(&mut self) {
+        self.config.enable_profiling();
+    }
+
     pub fn target(&self) -> &BuildTarget {
         &self.build_target
     }
@@ -618,6 +1233,25 @@ impl BuildEnv {
         self.offline
     }
 
+    pub fn message_format(&self) -> MessageFormat {
+        self.message_format
+    }
+
+    pub fn timings(&self) -> Option<Timings> {
+        self.timings
+    }
+
+    /// Whether `--reproducible` was passed.
+    pub fn reproducible(&self) -> bool {
+        self.source_date_epoch.is_some()
+    }
+
+    /// Timestamp embedded instead of the current time by reproducible
+    /// builds.
+    pub fn source_date_epoch(&self) -> Option<u64> {
+        self.source_date_epoch
+    }
+
     pub fn root_dir(&self) -> &Path {
         self.cargo.package_root()
     }
@@ -630,8 +1264,24 @@ impl BuildEnv {
         &self.cache_dir
     }
 
+    /// Downloaded sdks and other toolchain components, either vendored or
+    /// in the shared cache dir.
+    pub fn components_dir(&self) -> &Path {
+        self.vendor_dir.as_deref().unwrap_or(&self.cache_dir)
+    }
+
+    /// Scaled icons, shared between projects and keyed by content hash.
+    pub fn icon_cache_dir(&self) -> PathBuf {
+        self.cache_dir().join("icons")
+    }
+
     pub fn opt_dir(&self) -> PathBuf {
-        self.build_dir().join(self.target().opt().to_string())
+        if let Some(build_type) = self.target().build_type() {
+            self.build_dir().join(build_type)
+        } else {
+            self.build_dir()
+                .join(self.cargo.profile_dir(self.target().opt()))
+        }
     }
 
     pub fn platform_dir(&self) -> PathBuf {
@@ -643,23 +1293,121 @@ impl BuildEnv {
     }
 
     pub fn output(&self) -> PathBuf {
+        if let Some(packager) = self.packager_config() {
+            // Packagers get the artifacts of every arch
+            let output_dir = if self.target().compile_targets().count() > 1 {
+                self.platform_dir()
+            } else {
+                let target = self.target().compile_targets().next().unwrap();
+                self.arch_dir(target.arch())
+            };
+            return output_dir.join(format!("{}.{}", self.name(), packager.extension));
+        }
+        if self.is_msix_bundle() {
+            return self
+                .platform_dir()
+                .join(format!("{}.msixbundle", self.name()));
+        }
         let output_dir = if self.target().format().supports_multiarch() {
             self.platform_dir()
         } else {
             let target = self.target().compile_targets().next().unwrap();
             self.arch_dir(target.arch())
         };
-        let output_name = format!("{}.{}", self.name(), self.target().format().extension());
+        let output_name = if self.target().format() == Format::Appimage {
+            let target = self.target().compile_targets().next().unwrap();
+            format!("{}-{}.AppImage", self.name(), target.appimage_arch())
+        } else {
+            format!("{}.{}", self.name(), self.target().format().extension())
+        };
         output_dir.join(output_name)
     }
 
+    fn packager_config(&self) -> Option<&PackagerConfig> {
+        self.target()
+            .packager()
+            .and_then(|name| self.config().packager(name))
+    }
+
+    /// Msix packages of several architectures are bundled into an
+    /// `.msixbundle`.
+    pub fn is_msix_bundle(&self) -> bool {
+        self.target().format() == Format::Msix && self.target().compile_targets().count() > 1
+    }
+
+    /// Where the artifact is copied to after building, given `--out-dir`,
+    /// `artifact_dir` or `artifact_name`.
+    pub fn artifact_path(&self) -> Result<Option<PathBuf>> {
+        let platform = self.target().platform();
+        let dir = if let Some(dir) = &self.out_dir {
+            dir.clone()
+        } else if let Some(dir) = self.config().artifact_dir(platform) {
+            self.root_dir().join(dir)
+        } else if self.config().artifact_name(platform).is_some() {
+            self.output().parent().unwrap().to_path_buf()
+        } else {
+            return Ok(None);
+        };
+        let template = self
+            .config()
+            .artifact_name(platform)
+            .unwrap_or("{name}-{version}-{platform}-{arch}.{ext}");
+        let mut archs = self.target().compile_targets().map(|target| target.arch());
+        let arch = match (archs.next(), archs.next()) {
+            (Some(arch), None) => arch.to_string(),
+            _ => "universal".to_string(),
+        };
+        let profile = self
+            .target()
+            .build_type()
+            .map(str::to_string)
+            .unwrap_or_else(|| self.target().opt().to_string());
+        let format = self.target().format();
+        let (format, ext) = match (self.target().packager(), self.packager_config()) {
+            (Some(name), Some(packager)) => (name.to_string(), packager.extension.clone()),
+            _ => (format.to_string(), format.extension().to_string()),
+        };
+        let vars = [
+            ("name", self.name().to_string()),
+            ("version", self.config().version().to_string()),
+            ("platform", platform.to_string()),
+            ("arch", arch),
+            ("profile", profile),
+            ("format", format),
+            ("ext", ext),
+        ];
+        let mut name = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .with_context(|| format!("unclosed `{{` in `{}`", template))?;
+            let var = &rest[start + 1..start + end];
+            let (_, value) = vars
+                .iter()
+                .find(|(name, _)| *name == var)
+                .with_context(|| format!("unknown placeholder `{{{}}}` in `{}`", var, template))?;
+            name.push_str(value);
+            rest = &rest[start + end + 1..];
+        }
+        name.push_str(rest);
+        anyhow::ensure!(
+            !name.is_empty() && !name.contains(['/', '\\']),
+            "invalid artifact name `{}`",
+            name
+        );
+        Ok(Some(dir.join(name)))
+    }
+
     pub fn executable(&self) -> PathBuf {
         let out = self.output();
         match (self.target().format(), self.target().platform()) {
             (Format::Appdir, _) => out.join("AppRun"),
-            (Format::Appbundle, Platform::Macos) => {
-                out.join("Contents").join("MacOS").join(self.name())
-            }
+            (Format::Appbundle, Platform::Macos) => out
+                .join("Contents")
+                .join("MacOS")
+                .join(self.cargo.bin_name()),
             _ => out,
         }
     }
@@ -668,53 +1416,179 @@ impl BuildEnv {
         self.icon.as_deref()
     }
 
+    /// Renders the icon with the background and padding of the target
+    /// platform, see [`xcommon::Icon`].
+    pub fn render_icon(&self) -> Result<Option<xcommon::Icon>> {
+        let platform = self.target().platform();
+        let icon = if let Some(icon) = self.icon() {
+            icon
+        } else {
+            return Ok(None);
+        };
+        let cache = self.icon_cache_dir();
+        let mut icon = xcommon::Icon::render(
+            icon,
+            self.config.icon_background(platform),
+            self.config.icon_padding(platform).unwrap_or_default(),
+            &cache,
+        )?;
+        if platform == Platform::Android {
+            if let Some(monochrome) = &self.config.android().monochrome_icon {
+                icon = icon.with_monochrome(&self.root_dir().join(monochrome), &cache)?;
+            }
+        }
+        Ok(Some(icon))
+    }
+
+    /// Splash screen with the images rendered to png, if configured.
+    pub fn splash(&self) -> Result<Option<xcommon::Splash>> {
+        let config = if let Some(config) = self.config.splash() {
+            config
+        } else {
+            return Ok(None);
+        };
+        let cache = self.icon_cache_dir();
+        let image = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| xcommon::rasterize(&self.root_dir().join(path), &cache))
+                .transpose()
+        };
+        let color = |color: &Option<String>| color.as_deref().map(xcommon::parse_color).transpose();
+        let dark = config.dark.as_ref();
+        Ok(Some(xcommon::Splash {
+            background: color(&config.background)?.unwrap_or([255; 4]),
+            image: image(&config.image)?,
+            dark_background: dark
+                .map(|dark| color(&dark.background))
+                .transpose()?
+                .flatten(),
+            dark_image: dark.map(|dark| image(&dark.image)).transpose()?.flatten(),
+        }))
+    }
+
+    /// Translations of `localization.source`, if configured.
+    pub fn translations(&self) -> Result<Option<l10n::Translations>> {
+        self.config
+            .localization()
+            .map(|config| l10n::Translations::load(self.root_dir(), config))
+            .transpose()
+    }
+
     pub fn cargo(&self) -> &Cargo {
         &self.cargo
     }
 
+    /// Compression of zip based artifacts. Uses the configured compression or
+    /// otherwise only compresses release builds.
+    pub fn compression(&self) -> Compression {
+        self.config()
+            .compression(self.target().platform())
+            .unwrap_or(match self.target().opt() {
+                Opt::Debug => Compression::Stored,
+                Opt::Release => Compression::default(),
+            })
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.reproducible() || self.config().deterministic(self.target().platform())
+    }
+
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    pub fn min_sdk_version(&self) -> u32 {
+        self.config().android().min_sdk_version()
+    }
+
     pub fn target_sdk_version(&self) -> u32 {
-        self.config()
-            .android()
+        self.config().android().target_sdk_version()
+    }
+
+    /// Api level of the `android.jar` the app is compiled against, defaults
+    /// to the target sdk version.
+    pub fn compile_sdk_version(&self) -> u32 {
+        let android = self.config().android();
+        android
             .manifest
-            .sdk
-            .target_sdk_version
-            .unwrap()
+            .compile_sdk_version
+            .unwrap_or_else(|| android.target_sdk_version())
     }
 
     pub fn android_jar(&self) -> PathBuf {
-        self.cache_dir()
+        self.components_dir()
             .join("Android.sdk")
             .join("platforms")
-            .join(format!("android-{}", self.target_sdk_version()))
+            .join(format!("android-{}", self.compile_sdk_version()))
             .join("android.jar")
     }
 
     pub fn windows_sdk(&self) -> PathBuf {
-        self.cache_dir().join("Windows.sdk")
+        self.components_dir().join("Windows.sdk")
     }
 
     pub fn macos_sdk(&self) -> PathBuf {
-        self.cache_dir().join("MacOSX.sdk")
+        self.components_dir().join("MacOSX.sdk")
+    }
+
+    /// Glibc sysroot linux apps are linked against when cross compiling.
+    pub fn linux_sysroot(&self, arch: Arch) -> PathBuf {
+        self.components_dir()
+            .join(format!("Linux-{}.sysroot", arch))
+    }
+
+    /// AppImage runtime of `target`, `None` for x86_64 whose runtime is
+    /// bundled.
+    pub fn appimage_runtime(&self, target: CompileTarget) -> Option<PathBuf> {
+        if target.arch() == Arch::X64 {
+            return None;
+        }
+        Some(
+            self.components_dir()
+                .join(format!("runtime-{}", target.appimage_arch())),
+        )
     }
 
     pub fn android_sdk(&self) -> PathBuf {
-        self.cache_dir().join("Android.sdk")
+        self.components_dir().join("Android.sdk")
     }
 
+    /// Sysroot of the ndk, either the one shipped by xbuild or the pinned
+    /// `android.ndk_version` from the sdkmanager.
     pub fn android_ndk(&self) -> PathBuf {
-        self.cache_dir().join("Android.ndk")
+        if let Some(version) = self.config().android().ndk_version.as_ref() {
+            let host = if cfg!(target_os = "macos") {
+                "darwin-x86_64"
+            } else if cfg!(target_os = "windows") {
+                "windows-x86_64"
+            } else {
+                "linux-x86_64"
+            };
+            self.android_sdk()
+                .join("ndk")
+                .join(version)
+                .join("toolchains")
+                .join("llvm")
+                .join("prebuilt")
+                .join(host)
+                .join("sysroot")
+        } else {
+            self.components_dir().join("Android.ndk")
+        }
+    }
+
+    /// Pinned `android.build_tools_version` in the sdk.
+    pub fn android_build_tools(&self) -> Option<PathBuf> {
+        let version = self.config().android().build_tools_version.as_ref()?;
+        Some(self.android_sdk().join("build-tools").join(version))
     }
 
     pub fn ios_sdk(&self) -> PathBuf {
-        self.cache_dir().join("iPhoneOS.sdk")
+        self.components_dir().join("iPhoneOS.sdk")
     }
 
     pub fn developer_disk_image(&self, major: u32, minor: u32) -> PathBuf {
-        self.cache_dir()
+        self.components_dir()
             .join("iPhoneOS.platform")
             .join("DeviceSupport")
             .join(format!("{}.{}", major, minor))
@@ -736,21 +1610,115 @@ impl BuildEnv {
     }
 
     pub fn cargo_build(&self, target: CompileTarget, target_dir: &Path) -> Result<CargoBuild> {
-        let mut cargo = self.cargo.build(target, target_dir)?;
+        let toolchain = self.toolchain(target)?;
+        let mut cargo =
+            self.configure_cargo(self.cargo.build(target, toolchain, target_dir)?, target)?;
+        if self.message_format() == MessageFormat::Json {
+            cargo.arg("--message-format");
+            cargo.arg("json-diagnostic-rendered-ansi");
+        }
+        Ok(cargo)
+    }
+
+    /// Same as [`Self::cargo_build()`] but for compiling the test harnesses
+    /// with `cargo test`.
+    pub fn cargo_test(&self, target: CompileTarget, target_dir: &Path) -> Result<CargoBuild> {
+        let toolchain = self.toolchain(target)?;
+        self.configure_cargo(self.cargo.test(target, toolchain, target_dir)?, target)
+    }
+
+    /// Test harnesses of `target` built for the ios simulator, linked with
+    /// the simulator sdk of xcode instead of the iPhoneOS sdk.
+    pub fn cargo_test_simulator(
+        &self,
+        target: CompileTarget,
+        target_dir: &Path,
+    ) -> Result<CargoBuild> {
+        let toolchain = self.toolchain(target)?;
+        let mut cargo = self.cargo.test(target, toolchain, target_dir)?;
+        let minimum_version = self
+            .config()
+            .ios()
+            .info
+            .minimum_os_version
+            .as_ref()
+            .unwrap();
+        cargo.use_ios_simulator(minimum_version)?;
+        self.configure_flags(cargo, target)
+    }
+
+    /// Rustup toolchain configured for the triple of `target` in
+    /// `targets`, the default toolchain is used if `None`.
+    pub fn toolchain(&self, target: CompileTarget) -> Result<Option<&str>> {
+        Ok(self
+            .config()
+            .target(target.rust_triple()?)
+            .and_then(|target| target.toolchain.as_deref()))
+    }
+
+    /// Whether `--container` was passed, the toolchains of the targets are
+    /// in the images instead of on the host.
+    pub fn containerized(&self) -> bool {
+        self.container_engine.is_some()
+    }
+
+    /// Container the rust code of `target` is built in with `--container`,
+    /// the workspace and target dir are mounted.
+    pub fn container(&self, target: CompileTarget) -> Result<Option<Container>> {
+        let engine = if let Some(engine) = &self.container_engine {
+            engine
+        } else {
+            return Ok(None);
+        };
+        let triple = target.rust_triple()?;
+        let image = self
+            .config()
+            .target(triple)
+            .and_then(|target| target.container.clone())
+            .or_else(|| container::default_image(target))
+            .with_context(|| format!("no container image for {}", triple))
+            .categorize(
+                ErrorKind::Configuration,
+                &format!(
+                    "set `targets.{}.container` to an image with the toolchain of the target",
+                    triple
+                ),
+            )?;
+        let mut container = Container::new(engine, &image);
+        container.mount(self.workspace_root());
+        container.mount(self.cargo.target_dir());
+        Ok(Some(container))
+    }
+
+    fn workspace_root(&self) -> &Path {
+        self.cargo
+            .workspace_manifest_path()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| self.cargo.package_root())
+    }
+
+    fn configure_cargo(&self, mut cargo: CargoBuild, target: CompileTarget) -> Result<CargoBuild> {
         if target.platform() == Platform::Linux {
+            if !target.is_host()? && !self.containerized() {
+                cargo.use_linux_sysroot(&self.linux_sysroot(target.arch()))?;
+            }
             cargo.add_link_arg("-Wl,-rpath");
             cargo.add_link_arg("-Wl,$ORIGIN/lib");
         }
+        if let Some(container) = self.container(target)? {
+            // The image configures the compilers and linker of the target
+            cargo.use_container(container);
+        } else {
+            self.configure_sdk(&mut cargo, target)?;
+        }
+        self.configure_flags(cargo, target)
+    }
+
+    /// Compilers, linker and sysroot of the sdk of `target`.
+    fn configure_sdk(&self, cargo: &mut CargoBuild, target: CompileTarget) -> Result<()> {
         if target.platform() == Platform::Android {
             let ndk = self.android_ndk();
-            let target_sdk_version = self
-                .config()
-                .android()
-                .manifest
-                .sdk
-                .target_sdk_version
-                .unwrap();
-            cargo.use_android_ndk(&ndk, target_sdk_version, &target)?;
+            cargo.use_android_ndk(&ndk, self.min_sdk_version())?;
         }
         if target.platform() == Platform::Windows {
             let sdk = self.windows_sdk();
@@ -787,6 +1755,68 @@ impl BuildEnv {
                 cargo.use_ios_sdk(&sdk, minimum_version)?;
             }
         }
+        Ok(())
+    }
+
+    fn configure_flags(&self, mut cargo: CargoBuild, target: CompileTarget) -> Result<CargoBuild> {
+        let platform = target.platform();
+        let features = self.config().features(platform);
+        if !features.is_empty() {
+            cargo.arg("--features");
+            cargo.arg(&features.join(","));
+        }
+        for (name, value) in self.config().env(platform) {
+            cargo.env(&name, &value);
+        }
+        let triple = target.rust_triple()?;
+        for flag in self.config().rustflags(platform, triple) {
+            cargo.add_rustflag(&flag);
+        }
+        if let Some(linker) = self.config().linker(platform, triple) {
+            cargo.set_linker(linker);
+        }
+        if let Some(config) = self.config().target(triple) {
+            if !config.build_std.is_empty() {
+                cargo.use_build_std(&config.build_std, &config.build_std_features);
+            }
+            if let Some(spec) = &config.spec {
+                cargo.set_target_spec(&self.root_dir().join(spec))?;
+            }
+        }
+        for arg in self.config().link_args(platform, triple) {
+            cargo.add_link_arg(&arg);
+        }
+        // The executable of the host can't run in the container
+        if self.config().native_cache(platform) && !self.containerized() {
+            cargo.use_compiler_cache(&std::env::current_exe()?);
+        }
+        if let Some(epoch) = self.source_date_epoch {
+            cargo.env("SOURCE_DATE_EPOCH", &epoch.to_string());
+            cargo.env("CARGO_INCREMENTAL", "0");
+            // Archives created by the apple toolchain
+            cargo.env("ZERO_AR_DATE", "1");
+            // Later prefixes take precedence, the target dir may be in the
+            // workspace
+            let workspace = self.workspace_root();
+            let cargo_home = if self.containerized() {
+                Some(Container::cargo_home().to_path_buf())
+            } else {
+                std::env::var_os("CARGO_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+            };
+            let prefixes = [
+                Some((workspace, "/src")),
+                cargo_home.as_deref().map(|home| (home, "/cargo")),
+                Some((self.cargo.target_dir(), "/target")),
+            ];
+            for (from, to) in prefixes.into_iter().flatten() {
+                let map = format!("{}={}", from.display(), to);
+                cargo.add_rustflag(&format!("--remap-path-prefix={}", map));
+                // Also passed to the c++ compiler
+                cargo.add_cflag(&format!("-ffile-prefix-map={}", map));
+            }
+        }
         Ok(cargo)
     }
 
@@ -796,6 +1826,25 @@ impl BuildEnv {
         target: CompileTarget,
         crate_type: CrateType,
     ) -> Result<PathBuf> {
-        self.cargo.artifact(target_dir, target, None, crate_type)
+        let artifact = match self.cargo.selected_artifact() {
+            // Android apps are built from a `cdylib` example instead of the lib
+            Some(example @ Artifact::Example(_))
+                if crate_type == CrateType::Cdylib && target.platform() == Platform::Android =>
+            {
+                Some(example.clone())
+            }
+            _ => None,
+        };
+        self.cargo
+            .artifact(target_dir, target, artifact, crate_type)
+    }
+
+    /// Name of the library loaded by android apps, the example selected with
+    /// `--example` or the `[lib]` target.
+    pub fn android_lib_name(&self) -> String {
+        match self.cargo.selected_artifact() {
+            Some(Artifact::Example(example)) => example.clone(),
+            _ => self.cargo.lib_name(),
+        }
     }
 }
@@ -2,7 +2,10 @@ use anyhow::Result;
 use app_store_connect::certs_api::CertificateType;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
-use xbuild::{cargo::config::LocalizedConfig, command, BuildArgs, BuildEnv};
+use xbuild::{
+    cargo::config::LocalizedConfig, command, BuildArgs, BuildEnv, CleanArgs, LogFilter,
+    MetadataArgs, Platform, PortForward, Profiler, PublishArgs, SignersArgs, VersionArgs,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -11,7 +14,7 @@ struct Args {
     command: Commands,
 }
 
-fn main() -> Result<()> {
+fn main() {
     use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
     tracing_log::LogTracer::init().ok();
     let env = std::env::var("XBUILD_LOG").unwrap_or_else(|_| "error".into());
@@ -23,7 +26,20 @@ fn main() -> Result<()> {
     tracing::subscriber::set_global_default(subscriber).ok();
     log_panics::init();
     let args = Args::parse();
-    args.command.run()
+    if let Err(err) = args.command.run() {
+        eprintln!("Error: {:?}", err);
+        // Categorized errors exit with their own code, so ci can tell a
+        // missing sdk from a broken build
+        let code = if let Some(error) = xbuild::Error::find(&err) {
+            if let Some(hint) = error.remediation() {
+                eprintln!("\n{} error, {}", error.kind(), hint);
+            }
+            error.kind().exit_code()
+        } else {
+            1
+        };
+        std::process::exit(code);
+    }
 }
 
 #[derive(Subcommand)]
@@ -32,25 +48,273 @@ enum Commands {
     New {
         /// Project name
         name: String,
+        /// Project template, one of `dioxus`, `winit`, `game`, `service` or `lib`
+        #[clap(long, default_value = "dioxus")]
+        template: command::Template,
     },
     /// Show information about the installed tooling
-    Doctor,
+    Doctor {
+        /// Only check the tools needed for this platform
+        #[clap(long, conflicts_with = "device")]
+        platform: Option<Platform>,
+        /// Check the tools for the platform of the device and the device itself
+        #[clap(long)]
+        device: Option<String>,
+        /// Print the results as json
+        #[clap(long)]
+        json: bool,
+        /// Apply safe fixes: download the components of the platform when
+        /// run in a package, install the android platform-tools, create the
+        /// android debug keystore and suggest environment variables
+        #[clap(long)]
+        fix: bool,
+    },
     /// List all connected devices
-    Devices,
+    Devices {
+        /// Print id, transport, state, platform, os version, model and
+        /// features of each device as json
+        #[clap(long)]
+        json: bool,
+        #[clap(subcommand)]
+        command: Option<DevicesCommand>,
+    },
+    /// Manage android emulators and ios simulators
+    Emulators {
+        #[clap(subcommand)]
+        command: EmulatorsCommand,
+    },
     /// Build an executable app or install bundle
     Build {
         #[clap(flatten)]
         args: BuildArgs,
+        /// Stop after compiling, see `x bundle`
+        #[clap(long)]
+        no_bundle: bool,
+    },
+    /// Package and sign artifacts compiled with `x build --no-bundle`
+    Bundle {
+        #[clap(flatten)]
+        args: BuildArgs,
     },
     /// Run app on an attached device
     Run {
         #[clap(flatten)]
         args: BuildArgs,
+        /// Only show android logs matching `tag:level`, use `*:level` for
+        /// the remaining tags
+        #[clap(long)]
+        log_filter: Vec<LogFilter>,
+        /// Let the app reach a server on the host at `localhost:PORT`, as
+        /// `port` or `device:host`. Adds to `forward_ports`
+        #[clap(long)]
+        forward: Vec<PortForward>,
+        /// Rebuild and redeploy the app whenever a file of the workspace
+        /// changes
+        #[clap(long)]
+        watch: bool,
+    },
+    /// Run the package tests on an attached device or an ios simulator
+    Test {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Ios simulator to run the tests in instead of a device
+        #[clap(long)]
+        simulator: Option<String>,
+        /// Arguments passed to the test harness
+        #[clap(last = true)]
+        harness_args: Vec<String>,
+    },
+    /// Build and upload an app to google play, the app store or the microsoft store
+    Publish {
+        #[clap(flatten)]
+        args: PublishArgs,
+    },
+    /// Manage the store listings in `publish.metadata`
+    Metadata {
+        #[clap(subcommand)]
+        command: MetadataCommand,
+    },
+    /// Print the version or bump it consistently for all platforms
+    Version {
+        #[clap(flatten)]
+        args: VersionArgs,
+    },
+    /// Print the compiler, linker and flag variables a target is built
+    /// with, for running cargo or build tools outside of `x`
+    Env {
+        #[clap(flatten)]
+        args: BuildArgs,
+    },
+    /// Download the sdk components of a target for offline builds
+    Vendor {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Directory to store the components in
+        dir: PathBuf,
+    },
+    /// List the downloaded sdk components, replace stale ones and remove
+    /// unused ones
+    Update {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// List what would be changed without changing anything
+        #[clap(long)]
+        dry_run: bool,
+        /// Also remove components the project doesn't use and downloaded
+        /// archives from the vendor dir
+        #[clap(long)]
+        prune: bool,
+        /// With `--prune`, also remove them from the download cache shared
+        /// with other projects
+        #[clap(long, requires = "prune")]
+        all: bool,
+        /// Also update x itself with `cargo install` from its git repository
+        #[clap(long = "self")]
+        self_update: bool,
+    },
+    /// Compiler wrapper caching native object files
+    #[clap(hide = true)]
+    CcCache {
+        compiler: String,
+        #[clap(allow_hyphen_values = true, trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+    /// Remove build artifacts and optionally the download cache
+    Clean {
+        #[clap(flatten)]
+        args: CleanArgs,
     },
     /// Launch app in a debugger on an attached device
     Lldb {
         #[clap(flatten)]
         args: BuildArgs,
+        /// Attach to a running process given by pid or name instead
+        #[clap(long)]
+        attach: Option<String>,
+    },
+    /// Stream logs of the app installed on a device without rebuilding it
+    Attach {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Attach the debugger instead of streaming logs
+        #[clap(long)]
+        lldb: bool,
+        /// Only show android logs matching `tag:level`, use `*:level` for
+        /// the remaining tags
+        #[clap(long)]
+        log_filter: Vec<LogFilter>,
+    },
+    /// Stream the logs of the app on a device or simulator without
+    /// launching it
+    Logs {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Ios simulator to stream the logs of instead of a device
+        #[clap(long)]
+        simulator: Option<String>,
+        /// Only show android logs matching `tag:level`, use `*:level` for
+        /// the remaining tags
+        #[clap(long)]
+        log_filter: Vec<LogFilter>,
+        /// Only show lines containing the text
+        #[clap(long)]
+        grep: Option<String>,
+        /// Also write the shown lines to a file
+        #[clap(long)]
+        save: Option<PathBuf>,
+    },
+    /// Profile a release build on a device
+    Profile {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// `simpleperf` or `perfetto` on android, `instruments` or `dtrace` on
+        /// macos and `perf` on linux
+        #[clap(long)]
+        profiler: Option<Profiler>,
+        /// Length of the profile in seconds
+        #[clap(long, default_value = "10")]
+        duration: u64,
+        /// Also render the samples as a flamegraph svg
+        #[clap(long)]
+        flamegraph: bool,
+        /// Trace file, defaults to a timestamped file in `target/x/profile`
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Save a screenshot of a device or simulator
+    Screenshot {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Ios simulator to capture instead of a device
+        #[clap(long)]
+        simulator: Option<String>,
+        /// Output file, defaults to `<app>-<timestamp>.png`
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Copy a file or directory into the sandbox of the app on a device
+    Push {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// File or directory to copy
+        local: PathBuf,
+        /// Destination relative to the data dir on android or the app
+        /// container on ios
+        remote: String,
+    },
+    /// Copy a file or directory out of the sandbox of the app on a device
+    Pull {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Path relative to the data dir on android or the app container
+        /// on ios
+        remote: String,
+        /// Output path, defaults to the file name in the current directory
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Record the screen of a device or simulator
+    Record {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Ios simulator to record instead of a device
+        #[clap(long)]
+        simulator: Option<String>,
+        /// Output file, defaults to `<app>-<timestamp>.mp4`
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+        /// Length of the recording in seconds
+        #[clap(long, default_value = "10")]
+        duration: u64,
+    },
+    /// Install a built artifact on a device without running it
+    Install {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Artifacts to install, split apks are installed together. Defaults
+        /// to the output of the last build
+        paths: Vec<PathBuf>,
+    },
+    /// Uninstall the app from a device
+    Uninstall {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Package name, bundle identifier or app name, defaults to the one
+        /// in `manifest.yaml`
+        #[clap(long)]
+        id: Option<String>,
+    },
+    /// Resolve the native frames of a crash report using the local build
+    Symbolicate {
+        #[clap(flatten)]
+        args: BuildArgs,
+        /// Tombstone, logcat output, apple crash report or minidump
+        report: PathBuf,
+    },
+    /// List the signing identities and set the default of the project
+    Signers {
+        #[clap(flatten)]
+        args: SignersArgs,
     },
     /// Generates a PEM encoded RSA2048 signing key
     GenerateKey {
@@ -76,6 +340,65 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum DevicesCommand {
+    /// Pair with an android device using the code shown under wireless debugging
+    Pair {
+        /// Pairing address as `ip:port`
+        address: String,
+        /// Pairing code
+        code: String,
+    },
+    /// Connect to an android device over the network and remember it
+    Connect {
+        /// Address as `ip:port`
+        address: String,
+    },
+    /// Disconnect from an android network device and forget it
+    Disconnect {
+        /// Address as `ip:port`
+        address: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum EmulatorsCommand {
+    /// List android avds and ios simulators
+    List,
+    /// Create an android avd or ios simulator
+    Create {
+        name: String,
+        /// `android` or `ios`
+        #[clap(long, default_value = "android")]
+        platform: Platform,
+        /// Android system image, defaults to the android 33 google apis image
+        #[clap(long)]
+        image: Option<String>,
+        /// Hardware profile like `pixel_7` or simulator device type like `iPhone 14`
+        #[clap(long)]
+        device_type: Option<String>,
+    },
+    /// Boot an emulator or simulator and wait until it's ready
+    Start {
+        name: String,
+        /// Don't show a window, useful on ci
+        #[clap(long)]
+        headless: bool,
+    },
+    /// Shut down an emulator or simulator
+    Stop { name: String },
+}
+
+#[derive(Subcommand)]
+enum MetadataCommand {
+    /// Write the listings in the directory structure of fastlane supply and
+    /// deliver
+    Export {
+        #[clap(flatten)]
+        args: MetadataArgs,
+    },
+}
+
 /// Setup a partial build environment (e.g. read `[env]` from `.cargo/config.toml`) when there is
 /// no crate/manifest selected. Pretend `$PWD` is the workspace.
 ///
@@ -91,29 +414,199 @@ fn partial_build_env() -> Result<()> {
 impl Commands {
     pub fn run(self) -> Result<()> {
         match self {
-            Self::New { name } => command::new(&name)?,
-            Self::Doctor => {
+            Self::New { name, template } => command::new(&name, template)?,
+            Self::Doctor {
+                platform,
+                device,
+                json,
+                fix,
+            } => {
+                partial_build_env()?;
+                let device = device.map(|device| device.parse()).transpose()?;
+                command::doctor(platform, device.as_ref(), json, fix)?
+            }
+            Self::Devices { json, command } => {
                 partial_build_env()?;
-                command::doctor()
+                match command {
+                    None => command::devices(json)?,
+                    Some(DevicesCommand::Pair { address, code }) => {
+                        command::pair_device(&address, &code)?
+                    }
+                    Some(DevicesCommand::Connect { address }) => command::connect_device(&address)?,
+                    Some(DevicesCommand::Disconnect { address }) => {
+                        command::disconnect_device(&address)?
+                    }
+                }
             }
-            Self::Devices => {
+            Self::Emulators { command } => {
                 partial_build_env()?;
-                command::devices()?
+                match command {
+                    EmulatorsCommand::List => command::list_emulators()?,
+                    EmulatorsCommand::Create {
+                        name,
+                        platform,
+                        image,
+                        device_type,
+                    } => command::create_emulator(
+                        &name,
+                        platform,
+                        image.as_deref(),
+                        device_type.as_deref(),
+                    )?,
+                    EmulatorsCommand::Start { name, headless } => {
+                        command::start_emulator(&name, headless)?
+                    }
+                    EmulatorsCommand::Stop { name } => command::stop_emulator(&name)?,
+                }
             }
-            Self::Build { args } => {
+            Self::Build { args, no_bundle } => {
                 let env = BuildEnv::new(args)?;
-                command::build(&env)?;
+                if no_bundle {
+                    command::compile(&env)?;
+                } else {
+                    command::build(&env)?;
+                }
             }
-            Self::Run { args } => {
+            Self::Bundle { args } => {
                 let env = BuildEnv::new(args)?;
-                command::build(&env)?;
-                command::run(&env)?;
+                command::bundle(&env)?;
+            }
+            Self::Run {
+                args,
+                log_filter,
+                forward,
+                watch,
+            } => {
+                let env = BuildEnv::new(args)?;
+                if watch {
+                    command::watch(&env, &log_filter, &forward)?;
+                } else {
+                    command::build(&env)?;
+                    command::run(&env, &log_filter, &forward)?;
+                }
+            }
+            Self::Test {
+                args,
+                simulator,
+                harness_args,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::test(&env, simulator.as_deref(), &harness_args)?;
+            }
+            Self::Publish { args } => command::publish(args)?,
+            Self::Metadata { command } => match command {
+                MetadataCommand::Export { args } => command::export_metadata(args)?,
+            },
+            Self::Version { args } => command::version(args)?,
+            Self::Env { args } => {
+                let env = BuildEnv::new(args)?;
+                command::env(&env)?;
+            }
+            Self::Vendor { args, dir } => {
+                let env = BuildEnv::new(args.with_vendor_dir(dir))?;
+                command::vendor(&env)?;
             }
-            Self::Lldb { args } => {
+            Self::Update {
+                args,
+                dry_run,
+                prune,
+                all,
+                self_update,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::update(&env, dry_run, prune, all, self_update)?;
+            }
+            Self::CcCache { compiler, args } => {
+                let code = command::cc_cache(&xbuild::native_cache_dir(), &compiler, &args)?;
+                std::process::exit(code);
+            }
+            Self::Clean { args } => command::clean(args)?,
+            Self::Lldb { args, attach } => {
                 let env = BuildEnv::new(args)?;
                 command::build(&env)?;
-                command::lldb(&env)?;
+                command::lldb(&env, attach.as_deref())?;
+            }
+            Self::Logs {
+                args,
+                simulator,
+                log_filter,
+                grep,
+                save,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::logs(
+                    &env,
+                    simulator.as_deref(),
+                    &log_filter,
+                    grep,
+                    save.as_deref(),
+                )?;
+            }
+            Self::Attach {
+                args,
+                lldb,
+                log_filter,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::attach(&env, lldb, &log_filter)?;
+            }
+            Self::Profile {
+                args,
+                profiler,
+                duration,
+                flamegraph,
+                output,
+            } => {
+                let duration = std::time::Duration::from_secs(duration);
+                command::profile(args, profiler, duration, flamegraph, output)?;
+            }
+            Self::Screenshot {
+                args,
+                simulator,
+                output,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::screenshot(&env, simulator.as_deref(), output)?;
+            }
+            Self::Push {
+                args,
+                local,
+                remote,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::push(&env, &local, &remote)?;
+            }
+            Self::Pull {
+                args,
+                remote,
+                output,
+            } => {
+                let env = BuildEnv::new(args)?;
+                command::pull(&env, &remote, output)?;
+            }
+            Self::Record {
+                args,
+                simulator,
+                output,
+                duration,
+            } => {
+                let env = BuildEnv::new(args)?;
+                let duration = std::time::Duration::from_secs(duration);
+                command::record(&env, simulator.as_deref(), output, duration)?;
+            }
+            Self::Install { args, paths } => {
+                let env = BuildEnv::new(args)?;
+                command::install(&env, paths)?;
+            }
+            Self::Uninstall { args, id } => {
+                let env = BuildEnv::new(args)?;
+                command::uninstall(&env, id.as_deref())?;
+            }
+            Self::Symbolicate { args, report } => {
+                let env = BuildEnv::new(args)?;
+                command::symbolicate(&env, &report)?;
             }
+            Self::Signers { args } => command::signers(args)?,
             Self::GenerateKey {
                 api_key,
                 r#type,
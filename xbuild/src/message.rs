@@ -0,0 +1,41 @@
+use serde::Serialize;
+use std::path::Path;
+
+/// Events emitted on stdout with `--message-format json`, one json object
+/// per line. Cargo's own json messages are interleaved.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Message<'a> {
+    TaskStarted {
+        task: &'a str,
+    },
+    TaskFinished {
+        task: &'a str,
+        skipped: bool,
+        time_ms: u128,
+    },
+    Artifact {
+        path: &'a Path,
+        signed: bool,
+        /// Signed with a debug certificate because no signer is configured.
+        debug_signed: bool,
+        /// SHA-256 fingerprint of the signing certificate, if it's known.
+        signer: Option<&'a str>,
+        sbom: Option<&'a Path>,
+        /// Hash of the artifact printed by reproducible builds.
+        sha256: Option<&'a str>,
+    },
+    Launch {
+        device: &'a str,
+        path: &'a Path,
+    },
+    Exit {
+        device: &'a str,
+    },
+}
+
+impl Message<'_> {
+    pub fn emit(&self) {
+        println!("{}", serde_json::to_string(self).unwrap());
+    }
+}
@@ -0,0 +1,308 @@
+//! Packaging backends besides the built-in formats, like flatpak, snap or
+//! deb. They're configured in `packagers` of `manifest.yaml` and selected
+//! with `x build --packager <name>`, which packages the compiled artifacts
+//! with the backend instead of the default format of the platform.
+//!
+//! A backend is a [`Packager`] registered with [`register`] by a binary
+//! running xbuild's commands, or otherwise an external command: the
+//! configured `command` or `xbuild-<name>` found in `PATH`.
+
+use crate::cargo::CrateType;
+use crate::{BuildEnv, Categorize, CompileTarget, ErrorKind};
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use xcommon::Signer;
+
+static PACKAGERS: Mutex<Vec<Arc<dyn Packager>>> = Mutex::new(Vec::new());
+
+/// Rust binary or library compiled for a target.
+#[derive(Clone, Debug)]
+pub struct CompiledArtifact {
+    pub target: CompileTarget,
+    pub crate_type: CrateType,
+    pub path: PathBuf,
+}
+
+/// What a backend packages and where to.
+pub struct PackageInput<'a> {
+    pub env: &'a BuildEnv,
+    /// Binaries and dynamic libraries of every target.
+    pub artifacts: &'a [CompiledArtifact],
+    /// Icon rendered for the platform.
+    pub icon: Option<&'a Path>,
+    pub(crate) options: &'a serde_yaml::Value,
+    pub signer: Option<&'a Signer>,
+    /// Path the package is written to, see [`BuildEnv::output`].
+    pub output: &'a Path,
+}
+
+impl<'a> PackageInput<'a> {
+    /// `options` of the packager config, the default if none are set.
+    pub fn options<T: DeserializeOwned + Default>(&self) -> Result<T> {
+        if self.options.is_null() {
+            return Ok(T::default());
+        }
+        serde_yaml::from_value(self.options.clone())
+            .context("invalid packager options")
+            .categorize(
+                ErrorKind::Configuration,
+                "check the `options` of the packager in `manifest.yaml`",
+            )
+    }
+}
+
+pub trait Packager: Send + Sync {
+    /// Name the backend is configured and selected with.
+    fn name(&self) -> &str;
+
+    fn package(&self, input: &PackageInput) -> Result<()>;
+}
+
+/// Makes `packager` available to builds configuring a packager of its
+/// name, replacing a previously registered one.
+pub fn register(packager: impl Packager + 'static) {
+    let mut packagers = PACKAGERS.lock().unwrap();
+    packagers.retain(|registered| registered.name() != packager.name());
+    packagers.push(Arc::new(packager));
+}
+
+/// Backend of the packager selected with `--packager`.
+pub(crate) fn find(env: &BuildEnv) -> Result<Option<Arc<dyn Packager>>> {
+    let name = if let Some(name) = env.target().packager() {
+        name
+    } else {
+        return Ok(None);
+    };
+    let registered = PACKAGERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|packager| packager.name() == name)
+        .cloned();
+    if let Some(packager) = registered {
+        return Ok(Some(packager));
+    }
+    let config = env.config().packager(name).unwrap();
+    let command = if let Some(command) = &config.command {
+        command.clone()
+    } else {
+        let program = format!("xbuild-{}", name);
+        which::which(&program)
+            .with_context(|| {
+                format!(
+                    "no `{}` packager registered and {} not found",
+                    name, program
+                )
+            })
+            .categorize(
+                ErrorKind::Configuration,
+                "set `command` of the packager or install its backend",
+            )?;
+        program
+    };
+    Ok(Some(Arc::new(CommandPackager {
+        name: name.to_string(),
+        command,
+    })))
+}
+
+/// Backend running a shell command in the package root. The inputs are
+/// passed like to build hooks, in `XBUILD_*` environment variables.
+struct CommandPackager {
+    name: String,
+    command: String,
+}
+
+impl Packager for CommandPackager {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn package(&self, input: &PackageInput) -> Result<()> {
+        let env = input.env;
+        let triples = env
+            .target()
+            .compile_targets()
+            .map(|target| target.rust_triple())
+            .collect::<Result<Vec<_>>>()?;
+        let artifacts =
+            std::env::join_paths(input.artifacts.iter().map(|artifact| &artifact.path))?;
+        let mut cmd = crate::task::shell(&self.command);
+        cmd.current_dir(env.root_dir())
+            .env("XBUILD_NAME", env.name())
+            .env("XBUILD_VERSION", env.config().version())
+            .env("XBUILD_PLATFORM", env.target().platform().to_string())
+            .env("XBUILD_OPT", env.target().opt().to_string())
+            .env("XBUILD_TARGET", triples.join(" "))
+            .env("XBUILD_PLATFORM_DIR", env.platform_dir())
+            .env("XBUILD_ARTIFACTS", artifacts)
+            .env("XBUILD_OPTIONS", serde_json::to_string(input.options)?)
+            .env("XBUILD_OUTPUT", input.output);
+        if let Some(icon) = input.icon {
+            cmd.env("XBUILD_ICON", icon);
+        }
+        if let Some(epoch) = env.source_date_epoch() {
+            cmd.env("SOURCE_DATE_EPOCH", epoch.to_string());
+        }
+        crate::task::run(cmd, env.verbose(), env.message_format())
+            .with_context(|| format!("packager `{}` failed", self.name))?;
+        anyhow::ensure!(
+            input.output.exists(),
+            "packager `{}` didn't create {}",
+            self.name,
+            input.output.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildArgs, Error};
+    use clap::Parser;
+    use serde::Deserialize;
+
+    struct Fake(&'static str);
+
+    impl Packager for Fake {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn package(&self, input: &PackageInput) -> Result<()> {
+            std::fs::write(input.output, self.0)?;
+            Ok(())
+        }
+    }
+
+    /// Build env of a package configuring `packagers` as yaml.
+    fn build_env(name: &str, packagers: &str, packager: Option<&str>) -> BuildEnv {
+        let dir = std::env::temp_dir().join(format!("xbuild-packager-{}", name));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"example\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.join("manifest.yaml"), packagers).unwrap();
+        let manifest_path = dir.join("Cargo.toml");
+        let mut args = vec![
+            "build",
+            "--platform",
+            "linux",
+            "--arch",
+            "x64",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ];
+        if let Some(packager) = packager {
+            args.extend(["--packager", packager]);
+        }
+        BuildEnv::new(BuildArgs::try_parse_from(args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_register_find() {
+        let yaml = "packagers:\n  fake:\n    platform: linux\n    extension: fake\n";
+        let env = build_env("register", yaml, None);
+        assert!(find(&env).unwrap().is_none());
+
+        register(Fake("fake"));
+        register(Fake("fake"));
+        let env = build_env("register", yaml, Some("fake"));
+        assert_eq!(find(&env).unwrap().unwrap().name(), "fake");
+        let registered = PACKAGERS.lock().unwrap();
+        assert_eq!(registered.iter().filter(|p| p.name() == "fake").count(), 1);
+    }
+
+    #[test]
+    fn test_find_missing_backend() {
+        let yaml = "packagers:\n  missing-backend:\n    platform: linux\n    extension: x\n";
+        let env = build_env("missing", yaml, Some("missing-backend"));
+        let err = find(&env).err().unwrap();
+        let err = Error::find(&err).unwrap();
+        assert_eq!(err.kind(), ErrorKind::Configuration);
+    }
+
+    #[test]
+    fn test_options() {
+        #[derive(Debug, Default, Deserialize, PartialEq)]
+        struct Options {
+            runtime: String,
+        }
+        let env = build_env("options", "", None);
+        let options = serde_yaml::from_str("runtime: org.freedesktop.Platform").unwrap();
+        let input = PackageInput {
+            env: &env,
+            artifacts: &[],
+            icon: None,
+            options: &options,
+            signer: None,
+            output: Path::new("out"),
+        };
+        assert_eq!(
+            input.options::<Options>().unwrap().runtime,
+            "org.freedesktop.Platform"
+        );
+        let input = PackageInput {
+            options: &serde_yaml::Value::Null,
+            ..input
+        };
+        assert_eq!(input.options::<Options>().unwrap(), Options::default());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_packager() {
+        let yaml = r#"packagers:
+  env:
+    platform: linux
+    extension: txt
+    command: printf '%s %s %s' "$XBUILD_NAME" "$XBUILD_TARGET" "$XBUILD_OPTIONS" > "$XBUILD_OUTPUT"
+    options:
+      level: 3
+"#;
+        let env = build_env("command", yaml, Some("env"));
+        let packager = find(&env).unwrap().unwrap();
+        let output = env.output();
+        assert_eq!(output.extension().unwrap(), "txt");
+        std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+        std::fs::remove_file(&output).ok();
+        let options = env.config().packager("env").unwrap().options.clone();
+        packager
+            .package(&PackageInput {
+                env: &env,
+                artifacts: &[],
+                icon: None,
+                options: &options,
+                signer: None,
+                output: &output,
+            })
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "example x86_64-unknown-linux-gnu {\"level\":3}"
+        );
+
+        let yaml = yaml.replace("> \"$XBUILD_OUTPUT\"", "> /dev/null");
+        let env = build_env("command", &yaml, Some("env"));
+        std::fs::remove_file(&output).ok();
+        let err = find(&env)
+            .unwrap()
+            .unwrap()
+            .package(&PackageInput {
+                env: &env,
+                artifacts: &[],
+                icon: None,
+                options: &options,
+                signer: None,
+                output: &output,
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("didn't create"));
+    }
+}
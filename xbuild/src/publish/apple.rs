@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use app_store_connect::AppStoreConnectClient;
+use md5::{Digest, Md5};
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+
+const API: &str = "https://api.appstoreconnect.apple.com/v1";
+
+#[derive(Deserialize)]
+struct Document<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct Resource {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct UploadFile {
+    id: String,
+    attributes: UploadFileAttributes,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadFileAttributes {
+    upload_operations: Vec<UploadOperation>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadOperation {
+    method: String,
+    url: String,
+    offset: usize,
+    length: usize,
+    #[serde(default)]
+    request_headers: Vec<Header>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+/// Uploads builds through the build upload endpoints of the App Store
+/// Connect API.
+pub struct AppStoreConnect {
+    asc: AppStoreConnectClient,
+    client: Client,
+}
+
+impl AppStoreConnect {
+    /// Authenticates with the unified api key at `api_key`, see
+    /// `x create-apple-api-key`.
+    pub fn new(api_key: &Path) -> Result<Self> {
+        let asc = AppStoreConnectClient::from_json_path(api_key)?;
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30 * 60))
+            .build()?;
+        Ok(Self { asc, client })
+    }
+
+    /// Uploads `ipa` as build `build` of `version` of the app with the apple
+    /// id `app_id`. Processing happens asynchronously on apple's side, the
+    /// build shows up in app store connect and testflight once it's done.
+    pub fn upload(&self, app_id: &str, ipa: &Path, version: &str, build: &str) -> Result<String> {
+        let data = std::fs::read(ipa)?;
+        let upload: Document<Resource> = self.send(
+            reqwest::Method::POST,
+            "buildUploads",
+            json!({
+                "data": {
+                    "type": "buildUploads",
+                    "attributes": {
+                        "cfBundleShortVersionString": version,
+                        "cfBundleVersion": build,
+                        "platform": "IOS",
+                    },
+                    "relationships": {
+                        "app": { "data": { "type": "apps", "id": app_id } },
+                    },
+                },
+            }),
+        )?;
+        let file: Document<UploadFile> = self.send(
+            reqwest::Method::POST,
+            "buildUploadFiles",
+            json!({
+                "data": {
+                    "type": "buildUploadFiles",
+                    "attributes": {
+                        "assetType": "ASSET",
+                        "fileName": ipa.file_name().unwrap().to_str().unwrap(),
+                        "fileSize": data.len(),
+                        "uti": "com.apple.ipa",
+                    },
+                    "relationships": {
+                        "buildUpload": {
+                            "data": { "type": "buildUploads", "id": upload.data.id },
+                        },
+                    },
+                },
+            }),
+        )?;
+
+        for op in &file.data.attributes.upload_operations {
+            let chunk = data
+                .get(op.offset..op.offset + op.length)
+                .context("upload operation out of bounds")?;
+            let method = op.method.parse::<reqwest::Method>()?;
+            let mut request = self.client.request(method, &op.url).body(chunk.to_vec());
+            for header in &op.request_headers {
+                request = request.header(&header.name, &header.value);
+            }
+            let response = request.send()?;
+            anyhow::ensure!(
+                response.status().is_success(),
+                "uploading part at offset {} failed with {}",
+                op.offset,
+                response.status()
+            );
+        }
+
+        let md5 = Md5::digest(&data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let _: serde_json::Value = self.send(
+            reqwest::Method::PATCH,
+            &format!("buildUploadFiles/{}", file.data.id),
+            json!({
+                "data": {
+                    "type": "buildUploadFiles",
+                    "id": file.data.id,
+                    "attributes": {
+                        "sourceFileChecksums": {
+                            "file": { "hash": md5, "algorithm": "MD5" },
+                        },
+                        "uploaded": true,
+                    },
+                },
+            }),
+        )?;
+        Ok(upload.data.id)
+    }
+
+    fn send<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: serde_json::Value,
+    ) -> Result<T> {
+        let token = self.asc.get_token()?;
+        let request = self
+            .client
+            .request(method, format!("{}/{}", API, path))
+            .bearer_auth(token)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(&body)?);
+        let response = self.asc.send_request(request)?;
+        Ok(serde_json::from_slice(&response.bytes()?)?)
+    }
+}
@@ -0,0 +1,139 @@
+use super::ReleaseNotes;
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+use xcommon::{Compression, ZipBuilder, ZipFileOptions};
+
+const API: &str = "https://manage.devcenter.microsoft.com/v1.0/my/applications";
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+/// Creates submissions through the Microsoft Store submission API of
+/// Partner Center, authenticated as an azure ad application.
+pub struct PartnerCenter {
+    client: Client,
+    token: String,
+}
+
+impl PartnerCenter {
+    pub fn new(tenant_id: &str, client_id: &str, client_secret: &str) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30 * 60))
+            .build()?;
+        let token: Token = send(
+            client
+                .post(format!(
+                    "https://login.microsoftonline.com/{}/oauth2/token",
+                    tenant_id
+                ))
+                .form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("resource", "https://manage.devcenter.microsoft.com"),
+                ]),
+        )?;
+        Ok(Self {
+            client,
+            token: token.access_token,
+        })
+    }
+
+    /// Replaces the packages of a new submission of the app with store id
+    /// `app_id` by `msix` and commits it for certification. A `rollout`
+    /// below `1.0` enables a gradual package rollout.
+    pub fn publish(
+        &self,
+        app_id: &str,
+        msix: &Path,
+        rollout: Option<f64>,
+        notes: &[ReleaseNotes],
+    ) -> Result<String> {
+        let submissions = format!("{}/{}/submissions", API, app_id);
+        // The submission is a clone of the last published one
+        let mut submission: Value = send(self.auth(self.client.post(&submissions)))?;
+        let id = submission["id"]
+            .as_str()
+            .context("submission without id")?
+            .to_string();
+        let upload_url = submission["fileUploadUrl"]
+            .as_str()
+            .context("submission without upload url")?
+            .to_string();
+
+        let file_name = msix.file_name().unwrap().to_str().unwrap();
+        if let Some(packages) = submission["applicationPackages"].as_array_mut() {
+            for package in packages.iter_mut() {
+                package["fileStatus"] = "PendingDelete".into();
+            }
+            packages.push(serde_json::json!({
+                "fileName": file_name,
+                "fileStatus": "PendingUpload",
+            }));
+        }
+        if let Some(rollout) = rollout.filter(|rollout| *rollout < 1.0) {
+            submission["packageDeliveryOptions"]["packageRollout"] = serde_json::json!({
+                "isPackageRollout": true,
+                "packageRolloutPercentage": rollout * 100.0,
+            });
+        }
+        if let Some(listings) = submission["listings"].as_object_mut() {
+            for note in notes {
+                match listings.get_mut(&note.language.to_lowercase()) {
+                    Some(listing) => {
+                        listing["baseListing"]["releaseNotes"] = note.text.clone().into();
+                    }
+                    None => log::warn!("no store listing for language {}", note.language),
+                }
+            }
+        }
+        send::<Value>(
+            self.auth(self.client.put(format!("{}/{}", submissions, id)))
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_vec(&submission)?),
+        )?;
+
+        // Packages are uploaded as a zip to an azure blob
+        let mut zip = ZipBuilder::from_writer(Cursor::new(vec![]), Compression::Stored)?;
+        zip.add_file(msix, Path::new(file_name), ZipFileOptions::Unaligned)?;
+        let zip = zip.finish()?.into_inner();
+        let response = self
+            .client
+            .put(upload_url)
+            .header("x-ms-blob-type", "BlockBlob")
+            .body(zip)
+            .send()?;
+        anyhow::ensure!(
+            response.status().is_success(),
+            "uploading package failed with {}",
+            response.status()
+        );
+
+        send::<Value>(self.auth(self.client.post(format!("{}/{}/commit", submissions, id))))?;
+        Ok(id)
+    }
+
+    fn auth(&self, request: RequestBuilder) -> RequestBuilder {
+        request.bearer_auth(&self.token)
+    }
+}
+
+fn send<T: serde::de::DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let response = request.send()?;
+    let status = response.status();
+    let body = response.text()?;
+    anyhow::ensure!(
+        status.is_success(),
+        "partner center request failed with {}: {}",
+        status,
+        body
+    );
+    Ok(serde_json::from_str(&body)?)
+}
@@ -0,0 +1,16 @@
+//! Store upload backends used by `x publish`.
+
+mod apple;
+mod microsoft;
+mod play;
+
+pub use apple::AppStoreConnect;
+pub use microsoft::PartnerCenter;
+pub use play::{Play, Track};
+
+/// Localized release notes, `language` is a BCP-47 tag like `en-US`.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ReleaseNotes {
+    pub language: String,
+    pub text: String,
+}
@@ -0,0 +1,235 @@
+use super::ReleaseNotes;
+use anyhow::{Context, Result};
+use base64::alphabet::URL_SAFE;
+use base64::engine::fast_portable::{FastPortable, NO_PAD};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{PaddingScheme, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCOPE: &str = "https://www.googleapis.com/auth/androidpublisher";
+const API: &str = "https://androidpublisher.googleapis.com/androidpublisher/v3/applications";
+const UPLOAD_API: &str =
+    "https://androidpublisher.googleapis.com/upload/androidpublisher/v3/applications";
+const BASE64_URL: FastPortable = FastPortable::from(&URL_SAFE, NO_PAD);
+
+/// Release track of an app on Google Play.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Track {
+    Internal,
+    Alpha,
+    Beta,
+    Production,
+}
+
+impl std::fmt::Display for Track {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Internal => write!(f, "internal"),
+            Self::Alpha => write!(f, "alpha"),
+            Self::Beta => write!(f, "beta"),
+            Self::Production => write!(f, "production"),
+        }
+    }
+}
+
+impl std::str::FromStr for Track {
+    type Err = anyhow::Error;
+
+    fn from_str(track: &str) -> Result<Self> {
+        Ok(match track {
+            "internal" => Self::Internal,
+            "alpha" => Self::Alpha,
+            "beta" => Self::Beta,
+            "production" => Self::Production,
+            _ => anyhow::bail!("unsupported track {}", track),
+        })
+    }
+}
+
+/// Key file of a google cloud service account with access to the play
+/// console.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct Edit {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Uploaded {
+    version_code: i64,
+}
+
+#[derive(Serialize)]
+struct TrackRelease<'a> {
+    track: String,
+    releases: [Release<'a>; 1],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Release<'a> {
+    version_codes: [String; 1],
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    release_notes: &'a [ReleaseNotes],
+}
+
+/// Client for the Google Play Developer API.
+pub struct Play {
+    client: Client,
+    token: String,
+}
+
+impl Play {
+    /// Authenticates with the json key of a service account.
+    pub fn new(key: &str) -> Result<Self> {
+        let account: ServiceAccount =
+            serde_json::from_str(key).context("invalid service account key")?;
+        let iat = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = Claims {
+            iss: &account.client_email,
+            scope: SCOPE,
+            aud: &account.token_uri,
+            iat,
+            exp: iat + 3600,
+        };
+        let message = format!(
+            "{}.{}",
+            base64::encode_engine(br#"{"alg":"RS256","typ":"JWT"}"#, &BASE64_URL),
+            base64::encode_engine(serde_json::to_vec(&claims)?, &BASE64_URL),
+        );
+        let key = RsaPrivateKey::from_pkcs8_pem(&account.private_key)
+            .context("invalid service account private key")?;
+        let padding = PaddingScheme::new_pkcs1v15_sign::<Sha256>();
+        let signature = key.sign(padding, &Sha256::digest(message.as_bytes()))?;
+        let jwt = format!(
+            "{}.{}",
+            message,
+            base64::encode_engine(signature, &BASE64_URL)
+        );
+
+        let client = Client::builder()
+            // Uploads of large bundles take a while
+            .timeout(Duration::from_secs(30 * 60))
+            .build()?;
+        let token: Token = send(client.post(&account.token_uri).form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", &jwt),
+        ]))?;
+        Ok(Self {
+            client,
+            token: token.access_token,
+        })
+    }
+
+    /// Uploads an apk or aab and releases it on `track`. A `rollout` below
+    /// `1.0` creates a staged rollout to that fraction of users.
+    pub fn publish(
+        &self,
+        package: &str,
+        artifact: &Path,
+        track: Track,
+        rollout: Option<f64>,
+        notes: &[ReleaseNotes],
+    ) -> Result<i64> {
+        if let Some(rollout) = rollout {
+            anyhow::ensure!(
+                rollout > 0.0 && rollout <= 1.0,
+                "rollout must be a fraction in (0, 1]"
+            );
+        }
+        let kind = match artifact.extension().and_then(|ext| ext.to_str()) {
+            Some("aab") => "bundles",
+            Some("apk") => "apks",
+            _ => anyhow::bail!("only apk and aab files can be published on google play"),
+        };
+        let edit: Edit = send(
+            self.client
+                .post(format!("{}/{}/edits", API, package))
+                .bearer_auth(&self.token)
+                .header("Content-Type", "application/json")
+                .body("{}"),
+        )?;
+        let edit_url = format!("{}/{}/edits/{}", API, package, edit.id);
+
+        let uploaded: Uploaded = send(
+            self.client
+                .post(format!(
+                    "{}/{}/edits/{}/{}?uploadType=media",
+                    UPLOAD_API, package, edit.id, kind
+                ))
+                .bearer_auth(&self.token)
+                .header("Content-Type", "application/octet-stream")
+                .body(std::fs::read(artifact)?),
+        )?;
+
+        let staged = rollout.filter(|rollout| *rollout < 1.0);
+        let track_release = TrackRelease {
+            track: track.to_string(),
+            releases: [Release {
+                version_codes: [uploaded.version_code.to_string()],
+                status: if staged.is_some() {
+                    "inProgress"
+                } else {
+                    "completed"
+                },
+                user_fraction: staged,
+                release_notes: notes,
+            }],
+        };
+        send::<serde_json::Value>(
+            self.client
+                .put(format!("{}/tracks/{}", edit_url, track))
+                .bearer_auth(&self.token)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_vec(&track_release)?),
+        )?;
+        send::<serde_json::Value>(
+            self.client
+                .post(format!("{}:commit", edit_url))
+                .bearer_auth(&self.token),
+        )?;
+        Ok(uploaded.version_code)
+    }
+}
+
+fn send<T: serde::de::DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let response: Response = request.send()?;
+    let status = response.status();
+    let body = response.text()?;
+    anyhow::ensure!(
+        status.is_success(),
+        "google play api request failed with {}: {}",
+        status,
+        body
+    );
+    Ok(serde_json::from_str(&body)?)
+}
@@ -0,0 +1,198 @@
+use crate::cargo::metadata::Metadata;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// CycloneDX 1.4 software bill of materials of an artifact, covering the
+/// rust crate graph and native libraries bundled alongside it.
+///
+/// No timestamp or serial number is written, so that the bom of a
+/// reproducible build is reproducible too.
+#[derive(Debug, Default)]
+pub struct Sbom {
+    component: Option<Component>,
+    components: BTreeMap<String, Component>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bom<'a> {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: BomMetadata<'a>,
+    components: Vec<&'a Component>,
+}
+
+#[derive(Debug, Serialize)]
+struct BomMetadata<'a> {
+    tools: [Tool; 1],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    component: Option<&'a Component>,
+}
+
+#[derive(Debug, Serialize)]
+struct Tool {
+    vendor: &'static str,
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purl: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<License>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<Hash>,
+}
+
+#[derive(Debug, Serialize)]
+struct License {
+    expression: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Hash {
+    alg: &'static str,
+    content: String,
+}
+
+impl Sbom {
+    /// Adds `package` as the described application and every crate linked
+    /// into it as a library component.
+    pub fn add_crate_graph(&mut self, metadata: &Metadata, package: &str) -> Result<()> {
+        let root = metadata
+            .packages
+            .iter()
+            .find(|p| p.name == package && p.source.is_none())
+            .with_context(|| format!("package `{}` not found in cargo metadata", package))?;
+        self.component = Some(Component {
+            kind: "application",
+            bom_ref: root.id.clone(),
+            name: root.name.clone(),
+            version: Some(root.version.clone()),
+            purl: None,
+            licenses: licenses(root.license.as_deref()),
+            hashes: vec![],
+        });
+        for dep in metadata.dependencies(&root.id) {
+            // Path and git dependencies have no canonical package url
+            let purl = dep
+                .source
+                .as_deref()
+                .filter(|source| source.starts_with("registry+"))
+                .map(|_| format!("pkg:cargo/{}@{}", dep.name, dep.version));
+            self.components.insert(
+                dep.id.clone(),
+                Component {
+                    kind: "library",
+                    bom_ref: dep.id.clone(),
+                    name: dep.name.clone(),
+                    version: Some(dep.version.clone()),
+                    purl,
+                    licenses: licenses(dep.license.as_deref()),
+                    hashes: vec![],
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Adds a native component bundled into the artifact, identified by the
+    /// hash of its contents.
+    pub fn add_native(&mut self, name: &str, version: Option<&str>, data: &[u8]) {
+        let hash = Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let bom_ref = format!("{}#{}", name, hash);
+        self.components.insert(
+            bom_ref.clone(),
+            Component {
+                kind: "library",
+                bom_ref,
+                name: name.to_string(),
+                version: version.map(|version| version.to_string()),
+                purl: None,
+                licenses: vec![],
+                hashes: vec![Hash {
+                    alg: "SHA-256",
+                    content: hash,
+                }],
+            },
+        );
+    }
+
+    pub fn add_native_file(&mut self, path: &Path, version: Option<&str>) -> Result<()> {
+        let data =
+            std::fs::read(path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        let name = path.file_name().unwrap().to_str().unwrap();
+        self.add_native(name, version, &data);
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let bom = Bom {
+            bom_format: "CycloneDX",
+            spec_version: "1.4",
+            version: 1,
+            metadata: BomMetadata {
+                tools: [Tool {
+                    vendor: "rust-mobile",
+                    name: "xbuild",
+                    version: env!("CARGO_PKG_VERSION"),
+                }],
+                component: self.component.as_ref(),
+            },
+            components: self.components.values().collect(),
+        };
+        let mut json = serde_json::to_string_pretty(&bom)?;
+        json.push('\n');
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Path of the bom written next to `artifact`.
+pub fn sbom_path(artifact: &Path) -> PathBuf {
+    let mut name = artifact.file_name().unwrap().to_os_string();
+    name.push(".cdx.json");
+    artifact.with_file_name(name)
+}
+
+fn licenses(expression: Option<&str>) -> Vec<License> {
+    expression
+        .map(|expression| License {
+            // Cargo accepts the deprecated `/` separator
+            expression: expression.replace('/', " OR "),
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Version of the ndk at `ndk`, read from its `source.properties` if it
+/// has one.
+pub fn ndk_version(ndk: &Path) -> Option<String> {
+    // The sysroot of a pinned ndk is nested in the ndk
+    let properties = ndk
+        .ancestors()
+        .take(6)
+        .map(|dir| dir.join("source.properties"))
+        .find(|path| path.exists())?;
+    std::fs::read_to_string(properties)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Pkg.Revision"))
+        .map(|value| value.trim_start_matches([' ', '=']).trim().to_string())
+}
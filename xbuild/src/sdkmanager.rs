@@ -0,0 +1,239 @@
+//! Installs packages from the android sdk repository like the `sdkmanager`
+//! of the command line tools. Archives are checked against the sha1 the
+//! repository lists for them.
+
+use crate::download::DownloadManager;
+use crate::Platform;
+use anyhow::{Context, Result};
+use mvn::Download;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use zip::ZipArchive;
+
+const REPOSITORY: &str = "https://dl.google.com/android/repository/";
+
+/// Entries of a package archive to extract.
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    /// Files named like this.
+    Name(&'static str),
+    /// Entries whose path contains this.
+    Folder(&'static str),
+}
+
+impl Filter {
+    fn matches(self, path: &Path) -> bool {
+        match self {
+            Self::Name(name) => path.file_name() == Some(name.as_ref()),
+            Self::Folder(folder) => path.to_str().unwrap_or_default().contains(folder),
+        }
+    }
+}
+
+/// Archive of a package for the host, with the sha1 of its download.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Archive {
+    url: String,
+    sha1: String,
+}
+
+/// Installs `package` like `platforms;android-33` and its dependencies into
+/// the android sdk, extracting the entries matching `filter`, or all of
+/// them if it's empty.
+pub fn install(downloads: &DownloadManager, package: &str, filter: &[Filter]) -> Result<()> {
+    let download_dir = downloads.env().components_dir().join("download");
+    let index = download_dir.join("repository2-1.xml");
+    downloads.download(&format!("{}repository2-1.xml", REPOSITORY), &index)?;
+    let index = std::fs::read_to_string(&index)?;
+    let doc = roxmltree::Document::parse(&index).context("invalid android sdk repository")?;
+
+    let mut packages = BTreeSet::new();
+    let mut pending = vec![package.to_string()];
+    while let Some(package) = pending.pop() {
+        if packages.insert(package.clone()) {
+            pending.extend(dependencies(&doc, &package));
+        }
+    }
+    let host = host_os()?;
+    for package in packages {
+        let archive = archive(&doc, &package, host)
+            .with_context(|| format!("`{}` not found in the android sdk repository", package))?;
+        let path = download_dir.join(archive.url.rsplit('/').next().unwrap());
+        downloads.download(&archive.url, &path)?;
+        let actual = sha1(&path)?;
+        if actual != archive.sha1 {
+            std::fs::remove_file(&path).ok();
+            anyhow::bail!(
+                "checksum mismatch for {}\n  expected sha1 {}\n  got sha1      {}\nthe download is corrupted or was tampered with",
+                archive.url,
+                archive.sha1,
+                actual
+            );
+        }
+        let dest = downloads
+            .env()
+            .android_sdk()
+            .join(package.replace(';', "/"));
+        extract(&path, &dest, filter)?;
+        std::fs::remove_file(&path).ok();
+    }
+    Ok(())
+}
+
+/// Name of the host in the `host-os` of archives.
+fn host_os() -> Result<&'static str> {
+    Ok(match Platform::host()? {
+        Platform::Linux => "linux",
+        Platform::Macos => "macosx",
+        Platform::Windows => "windows",
+        _ => unreachable!(),
+    })
+}
+
+fn remote_package<'a>(
+    doc: &'a roxmltree::Document<'a>,
+    package: &str,
+) -> Option<roxmltree::Node<'a, 'a>> {
+    doc.descendants()
+        .find(|node| node.has_tag_name("remotePackage") && node.attribute("path") == Some(package))
+}
+
+fn dependencies(doc: &roxmltree::Document, package: &str) -> Vec<String> {
+    remote_package(doc, package)
+        .into_iter()
+        .flat_map(|package| package.descendants())
+        .filter(|node| node.has_tag_name("dependency"))
+        .filter_map(|node| node.attribute("path").map(str::to_string))
+        .collect()
+}
+
+/// Archive of `package` for `host`, or the one for all hosts.
+fn archive(doc: &roxmltree::Document, package: &str, host: &str) -> Option<Archive> {
+    let child = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.has_tag_name(name))
+            .and_then(|child| child.text())
+            .map(str::to_string)
+    };
+    remote_package(doc, package)?
+        .descendants()
+        .filter(|node| node.has_tag_name("archive"))
+        .find(|archive| {
+            child(*archive, "host-os")
+                .map(|os| os == host)
+                .unwrap_or(true)
+        })
+        .and_then(|archive| {
+            let complete = archive
+                .children()
+                .find(|child| child.has_tag_name("complete"))?;
+            Some(Archive {
+                url: format!("{}{}", REPOSITORY, child(complete, "url")?),
+                sha1: child(complete, "checksum")?.to_lowercase(),
+            })
+        })
+}
+
+fn sha1(path: &Path) -> Result<String> {
+    let mut hasher = Sha1::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Extracts the archive into `dest`, replacing the top level directory of
+/// its entries.
+fn extract(archive: &Path, dest: &Path, filter: &[Filter]) -> Result<()> {
+    let mut archive = ZipArchive::new(File::open(archive)?)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = file.enclosed_name().context("invalid path in archive")?;
+        if !filter.is_empty() && !filter.iter().any(|filter| filter.matches(path)) {
+            continue;
+        }
+        let relative = path
+            .components()
+            .skip(1)
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect::<PathBuf>();
+        let out = dest.join(relative);
+        if file.is_dir() {
+            std::fs::create_dir_all(&out)?;
+            continue;
+        }
+        std::fs::create_dir_all(out.parent().unwrap())?;
+        const S_IFLNK: u32 = 0o120000;
+        let mode = file.unix_mode();
+        if mode
+            .map(|mode| mode & S_IFLNK == S_IFLNK)
+            .unwrap_or_default()
+        {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            std::fs::remove_file(&out).ok();
+            xcommon::symlink(Path::new(&target), &out)?;
+            continue;
+        }
+        std::io::copy(&mut file, &mut File::create(&out)?)?;
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&out, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX: &str = r#"<sdk:sdk-repository xmlns:sdk="http://schemas.android.com/sdk/android/repo/repository2/01">
+    <remotePackage path="build-tools;34.0.0">
+        <dependencies><dependency path="tools"/></dependencies>
+        <archives>
+            <archive>
+                <complete><size>1</size><checksum type="sha1">AAAA</checksum><url>build-tools_r34-macosx.zip</url></complete>
+                <host-os>macosx</host-os>
+            </archive>
+            <archive>
+                <complete><size>1</size><checksum type="sha1">BBBB</checksum><url>build-tools_r34-linux.zip</url></complete>
+                <host-os>linux</host-os>
+            </archive>
+        </archives>
+    </remotePackage>
+    <remotePackage path="platforms;android-34">
+        <archives>
+            <archive>
+                <complete><size>1</size><checksum type="sha1">cccc</checksum><url>platform-34-ext7_r01.zip</url></complete>
+            </archive>
+        </archives>
+    </remotePackage>
+</sdk:sdk-repository>"#;
+
+    #[test]
+    fn test_archive() {
+        let doc = roxmltree::Document::parse(INDEX).unwrap();
+        assert_eq!(
+            archive(&doc, "build-tools;34.0.0", "linux"),
+            Some(Archive {
+                url: format!("{}build-tools_r34-linux.zip", REPOSITORY),
+                sha1: "bbbb".into(),
+            })
+        );
+        assert_eq!(
+            archive(&doc, "platforms;android-34", "windows")
+                .unwrap()
+                .sha1,
+            "cccc"
+        );
+        assert_eq!(archive(&doc, "build-tools;34.0.0", "windows"), None);
+        assert_eq!(dependencies(&doc, "build-tools;34.0.0"), vec!["tools"]);
+    }
+}
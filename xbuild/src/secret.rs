@@ -0,0 +1,286 @@
+use crate::task::powershell_quote;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Reference to a password or key, so the material itself stays out of
+/// `manifest.yaml` and the shell history. Parsed from `env:NAME`,
+/// `keychain:SERVICE[/ACCOUNT]`, `file:PATH` or `command:COMMAND`, anything
+/// else is a path.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Secret {
+    Env(String),
+    Keychain {
+        service: String,
+        account: Option<String>,
+    },
+    File(PathBuf),
+    Command(String),
+}
+
+impl std::str::FromStr for Secret {
+    type Err = anyhow::Error;
+
+    fn from_str(secret: &str) -> Result<Self> {
+        let (scheme, value) = secret.split_once(':').unwrap_or(("", secret));
+        let secret = match scheme {
+            "env" => Self::Env(value.to_string()),
+            "keychain" => {
+                let (service, account) = match value.split_once('/') {
+                    Some((service, account)) => (service, Some(account.to_string())),
+                    None => (value, None),
+                };
+                Self::Keychain {
+                    service: service.to_string(),
+                    account,
+                }
+            }
+            "file" => Self::File(PathBuf::from(value)),
+            "command" => Self::Command(value.to_string()),
+            // Windows paths like `C:\key.json`
+            _ => return Ok(Self::File(PathBuf::from(secret))),
+        };
+        anyhow::ensure!(!value.is_empty(), "empty secret `{}`", secret);
+        Ok(secret)
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Env(name) => write!(f, "env:{}", name),
+            Self::Keychain {
+                service,
+                account: Some(account),
+            } => write!(f, "keychain:{}/{}", service, account),
+            Self::Keychain { service, .. } => write!(f, "keychain:{}", service),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Command(command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Secret {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let secret = String::deserialize(deserializer)?;
+        secret.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Secret {
+    /// Reads the secret, relative paths are resolved against `root`. A
+    /// trailing newline is removed.
+    pub fn resolve(&self, root: &Path) -> Result<String> {
+        let secret = match self {
+            Self::Env(name) => {
+                std::env::var(name).with_context(|| format!("`{}` is not set", name))?
+            }
+            Self::Keychain { service, account } => keychain(service, account.as_deref())?,
+            Self::File(path) => {
+                let path = root.join(path);
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?
+            }
+            Self::Command(command) => {
+                let output = crate::task::shell(command).current_dir(root).output()?;
+                anyhow::ensure!(
+                    output.status.success(),
+                    "`{}` failed: {}",
+                    command,
+                    std::str::from_utf8(&output.stderr)?.trim()
+                );
+                String::from_utf8(output.stdout)
+                    .with_context(|| format!("`{}` printed invalid utf8", command))?
+            }
+        };
+        Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// A file containing the secret, for tools that only read keys from
+    /// files. Secrets that aren't files already are written to a temporary
+    /// file readable only by the user, which is removed on drop.
+    pub fn to_file(&self, root: &Path) -> Result<SecretFile> {
+        if let Self::File(path) = self {
+            let path = root.join(path);
+            anyhow::ensure!(path.exists(), "{} doesn't exist", path.display());
+            return Ok(SecretFile { path, temp: false });
+        }
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "x-secret-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let secret = self.resolve(root)?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = SecretFile { path, temp: true };
+        std::io::Write::write_all(&mut options.open(&file.path)?, secret.as_bytes())?;
+        Ok(file)
+    }
+}
+
+/// Path of a file containing a secret, see [`Secret::to_file`].
+#[derive(Debug)]
+pub struct SecretFile {
+    path: PathBuf,
+    temp: bool,
+}
+
+impl SecretFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        if self.temp {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Reads the generic credential `$service` of the user `$account`, if not
+/// empty, from the windows credential manager.
+const CREDENTIAL_MANAGER: &str = r#"
+Add-Type -TypeDefinition @'
+using System;
+using System.Runtime.InteropServices;
+public static class XbuildCredential {
+    [StructLayout(LayoutKind.Sequential, CharSet = CharSet.Unicode)]
+    struct CREDENTIAL {
+        public int Flags;
+        public int Type;
+        public string TargetName;
+        public string Comment;
+        public System.Runtime.InteropServices.ComTypes.FILETIME LastWritten;
+        public int CredentialBlobSize;
+        public IntPtr CredentialBlob;
+        public int Persist;
+        public int AttributeCount;
+        public IntPtr Attributes;
+        public string TargetAlias;
+        public string UserName;
+    }
+    [DllImport("advapi32.dll", CharSet = CharSet.Unicode, SetLastError = true)]
+    static extern bool CredRead(string target, int type, int flags, out IntPtr credential);
+    [DllImport("advapi32.dll")]
+    static extern void CredFree(IntPtr credential);
+    public static string Read(string target, string user) {
+        IntPtr ptr;
+        // CRED_TYPE_GENERIC
+        if (!CredRead(target, 1, 0, out ptr)) return null;
+        try {
+            var cred = (CREDENTIAL)Marshal.PtrToStructure(ptr, typeof(CREDENTIAL));
+            if (user != "" && cred.UserName != user) return null;
+            return Marshal.PtrToStringUni(cred.CredentialBlob, cred.CredentialBlobSize / 2);
+        } finally {
+            CredFree(ptr);
+        }
+    }
+}
+'@
+$secret = [XbuildCredential]::Read($service, $account)
+if ($secret -eq $null) { exit 1 }
+[Console]::Out.Write($secret)
+"#;
+
+/// Looks up a password in the macos keychain, the secret service on linux
+/// or the windows credential manager, stored with
+/// `security add-generic-password -s SERVICE -a ACCOUNT`,
+/// `secret-tool store service SERVICE account ACCOUNT` or
+/// `cmdkey /generic:SERVICE /user:ACCOUNT /pass`.
+fn keychain(service: &str, account: Option<&str>) -> Result<String> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("security");
+        cmd.arg("find-generic-password").arg("-s").arg(service);
+        if let Some(account) = account {
+            cmd.arg("-a").arg(account);
+        }
+        cmd.arg("-w");
+        cmd
+    } else if cfg!(target_os = "linux") {
+        which::which("secret-tool").context("secret-tool is required for keychain secrets")?;
+        let mut cmd = Command::new("secret-tool");
+        cmd.arg("lookup").arg("service").arg(service);
+        if let Some(account) = account {
+            cmd.arg("account").arg(account);
+        }
+        cmd
+    } else if cfg!(target_os = "windows") {
+        let script = format!(
+            "$service = {}\n$account = {}\n{}",
+            powershell_quote(service),
+            powershell_quote(account.unwrap_or_default()),
+            CREDENTIAL_MANAGER
+        );
+        let mut cmd = Command::new("powershell");
+        cmd.arg("-NoProfile")
+            .arg("-NonInteractive")
+            .arg("-Command")
+            .arg(script);
+        cmd
+    } else {
+        anyhow::bail!("keychain secrets are not supported on this host, use `env:` or `command:`");
+    };
+    let output = cmd.output()?;
+    anyhow::ensure!(
+        output.status.success() && !output.stdout.is_empty(),
+        "no keychain entry for service `{}`{}",
+        service,
+        account
+            .map(|account| format!(" and account `{}`", account))
+            .unwrap_or_default()
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() -> Result<()> {
+        assert_eq!("env:X_PEM".parse::<Secret>()?, Secret::Env("X_PEM".into()));
+        assert_eq!(
+            "keychain:xbuild/release".parse::<Secret>()?,
+            Secret::Keychain {
+                service: "xbuild".into(),
+                account: Some("release".into()),
+            }
+        );
+        assert_eq!(
+            "keychain:xbuild".parse::<Secret>()?,
+            Secret::Keychain {
+                service: "xbuild".into(),
+                account: None,
+            }
+        );
+        assert_eq!(
+            "file:keys/release.pem".parse::<Secret>()?,
+            Secret::File("keys/release.pem".into())
+        );
+        assert_eq!(
+            "command:pass show xbuild:pem".parse::<Secret>()?,
+            Secret::Command("pass show xbuild:pem".into())
+        );
+        assert_eq!(
+            "keys/release.pem".parse::<Secret>()?,
+            Secret::File("keys/release.pem".into())
+        );
+        assert_eq!(
+            r"C:\keys\release.pem".parse::<Secret>()?,
+            Secret::File(r"C:\keys\release.pem".into())
+        );
+        assert!("env:".parse::<Secret>().is_err());
+        assert!("command:".parse::<Secret>().is_err());
+        for secret in ["env:X_PEM", "keychain:xbuild/release", "command:pass pem"] {
+            assert_eq!(secret.parse::<Secret>()?.to_string(), secret);
+        }
+        Ok(())
+    }
+}
@@ -1,28 +1,110 @@
+use crate::message::Message;
+use crate::{BuildEnv, MessageFormat, Timings};
 use anyhow::Result;
-use console::{style, Term};
+use console::style;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
 use std::process::Command;
-use std::time::Instant;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Progress bars of the running task and of concurrent work like downloads,
+/// drawn together on stdout.
+pub fn progress() -> &'static MultiProgress {
+    static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+    PROGRESS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout()))
+}
+
+/// Prints a line above the progress bars.
+fn print_line(line: &str) {
+    if progress().is_hidden() {
+        println!("{}", line);
+    } else {
+        progress().println(line).unwrap();
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TaskTiming {
+    task: String,
+    skipped: bool,
+    time_ms: u128,
+    stages: Vec<StageTiming>,
+}
+
+#[derive(Debug, Serialize)]
+struct StageTiming {
+    stage: String,
+    time_ms: u128,
+    /// Whether the stage reused a previous result, `None` if it isn't
+    /// cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<Cache>,
+}
+
+/// Outcome of a cached stage in the `--timings` report.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cache {
+    Hit,
+    Miss,
+}
+
+impl Cache {
+    pub fn hit(hit: bool) -> Self {
+        if hit {
+            Self::Hit
+        } else {
+            Self::Miss
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TimingsReport<'a> {
+    target: &'a str,
+    time_ms: u128,
+    cache_hits: usize,
+    cache_misses: usize,
+    tasks: &'a [TaskTiming],
+}
+
+impl TimingsReport<'_> {
+    fn stages(&self) -> impl Iterator<Item = &StageTiming> {
+        self.tasks.iter().flat_map(|task| &task.stages)
+    }
+}
 
 pub struct TaskRunner {
-    term: Term,
     num_tasks: u32,
     current_task: u32,
+    start: Instant,
     now: Instant,
     descr: String,
     verbose: bool,
     started: bool,
+    message_format: MessageFormat,
+    spinner: Option<ProgressBar>,
+    stage: Option<(String, Instant, Option<Cache>)>,
+    stages: Vec<StageTiming>,
+    timings: Vec<TaskTiming>,
 }
 
 impl TaskRunner {
-    pub fn new(num_tasks: u32, verbose: bool) -> Self {
+    pub fn new(num_tasks: u32, verbose: bool, message_format: MessageFormat) -> Self {
         Self {
-            term: Term::stdout(),
             num_tasks,
             current_task: 0,
+            start: Instant::now(),
             now: Instant::now(),
             descr: "".into(),
             verbose,
             started: false,
+            message_format,
+            spinner: None,
+            stage: None,
+            stages: vec![],
+            timings: vec![],
         }
     }
 
@@ -32,41 +114,282 @@ impl TaskRunner {
             .to_string()
     }
 
+    /// Starts a task that doesn't print anything, shown as a spinner unless
+    /// the output is verbose.
     pub fn start_task(&mut self, descr: impl Into<String>) {
+        self.begin(descr.into(), !self.verbose);
+    }
+
+    /// Starts a task that prints the output of the tools it runs, like
+    /// cargo or hooks.
+    pub fn start_verbose_task(&mut self, descr: impl Into<String>) {
+        self.begin(descr.into(), false);
+    }
+
+    fn begin(&mut self, descr: String, spinner: bool) {
         if self.started {
-            self.finish_task(true, true);
+            self.finish_task(true);
         }
         self.now = Instant::now();
-        self.descr = descr.into();
+        self.descr = descr;
         self.started = true;
-        println!("{} {}", self.task_id(), &self.descr);
+        if self.message_format == MessageFormat::Json {
+            Message::TaskStarted { task: &self.descr }.emit();
+        } else if spinner {
+            let pb = progress().add(
+                ProgressBar::new_spinner().with_style(
+                    ProgressStyle::default_spinner()
+                        .template("{spinner:.green} {prefix} {msg:.dim} [{elapsed}]")
+                        .unwrap(),
+                ),
+            );
+            pb.set_prefix(format!("{} {}", self.task_id(), self.descr));
+            pb.enable_steady_tick(Duration::from_millis(100));
+            self.spinner = Some(pb);
+        } else {
+            print_line(&format!("{} {}", self.task_id(), &self.descr));
+        }
+    }
+
+    /// Marks the start of a stage of the current task, which is shown next
+    /// to the spinner and timed separately in the `--timings` report.
+    pub fn stage(&mut self, stage: impl Into<String>) {
+        self.end_stage();
+        let stage = stage.into();
+        if let Some(spinner) = &self.spinner {
+            spinner.set_message(stage.clone());
+        } else if self.verbose && self.message_format == MessageFormat::Human {
+            print_line(&format!("  {}", style(&stage).dim()));
+        }
+        self.stage = Some((stage, Instant::now(), None));
+    }
+
+    /// Reports whether the current stage reused a previous result.
+    pub fn cache(&mut self, cache: Cache) {
+        if let Some((_, _, current)) = &mut self.stage {
+            *current = Some(cache);
+        }
+    }
+
+    fn end_stage(&mut self) {
+        if let Some((stage, start, cache)) = self.stage.take() {
+            self.stages.push(StageTiming {
+                stage,
+                time_ms: start.elapsed().as_millis(),
+                cache,
+            });
+        }
     }
 
-    fn finish_task(&mut self, skipped: bool, clear_last: bool) {
+    /// Adds a stage of the current task that was timed separately, like
+    /// the downloads or the cargo builds running concurrently.
+    pub fn record_stage(&mut self, stage: impl Into<String>, time: Duration, cache: Cache) {
+        self.stages.push(StageTiming {
+            stage: stage.into(),
+            time_ms: time.as_millis(),
+            cache: Some(cache),
+        });
+    }
+
+    /// Hides the spinner while `f` prints to the terminal.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        if let Some(spinner) = &self.spinner {
+            spinner.suspend(f)
+        } else {
+            f()
+        }
+    }
+
+    fn finish_task(&mut self, skipped: bool) {
         self.started = false;
-        if clear_last {
-            self.term.clear_last_lines(1).unwrap();
+        self.end_stage();
+        let time_ms = self.now.elapsed().as_millis();
+        self.timings.push(TaskTiming {
+            task: self.descr.clone(),
+            skipped,
+            time_ms,
+            stages: std::mem::take(&mut self.stages),
+        });
+        if self.message_format == MessageFormat::Json {
+            Message::TaskFinished {
+                task: &self.descr,
+                skipped,
+                time_ms,
+            }
+            .emit();
+            self.current_task += 1;
+            return;
+        }
+        if let Some(spinner) = self.spinner.take() {
+            spinner.finish_and_clear();
+            progress().remove(&spinner);
         }
         let status = if skipped {
             "[SKIPPED]".to_string()
         } else {
-            let time = self.now.elapsed();
-            format!("[{}ms]", time.as_millis())
+            format!("[{}ms]", time_ms)
         };
-        println!("{} {} {}", self.task_id(), &self.descr, status,);
+        print_line(&format!("{} {} {}", self.task_id(), &self.descr, status));
         self.current_task += 1;
     }
 
     pub fn end_task(&mut self) {
-        self.finish_task(false, !self.verbose);
+        self.finish_task(false);
+    }
+
+    /// Writes the durations of the finished tasks to `timings.json` or
+    /// `timings.html` in the build dir and prints a summary, if `--timings`
+    /// was passed.
+    pub fn write_timings(&mut self, env: &BuildEnv) -> Result<()> {
+        let format = if let Some(format) = env.timings() {
+            format
+        } else {
+            return Ok(());
+        };
+        if self.started {
+            self.finish_task(true);
+        }
+        let target = env
+            .target()
+            .compile_targets()
+            .map(|target| target.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (hits, misses) = cache_counts(&self.timings);
+        let report = TimingsReport {
+            target: &target,
+            time_ms: self.start.elapsed().as_millis(),
+            cache_hits: hits,
+            cache_misses: misses,
+            tasks: &self.timings,
+        };
+        let path = env.build_dir().join(format!("timings.{}", format));
+        std::fs::create_dir_all(env.build_dir())?;
+        let contents = match format {
+            Timings::Json => serde_json::to_string_pretty(&report)?,
+            Timings::Html => timings_html(&report),
+        };
+        std::fs::write(&path, contents)?;
+        if self.message_format == MessageFormat::Human {
+            print_summary(&report);
+            print_line(&format!("timings written to {}", path.display()));
+        }
+        Ok(())
     }
+}
+
+/// Number of cache hits and misses of the stages of `tasks`.
+fn cache_counts(tasks: &[TaskTiming]) -> (usize, usize) {
+    tasks
+        .iter()
+        .flat_map(|task| &task.stages)
+        .filter_map(|stage| stage.cache)
+        .fold((0, 0), |(hits, misses), cache| match cache {
+            Cache::Hit => (hits + 1, misses),
+            Cache::Miss => (hits, misses + 1),
+        })
+}
 
-    pub fn end_verbose_task(&mut self) {
-        self.finish_task(false, false);
+/// Prints the total time, the cache hits and the slowest stages.
+fn print_summary(report: &TimingsReport) {
+    print_line(&format!(
+        "{} in {:.1}s, cache hits: {}, misses: {}",
+        report.target,
+        report.time_ms as f64 / 1000.0,
+        report.cache_hits,
+        report.cache_misses
+    ));
+    let mut stages = report.stages().collect::<Vec<_>>();
+    stages.sort_by_key(|stage| std::cmp::Reverse(stage.time_ms));
+    for stage in stages.iter().take(5) {
+        let cache = match stage.cache {
+            Some(Cache::Hit) => " (cached)",
+            _ => "",
+        };
+        print_line(&format!(
+            "  {:>7.1}s {}{}",
+            stage.time_ms as f64 / 1000.0,
+            stage.stage,
+            style(cache).dim()
+        ));
     }
 }
 
-pub fn run(mut command: Command, verbose: bool) -> Result<()> {
+fn timings_html(report: &TimingsReport) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+    fn row(html: &mut String, class: &str, name: &str, time_ms: u128, total_ms: u128) {
+        let width = time_ms as f64 * 100.0 / total_ms.max(1) as f64;
+        html.push_str(&format!(
+            "<tr class=\"{}\"><td>{}</td><td>{:.1}s</td><td><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>\n",
+            class,
+            escape(name),
+            time_ms as f64 / 1000.0,
+            width
+        ));
+    }
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>x build timings</title>\n<style>\n");
+    html.push_str("body { font-family: sans-serif; margin: 2em; }\n");
+    html.push_str("table { border-collapse: collapse; width: 100%; }\n");
+    html.push_str("td { padding: 0.2em 0.5em; white-space: nowrap; }\n");
+    html.push_str("td:last-child { width: 100%; }\n");
+    html.push_str(".bar { background: #4a90d9; height: 1em; }\n");
+    html.push_str(".stage td:first-child { padding-left: 2em; color: #555; }\n");
+    html.push_str(".stage .bar { background: #9cc3ec; }\n");
+    html.push_str(".skipped { color: #999; }\n");
+    html.push_str("</style>\n</head>\n<body>\n");
+    html.push_str(&format!(
+        "<h1>x build timings</h1>\n<p>{} in {:.1}s, cache hits: {}, misses: {}</p>\n<table>\n",
+        escape(report.target),
+        report.time_ms as f64 / 1000.0,
+        report.cache_hits,
+        report.cache_misses
+    ));
+    for task in report.tasks {
+        let class = if task.skipped { "task skipped" } else { "task" };
+        row(&mut html, class, &task.task, task.time_ms, report.time_ms);
+        for stage in &task.stages {
+            let name = match stage.cache {
+                Some(Cache::Hit) => format!("{} (cache hit)", stage.stage),
+                Some(Cache::Miss) => format!("{} (cache miss)", stage.stage),
+                None => stage.stage.clone(),
+            };
+            row(&mut html, "stage", &name, stage.time_ms, report.time_ms);
+        }
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// `command` run by `sh`, or `cmd` on windows.
+pub fn shell(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
+
+/// Quotes `arg` as a powershell string literal.
+pub fn powershell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "''"))
+}
+
+/// Runs `command`, printing its output if `verbose` or it failed. With
+/// `--message-format json` the output goes to stderr, stdout only gets the
+/// json messages.
+pub fn run(mut command: Command, verbose: bool, message_format: MessageFormat) -> Result<()> {
     fn print_error(command: &Command, status: Option<i32>) {
         let program = command.get_program().to_str().unwrap();
         let args = command
@@ -79,19 +402,23 @@ pub fn run(mut command: Command, verbose: bool) -> Result<()> {
         } else {
             Default::default()
         };
-        println!("{} {} {} {}", style("[ERROR]").red(), program, args, status);
+        eprintln!("{} {} {} {}", style("[ERROR]").red(), program, args, status);
     }
     if !verbose {
         let output = command.output()?;
         if !output.status.success() {
-            print_error(&command, output.status.code());
-            let stdout = std::str::from_utf8(&output.stdout)?;
-            print!("{}", stdout);
-            let stderr = std::str::from_utf8(&output.stderr)?;
-            print!("{}", stderr);
+            // Keeps the spinners from drawing over the output
+            progress().suspend(|| {
+                print_error(&command, output.status.code());
+                eprint!("{}", String::from_utf8_lossy(&output.stdout));
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            });
             std::process::exit(1);
         }
     } else {
+        if message_format == MessageFormat::Json {
+            command.stdout(std::io::stderr());
+        }
         let status = command.status()?;
         if !status.success() {
             print_error(&command, status.code());
@@ -100,3 +427,47 @@ pub fn run(mut command: Command, verbose: bool) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_powershell_quote() {
+        assert_eq!(powershell_quote("xbuild"), "'xbuild'");
+        assert_eq!(powershell_quote("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn test_cache_counts() {
+        let mut runner = TaskRunner::new(2, true, MessageFormat::Human);
+        runner.start_task("Fetch precompiled artifacts");
+        runner.record_stage("Fetch android.jar", Duration::from_millis(5), Cache::Hit);
+        runner.record_stage("Fetch ndk", Duration::from_millis(50), Cache::Miss);
+        runner.end_task();
+        runner.start_task("Create apk");
+        runner.stage("Compile resources");
+        runner.cache(Cache::Hit);
+        runner.stage("Add native libraries");
+        runner.stage("Sign");
+        runner.cache(Cache::Miss);
+        runner.end_task();
+
+        assert_eq!(cache_counts(&runner.timings), (2, 2));
+        let stages = &runner.timings[1].stages;
+        assert_eq!(stages.len(), 3);
+        assert_eq!(stages[0].cache, Some(Cache::Hit));
+        assert_eq!(stages[1].cache, None);
+        let report = TimingsReport {
+            target: "aarch64-linux-android",
+            time_ms: 1,
+            cache_hits: 2,
+            cache_misses: 2,
+            tasks: &runner.timings,
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["tasks"][1]["stages"][0]["cache"], "hit");
+        assert!(json["tasks"][1]["stages"][1].get("cache").is_none());
+        assert!(timings_html(&report).contains("Sign (cache miss)"));
+    }
+}
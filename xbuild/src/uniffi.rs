@@ -0,0 +1,97 @@
+use crate::download::DownloadManager;
+use crate::{Arch, BuildEnv};
+use anyhow::{Context, Result};
+use mvn::{Maven, Package, Version};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+const MAVEN_CENTRAL: &str = "https://repo1.maven.org/maven2";
+/// Jna the generated kotlin bindings call the rust library with.
+pub const JNA_VERSION: &str = "5.14.0";
+
+/// Foreign language of generated bindings.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Language {
+    Kotlin,
+    Swift,
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Kotlin => write!(f, "kotlin"),
+            Self::Swift => write!(f, "swift"),
+        }
+    }
+}
+
+/// Runs `uniffi-bindgen` on the udl or, in library mode, on the compiled
+/// `library` and returns the directory of the generated bindings. Returns
+/// `None` unless `uniffi` is configured.
+pub fn generate(env: &BuildEnv, language: Language, library: &Path) -> Result<Option<PathBuf>> {
+    let config = if let Some(config) = env.config().uniffi() {
+        config
+    } else {
+        return Ok(None);
+    };
+    let out_dir = env.platform_dir().join("uniffi").join(language.to_string());
+    if out_dir.exists() {
+        std::fs::remove_dir_all(&out_dir)?;
+    }
+    std::fs::create_dir_all(&out_dir)?;
+    let bindgen = config.bindgen.as_deref().unwrap_or("uniffi-bindgen");
+    let mut args = bindgen.split_whitespace();
+    let mut cmd = Command::new(args.next().context("empty `uniffi.bindgen` command")?);
+    cmd.args(args).arg("generate");
+    if let Some(udl) = &config.udl {
+        cmd.arg(env.root_dir().join(udl))
+            .arg("--lib-file")
+            .arg(library);
+    } else {
+        cmd.arg("--library").arg(library);
+    }
+    if let Some(path) = &config.config {
+        cmd.arg("--config").arg(env.root_dir().join(path));
+    }
+    cmd.arg("--language")
+        .arg(language.to_string())
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .current_dir(env.root_dir());
+    crate::task::run(cmd, env.verbose(), env.message_format())
+        .with_context(|| format!("`{} generate` failed", bindgen))?;
+    Ok(Some(out_dir))
+}
+
+/// Jar of jna, downloaded from maven central.
+pub fn jna(env: &BuildEnv) -> Result<PathBuf> {
+    let manager = DownloadManager::new(env)?;
+    let mut maven = Maven::new(env.cache_dir().join("maven"), &manager)?;
+    maven.add_repository(MAVEN_CENTRAL);
+    maven.package(
+        &Package::new("net.java.dev.jna", "jna"),
+        &Version::from_str(JNA_VERSION)?,
+    )
+}
+
+/// Extracts the `libjnidispatch.so` jna loads at runtime from the jna jar.
+pub fn jnidispatch(env: &BuildEnv, arch: Arch) -> Result<PathBuf> {
+    let platform = match arch {
+        Arch::Arm => "android-arm",
+        Arch::Arm64 => "android-aarch64",
+        Arch::X64 => "android-x86-64",
+        Arch::Wasm32 => anyhow::bail!("jna doesn't support wasm"),
+    };
+    let lib_dir = env.arch_dir(arch).join("jna");
+    let lib = lib_dir.join("libjnidispatch.so");
+    if !lib.exists() {
+        let so = xcommon::extract_zip_file(
+            &jna(env)?,
+            &format!("com/sun/jna/{}/libjnidispatch.so", platform),
+        )?;
+        std::fs::create_dir_all(&lib_dir)?;
+        std::fs::write(&lib, so)?;
+    }
+    Ok(lib)
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use xcommon::{Scaler, ScalerOpts};
+
+/// A static site loading a `wasm-bindgen` generated module.
+pub struct WebSite {
+    dir: PathBuf,
+    name: String,
+}
+
+impl WebSite {
+    pub fn new(dir: PathBuf, name: String) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, name })
+    }
+
+    /// Generates the js bindings for `wasm` with the `wasm-bindgen` cli.
+    pub fn add_wasm(&self, wasm: &Path) -> Result<()> {
+        let wasm_bindgen = which::which("wasm-bindgen").map_err(|_| {
+            anyhow::anyhow!(
+                "wasm-bindgen not found, install it with `cargo install wasm-bindgen-cli`"
+            )
+        })?;
+        let status = Command::new(wasm_bindgen)
+            .arg("--target")
+            .arg("web")
+            .arg("--no-typescript")
+            .arg("--out-dir")
+            .arg(&self.dir)
+            .arg("--out-name")
+            .arg(&self.name)
+            .arg(wasm)
+            .status()?;
+        anyhow::ensure!(status.success(), "wasm-bindgen failed");
+        Ok(())
+    }
+
+    pub fn add_icon(&self, path: &Path, cache: &Path) -> Result<()> {
+        let scaler = Scaler::open(path)?.with_cache(cache);
+        let mut f = std::fs::File::create(self.dir.join("favicon.png"))?;
+        scaler.write(&mut f, ScalerOpts::new(32))?;
+        Ok(())
+    }
+
+    pub fn add_index_html(&self, template: Option<&Path>, title: &str) -> Result<()> {
+        let index = if let Some(template) = template {
+            std::fs::read_to_string(template)?
+        } else {
+            format!(
+                r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{title}</title>
+    <link rel="icon" href="favicon.png">
+  </head>
+  <body>
+    <script type="module">
+      import init from "./{name}.js";
+      init();
+    </script>
+  </body>
+</html>
+"#,
+                title = title,
+                name = self.name,
+            )
+        };
+        std::fs::write(self.dir.join("index.html"), index)?;
+        Ok(())
+    }
+}
+
+/// Serves the files in `dir` on `localhost:port` until interrupted.
+pub fn serve(dir: &Path, port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("serving {} on http://localhost:{}", dir.display(), port);
+    for stream in listener.incoming() {
+        if let Err(err) = handle(dir, stream?) {
+            log::warn!("{}", err);
+        }
+    }
+    Ok(())
+}
+
+fn handle(dir: &Path, mut stream: TcpStream) -> Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap().trim_start_matches('/');
+    let path = if path.is_empty() { "index.html" } else { path };
+    let path = Path::new(path);
+    let file = dir.join(path);
+    let safe = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !safe || !file.is_file() {
+        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")?;
+        return Ok(());
+    }
+    let body = std::fs::read(&file)?;
+    let mime = match file.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html",
+        Some("js") => "text/javascript",
+        Some("wasm") => "application/wasm",
+        Some("png") => "image/png",
+        Some("css") => "text/css",
+        _ => "application/octet-stream",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        mime,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
@@ -1,3 +1,3 @@
-package com.example.template
+package com.example.{{crate_name}}
 
 class MainActivity : TauriActivity()
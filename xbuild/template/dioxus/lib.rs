@@ -6,10 +6,10 @@ pub extern "C" fn start_app() {
     android_logger::init_once(
         android_logger::Config::default()
             .with_min_level(log::Level::Trace)
-            .with_tag("template"),
+            .with_tag("{{crate_name}}"),
     );
 
-    dioxus_desktop::wry::android_binding!(com_example, template, _start_app, dioxus_desktop::wry);
+    dioxus_desktop::wry::android_binding!(com_example, {{crate_name}}, _start_app, dioxus_desktop::wry);
 }
 
 #[cfg(target_os = "android")]
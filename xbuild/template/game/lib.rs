@@ -0,0 +1,34 @@
+use bevy::prelude::*;
+
+#[bevy_main]
+pub fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                title: "{{name}}".into(),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_startup_system(setup)
+        .add_system(rotate)
+        .run();
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn(SpriteBundle {
+        sprite: Sprite {
+            color: Color::rgb(0.87, 0.65, 0.52),
+            custom_size: Some(Vec2::splat(128.0)),
+            ..default()
+        },
+        ..default()
+    });
+}
+
+fn rotate(time: Res<Time>, mut sprites: Query<&mut Transform, With<Sprite>>) {
+    for mut transform in &mut sprites {
+        transform.rotate_z(time.delta_seconds());
+    }
+}
@@ -0,0 +1,3 @@
+fn main() {
+    {{crate_name}}::main();
+}
@@ -0,0 +1,18 @@
+//! C abi of the library, for loading it as a plugin from other languages.
+
+use std::ffi::c_char;
+
+/// Version of the library as a nul terminated string.
+#[no_mangle]
+pub extern "C" fn {{crate_name}}_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[no_mangle]
+pub extern "C" fn {{crate_name}}_add(a: i32, b: i32) -> i32 {
+    add(a, b)
+}
+
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
@@ -0,0 +1,16 @@
+use std::time::Duration;
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    log::info!("{{name}} {} started", env!("CARGO_PKG_VERSION"));
+    loop {
+        tick()?;
+        std::thread::sleep(Duration::from_secs(60));
+    }
+}
+
+/// Runs the periodic work of the service.
+fn tick() -> anyhow::Result<()> {
+    log::info!("tick");
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopBuilder};
+use winit::window::{Window, WindowBuilder};
+
+#[cfg(target_os = "android")]
+#[no_mangle]
+fn android_main(app: winit::platform::android::activity::AndroidApp) {
+    use winit::platform::android::EventLoopBuilderExtAndroid;
+
+    android_logger::init_once(
+        android_logger::Config::default()
+            .with_min_level(log::Level::Info)
+            .with_tag("{{crate_name}}"),
+    );
+    run(EventLoopBuilder::new().with_android_app(app).build());
+}
+
+#[cfg(not(target_os = "android"))]
+pub fn main() {
+    env_logger::init();
+    run(EventLoopBuilder::new().build());
+}
+
+fn run(event_loop: EventLoop<()>) {
+    // Mobile platforms destroy the window when the app is suspended
+    let mut window: Option<Window> = None;
+    event_loop.run(move |event, event_loop, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match event {
+            Event::Resumed => {
+                let new_window = WindowBuilder::new()
+                    .with_title("{{name}}")
+                    .build(event_loop)
+                    .unwrap();
+                log::info!("window created {:?}", new_window.inner_size());
+                window = Some(new_window);
+            }
+            Event::Suspended => window = None,
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => control_flow.set_exit(),
+            Event::RedrawRequested(_) => {
+                if let Some(window) = &window {
+                    // draw the frame here
+                    log::trace!("redraw {:?}", window.inner_size());
+                }
+            }
+            _ => {}
+        }
+    });
+}
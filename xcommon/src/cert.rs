@@ -0,0 +1,364 @@
+use crate::{Signer, SigningKey};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Timelike, Utc};
+use rand::rngs::OsRng;
+use rasn::prelude::*;
+use rasn_pkix::{
+    AlgorithmIdentifier, AttributeTypeAndValue, BasicConstraints, Certificate, Extension, Name,
+    SubjectPublicKeyInfo, TbsCertificate, Time, Validity, Version,
+};
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+
+const COMMON_NAME_OBJID: ConstOid = ConstOid(&[2, 5, 4, 3]);
+const COUNTRY_OBJID: ConstOid = ConstOid(&[2, 5, 4, 6]);
+const LOCALITY_OBJID: ConstOid = ConstOid(&[2, 5, 4, 7]);
+const STATE_OBJID: ConstOid = ConstOid(&[2, 5, 4, 8]);
+const ORGANIZATION_OBJID: ConstOid = ConstOid(&[2, 5, 4, 10]);
+const ORGANIZATIONAL_UNIT_OBJID: ConstOid = ConstOid(&[2, 5, 4, 11]);
+const KEY_USAGE_OBJID: ConstOid = ConstOid(&[2, 5, 29, 15]);
+const BASIC_CONSTRAINTS_OBJID: ConstOid = ConstOid(&[2, 5, 29, 19]);
+const EXT_KEY_USAGE_OBJID: ConstOid = ConstOid(&[2, 5, 29, 37]);
+const CODE_SIGNING_OBJID: ConstOid = ConstOid(&[1, 3, 6, 1, 5, 5, 7, 3, 3]);
+const SHA256_WITH_RSA_OBJID: ConstOid = ConstOid(&[1, 2, 840, 113549, 1, 1, 11]);
+const ECDSA_WITH_SHA256_OBJID: ConstOid = ConstOid(&[1, 2, 840, 10045, 4, 3, 2]);
+const ED25519_OBJID: ConstOid = ConstOid(&[1, 3, 101, 112]);
+
+/// Key type of a generated certificate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyAlgorithm {
+    Rsa { bits: usize },
+    EcdsaP256,
+    Ed25519,
+}
+
+impl Default for KeyAlgorithm {
+    fn default() -> Self {
+        Self::Rsa { bits: 2048 }
+    }
+}
+
+/// Generates a new key and a self signed certificate for it.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let signer = xcommon::CertificateBuilder::new("Example")
+///     .organization("Example Inc.")
+///     .country("US")
+///     .validity_days(365)
+///     .code_signing(true)
+///     .build()?;
+/// std::fs::write("dev.pem", signer.to_pem()?)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CertificateBuilder {
+    subject: Vec<(ConstOid, String)>,
+    validity_days: i64,
+    key_algorithm: KeyAlgorithm,
+    code_signing: bool,
+    extensions: Vec<Extension>,
+}
+
+impl CertificateBuilder {
+    pub fn new(common_name: &str) -> Self {
+        Self {
+            subject: vec![(COMMON_NAME_OBJID, common_name.to_string())],
+            validity_days: 3650,
+            key_algorithm: KeyAlgorithm::default(),
+            code_signing: false,
+            extensions: vec![],
+        }
+    }
+
+    /// Two letter ISO 3166 country code.
+    pub fn country(mut self, country: &str) -> Self {
+        self.subject.push((COUNTRY_OBJID, country.to_string()));
+        self
+    }
+
+    pub fn state(mut self, state: &str) -> Self {
+        self.subject.push((STATE_OBJID, state.to_string()));
+        self
+    }
+
+    pub fn locality(mut self, locality: &str) -> Self {
+        self.subject.push((LOCALITY_OBJID, locality.to_string()));
+        self
+    }
+
+    pub fn organization(mut self, organization: &str) -> Self {
+        self.subject
+            .push((ORGANIZATION_OBJID, organization.to_string()));
+        self
+    }
+
+    pub fn organizational_unit(mut self, unit: &str) -> Self {
+        self.subject
+            .push((ORGANIZATIONAL_UNIT_OBJID, unit.to_string()));
+        self
+    }
+
+    /// Number of days the certificate is valid for, starting now.
+    pub fn validity_days(mut self, days: u32) -> Self {
+        self.validity_days = days as i64;
+        self
+    }
+
+    pub fn key_algorithm(mut self, key_algorithm: KeyAlgorithm) -> Self {
+        self.key_algorithm = key_algorithm;
+        self
+    }
+
+    /// Adds the code signing extended key usage, required for signing msix
+    /// packages.
+    pub fn code_signing(mut self, code_signing: bool) -> Self {
+        self.code_signing = code_signing;
+        self
+    }
+
+    /// Adds a custom extension.
+    pub fn extension(mut self, extension: Extension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+
+    pub fn build(self) -> Result<Signer> {
+        // The der encoder of rasn drops trailing zero bytes of bit strings,
+        // which corrupts keys and signatures ending in one. Regenerate until
+        // the certificate survives encoding, which fails with a chance of
+        // about 1/128 per attempt.
+        const ATTEMPTS: usize = 16;
+        for _ in 0..ATTEMPTS {
+            let signer = self.generate()?;
+            let cert: Certificate = rasn::der::decode(&der(signer.cert())?)
+                .map_err(|err| anyhow::anyhow!("{}", err))?;
+            if &cert == signer.cert() {
+                return Ok(signer);
+            }
+        }
+        anyhow::bail!("generated certificates failed to encode {} times", ATTEMPTS);
+    }
+
+    fn generate(&self) -> Result<Signer> {
+        let key = match self.key_algorithm {
+            KeyAlgorithm::Rsa { bits } => {
+                anyhow::ensure!(bits >= 2048, "rsa keys need at least 2048 bits");
+                SigningKey::Rsa(RsaPrivateKey::new(&mut OsRng, bits)?)
+            }
+            KeyAlgorithm::EcdsaP256 => {
+                SigningKey::EcdsaP256(p256::ecdsa::SigningKey::random(&mut OsRng))
+            }
+            KeyAlgorithm::Ed25519 => {
+                SigningKey::Ed25519(ed25519_dalek::SigningKey::generate(&mut OsRng))
+            }
+        };
+        let signature_algorithm = match &key {
+            SigningKey::Rsa(_) => AlgorithmIdentifier {
+                algorithm: SHA256_WITH_RSA_OBJID.into(),
+                parameters: Some(Any::new(vec![5, 0])),
+            },
+            SigningKey::EcdsaP256(_) => AlgorithmIdentifier {
+                algorithm: ECDSA_WITH_SHA256_OBJID.into(),
+                parameters: None,
+            },
+            SigningKey::Ed25519(_) => AlgorithmIdentifier {
+                algorithm: ED25519_OBJID.into(),
+                parameters: None,
+            },
+        };
+
+        let mut name = SequenceOf::default();
+        for (oid, value) in &self.subject {
+            let value = if *oid == COUNTRY_OBJID {
+                anyhow::ensure!(value.len() == 2, "expected a two letter country code");
+                rasn::der::encode(&PrintableString::new(value.clone()))
+            } else {
+                rasn::der::encode(value)
+            }
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+            let mut rdn = SetOf::default();
+            rdn.insert(AttributeTypeAndValue {
+                r#type: (*oid).into(),
+                value: Any::new(value),
+            });
+            name.push(rdn);
+        }
+        let name = Name::RdnSequence(name);
+
+        let mut extensions = vec![
+            Extension {
+                extn_id: BASIC_CONSTRAINTS_OBJID.into(),
+                critical: true,
+                extn_value: der(&BasicConstraints {
+                    ca: false,
+                    path_len_constraint: None,
+                })?
+                .into(),
+            },
+            Extension {
+                extn_id: KEY_USAGE_OBJID.into(),
+                critical: true,
+                // digitalSignature
+                extn_value: der(&BitString::from_element(0x80))?.into(),
+            },
+        ];
+        if self.code_signing {
+            let usages: SequenceOf<ObjectIdentifier> = vec![CODE_SIGNING_OBJID.into()];
+            extensions.push(Extension {
+                extn_id: EXT_KEY_USAGE_OBJID.into(),
+                critical: false,
+                extn_value: der(&usages)?.into(),
+            });
+        }
+        extensions.extend(self.extensions.iter().cloned());
+
+        let not_before = Utc::now().with_nanosecond(0).unwrap();
+        let not_after = not_before + Duration::days(self.validity_days);
+        let mut signer = Signer {
+            key,
+            cert: Certificate {
+                tbs_certificate: TbsCertificate {
+                    version: Version::V3,
+                    serial_number: (rand::random::<u64>() >> 1).into(),
+                    signature: signature_algorithm.clone(),
+                    issuer: name.clone(),
+                    validity: Validity {
+                        not_before: to_time(not_before),
+                        not_after: to_time(not_after),
+                    },
+                    subject: name,
+                    subject_public_key_info: SubjectPublicKeyInfo {
+                        algorithm: signature_algorithm.clone(),
+                        subject_public_key: Default::default(),
+                    },
+                    issuer_unique_id: None,
+                    subject_unique_id: None,
+                    extensions: Some(extensions),
+                },
+                signature_algorithm,
+                signature_value: Default::default(),
+            },
+        };
+        signer.cert.tbs_certificate.subject_public_key_info =
+            rasn::der::decode(&signer.public_key_der()?)
+                .map_err(|err| anyhow::anyhow!("{}", err))?;
+        let tbs = der(&signer.cert.tbs_certificate)?;
+        signer.cert.signature_value = BitString::from_vec(signer.sign(&tbs));
+        Ok(signer)
+    }
+}
+
+/// Subject, expiry and fingerprint of a certificate, used to tell signing
+/// identities apart.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CertificateInfo {
+    /// Common name of the subject, empty if it has none.
+    pub subject: String,
+    pub not_after: DateTime<Utc>,
+    /// Colon separated uppercase hex SHA-256 of the DER certificate, the
+    /// format printed by keytool and apksigner.
+    pub sha256: String,
+}
+
+impl CertificateInfo {
+    pub fn new(cert: &Certificate) -> Result<Self> {
+        Ok(Self::with_der(cert, &der(cert)?))
+    }
+
+    pub fn from_der(bytes: &[u8]) -> Result<Self> {
+        let cert =
+            rasn::der::decode::<Certificate>(bytes).map_err(|err| anyhow::anyhow!("{}", err))?;
+        Ok(Self::with_der(&cert, bytes))
+    }
+
+    /// All certificates in a PEM file.
+    pub fn from_pem(pem: &str) -> Result<Vec<Self>> {
+        pem::parse_many(pem)?
+            .iter()
+            .filter(|pem| pem.tag == "CERTIFICATE")
+            .map(|pem| Self::from_der(&pem.contents))
+            .collect()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.not_after < Utc::now()
+    }
+
+    fn with_der(cert: &Certificate, bytes: &[u8]) -> Self {
+        let mut subject = String::new();
+        let Name::RdnSequence(rdns) = &cert.tbs_certificate.subject;
+        for attr in rdns.iter().flatten() {
+            if COMMON_NAME_OBJID == attr.r#type {
+                let value = attr.value.as_bytes();
+                if let Ok(name) = rasn::der::decode::<String>(value) {
+                    subject = name;
+                } else if let Ok(name) = rasn::der::decode::<PrintableString>(value) {
+                    subject = name.value;
+                }
+            }
+        }
+        let not_after = match &cert.tbs_certificate.validity.not_after {
+            Time::Utc(time) => *time,
+            Time::General(time) => time.with_timezone(&Utc),
+        };
+        let sha256 = Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(":");
+        Self {
+            subject,
+            not_after,
+            sha256,
+        }
+    }
+}
+
+fn der<T: Encode>(value: &T) -> Result<Vec<u8>> {
+    rasn::der::encode(value).map_err(|err| anyhow::anyhow!("{}", err))
+}
+
+/// Dates until 2049 are encoded as utc time, later ones as generalized time
+/// (RFC 5280 4.1.2.5).
+fn to_time(time: chrono::DateTime<Utc>) -> Time {
+    use chrono::Datelike;
+    if time.year() < 2050 {
+        Time::Utc(time)
+    } else {
+        Time::General(time.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignatureAlgorithm;
+
+    #[test]
+    fn generate_certificate() {
+        for (key_algorithm, algorithm) in [
+            (KeyAlgorithm::default(), SignatureAlgorithm::RsaPkcs1Sha256),
+            (KeyAlgorithm::EcdsaP256, SignatureAlgorithm::EcdsaP256Sha256),
+            (KeyAlgorithm::Ed25519, SignatureAlgorithm::Ed25519),
+        ] {
+            let signer = CertificateBuilder::new("xbuild")
+                .organization("rust-mobile")
+                .country("US")
+                .key_algorithm(key_algorithm)
+                .code_signing(true)
+                .build()
+                .unwrap();
+            assert_eq!(signer.algorithm(), algorithm);
+            let signer2 = Signer::new(&signer.to_pem().unwrap()).unwrap();
+            assert_eq!(signer.cert(), signer2.cert());
+            let info = CertificateInfo::new(signer.cert()).unwrap();
+            assert_eq!(info.subject, "xbuild");
+            assert_eq!(
+                CertificateInfo::from_pem(&signer.to_pem().unwrap()).unwrap(),
+                [info]
+            );
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Size svg icons are rendered at.
+const SVG_SIZE: u32 = 1024;
+/// Part of an android adaptive icon layer that's visible under every mask,
+/// 66dp of 108dp.
+const ADAPTIVE_SAFE_ZONE: f32 = 66.0 / 108.0;
+
+/// App icon of every platform, rendered once from a png or svg source.
+///
+/// The [`Icon::path`] png has the background and padding applied and is
+/// passed to the packagers, which scale it with [`crate::Scaler`]. Android
+/// adaptive icons use the separate [`Icon::foreground`] and
+/// [`Icon::background`] layers, and the [`Icon::monochrome`] layer of
+/// themed icons if one is set.
+#[derive(Clone, Debug)]
+pub struct Icon {
+    path: PathBuf,
+    foreground: PathBuf,
+    background: PathBuf,
+    monochrome: Option<PathBuf>,
+    padding: f32,
+}
+
+impl Icon {
+    /// Renders `source` into `cache`, keyed by its content and the options.
+    /// `background` is a `#rrggbb` or `#rrggbbaa` color, `padding` the part
+    /// of the size left empty around the icon.
+    pub fn render(
+        source: &Path,
+        background: Option<&str>,
+        padding: f32,
+        cache: &Path,
+    ) -> Result<Self> {
+        anyhow::ensure!(
+            (0.0..1.0).contains(&padding),
+            "icon padding {} is not between 0 and 1",
+            padding
+        );
+        let color = background.map(parse_color).transpose()?.map(Rgba);
+        let hash = hash(source, &format!("{:?}{}", color, padding))?;
+        let path = |layer: &str| cache.join(format!("{}-{}.png", hash, layer));
+        let icon = Self {
+            path: path("icon"),
+            foreground: path("foreground"),
+            background: path("background"),
+            monochrome: None,
+            padding,
+        };
+        if icon.path.exists() && icon.foreground.exists() && icon.background.exists() {
+            return Ok(icon);
+        }
+
+        std::fs::create_dir_all(cache)?;
+        let img = open_square(source, cache)?;
+        let size = img.width();
+        let transparent = Rgba([0, 0, 0, 0]);
+        let color = color.unwrap_or(transparent);
+        layer(&img, size, color, 1.0 - padding).save(&icon.path)?;
+        layer(
+            &img,
+            size,
+            transparent,
+            ADAPTIVE_SAFE_ZONE * (1.0 - padding),
+        )
+        .save(&icon.foreground)?;
+        // Launchers draw legacy icons on white
+        let background = if color == transparent {
+            Rgba([255, 255, 255, 255])
+        } else {
+            color
+        };
+        RgbaImage::from_pixel(size, size, background).save(&icon.background)?;
+        Ok(icon)
+    }
+
+    /// Renders `source`, a silhouette of the icon, into the monochrome layer
+    /// android 13 tints for themed icons. It uses the padding of the icon.
+    pub fn with_monochrome(mut self, source: &Path, cache: &Path) -> Result<Self> {
+        let hash = hash(source, &self.padding.to_string())?;
+        let monochrome = cache.join(format!("{}-monochrome.png", hash));
+        if !monochrome.exists() {
+            std::fs::create_dir_all(cache)?;
+            let img = open_square(source, cache)?;
+            layer(
+                &img,
+                img.width(),
+                Rgba([0, 0, 0, 0]),
+                ADAPTIVE_SAFE_ZONE * (1.0 - self.padding),
+            )
+            .save(&monochrome)?;
+        }
+        self.monochrome = Some(monochrome);
+        Ok(self)
+    }
+
+    /// Icon with the background and padding applied.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Icon within the safe zone of android adaptive icons on a transparent
+    /// background.
+    pub fn foreground(&self) -> &Path {
+        &self.foreground
+    }
+
+    /// Monochrome layer of android adaptive icons, see
+    /// [`Icon::with_monochrome`].
+    pub fn monochrome(&self) -> Option<&Path> {
+        self.monochrome.as_deref()
+    }
+
+    /// Background layer of android adaptive icons, white if no background
+    /// color is set.
+    pub fn background(&self) -> &Path {
+        &self.background
+    }
+}
+
+/// Screen shown until the first frame is drawn, an optional image centered
+/// on a background color with variants for dark mode.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Splash {
+    /// Background color as rgba
+    pub background: [u8; 4],
+    /// Square png of at least 512x512 px
+    pub image: Option<PathBuf>,
+    pub dark_background: Option<[u8; 4]>,
+    pub dark_image: Option<PathBuf>,
+}
+
+/// Png of `source`, svg files are rendered into `cache`.
+pub fn rasterize(source: &Path, cache: &Path) -> Result<PathBuf> {
+    if source.extension() != Some("svg".as_ref()) {
+        return Ok(source.to_path_buf());
+    }
+    let hash = Sha256::digest(
+        std::fs::read(source).with_context(|| format!("failed to read {}", source.display()))?,
+    )
+    .iter()
+    .take(16)
+    .map(|b| format!("{:02x}", b))
+    .collect::<String>();
+    let png = cache.join(format!("{}-svg.png", hash));
+    if !png.exists() {
+        std::fs::create_dir_all(cache)?;
+        render_svg(source, &png, SVG_SIZE)?;
+    }
+    Ok(png)
+}
+
+/// Hex encoded hash of the content of `source` and `options`.
+fn hash(source: &Path, options: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(
+        std::fs::read(source)
+            .with_context(|| format!("failed to read icon {}", source.display()))?,
+    );
+    hasher.update(options);
+    Ok(hasher
+        .finalize()
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Decodes the square icon `source` of at least 512x512 px.
+fn open_square(source: &Path, cache: &Path) -> Result<DynamicImage> {
+    let img = ImageReader::open(rasterize(source, cache)?)?.decode()?;
+    let (width, height) = img.dimensions();
+    anyhow::ensure!(
+        width == height,
+        "expected a square icon, {} is {}x{}",
+        source.display(),
+        width,
+        height
+    );
+    anyhow::ensure!(width >= 512, "expected icon of at least 512x512 px");
+    Ok(img)
+}
+
+/// `img` scaled to `scale` of `size` and centered on a `size` square of
+/// `color`.
+fn layer(img: &DynamicImage, size: u32, color: Rgba<u8>, scale: f32) -> RgbaImage {
+    let scaled = (size as f32 * scale) as u32;
+    let resized = img.resize(scaled, scaled, FilterType::Lanczos3);
+    let mut canvas = RgbaImage::from_pixel(size, size, color);
+    let offset = ((size - scaled) / 2) as i64;
+    image::imageops::overlay(&mut canvas, &resized, offset, offset);
+    canvas
+}
+
+/// Parses a `#rrggbb` or `#rrggbbaa` color into rgba.
+pub fn parse_color(color: &str) -> Result<[u8; 4]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    anyhow::ensure!(
+        (hex.len() == 6 || hex.len() == 8) && hex.is_ascii(),
+        "expected a `#rrggbb` color, found `{}`",
+        color
+    );
+    let mut rgba = [255; 4];
+    for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .with_context(|| format!("expected a `#rrggbb` color, found `{}`", color))?;
+    }
+    Ok(rgba)
+}
+
+/// Renders `svg` with the first of `resvg`, `rsvg-convert` or `inkscape`
+/// that is installed.
+fn render_svg(svg: &Path, png: &Path, size: u32) -> Result<()> {
+    let size = size.to_string();
+    let mut resvg = Command::new("resvg");
+    resvg
+        .arg("-w")
+        .arg(&size)
+        .arg("-h")
+        .arg(&size)
+        .arg(svg)
+        .arg(png);
+    let mut rsvg = Command::new("rsvg-convert");
+    rsvg.arg("-w")
+        .arg(&size)
+        .arg("-h")
+        .arg(&size)
+        .arg("-o")
+        .arg(png)
+        .arg(svg);
+    let mut inkscape = Command::new("inkscape");
+    inkscape
+        .arg(svg)
+        .arg("-w")
+        .arg(&size)
+        .arg("-h")
+        .arg(&size)
+        .arg("-o")
+        .arg(png);
+    for mut cmd in [resvg, rsvg, inkscape] {
+        match cmd.output() {
+            Ok(output) => {
+                anyhow::ensure!(
+                    output.status.success(),
+                    "failed to render {}: {}",
+                    svg.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                return Ok(());
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    anyhow::bail!("rendering svg icons needs resvg, rsvg-convert or inkscape");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() -> Result<()> {
+        assert_eq!(parse_color("#ff8000")?, [255, 128, 0, 255]);
+        assert_eq!(parse_color("00000080")?, [0, 0, 0, 128]);
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("#gg0000").is_err());
+        Ok(())
+    }
+}
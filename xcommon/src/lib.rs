@@ -1,64 +1,109 @@
+mod cert;
+mod icon;
 pub mod llvm;
 
 use anyhow::{Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
+use ed25519_dalek::pkcs8::DecodePrivateKey as _;
 use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
 use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbaImage};
-use rsa::pkcs8::DecodePrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey};
 use rsa::{PaddingScheme, RsaPrivateKey, RsaPublicKey};
 use sha2::{Digest, Sha256};
+use std::cell::OnceCell;
 use std::fs::{File, OpenOptions};
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use zip::write::FileOptions;
-use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
 
+pub use cert::{CertificateBuilder, CertificateInfo, KeyAlgorithm};
+pub use icon::{parse_color, rasterize, Icon, Splash};
 pub use rasn_pkix::Certificate;
 pub use zip::read::ZipFile;
 
+/// Scales a square icon to the sizes required by the different platforms.
+///
+/// The source image is only decoded when a scaled variant isn't found in the
+/// cache set with [`Scaler::with_cache`].
 pub struct Scaler {
-    img: DynamicImage,
+    path: PathBuf,
+    hash: String,
+    optimize: bool,
+    cache: Option<PathBuf>,
+    img: OnceCell<DynamicImage>,
 }
 
 impl Scaler {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let img = ImageReader::open(path)?.decode()?;
-        let (width, height) = img.dimensions();
+        let path = path.as_ref();
+        let (width, height) = ImageReader::open(path)?.into_dimensions()?;
         anyhow::ensure!(width == height, "expected width == height");
         anyhow::ensure!(width >= 512, "expected icon of at least 512x512 px");
-        Ok(Self { img })
+        let hash = Sha256::digest(std::fs::read(path)?)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            hash,
+            optimize: false,
+            cache: None,
+            img: OnceCell::new(),
+        })
+    }
+
+    /// Caches scaled variants in `dir`, keyed by the source content hash and
+    /// the output size and format.
+    pub fn with_cache(mut self, dir: &Path) -> Self {
+        self.cache = Some(dir.to_path_buf());
+        self
     }
 
+    /// Reduces the color type of the image to grayscale and/or opaque if
+    /// possible.
     pub fn optimize(&mut self) {
-        let mut is_grayscale = true;
-        let mut is_opaque = true;
-        let (width, height) = self.img.dimensions();
-        for x in 0..width {
-            for y in 0..height {
-                let pixel = self.img.get_pixel(x, y);
-                if pixel[0] != pixel[1] || pixel[1] != pixel[2] {
-                    is_grayscale = false;
-                }
-                if pixel[3] != 255 {
-                    is_opaque = false;
-                }
-                if !is_grayscale && !is_opaque {
-                    break;
+        self.optimize = true;
+        self.img = OnceCell::new();
+    }
+
+    fn image(&self) -> Result<&DynamicImage> {
+        if let Some(img) = self.img.get() {
+            return Ok(img);
+        }
+        let mut img = ImageReader::open(&self.path)?.decode()?;
+        if self.optimize {
+            let mut is_grayscale = true;
+            let mut is_opaque = true;
+            let (width, height) = img.dimensions();
+            for x in 0..width {
+                for y in 0..height {
+                    let pixel = img.get_pixel(x, y);
+                    if pixel[0] != pixel[1] || pixel[1] != pixel[2] {
+                        is_grayscale = false;
+                    }
+                    if pixel[3] != 255 {
+                        is_opaque = false;
+                    }
+                    if !is_grayscale && !is_opaque {
+                        break;
+                    }
                 }
             }
+            match (is_grayscale, is_opaque) {
+                (true, true) => img = DynamicImage::ImageLuma8(img.to_luma8()),
+                (true, false) => img = DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+                (false, true) => img = DynamicImage::ImageRgb8(img.to_rgb8()),
+                (false, false) => {}
+            }
         }
-        match (is_grayscale, is_opaque) {
-            (true, true) => self.img = DynamicImage::ImageLuma8(self.img.to_luma8()),
-            (true, false) => self.img = DynamicImage::ImageLumaA8(self.img.to_luma_alpha8()),
-            (false, true) => self.img = DynamicImage::ImageRgb8(self.img.to_rgb8()),
-            (false, false) => {}
-        }
+        Ok(self.img.get_or_init(|| img))
     }
 
-    pub fn write<W: Write + Seek>(&self, w: &mut W, opts: ScalerOpts) -> Result<()> {
+    fn encode<W: Write + Seek>(&self, w: &mut W, opts: ScalerOpts) -> Result<()> {
         let resized = self
-            .img
+            .image()?
             .resize(opts.scaled_size, opts.scaled_size, FilterType::Nearest);
         if opts.scaled_size == opts.target_width && opts.scaled_size == opts.target_height {
             resized.write_to(w, ImageOutputFormat::Png)?;
@@ -72,6 +117,48 @@ impl Scaler {
         Ok(())
     }
 
+    /// Path of the scaled image in the cache.
+    fn entry(&self, cache: &Path, opts: ScalerOpts) -> PathBuf {
+        cache.join(format!(
+            "{}-{}x{}-{}{}.png",
+            self.hash,
+            opts.target_width,
+            opts.target_height,
+            opts.scaled_size,
+            if self.optimize { "-optimized" } else { "" },
+        ))
+    }
+
+    /// If writing the image scaled with `opts` reads it from the cache.
+    pub fn is_cached(&self, opts: ScalerOpts) -> bool {
+        self.cache
+            .as_ref()
+            .map(|cache| self.entry(cache, opts).exists())
+            .unwrap_or_default()
+    }
+
+    pub fn write<W: Write + Seek>(&self, w: &mut W, opts: ScalerOpts) -> Result<()> {
+        let cache = if let Some(cache) = self.cache.as_ref() {
+            cache
+        } else {
+            return self.encode(w, opts);
+        };
+        let entry = self.entry(cache, opts);
+        if let Ok(cached) = std::fs::read(&entry) {
+            w.write_all(&cached)?;
+            return Ok(());
+        }
+        let mut buf = vec![];
+        self.encode(&mut Cursor::new(&mut buf), opts)?;
+        std::fs::create_dir_all(cache)?;
+        // write to a temporary file first so readers never see partial entries
+        let tmp = entry.with_extension("png.tmp");
+        std::fs::write(&tmp, &buf)?;
+        std::fs::rename(&tmp, &entry)?;
+        w.write_all(&buf)?;
+        Ok(())
+    }
+
     pub fn to_vec(&self, opts: ScalerOpts) -> Vec<u8> {
         let mut buf = vec![];
         let mut cursor = Cursor::new(&mut buf);
@@ -138,17 +225,50 @@ impl ScalerOpts {
     }
 }
 
+/// Signature algorithm of a [`Signer`], determined by its key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SignatureAlgorithm {
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    RsaPkcs1Sha256,
+    /// ECDSA on the P-256 curve with SHA-256, DER encoded signatures.
+    EcdsaP256Sha256,
+    /// Pure Ed25519.
+    Ed25519,
+}
+
+#[derive(Clone)]
+enum SigningKey {
+    Rsa(RsaPrivateKey),
+    EcdsaP256(p256::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl SigningKey {
+    fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_der(der) {
+            return Ok(Self::Rsa(key));
+        }
+        if let Ok(key) = p256::SecretKey::from_pkcs8_der(der) {
+            return Ok(Self::EcdsaP256(key.into()));
+        }
+        if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_der(der) {
+            return Ok(Self::Ed25519(key));
+        }
+        anyhow::bail!("unsupported private key, expected an rsa, p256 or ed25519 key");
+    }
+}
+
 #[derive(Clone)]
 pub struct Signer {
-    key: RsaPrivateKey,
-    pubkey: RsaPublicKey,
+    key: SigningKey,
     cert: Certificate,
 }
 
 impl Signer {
     /// Creates a new signer using a private key and a certificate.
     ///
-    /// A new self signed certificate can be generated using openssl:
+    /// The private key can be an RSA, EC P-256 or Ed25519 key. A new self
+    /// signed certificate can be generated using openssl:
     /// ```sh
     /// openssl req -newkey rsa:2048 -new -nodes -x509 -days 3650 -keyout key.pem -out cert.pem
     /// cat cert.pem > pem
@@ -157,7 +277,9 @@ impl Signer {
     pub fn new(pem: &str) -> Result<Self> {
         let pem = pem::parse_many(pem)?;
         let key = if let Some(key) = pem.iter().find(|pem| pem.tag == "PRIVATE KEY") {
-            RsaPrivateKey::from_pkcs8_der(&key.contents)?
+            SigningKey::from_pkcs8_der(&key.contents)?
+        } else if let Some(key) = pem.iter().find(|pem| pem.tag == "EC PRIVATE KEY") {
+            SigningKey::EcdsaP256(p256::SecretKey::from_sec1_der(&key.contents)?.into())
         } else {
             anyhow::bail!("no private key found");
         };
@@ -167,37 +289,98 @@ impl Signer {
         } else {
             anyhow::bail!("no certificate found");
         };
-        let pubkey = RsaPublicKey::from(&key);
-        Ok(Self { key, pubkey, cert })
+        Ok(Self { key, cert })
     }
 
     pub fn from_path(path: &Path) -> Result<Self> {
         Self::new(&std::fs::read_to_string(path)?)
     }
 
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match &self.key {
+            SigningKey::Rsa(_) => SignatureAlgorithm::RsaPkcs1Sha256,
+            SigningKey::EcdsaP256(_) => SignatureAlgorithm::EcdsaP256Sha256,
+            SigningKey::Ed25519(_) => SignatureAlgorithm::Ed25519,
+        }
+    }
+
+    /// Signs `bytes` with the [`SignatureAlgorithm`] of the key.
     pub fn sign(&self, bytes: &[u8]) -> Vec<u8> {
-        let digest = Sha256::digest(bytes);
-        let padding = PaddingScheme::new_pkcs1v15_sign::<sha2::Sha256>();
-        self.key.sign(padding, &digest).unwrap()
+        match &self.key {
+            SigningKey::Rsa(key) => {
+                let digest = Sha256::digest(bytes);
+                let padding = PaddingScheme::new_pkcs1v15_sign::<sha2::Sha256>();
+                key.sign(padding, &digest).unwrap()
+            }
+            SigningKey::EcdsaP256(key) => {
+                let sig: p256::ecdsa::Signature = p256::ecdsa::signature::Signer::sign(key, bytes);
+                sig.to_der().as_bytes().to_vec()
+            }
+            SigningKey::Ed25519(key) => ed25519_dalek::Signer::sign(key, bytes).to_bytes().to_vec(),
+        }
     }
 
-    pub fn pubkey(&self) -> &RsaPublicKey {
-        &self.pubkey
+    /// DER encoded SubjectPublicKeyInfo of the key.
+    pub fn public_key_der(&self) -> Result<Vec<u8>> {
+        Ok(match &self.key {
+            SigningKey::Rsa(key) => RsaPublicKey::from(key)
+                .to_public_key_der()?
+                .as_ref()
+                .to_vec(),
+            SigningKey::EcdsaP256(key) => {
+                key.verifying_key().to_public_key_der()?.as_ref().to_vec()
+            }
+            SigningKey::Ed25519(key) => {
+                use ed25519_dalek::pkcs8::EncodePublicKey;
+                key.verifying_key().to_public_key_der()?.as_ref().to_vec()
+            }
+        })
     }
 
-    pub fn key(&self) -> &RsaPrivateKey {
-        &self.key
+    /// DER encoded PKCS#8 private key.
+    pub fn pkcs8_der(&self) -> Result<Vec<u8>> {
+        Ok(match &self.key {
+            SigningKey::Rsa(key) => key.to_pkcs8_der()?.as_bytes().to_vec(),
+            SigningKey::EcdsaP256(key) => p256::SecretKey::from(key)
+                .to_pkcs8_der()?
+                .as_bytes()
+                .to_vec(),
+            SigningKey::Ed25519(key) => {
+                use ed25519_dalek::pkcs8::{EncodePrivateKey, KeypairBytes};
+                // v1 without the public key, which is more widely supported
+                let keypair = KeypairBytes {
+                    secret_key: key.to_bytes(),
+                    public_key: None,
+                };
+                keypair.to_pkcs8_der()?.as_bytes().to_vec()
+            }
+        })
     }
 
     pub fn cert(&self) -> &Certificate {
         &self.cert
     }
+
+    /// PEM encoded certificate and private key, as accepted by [`Signer::new`].
+    pub fn to_pem(&self) -> Result<String> {
+        Ok(pem::encode_many(&[
+            pem::Pem {
+                tag: "CERTIFICATE".into(),
+                contents: rasn::der::encode(&self.cert)
+                    .map_err(|err| anyhow::anyhow!("{}", err))?,
+            },
+            pem::Pem {
+                tag: "PRIVATE KEY".into(),
+                contents: self.pkcs8_der()?,
+            },
+        ]))
+    }
 }
 
 impl std::fmt::Debug for Signer {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("Signer")
-            .field("pubkey", &self.pubkey)
+            .field("algorithm", &self.algorithm())
             .field("cert", &self.cert)
             .finish_non_exhaustive()
     }
@@ -207,7 +390,10 @@ impl std::fmt::Debug for Signer {
 pub enum ZipFileOptions {
     Unaligned,
     Aligned(u16),
+    /// Compressed with the default [`Compression`] of the archive
     Compressed,
+    /// Compressed with an explicit method and level, overriding the archive default
+    CompressedWith(Compression),
 }
 
 impl ZipFileOptions {
@@ -218,28 +404,123 @@ impl ZipFileOptions {
         }
     }
 
-    pub fn compression_method(&self) -> CompressionMethod {
+    /// Resolves the compression used for this entry given the archive default.
+    ///
+    /// Aligned and unaligned entries are always stored.
+    pub fn compression(self, default: Compression) -> Compression {
         match self {
-            Self::Compressed => CompressionMethod::Deflated,
-            _ => CompressionMethod::Stored,
+            Self::Compressed => default,
+            Self::CompressedWith(compression) => compression,
+            _ => Compression::Stored,
         }
     }
 }
 
+/// Compression method and level of zip entries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Store entries without compressing them
+    Stored,
+    /// Deflate with the given level (0-9), or the default level when `None`
+    Deflated(Option<i32>),
+    /// Zstd with the given level (1-21), or the default level when `None`.
+    ///
+    /// Only use this for containers that support it, Android and Windows
+    /// package readers only understand stored and deflated entries.
+    Zstd(Option<i32>),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Deflated(None)
+    }
+}
+
+impl Compression {
+    pub fn method(self) -> CompressionMethod {
+        match self {
+            Self::Stored => CompressionMethod::Stored,
+            Self::Deflated(_) => CompressionMethod::Deflated,
+            Self::Zstd(_) => CompressionMethod::Zstd,
+        }
+    }
+
+    pub fn level(self) -> Option<i32> {
+        match self {
+            Self::Stored => None,
+            Self::Deflated(level) | Self::Zstd(level) => level,
+        }
+    }
+
+    /// Returns `true` if the method is understood by every zip reader,
+    /// i.e. it is either stored or deflated.
+    pub fn is_portable(self) -> bool {
+        !matches!(self, Self::Zstd(_))
+    }
+
+    /// Ensures the method and level is usable for zip entries.
+    pub fn validate(self) -> Result<()> {
+        match self {
+            Self::Stored | Self::Deflated(None) | Self::Zstd(None) => {}
+            Self::Deflated(Some(level)) => anyhow::ensure!(
+                (0..=9).contains(&level),
+                "deflate compression level must be between 0 and 9, got {}",
+                level
+            ),
+            Self::Zstd(Some(level)) => anyhow::ensure!(
+                (1..=21).contains(&level),
+                "zstd compression level must be between 1 and 21, got {}",
+                level
+            ),
+        }
+        Ok(())
+    }
+}
+
 pub struct ZipInfo {
+    /// Start of the end of central directory record.
     pub cde_start: u64,
+    /// Start of the central directory.
     pub cd_start: u64,
+    /// Start of the zip64 end of central directory record, if present.
+    pub zip64_cde_start: Option<u64>,
 }
 
 impl ZipInfo {
     pub fn new<R: Read + Seek>(r: &mut R) -> Result<Self> {
+        const ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06064b50;
+        const ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+        const ZIP64_LOCATOR_SIZE: u64 = 20;
         let cde_start = find_cde_start_pos(r)?;
         r.seek(SeekFrom::Start(cde_start + 16))?;
-        let cd_start = r.read_u32::<LittleEndian>()? as u64;
-        Ok(Self {
+        let mut info = Self {
             cde_start,
-            cd_start,
-        })
+            cd_start: r.read_u32::<LittleEndian>()? as u64,
+            zip64_cde_start: None,
+        };
+        // the zip64 locator immediately precedes the end of central directory
+        if cde_start >= ZIP64_LOCATOR_SIZE {
+            r.seek(SeekFrom::Start(cde_start - ZIP64_LOCATOR_SIZE))?;
+            if r.read_u32::<LittleEndian>()? == ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE {
+                r.seek(SeekFrom::Current(4))?;
+                let zip64_cde_start = r.read_u64::<LittleEndian>()?;
+                r.seek(SeekFrom::Start(zip64_cde_start))?;
+                anyhow::ensure!(
+                    r.read_u32::<LittleEndian>()? == ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE,
+                    "Invalid zip64 central directory end"
+                );
+                r.seek(SeekFrom::Start(zip64_cde_start + 48))?;
+                info.cd_start = r.read_u64::<LittleEndian>()?;
+                info.zip64_cde_start = Some(zip64_cde_start);
+            }
+        }
+        Ok(info)
+    }
+
+    /// End of the central directory, which is where the (zip64) end of
+    /// central directory records start.
+    pub fn cd_end(&self) -> u64 {
+        self.zip64_cde_start.unwrap_or(self.cde_start)
     }
 }
 
@@ -248,7 +529,7 @@ fn find_cde_start_pos<R: Read + Seek>(reader: &mut R) -> Result<u64> {
     const CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06054b50;
     const HEADER_SIZE: u64 = 22;
     let file_length = reader.seek(SeekFrom::End(0))?;
-    let search_upper_bound = file_length.saturating_sub(HEADER_SIZE + ::std::u16::MAX as u64);
+    let search_upper_bound = file_length.saturating_sub(HEADER_SIZE + u16::MAX as u64);
     anyhow::ensure!(file_length >= HEADER_SIZE, "Invalid zip header");
     let mut pos = file_length - HEADER_SIZE;
     while pos >= search_upper_bound {
@@ -264,31 +545,82 @@ fn find_cde_start_pos<R: Read + Seek>(reader: &mut R) -> Result<u64> {
     anyhow::bail!("Could not find central directory end");
 }
 
-pub struct Zip {
-    zip: ZipWriter<File>,
-    compress: bool,
+/// Entries and offsets beyond this size require zip64 records.
+const ZIP64_BYTES_THR: u64 = u32::MAX as u64;
+
+/// Streaming zip writer over any [`Write`] + [`Seek`] sink.
+///
+/// Takes care of entry alignment, raw copies from other archives, directory
+/// recursion and applies the archive [`Compression`] to all entries added with
+/// [`ZipFileOptions::Compressed`].
+pub struct ZipBuilder<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    compression: Compression,
+    deterministic: bool,
 }
 
+/// A [`ZipBuilder`] writing to a file.
+pub type Zip = ZipBuilder<File>;
+
 impl Zip {
-    pub fn new(path: &Path, compress: bool) -> Result<Self> {
+    /// Creates a new zip archive, using `compression` for all entries
+    /// added with [`ZipFileOptions::Compressed`].
+    pub fn new(path: &Path, compression: Compression) -> Result<Self> {
+        compression.validate()?;
+        Self::from_writer(File::create(path)?, compression)
+    }
+
+    pub fn append(path: &Path, compression: Compression) -> Result<Self> {
+        compression.validate()?;
+        let f = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::from_append(f, compression)
+    }
+}
+
+impl<W: Read + Write + Seek> ZipBuilder<W> {
+    /// Appends entries to the existing archive in `w`.
+    pub fn from_append(w: W, compression: Compression) -> Result<Self> {
+        compression.validate()?;
         Ok(Self {
-            zip: ZipWriter::new(File::create(path)?),
-            compress,
+            zip: ZipWriter::new_append(w)?,
+            compression,
+            deterministic: false,
         })
     }
+}
 
-    pub fn append(path: &Path, compress: bool) -> Result<Self> {
-        let f = OpenOptions::new().read(true).write(true).open(path)?;
+impl<W: Write + Seek> ZipBuilder<W> {
+    /// Starts a new archive in `w`.
+    pub fn from_writer(w: W, compression: Compression) -> Result<Self> {
+        compression.validate()?;
         Ok(Self {
-            zip: ZipWriter::new_append(f)?,
-            compress,
+            zip: ZipWriter::new(w),
+            compression,
+            deterministic: false,
         })
     }
 
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Makes the archive contents independent of the machine it was built on.
+    ///
+    /// All entries get the same DOS timestamp (1980-01-01 00:00) and `0o644`
+    /// permissions, and directories are added in sorted order.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
     pub fn add_file(&mut self, source: &Path, dest: &Path, opts: ZipFileOptions) -> Result<()> {
         let mut f = File::open(source)
             .with_context(|| format!("While opening file `{}`", source.display()))?;
-        self.start_file(dest, opts)?;
+        let large_file = f.metadata()?.len() > ZIP64_BYTES_THR;
+        self.start_entry(dest, opts, large_file)?;
         std::io::copy(&mut f, &mut self.zip)?;
         Ok(())
     }
@@ -299,7 +631,23 @@ impl Zip {
         dest: &Path,
         opts: ZipFileOptions,
     ) -> Result<()> {
-        add_recursive(self, source, dest, opts)?;
+        let mut entries = std::fs::read_dir(source)
+            .with_context(|| format!("While reading directory `{}`", source.display()))?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        if self.deterministic {
+            entries.sort_by_key(|entry| entry.file_name());
+        }
+        for entry in entries {
+            let file_name = entry.file_name();
+            let source = source.join(&file_name);
+            let dest = dest.join(&file_name);
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.add_directory(&source, &dest, opts)?;
+            } else if file_type.is_file() {
+                self.add_file(&source, &dest, opts)?;
+            }
+        }
         Ok(())
     }
 
@@ -314,52 +662,50 @@ impl Zip {
         opts: ZipFileOptions,
         contents: &[u8],
     ) -> Result<()> {
-        self.start_file(dest, opts)?;
+        self.start_entry(dest, opts, contents.len() as u64 > ZIP64_BYTES_THR)?;
         self.zip.write_all(contents)?;
         Ok(())
     }
 
+    /// Starts a new entry. Entries larger than 4GB must be started with
+    /// [`Self::start_large_file`] instead.
     pub fn start_file(&mut self, dest: &Path, opts: ZipFileOptions) -> Result<()> {
+        self.start_entry(dest, opts, false)
+    }
+
+    /// Starts a new entry that may exceed 4GB, which requires zip64 headers.
+    pub fn start_large_file(&mut self, dest: &Path, opts: ZipFileOptions) -> Result<()> {
+        self.start_entry(dest, opts, true)
+    }
+
+    fn start_entry(&mut self, dest: &Path, opts: ZipFileOptions, large_file: bool) -> Result<()> {
         let name = dest
             .iter()
             .map(|seg| seg.to_str().unwrap())
             .collect::<Vec<_>>()
             .join("/");
-        let compression_method = if self.compress {
-            opts.compression_method()
-        } else {
-            CompressionMethod::Stored
-        };
-        let zopts = FileOptions::default().compression_method(compression_method);
+        let compression = opts.compression(self.compression);
+        compression.validate()?;
+        let mut zopts = FileOptions::default()
+            .compression_method(compression.method())
+            .compression_level(compression.level())
+            .large_file(large_file);
+        if self.deterministic {
+            zopts = zopts
+                .last_modified_time(DateTime::default())
+                .unix_permissions(0o644);
+        }
         self.zip.start_file_aligned(name, zopts, opts.alignment())?;
         Ok(())
     }
 
-    pub fn finish(mut self) -> Result<()> {
-        self.zip.finish()?;
-        Ok(())
+    /// Writes the central directory and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        Ok(self.zip.finish()?)
     }
 }
 
-fn add_recursive(zip: &mut Zip, source: &Path, dest: &Path, opts: ZipFileOptions) -> Result<()> {
-    for entry in std::fs::read_dir(source)
-        .with_context(|| format!("While reading directory `{}`", source.display()))?
-    {
-        let entry = entry?;
-        let file_name = entry.file_name();
-        let source = source.join(&file_name);
-        let dest = dest.join(&file_name);
-        let file_type = entry.file_type()?;
-        if file_type.is_dir() {
-            add_recursive(zip, &source, &dest, opts)?;
-        } else if file_type.is_file() {
-            zip.add_file(&source, &dest, opts)?;
-        }
-    }
-    Ok(())
-}
-
-impl Write for Zip {
+impl<W: Write + Seek> Write for ZipBuilder<W> {
     fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
         self.zip.write(bytes)
     }
@@ -485,4 +831,185 @@ mod tests {
     fn create_signer() {
         Signer::new(PEM).unwrap();
     }
+
+    #[test]
+    fn create_signer_ec() {
+        for (pem, algorithm) in [
+            (
+                include_str!("../assets/test-ec.pem"),
+                SignatureAlgorithm::EcdsaP256Sha256,
+            ),
+            (
+                include_str!("../assets/test-ed25519.pem"),
+                SignatureAlgorithm::Ed25519,
+            ),
+        ] {
+            let signer = Signer::new(pem).unwrap();
+            assert_eq!(signer.algorithm(), algorithm);
+            assert!(!signer.sign(b"hello").is_empty());
+            let key = Signer::new(&format!(
+                "{}{}",
+                pem::encode(&pem::Pem {
+                    tag: "CERTIFICATE".into(),
+                    contents: rasn::der::encode(signer.cert()).unwrap(),
+                }),
+                pem::encode(&pem::Pem {
+                    tag: "PRIVATE KEY".into(),
+                    contents: signer.pkcs8_der().unwrap(),
+                })
+            ))
+            .unwrap();
+            assert_eq!(
+                key.public_key_der().unwrap(),
+                signer.public_key_der().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn zip_compression() {
+        let path = std::env::temp_dir().join("xcommon-zip-compression.zip");
+        let mut zip = Zip::new(&path, Compression::Deflated(Some(9))).unwrap();
+        let contents = [b'x'; 1024];
+        zip.create_file("a".as_ref(), ZipFileOptions::Compressed, &contents)
+            .unwrap();
+        zip.create_file("b".as_ref(), ZipFileOptions::Aligned(4), &contents)
+            .unwrap();
+        zip.create_file(
+            "c".as_ref(),
+            ZipFileOptions::CompressedWith(Compression::Zstd(Some(19))),
+            &contents,
+        )
+        .unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let methods = ["a", "b", "c"].map(|name| archive.by_name(name).unwrap().compression());
+        assert_eq!(
+            methods,
+            [
+                CompressionMethod::Deflated,
+                CompressionMethod::Stored,
+                CompressionMethod::Zstd
+            ]
+        );
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn zip_builder_in_memory() {
+        let mut zip = ZipBuilder::from_writer(Cursor::new(vec![]), Compression::Stored).unwrap();
+        zip.create_file("a".as_ref(), ZipFileOptions::Aligned(4), b"hello")
+            .unwrap();
+        let mut buf = zip.finish().unwrap();
+        let mut zip = ZipBuilder::from_append(&mut buf, Compression::Stored).unwrap();
+        zip.create_file(
+            Path::new("b").join("c").as_ref(),
+            ZipFileOptions::Compressed,
+            b"world",
+        )
+        .unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = ZipArchive::new(buf).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut contents = String::new();
+        archive
+            .by_name("b/c")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "world");
+    }
+
+    #[test]
+    fn deterministic_zip() {
+        let dir = std::env::temp_dir().join("xcommon-deterministic-zip");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b", "a", "sub/c"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = std::fs::Permissions::from_mode(0o755);
+            std::fs::set_permissions(dir.join("a"), perms).unwrap();
+        }
+        let build = || {
+            let mut zip =
+                ZipBuilder::from_writer(Cursor::new(vec![]), Compression::default()).unwrap();
+            zip.set_deterministic(true);
+            zip.add_directory(&dir, Path::new("root"), ZipFileOptions::Compressed)
+                .unwrap();
+            zip.finish().unwrap().into_inner()
+        };
+        let first = build();
+        assert_eq!(first, build());
+
+        let mut archive = ZipArchive::new(Cursor::new(first)).unwrap();
+        let names = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["root/a", "root/b", "root/sub/c"]);
+        for i in 0..archive.len() {
+            let file = archive.by_index(i).unwrap();
+            assert_eq!(file.unix_mode().unwrap() & 0o777, 0o644);
+            assert_eq!(
+                file.last_modified().datepart(),
+                DateTime::default().datepart()
+            );
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn zip64_info() {
+        // more than 65535 entries forces zip64 end of central directory records
+        let mut zip = ZipBuilder::from_writer(Cursor::new(vec![]), Compression::Stored).unwrap();
+        for i in 0..=u16::MAX as u32 {
+            zip.create_file(format!("{i}").as_ref(), ZipFileOptions::Compressed, &[])
+                .unwrap();
+        }
+        let mut r = zip.finish().unwrap();
+        let info = ZipInfo::new(&mut r).unwrap();
+        let zip64_cde_start = info.zip64_cde_start.unwrap();
+        assert!(info.cd_start < zip64_cde_start);
+        assert!(zip64_cde_start < info.cde_start);
+        r.seek(SeekFrom::Start(info.cd_start)).unwrap();
+        assert_eq!(r.read_u32::<LittleEndian>().unwrap(), 0x02014b50);
+    }
+
+    #[test]
+    fn scaler_cache() {
+        let dir = std::env::temp_dir().join("xcommon-scaler-cache");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let icon = dir.join("icon.png");
+        RgbaImage::new(512, 512).save(&icon).unwrap();
+        let cache = dir.join("cache");
+        let scaler = Scaler::open(&icon).unwrap().with_cache(&cache);
+        let scaled = scaler.to_vec(ScalerOpts::new(48));
+        let entries = std::fs::read_dir(&cache)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(std::fs::read(&entries[0]).unwrap(), scaled);
+
+        // hits are served from the cache without re-encoding
+        std::fs::write(&entries[0], b"cached").unwrap();
+        let scaler = Scaler::open(&icon).unwrap().with_cache(&cache);
+        assert_eq!(scaler.to_vec(ScalerOpts::new(48)), b"cached");
+        assert_ne!(scaler.to_vec(ScalerOpts::new(64)), b"cached");
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn compression_level_range() {
+        assert!(Compression::Deflated(Some(9)).validate().is_ok());
+        assert!(Compression::Deflated(Some(10)).validate().is_err());
+        assert!(Compression::Zstd(Some(0)).validate().is_err());
+        assert!(Zip::new(Path::new("unused.zip"), Compression::Zstd(Some(22))).is_err());
+    }
 }